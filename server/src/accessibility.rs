@@ -0,0 +1,149 @@
+use crate::control::{broadcast_event, ControlEvent};
+use crate::process::list_processes;
+use log::{info, warn};
+use serde::Serialize;
+use std::io::{Error, ErrorKind};
+use std::process::Command;
+use std::sync::Mutex;
+use windows::Win32::UI::Accessibility::{HCF_HIGHCONTRASTON, HIGHCONTRASTW};
+use windows::Win32::UI::WindowsAndMessaging::{
+    SystemParametersInfoW, SPIF_SENDCHANGE, SPI_GETHIGHCONTRAST, SPI_SETHIGHCONTRAST,
+};
+
+const MAGNIFIER_EXE: &str = "Magnify.exe";
+
+/// Accessibility features of the host that clients can toggle.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct AccessibilityState {
+    pub magnifier: bool,
+    pub high_contrast: bool,
+}
+
+// The state before the first change of the current session.
+static ORIGINAL_STATE: Mutex<Option<AccessibilityState>> = Mutex::new(None);
+
+fn is_magnifier_running() -> std::io::Result<bool> {
+    Ok(list_processes()?
+        .iter()
+        .any(|process| process.exe_name.eq_ignore_ascii_case(MAGNIFIER_EXE)))
+}
+
+fn get_high_contrast() -> std::io::Result<HIGHCONTRASTW> {
+    let mut high_contrast = HIGHCONTRASTW {
+        cbSize: size_of::<HIGHCONTRASTW>() as u32,
+        ..Default::default()
+    };
+
+    unsafe {
+        SystemParametersInfoW(
+            SPI_GETHIGHCONTRAST,
+            high_contrast.cbSize,
+            Some(&mut high_contrast as *mut _ as *mut _),
+            Default::default(),
+        )?;
+    }
+
+    Ok(high_contrast)
+}
+
+pub fn accessibility_state() -> std::io::Result<AccessibilityState> {
+    Ok(AccessibilityState {
+        magnifier: is_magnifier_running()?,
+        high_contrast: get_high_contrast()?.dwFlags.contains(HCF_HIGHCONTRASTON),
+    })
+}
+
+// Remembers how the host was set up before a client changed it.
+fn save_original_state() -> std::io::Result<()> {
+    let mut original = ORIGINAL_STATE.lock().unwrap();
+    if original.is_none() {
+        *original = Some(accessibility_state()?);
+    }
+    Ok(())
+}
+
+// Tells clients the new state, so their toggles show what the host actually did.
+fn broadcast_state() {
+    match accessibility_state() {
+        Ok(state) => broadcast_event(&ControlEvent::Accessibility(state)),
+        Err(e) => warn!("Failed to read the accessibility state: {}", e),
+    }
+}
+
+fn apply_magnifier(enabled: bool) -> std::io::Result<()> {
+    if enabled == is_magnifier_running()? {
+        return Ok(());
+    }
+
+    if enabled {
+        Command::new(MAGNIFIER_EXE).spawn()?;
+    } else {
+        let status = Command::new("taskkill")
+            .args(["/IM", MAGNIFIER_EXE])
+            .status()?;
+        if !status.success() {
+            return Err(Error::new(
+                ErrorKind::Other,
+                "Failed to close the Magnifier",
+            ));
+        }
+    }
+
+    info!("Magnifier {}.", if enabled { "started" } else { "closed" });
+    Ok(())
+}
+
+fn apply_high_contrast(enabled: bool) -> std::io::Result<()> {
+    let mut high_contrast = get_high_contrast()?;
+    if enabled {
+        high_contrast.dwFlags |= HCF_HIGHCONTRASTON;
+    } else {
+        high_contrast.dwFlags &= !HCF_HIGHCONTRASTON;
+    }
+
+    // Without SPIF_UPDATEINIFILE the change is not persisted for the next logon.
+    unsafe {
+        SystemParametersInfoW(
+            SPI_SETHIGHCONTRAST,
+            high_contrast.cbSize,
+            Some(&mut high_contrast as *mut _ as *mut _),
+            SPIF_SENDCHANGE,
+        )?;
+    }
+
+    info!(
+        "High contrast {}.",
+        if enabled { "enabled" } else { "disabled" }
+    );
+    Ok(())
+}
+
+/// Starts or closes Windows Magnifier on the host.
+pub fn set_magnifier(enabled: bool) -> std::io::Result<()> {
+    save_original_state()?;
+    let result = apply_magnifier(enabled);
+    broadcast_state();
+    result
+}
+
+/// Turns high-contrast mode of the host on or off.
+pub fn set_high_contrast(enabled: bool) -> std::io::Result<()> {
+    save_original_state()?;
+    let result = apply_high_contrast(enabled);
+    broadcast_state();
+    result
+}
+
+/// Undoes the accessibility changes made during the session. Called when the last client leaves.
+pub fn restore_accessibility() {
+    let Some(original) = ORIGINAL_STATE.lock().unwrap().take() else {
+        return;
+    };
+
+    if let Err(e) = apply_magnifier(original.magnifier) {
+        warn!("Failed to restore the Magnifier: {}", e);
+    }
+    if let Err(e) = apply_high_contrast(original.high_contrast) {
+        warn!("Failed to restore high contrast: {}", e);
+    }
+}
@@ -0,0 +1,158 @@
+use log::{info, warn};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use windows::core::{w, PCWSTR};
+use windows::Win32::Foundation::HANDLE;
+use windows::Win32::System::Threading::{
+    AvRevertMmThreadCharacteristics, AvSetMmThreadCharacteristicsW, AvSetMmThreadPriority,
+    GetCurrentProcess, GetCurrentThread, GetProcessAffinityMask, SetThreadAffinityMask,
+    AVRT_PRIORITY_HIGH,
+};
+
+// Where the threads that keep the stream smooth run, and whether MMCSS schedules them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ThreadTuning {
+    // Logical processors as a bit mask, 0 for any.
+    stream_cores: usize,
+    input_cores: usize,
+    raise_priority: bool,
+}
+
+static TUNING: Mutex<ThreadTuning> = Mutex::new(ThreadTuning {
+    stream_cores: 0,
+    input_cores: 0,
+    raise_priority: false,
+});
+// Bumped on every change, so the long-running input thread applies it again.
+static GENERATION: AtomicU32 = AtomicU32::new(0);
+
+thread_local! {
+    // Set while MMCSS schedules the current thread.
+    static MMCSS_TASK: Cell<Option<HANDLE>> = const { Cell::new(None) };
+    static APPLIED_GENERATION: Cell<Option<u32>> = const { Cell::new(None) };
+}
+
+/// Parses a core list like "0,2,4-7" into an affinity mask. Empty means any core.
+pub fn parse_cores(list: &str) -> Result<usize, String> {
+    let core_count = std::thread::available_parallelism()
+        .map_or(1, |count| count.get())
+        .min(usize::BITS as usize);
+
+    let mut mask = 0;
+    for part in list
+        .split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+    {
+        let (first, last) = part.split_once('-').unwrap_or((part, part));
+        let (Ok(first), Ok(last)) = (first.trim().parse::<usize>(), last.trim().parse::<usize>())
+        else {
+            return Err(format!("\"{}\" is not a core or a range of cores", part));
+        };
+        if first > last || last >= core_count {
+            return Err(format!("Cores go from 0 to {}", core_count - 1));
+        }
+
+        for core in first..=last {
+            mask |= 1 << core;
+        }
+    }
+    Ok(mask)
+}
+
+/// Changes where the capture/encode and input threads run. Streaming threads pick it up when
+/// the pipeline starts, the input thread right away. Returns whether anything changed.
+pub fn configure(
+    stream_cores: &str,
+    input_cores: &str,
+    raise_priority: bool,
+) -> Result<bool, String> {
+    let tuning = ThreadTuning {
+        stream_cores: parse_cores(stream_cores)?,
+        input_cores: parse_cores(input_cores)?,
+        raise_priority,
+    };
+
+    let mut current = TUNING.lock().unwrap();
+    if *current == tuning {
+        return Ok(false);
+    }
+
+    info!(
+        "Thread tuning: stream cores {:#x}, input cores {:#x}, MMCSS {}.",
+        tuning.stream_cores, tuning.input_cores, tuning.raise_priority
+    );
+    *current = tuning;
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+    Ok(true)
+}
+
+fn tune_current_thread(mask: usize, raise_priority: bool, task_name: PCWSTR) {
+    unsafe {
+        // Any core means the cores of the process, to undo an earlier pin.
+        let mask = if mask != 0 {
+            mask
+        } else {
+            let mut process_mask = 0;
+            let mut system_mask = 0;
+            if GetProcessAffinityMask(GetCurrentProcess(), &mut process_mask, &mut system_mask)
+                .is_err()
+            {
+                return;
+            }
+            process_mask
+        };
+        if SetThreadAffinityMask(GetCurrentThread(), mask) == 0 {
+            warn!(
+                "Failed to set the thread affinity: {}",
+                windows::core::Error::from_win32()
+            );
+        }
+    }
+
+    MMCSS_TASK.with(|task| match (task.get(), raise_priority) {
+        (None, true) => {
+            let mut task_index = 0;
+            match unsafe { AvSetMmThreadCharacteristicsW(task_name, &mut task_index) } {
+                Ok(handle) => {
+                    if let Err(e) = unsafe { AvSetMmThreadPriority(handle, AVRT_PRIORITY_HIGH) } {
+                        warn!("Failed to raise the MMCSS priority: {}", e);
+                    }
+                    task.set(Some(handle));
+                }
+                Err(e) => warn!("Failed to join the MMCSS task: {}", e),
+            }
+        }
+        (Some(_), false) => leave_mmcss(),
+        _ => {}
+    });
+}
+
+fn leave_mmcss() {
+    if let Some(handle) = MMCSS_TASK.with(Cell::take) {
+        let _ = unsafe { AvRevertMmThreadCharacteristics(handle) };
+    }
+}
+
+/// Pins the calling streaming thread. Called as a pipeline thread starts.
+pub fn tune_stream_thread() {
+    let tuning = *TUNING.lock().unwrap();
+    tune_current_thread(tuning.stream_cores, tuning.raise_priority, w!("Capture"));
+}
+
+/// Leaves MMCSS before the streaming thread ends.
+pub fn release_stream_thread() {
+    leave_mmcss();
+}
+
+/// Pins the input thread, again after each change. Called from its loop.
+pub fn tune_input_thread() {
+    let generation = GENERATION.load(Ordering::Relaxed);
+    if APPLIED_GENERATION.with(|applied| applied.replace(Some(generation))) == Some(generation) {
+        return;
+    }
+
+    let tuning = *TUNING.lock().unwrap();
+    tune_current_thread(tuning.input_cores, tuning.raise_priority, w!("Games"));
+}
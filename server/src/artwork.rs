@@ -0,0 +1,72 @@
+use crate::library::GAME_LIBRARY;
+use base64::Engine;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use log::debug;
+use std::io::{Cursor, Error, ErrorKind};
+use std::sync::Mutex;
+
+pub const DEFAULT_THUMBNAIL_WIDTH: u32 = 300;
+const MAX_THUMBNAIL_WIDTH: u32 = 1200;
+const MAX_CACHED_THUMBNAILS: usize = 64;
+
+struct CachedThumbnail {
+    id: String,
+    width: u32,
+    jpeg: Vec<u8>,
+}
+
+// Recently served thumbnails, oldest first.
+static THUMBNAIL_CACHE: Mutex<Vec<CachedThumbnail>> = Mutex::new(Vec::new());
+
+/// Returns the artwork of a scanned game as a JPEG no wider than `max_width`.
+pub fn thumbnail(id: &str, max_width: u32) -> std::io::Result<Vec<u8>> {
+    let width = max_width.clamp(1, MAX_THUMBNAIL_WIDTH);
+
+    {
+        let cache = THUMBNAIL_CACHE.lock().unwrap();
+        if let Some(cached) = cache.iter().find(|c| c.id == id && c.width == width) {
+            debug!("Artwork cache hit for {} ({}px)", id, width);
+            return Ok(cached.jpeg.clone());
+        }
+    }
+
+    let path = GAME_LIBRARY
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|game| game.id == id)
+        .and_then(|game| game.artwork_path.clone())
+        .ok_or(Error::new(ErrorKind::NotFound, "No artwork for this game"))?;
+
+    let image = image::open(&path).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    let image = if image.width() > width {
+        image.resize(width, u32::MAX, FilterType::Triangle)
+    } else {
+        image
+    };
+
+    // JPEG has no alpha channel.
+    let mut jpeg = Vec::new();
+    DynamicImage::ImageRgb8(image.to_rgb8())
+        .write_to(&mut Cursor::new(&mut jpeg), ImageFormat::Jpeg)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+    let mut cache = THUMBNAIL_CACHE.lock().unwrap();
+    if cache.len() >= MAX_CACHED_THUMBNAILS {
+        cache.remove(0);
+    }
+    cache.push(CachedThumbnail {
+        id: id.to_string(),
+        width,
+        jpeg: jpeg.clone(),
+    });
+
+    Ok(jpeg)
+}
+
+/// Same as `thumbnail()`, base64-encoded for embedding into JSON messages.
+pub fn thumbnail_base64(id: &str, max_width: u32) -> std::io::Result<String> {
+    let jpeg = thumbnail(id, max_width)?;
+    Ok(base64::engine::general_purpose::STANDARD.encode(jpeg))
+}
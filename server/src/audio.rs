@@ -0,0 +1,501 @@
+use crate::pipeline::{make, BuildError};
+use crate::stream::{check_factory_exists, pipeline_element, STREAMING_STATE_GUARD};
+use gst::prelude::*;
+use gstreamer as gst;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+// NirSoft SoundVolumeCommandLine, used to assign per-app audio endpoints.
+// Windows has no public API for this.
+const SVCL_EXE: &str = "svcl.exe";
+
+/// Range of the audio bitrate, in kbps.
+pub const MIN_AUDIO_BITRATE_KBPS: u32 = 64;
+pub const MAX_AUDIO_BITRATE_KBPS: u32 = 512;
+/// Opus frame durations in ms. Shorter frames cut latency but cost bitrate.
+pub const AUDIO_FRAME_SIZES: [u32; 5] = [5, 10, 20, 40, 60];
+/// Channels of the stream unless the host and client both do surround.
+pub const STEREO_CHANNELS: u32 = 2;
+/// Most channels Opus maps, 7.1.
+pub const MAX_AUDIO_CHANNELS: u32 = 8;
+// AAC frames are always 1024 samples, about 21 ms at 48 kHz.
+const AAC_FRAME_SIZE: u32 = 20;
+
+// Audio packet loss the client last reported over RTCP, in percent.
+static MEASURED_LOSS_PERCENT: AtomicU32 = AtomicU32::new(0);
+// Channels of the running stream, which its bitrate scales with.
+static STREAM_CHANNELS: AtomicU32 = AtomicU32::new(STEREO_CHANNELS);
+
+/// Audio codecs the stream can be encoded with.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioCodec {
+    Opus,
+    // For clients with hardware AAC decoding but no Opus, e.g. some TVs.
+    Aac,
+}
+
+impl AudioCodec {
+    pub const ALL: [AudioCodec; 2] = [AudioCodec::Opus, AudioCodec::Aac];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AudioCodec::Opus => "opus",
+            AudioCodec::Aac => "aac",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "opus" => Some(AudioCodec::Opus),
+            "aac" => Some(AudioCodec::Aac),
+            _ => None,
+        }
+    }
+
+    fn encoder(&self) -> &'static str {
+        match self {
+            AudioCodec::Opus => "opusenc",
+            AudioCodec::Aac => "avenc_aac",
+        }
+    }
+
+    pub fn payloader(&self) -> &'static str {
+        match self {
+            AudioCodec::Opus => "rtpopuspay",
+            AudioCodec::Aac => "rtpmp4gpay",
+        }
+    }
+
+    /// Parser that frames the encoded stream for a muxer, see `recording`.
+    pub fn parser(&self) -> &'static str {
+        match self {
+            AudioCodec::Opus => "opusparse",
+            AudioCodec::Aac => "aacparse",
+        }
+    }
+
+    /// Whether the encoder and payloader are installed.
+    pub fn is_available(&self) -> bool {
+        check_factory_exists(self.encoder()) && check_factory_exists(self.payloader())
+    }
+
+    /// Frame duration in ms, `configured` if the codec lets it be picked.
+    pub fn frame_size(&self, configured: u32) -> u32 {
+        match self {
+            AudioCodec::Opus => configured,
+            AudioCodec::Aac => AAC_FRAME_SIZE,
+        }
+    }
+
+    /// The encoder of the audio branch, followed by the parser AAC needs. The payloader follows
+    /// them in `stream`. Opus gets its FEC and DTX settings from `configure_opus`.
+    pub fn make_encoder(
+        &self,
+        bitrate_kbps: u32,
+        frame_size: u32,
+        channels: u32,
+    ) -> Result<Vec<gst::Element>, BuildError> {
+        let bitrate = surround_bitrate_kbps(bitrate_kbps, channels) * 1000;
+        match self {
+            // Mapping family 1 carries up to 7.1 in the Vorbis channel order.
+            AudioCodec::Opus => Ok(vec![make("opusenc")?
+                .name("opusenc")
+                .property("perfect-timestamp", true)
+                .property_from_str("audio-type", "restricted-lowdelay")
+                .property_from_str("bitrate-type", "cbr")
+                .property("bitrate", bitrate as i32)
+                .property_from_str("frame-size", &frame_size.to_string())
+                .property_from_str(
+                    "channel-mapping-family",
+                    if channels > STEREO_CHANNELS { "1" } else { "0" },
+                )
+                .build()?]),
+            AudioCodec::Aac => Ok(vec![
+                make("avenc_aac")?
+                    .name("aacenc")
+                    .property_from_str("bitrate", &bitrate.to_string())
+                    .build()?,
+                make("aacparse")?.build()?,
+            ]),
+        }
+    }
+
+    /// The `encoding-name` of the RTP caps of a stream with `channels`.
+    pub fn encoding_name(&self, channels: u32) -> &'static str {
+        match self {
+            AudioCodec::Opus if channels > STEREO_CHANNELS => "MULTIOPUS",
+            AudioCodec::Opus => "OPUS",
+            AudioCodec::Aac => "MPEG4-GENERIC",
+        }
+    }
+}
+
+impl std::fmt::Display for AudioCodec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AudioCodec::Opus => write!(f, "Opus"),
+            AudioCodec::Aac => write!(f, "AAC"),
+        }
+    }
+}
+
+/// The configured bitrate is for stereo, surround gets as much per pair of channels.
+pub fn surround_bitrate_kbps(bitrate_kbps: u32, channels: u32) -> u32 {
+    (bitrate_kbps * channels.max(STEREO_CHANNELS) / STEREO_CHANNELS).min(MAX_AUDIO_BITRATE_KBPS)
+}
+
+/// Picks the channels of the next stream: the host layout if surround is allowed, as far as the
+/// client plays it.
+pub fn select_channels(source_channels: u32, client_channels: Option<u32>) -> u32 {
+    let surround = {
+        let guard = STREAMING_STATE_GUARD.lock().unwrap();
+        guard.as_ref().is_some_and(|state| state.surround)
+    };
+    let channels = if surround {
+        source_channels
+            .min(client_channels.unwrap_or(STEREO_CHANNELS))
+            .clamp(1, MAX_AUDIO_CHANNELS)
+    } else {
+        STEREO_CHANNELS
+    };
+    STREAM_CHANNELS.store(channels, Ordering::Relaxed);
+    channels
+}
+
+/// Channels the host would stream to a client that plays them all, for the capability exchange.
+/// GStreamer must be initialized.
+pub fn available_channels() -> u32 {
+    let surround = {
+        let guard = STREAMING_STATE_GUARD.lock().unwrap();
+        guard.as_ref().is_some_and(|state| state.surround)
+    };
+    if surround {
+        source_device().channels.clamp(1, MAX_AUDIO_CHANNELS)
+    } else {
+        STEREO_CHANNELS
+    }
+}
+
+/// Audio codecs the server can encode, for clients to negotiate with. Opus always works.
+pub fn supported_codecs() -> Vec<AudioCodec> {
+    AudioCodec::ALL
+        .into_iter()
+        .filter(|codec| *codec == AudioCodec::Opus || codec.is_available())
+        .collect()
+}
+
+/// The configured codec if the client decodes it and it is installed, Opus otherwise.
+pub fn select_codec(configured: AudioCodec, client_codecs: &[AudioCodec]) -> AudioCodec {
+    if configured == AudioCodec::Opus {
+        return configured;
+    }
+    if !client_codecs.contains(&configured) {
+        info!("The client does not decode {}, using Opus.", configured);
+    } else if !configured.is_available() {
+        warn!("{} is not installed, using Opus.", configured.encoder());
+    } else {
+        return configured;
+    }
+    AudioCodec::Opus
+}
+
+/// A WASAPI endpoint audio can be streamed from.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AudioDevice {
+    // Endpoint ID, which stays the same across reboots.
+    pub id: String,
+    pub name: String,
+    // Whether it is an output, captured in loopback, rather than a microphone or line in.
+    pub loopback: bool,
+    // Channels of its mix format, e.g. 6 for a 5.1 output.
+    pub channels: u32,
+    // Whether it is the default output or input.
+    pub default: bool,
+}
+
+impl std::fmt::Display for AudioDevice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.loopback {
+            write!(f, "{} (output)", self.name)
+        } else {
+            write!(f, "{}", self.name)
+        }
+    }
+}
+
+/// Outputs and inputs, outputs first. GStreamer must be initialized.
+pub fn list_devices() -> Vec<AudioDevice> {
+    let monitor = gst::DeviceMonitor::new();
+    monitor.add_filter(Some("Audio/Sink"), None);
+    monitor.add_filter(Some("Audio/Source"), None);
+
+    if let Err(e) = monitor.start() {
+        warn!("Failed to start audio device monitor: {}", e);
+        return Vec::new();
+    }
+
+    let mut devices: Vec<AudioDevice> = monitor
+        .devices()
+        .into_iter()
+        .filter_map(|device| {
+            let properties = device.properties()?;
+            let id = properties.get::<String>("device.id").ok()?;
+            // The mix format is the only caps structure, with fixed channels.
+            let channels = device
+                .caps()
+                .and_then(|caps| caps.structure(0)?.get::<i32>("channels").ok())
+                .map_or(STEREO_CHANNELS, |channels| channels as u32);
+            Some(AudioDevice {
+                id,
+                name: device.display_name().to_string(),
+                loopback: device.has_classes("Audio/Sink"),
+                channels,
+                default: properties.get::<bool>("device.default").unwrap_or(false),
+            })
+        })
+        .collect();
+
+    monitor.stop();
+    devices.sort_by_key(|device| !device.loopback);
+    devices
+}
+
+/// What `wasapi2src` captures.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioSource {
+    // Endpoint ID, None for the default output.
+    pub device_id: Option<String>,
+    pub name: String,
+    pub loopback: bool,
+    pub channels: u32,
+}
+
+/// The configured source: the chosen device, else the stream audio device, else the default
+/// output. GStreamer must be initialized.
+pub fn source_device() -> AudioSource {
+    let devices = list_devices();
+    let default_device = devices
+        .iter()
+        .find(|device| device.loopback && device.default);
+    let default_output = AudioSource {
+        device_id: None,
+        name: default_device.map_or("the default output".to_string(), |device| {
+            device.to_string()
+        }),
+        loopback: true,
+        channels: default_device.map_or(STEREO_CHANNELS, |device| device.channels),
+    };
+    let source = |device: &AudioDevice| AudioSource {
+        device_id: Some(device.id.clone()),
+        name: device.to_string(),
+        loopback: device.loopback,
+        channels: device.channels,
+    };
+
+    let (source_device, stream_audio_device) = {
+        let guard = STREAMING_STATE_GUARD.lock().unwrap();
+        guard
+            .as_ref()
+            .map_or((String::new(), String::new()), |state| {
+                (
+                    state.audio_source_device.clone(),
+                    state.stream_audio_device.clone(),
+                )
+            })
+    };
+
+    if !source_device.is_empty() {
+        if let Some(device) = devices.iter().find(|device| device.id == source_device) {
+            return source(device);
+        }
+        warn!("Audio source {} not found.", source_device);
+    }
+
+    if stream_audio_device.is_empty() {
+        return default_output;
+    }
+    let name = stream_audio_device.to_lowercase();
+    match devices
+        .iter()
+        .find(|device| device.loopback && device.name.to_lowercase().contains(&name))
+    {
+        Some(device) => source(device),
+        None => {
+            warn!(
+                "Audio device {} not found, capturing the default device.",
+                stream_audio_device
+            );
+            default_output
+        }
+    }
+}
+
+/// Captures audio from the device with endpoint ID `id`, or the default output if empty. A
+/// running stream only restarts its audio source. Blocking.
+pub fn select_source_device(id: &str) {
+    {
+        let mut guard = STREAMING_STATE_GUARD.lock().unwrap();
+        let Some(state) = guard.as_mut() else {
+            return;
+        };
+        if state.audio_source_device == id {
+            return;
+        }
+        state.audio_source_device = id.to_string();
+    }
+
+    let Some(source) = pipeline_element("audiosrc") else {
+        return;
+    };
+    // The stream keeps its channels, audioconvert mixes the new device to them.
+    let AudioSource {
+        device_id,
+        loopback,
+        ..
+    } = source_device();
+    // wasapi2src only opens its device when it starts.
+    if let Err(e) = source.set_state(gst::State::Null) {
+        warn!("Failed to stop the audio source: {}", e);
+        return;
+    }
+    source.set_property("device", device_id);
+    source.set_property("loopback", loopback);
+    if let Err(e) = source.sync_state_with_parent() {
+        warn!("Failed to restart the audio source: {}", e);
+    }
+}
+
+/// Finds the WASAPI endpoint ID of the render device whose name contains `name`.
+/// GStreamer must be initialized.
+pub fn find_render_device_id(name: &str) -> Option<String> {
+    let monitor = gst::DeviceMonitor::new();
+    monitor.add_filter(Some("Audio/Sink"), None);
+
+    if let Err(e) = monitor.start() {
+        warn!("Failed to start audio device monitor: {}", e);
+        return None;
+    }
+
+    let name = name.to_lowercase();
+    let id = monitor.devices().into_iter().find_map(|device| {
+        if !device.display_name().to_lowercase().contains(&name) {
+            return None;
+        }
+        device.properties()?.get::<String>("device.id").ok()
+    });
+
+    monitor.stop();
+    id
+}
+
+fn svcl_path() -> Option<PathBuf> {
+    // Prefer a copy shipped next to the server executable, then fall back to PATH.
+    let local = std::env::current_exe().ok()?.with_file_name(SVCL_EXE);
+    if local.exists() {
+        return Some(local);
+    }
+
+    Command::new("where")
+        .arg(SVCL_EXE)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|_| PathBuf::from(SVCL_EXE))
+}
+
+/// Makes `device_name` the default audio endpoint of process `pid`,
+/// so the app plays into the stream-only device instead of the speakers.
+pub fn route_process_audio(pid: u32, device_name: &str) -> std::io::Result<()> {
+    let svcl = svcl_path().ok_or(Error::new(
+        ErrorKind::NotFound,
+        "svcl.exe not found, per-app audio routing is unavailable",
+    ))?;
+
+    let output = Command::new(svcl)
+        .args(["/SetAppDefault", device_name, "all", &pid.to_string()])
+        .output()?;
+
+    if !output.status.success() {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!("svcl.exe failed to route pid {} to {}", pid, device_name),
+        ));
+    }
+
+    info!("Routed audio of pid {} to {}.", pid, device_name);
+    Ok(())
+}
+
+/// Routes `pid` to the configured stream audio device, if any.
+pub fn route_to_stream_device(pid: u32) {
+    let device = {
+        let guard = STREAMING_STATE_GUARD.lock().unwrap();
+        guard
+            .as_ref()
+            .map_or(String::new(), |state| state.stream_audio_device.clone())
+    };
+
+    if device.is_empty() {
+        return;
+    }
+
+    if let Err(e) = route_process_audio(pid, &device) {
+        warn!("{}", e);
+    }
+}
+
+/// Loss opusenc should plan its FEC for: the configured percentage, raised by measured loss.
+pub fn expected_loss_percent(configured: u32) -> u32 {
+    configured
+        .max(MEASURED_LOSS_PERCENT.load(Ordering::Relaxed))
+        .min(100)
+}
+
+/// Forgets the loss measured for the previous stream.
+pub fn reset_measured_loss() {
+    MEASURED_LOSS_PERCENT.store(0, Ordering::Relaxed);
+}
+
+/// Records the fraction of audio packets a client reported lost.
+/// Returns whether the percentage changed, so the encoder needs updating.
+pub fn record_measured_loss(fraction: f32) -> bool {
+    let percent = (fraction * 100.0).ceil() as u32;
+    MEASURED_LOSS_PERCENT.swap(percent, Ordering::Relaxed) != percent
+}
+
+/// Applies the bitrate, FEC and DTX settings to `opusenc`. They can change while playing.
+pub fn configure_opus(opusenc: &gst::Element) {
+    let (bitrate_kbps, fec, dtx, loss_percentage) = {
+        let guard = STREAMING_STATE_GUARD.lock().unwrap();
+        let Some(state) = guard.as_ref() else {
+            return;
+        };
+        (
+            state.audio_bitrate_kbps,
+            state.audio_fec,
+            state.audio_dtx,
+            expected_loss_percent(state.audio_loss_percentage),
+        )
+    };
+
+    let channels = STREAM_CHANNELS.load(Ordering::Relaxed);
+    opusenc.set_property(
+        "bitrate",
+        (surround_bitrate_kbps(bitrate_kbps, channels) * 1000) as i32,
+    );
+    opusenc.set_property("inband-fec", fec);
+    opusenc.set_property("dtx", dtx);
+    opusenc.set_property("packet-loss-percentage", loss_percentage as i32);
+}
+
+/// Applies changed audio settings to the running stream, if any.
+pub fn apply_opus_settings() {
+    if let Some(opusenc) = pipeline_element("opusenc") {
+        configure_opus(&opusenc);
+    }
+}
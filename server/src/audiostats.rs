@@ -0,0 +1,115 @@
+use crate::control::{broadcast_event, ControlEvent};
+use gstreamer as gst;
+use log::warn;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// A timestamp gap longer than this between audio buffers is heard as a dropout.
+const DROPOUT_GAP: gst::ClockTime = gst::ClockTime::from_mseconds(5);
+const DROPOUT_WINDOW: Duration = Duration::from_secs(60);
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Health of the captured audio over the last minute.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct AudioStats {
+    pub dropouts_per_minute: u32,
+    // Variation of buffer arrival against their timestamps, see RFC 3550 section 6.4.1.
+    pub jitter_ms: f32,
+}
+
+struct AudioMonitor {
+    // Where the next buffer should start if nothing was lost.
+    expected_pts: Option<gst::ClockTime>,
+    last_arrival: Option<(Instant, gst::ClockTime)>,
+    jitter_ms: f32,
+    dropouts: VecDeque<Instant>,
+    last_report: Instant,
+}
+
+impl AudioMonitor {
+    fn stats(&mut self) -> AudioStats {
+        while self
+            .dropouts
+            .front()
+            .map_or(false, |at| at.elapsed() > DROPOUT_WINDOW)
+        {
+            self.dropouts.pop_front();
+        }
+
+        AudioStats {
+            dropouts_per_minute: self.dropouts.len() as u32,
+            jitter_ms: self.jitter_ms,
+        }
+    }
+}
+
+// Present while an audio stream is running.
+static MONITOR: Mutex<Option<AudioMonitor>> = Mutex::new(None);
+
+/// Starts counting for a new stream.
+pub fn start() {
+    *MONITOR.lock().unwrap() = Some(AudioMonitor {
+        expected_pts: None,
+        last_arrival: None,
+        jitter_ms: 0.0,
+        dropouts: VecDeque::new(),
+        last_report: Instant::now(),
+    });
+}
+
+pub fn stop() {
+    *MONITOR.lock().unwrap() = None;
+}
+
+/// Stats of the running stream, for the GUI.
+pub fn current_stats() -> Option<AudioStats> {
+    MONITOR.lock().unwrap().as_mut().map(AudioMonitor::stats)
+}
+
+/// Checks one captured audio buffer for discontinuities and arrival jitter.
+/// Called from the streaming thread of the audio source.
+pub fn record_buffer(buffer: &gst::BufferRef) {
+    let now = Instant::now();
+    let Some(pts) = buffer.pts() else {
+        return;
+    };
+
+    let report = {
+        let mut guard = MONITOR.lock().unwrap();
+        let Some(monitor) = guard.as_mut() else {
+            return;
+        };
+
+        let gap = monitor
+            .expected_pts
+            .map_or(false, |expected| pts > expected + DROPOUT_GAP);
+        let discont =
+            monitor.expected_pts.is_some() && buffer.flags().contains(gst::BufferFlags::DISCONT);
+        if gap || discont {
+            warn!("Audio dropout at {}.", pts);
+            monitor.dropouts.push_back(now);
+        }
+        monitor.expected_pts = Some(pts + buffer.duration().unwrap_or(gst::ClockTime::ZERO));
+
+        if let Some((last_instant, last_pts)) = monitor.last_arrival {
+            let arrival_ms = now.duration_since(last_instant).as_secs_f32() * 1000.0;
+            let media_ms = pts.saturating_sub(last_pts).nseconds() as f32 / 1_000_000.0;
+            monitor.jitter_ms += ((arrival_ms - media_ms).abs() - monitor.jitter_ms) / 16.0;
+        }
+        monitor.last_arrival = Some((now, pts));
+
+        if monitor.last_report.elapsed() >= REPORT_INTERVAL {
+            monitor.last_report = now;
+            Some(monitor.stats())
+        } else {
+            None
+        }
+    };
+
+    // Sent without holding the monitor, broadcasting locks the streaming state.
+    if let Some(stats) = report {
+        broadcast_event(&ControlEvent::AudioStats(stats));
+    }
+}
@@ -0,0 +1,254 @@
+use crate::encoder::{supported_codecs, Av1Tuning, ContentTune, VideoCodec, VideoEncoder};
+use crate::latency::LatencyPreset;
+use crate::pipeline::{add_chain, capsfilter, make, BuildError};
+use crate::stream::init_gstreamer;
+use gst::prelude::*;
+use gstreamer as gst;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const BENCH_SECONDS: u64 = 10;
+const BENCH_RESOLUTIONS: [(u32, u32); 4] = [(1280, 720), (1920, 1080), (2560, 1440), (3840, 2160)];
+// Asked of the capture source, so the encoder is the bottleneck rather than the display refresh rate.
+const BENCH_FRAMERATE: u32 = 240;
+const BENCH_BITRATE_KBPS: u32 = 20000;
+
+const CPU_COUNTER: &str = "\\Processor(_Total)\\% Processor Time";
+const GPU_ENCODE_COUNTER: &str = "\\GPU Engine(*engtype_VideoEncode)\\Utilization Percentage";
+
+struct BenchResult {
+    encoder: VideoEncoder,
+    codec: VideoCodec,
+    resolution: (u32, u32),
+    fps: f32,
+    cpu_percent: Option<f32>,
+    gpu_percent: Option<f32>,
+    error: Option<String>,
+}
+
+// Samples CPU and GPU encode usage once per second while the benchmark runs.
+fn start_usage_sampler() -> Option<std::process::Child> {
+    Command::new("typeperf")
+        .args([
+            CPU_COUNTER,
+            GPU_ENCODE_COUNTER,
+            "-si",
+            "1",
+            "-sc",
+            &BENCH_SECONDS.to_string(),
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()
+}
+
+// Averages typeperf CSV output. GPU engine instances are summed per sample.
+fn parse_usage(output: &str) -> (Option<f32>, Option<f32>) {
+    let rows: Vec<Vec<&str>> = output
+        .lines()
+        .filter(|line| line.starts_with('"'))
+        .map(|line| {
+            line.split(',')
+                .map(|field| field.trim_matches('"'))
+                .collect()
+        })
+        .collect();
+
+    let Some((header, samples)) = rows.split_first() else {
+        return (None, None);
+    };
+
+    let mut cpu = Vec::new();
+    let mut gpu = Vec::new();
+    for sample in samples {
+        let mut gpu_sum = 0.0;
+        for (column, field) in header.iter().zip(sample.iter()).skip(1) {
+            let Ok(value) = field.trim().parse::<f32>() else {
+                continue;
+            };
+            if column.contains("Processor(_Total)") {
+                cpu.push(value);
+            } else {
+                gpu_sum += value;
+            }
+        }
+        if header.len() > 2 {
+            gpu.push(gpu_sum);
+        }
+    }
+
+    let average = |values: &[f32]| {
+        (!values.is_empty()).then(|| values.iter().sum::<f32>() / values.len() as f32)
+    };
+    (average(&cpu), average(&gpu))
+}
+
+fn bench_one(encoder: VideoEncoder, codec: VideoCodec, (width, height): (u32, u32)) -> BenchResult {
+    let mut result = BenchResult {
+        encoder,
+        codec,
+        resolution: (width, height),
+        fps: 0.0,
+        cpu_percent: None,
+        gpu_percent: None,
+        error: None,
+    };
+
+    let raw_caps = format!(
+        "video/x-raw{},width={},height={},format=NV12,framerate={}/1",
+        if encoder.takes_d3d11_memory() {
+            "(memory:D3D11Memory)"
+        } else {
+            ""
+        },
+        width,
+        height,
+        BENCH_FRAMERATE
+    );
+
+    // The encoder as streams set it up with the lowest latency preset.
+    let build = || -> Result<(gst::Pipeline, gst::Element), BuildError> {
+        let mut elements = vec![make("d3d11screencapturesrc")?
+            .property("show-cursor", true)
+            .build()?];
+        let converters: &[&str] = if encoder.takes_d3d11_memory() {
+            &["d3d11convert"]
+        } else {
+            &["d3d11download", "videoconvert", "videoscale"]
+        };
+        for converter in converters {
+            elements.push(make(converter)?.build()?);
+        }
+        elements.push(capsfilter(None, &raw_caps)?);
+        elements.push(encoder.make_element(
+            encoder.factory_name(codec),
+            codec,
+            &LatencyPreset::UltraLow.params(),
+            BENCH_BITRATE_KBPS,
+            false,
+            "",
+            &Av1Tuning::DEFAULT,
+            ContentTune::Game,
+            0,
+        )?);
+        let sink = make("fakesink")?.property("sync", false).build()?;
+        elements.push(sink.clone());
+
+        let pipeline = gst::Pipeline::new();
+        add_chain(pipeline.upcast_ref(), None, &elements)?;
+        Ok((pipeline, sink))
+    };
+    let (pipeline, sink) = match build() {
+        Ok(built) => built,
+        Err(e) => {
+            result.error = Some(e.to_string());
+            return result;
+        }
+    };
+
+    let frames = Arc::new(AtomicUsize::new(0));
+    let sink_frames = frames.clone();
+    sink.static_pad("sink")
+        .unwrap()
+        .add_probe(gst::PadProbeType::BUFFER, move |_, _| {
+            sink_frames.fetch_add(1, Ordering::Relaxed);
+            gst::PadProbeReturn::Ok
+        });
+
+    if let Err(e) = pipeline.set_state(gst::State::Playing) {
+        result.error = Some(e.to_string());
+        return result;
+    }
+
+    // Let the encoder warm up before measuring.
+    thread::sleep(Duration::from_secs(1));
+    let sampler = start_usage_sampler();
+    let frames_before = frames.load(Ordering::Relaxed);
+    let start = Instant::now();
+
+    thread::sleep(Duration::from_secs(BENCH_SECONDS));
+
+    result.fps =
+        (frames.load(Ordering::Relaxed) - frames_before) as f32 / start.elapsed().as_secs_f32();
+
+    result.error = pipeline.bus().and_then(|bus| {
+        bus.pop_filtered(&[gst::MessageType::Error])
+            .and_then(|msg| match msg.view() {
+                gst::MessageView::Error(err) => Some(err.error().to_string()),
+                _ => None,
+            })
+    });
+
+    let _ = pipeline.set_state(gst::State::Null);
+
+    if let Some(output) = sampler.and_then(|sampler| sampler.wait_with_output().ok()) {
+        (result.cpu_percent, result.gpu_percent) =
+            parse_usage(&String::from_utf8_lossy(&output.stdout));
+    }
+
+    result
+}
+
+fn format_percent(value: Option<f32>) -> String {
+    value.map_or("-".to_string(), |value| format!("{:.0}%", value))
+}
+
+/// Entry point of `rstream-server bench`. Runs capture and encoding without a network with
+/// every available encoder and codec at several resolutions and prints the sustained frame rate
+/// and resource usage.
+pub fn run_bench() {
+    init_gstreamer();
+
+    // Every encoder that is installed for each codec streams can use.
+    let runs: Vec<(VideoEncoder, VideoCodec)> = supported_codecs()
+        .into_iter()
+        .flat_map(|codec| {
+            VideoEncoder::ALL
+                .into_iter()
+                .filter(move |encoder| encoder.is_available(codec))
+                .map(move |encoder| (encoder, codec))
+        })
+        .collect();
+
+    if runs.is_empty() {
+        println!("No supported encoder found.");
+        return;
+    }
+
+    println!(
+        "Benchmarking {} for {} s per run...\n",
+        runs.iter()
+            .map(|(encoder, codec)| encoder.factory_name(*codec))
+            .collect::<Vec<_>>()
+            .join(", "),
+        BENCH_SECONDS
+    );
+    println!(
+        "{:<12} {:<11} {:>7} {:>6} {:>11}",
+        "Encoder", "Resolution", "FPS", "CPU", "GPU encode"
+    );
+
+    for (encoder, codec) in runs {
+        for resolution in BENCH_RESOLUTIONS {
+            let result = bench_one(encoder, codec, resolution);
+            let resolution = format!("{}x{}", result.resolution.0, result.resolution.1);
+            let element = result.encoder.factory_name(result.codec);
+
+            match result.error {
+                Some(error) => println!("{:<12} {:<11} failed: {}", element, resolution, error),
+                None => println!(
+                    "{:<12} {:<11} {:>7.1} {:>6} {:>11}",
+                    element,
+                    resolution,
+                    result.fps,
+                    format_percent(result.cpu_percent),
+                    format_percent(result.gpu_percent)
+                ),
+            }
+        }
+    }
+}
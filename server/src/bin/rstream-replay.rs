@@ -0,0 +1,295 @@
+//! Replays a capture of control messages and input packets against a running server, e.g. to
+//! check input handling or control message behavior after a change.
+//!
+//! Usage: `rstream-replay <capture.jsonl> [host] [--ws-port N] [--enet-port N] [--channels N]
+//! [--speed X]`
+//!
+//! `rstream-server --record-protocol` records what its clients send in this format, with the PIN
+//! and client secret replaced by `<redacted>`, so put the PIN back into the stream config first.
+//! The replay then pairs as a new client. `--channels` has to stay within the ENet channel limit
+//! the server is set to.
+//!
+//! The capture has one JSON object per line, sent in file order once `at_ms` passed:
+//!
+//! ```text
+//! {"at_ms": 0, "control": {"pin": "1234", "video_width": 1280, ...}}
+//! {"at_ms": 500, "input": "04 00 00 00 00 00 00 00 00"}
+//! {"at_ms": 520, "input": "...", "channel": 1, "reliable": false}
+//! ```
+//!
+//! `control` goes over the WebSocket as is, so a capture usually starts with the stream config
+//! to authenticate. `input` is an ENet packet in hex, channel 0 and reliable by default. Lines
+//! starting with `#` are skipped. Events from the server are printed with the time they arrived,
+//! so two runs can be diffed. TLS is not supported, switch it off on the server.
+
+use async_std::net::TcpStream;
+use async_std::task;
+use async_tungstenite::tungstenite::Message;
+use futures::prelude::*;
+use rusty_enet as enet;
+use serde::Deserialize;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+const DEFAULT_WS_PORT: u16 = 5600;
+const DEFAULT_ENET_PORT: u16 = 7777;
+// The default ENet channel limit of the server.
+const DEFAULT_ENET_CHANNELS: usize = 2;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+// How often ENet is serviced while waiting for the next entry.
+const SERVICE_INTERVAL: Duration = Duration::from_millis(5);
+// Events still arriving after the last entry are printed for this long.
+const LINGER: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Deserialize)]
+struct Entry {
+    at_ms: u64,
+    #[serde(default)]
+    control: Option<serde_json::Value>,
+    #[serde(default)]
+    input: Option<String>,
+    #[serde(default)]
+    channel: u8,
+    #[serde(default = "reliable_default")]
+    reliable: bool,
+}
+
+fn reliable_default() -> bool {
+    true
+}
+
+struct Options {
+    capture: String,
+    host: String,
+    ws_port: u16,
+    enet_port: u16,
+    enet_channels: usize,
+    speed: f64,
+}
+
+fn parse_options() -> Result<Options, String> {
+    let mut args = std::env::args().skip(1);
+    let mut positional = Vec::new();
+    let mut options = Options {
+        capture: String::new(),
+        host: "127.0.0.1".to_string(),
+        ws_port: DEFAULT_WS_PORT,
+        enet_port: DEFAULT_ENET_PORT,
+        enet_channels: DEFAULT_ENET_CHANNELS,
+        speed: 1.0,
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+        match arg.as_str() {
+            "--ws-port" => {
+                options.ws_port = value(&arg)?.parse().map_err(|_| "Invalid --ws-port")?
+            }
+            "--enet-port" => {
+                options.enet_port = value(&arg)?.parse().map_err(|_| "Invalid --enet-port")?
+            }
+            "--channels" => {
+                options.enet_channels = value(&arg)?
+                    .parse()
+                    .ok()
+                    .filter(|channels: &usize| *channels > 0)
+                    .ok_or("Invalid --channels")?
+            }
+            "--speed" => {
+                options.speed = value(&arg)?
+                    .parse()
+                    .ok()
+                    .filter(|speed: &f64| *speed > 0.0)
+                    .ok_or("Invalid --speed")?
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    options.capture = positional.next().ok_or(
+        "Usage: rstream-replay <capture.jsonl> [host] [--ws-port N] [--enet-port N] \
+         [--channels N] [--speed X]",
+    )?;
+    if let Some(host) = positional.next() {
+        options.host = host;
+    }
+    Ok(options)
+}
+
+fn load_entries(path: &str) -> Result<Vec<Entry>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| (index, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(index, line)| {
+            serde_json::from_str(line).map_err(|e| format!("{}:{}: {}", path, index + 1, e))
+        })
+        .collect()
+}
+
+fn parse_hex(text: &str) -> Result<Vec<u8>, String> {
+    let digits: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.len() % 2 != 0 {
+        return Err(format!("Odd number of hex digits in \"{}\"", text));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&digits[i..i + 2], 16)
+                .map_err(|_| format!("Invalid hex in \"{}\"", text))
+        })
+        .collect()
+}
+
+// Connects to the input server, None if it does not answer.
+fn connect_enet(
+    server: SocketAddr,
+    channels: usize,
+) -> Option<(enet::Host<UdpSocket>, enet::PeerID)> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    let mut host = enet::Host::new(
+        socket,
+        enet::HostSettings {
+            peer_limit: 1,
+            channel_limit: channels,
+            ..Default::default()
+        },
+    )
+    .ok()?;
+    host.connect(server, channels, 0).ok()?;
+
+    let started = Instant::now();
+    while started.elapsed() < CONNECT_TIMEOUT {
+        match host.service() {
+            Ok(Some(enet::Event::Connect { peer, .. })) => {
+                let id = peer.id();
+                return Some((host, id));
+            }
+            Ok(_) => std::thread::sleep(SERVICE_INTERVAL),
+            Err(_) => return None,
+        }
+    }
+    None
+}
+
+async fn replay(options: Options) -> Result<(), String> {
+    let entries = load_entries(&options.capture)?;
+    if let Some(entry) = entries
+        .iter()
+        .find(|entry| entry.input.is_some() && entry.channel as usize >= options.enet_channels)
+    {
+        return Err(format!(
+            "Input at {} ms is on channel {}, pass a higher --channels.",
+            entry.at_ms, entry.channel
+        ));
+    }
+    let ws_addr = (options.host.as_str(), options.ws_port)
+        .to_socket_addrs()
+        .map_err(|e| format!("{}: {}", options.host, e))?
+        .next()
+        .ok_or_else(|| format!("{} has no address", options.host))?;
+
+    let stream = TcpStream::connect(ws_addr)
+        .await
+        .map_err(|e| format!("Failed to connect to {}: {}", ws_addr, e))?;
+    let (ws_stream, _) = async_tungstenite::client_async(format!("ws://{}/", ws_addr), stream)
+        .await
+        .map_err(|e| format!("WebSocket handshake with {} failed: {}", ws_addr, e))?;
+    let (mut outgoing, mut incoming) = ws_stream.split();
+    println!(
+        "Connected to {}, replaying {} entries.",
+        ws_addr,
+        entries.len()
+    );
+
+    let started = Instant::now();
+    task::spawn(async move {
+        while let Some(Ok(message)) = incoming.next().await {
+            if let Message::Text(text) = message {
+                println!("[{:>8} ms] <- {}", started.elapsed().as_millis(), text);
+            }
+        }
+        println!(
+            "[{:>8} ms] WebSocket closed.",
+            started.elapsed().as_millis()
+        );
+    });
+
+    // Input is only taken from authenticated clients, so ENet connects once it is needed.
+    let enet_server = SocketAddr::new(ws_addr.ip(), options.enet_port);
+    let mut enet = None;
+
+    for entry in &entries {
+        let due = Duration::from_secs_f64(entry.at_ms as f64 / 1000.0 / options.speed);
+        while started.elapsed() < due {
+            if let Some((host, _)) = enet.as_mut() {
+                let _ = host.service();
+            }
+            task::sleep(SERVICE_INTERVAL.min(due.saturating_sub(started.elapsed()))).await;
+        }
+        let elapsed = started.elapsed().as_millis();
+
+        if let Some(control) = &entry.control {
+            let text = control.to_string();
+            println!("[{:>8} ms] -> {}", elapsed, text);
+            outgoing
+                .send(Message::Text(text.into()))
+                .await
+                .map_err(|e| format!("Failed to send a control message: {}", e))?;
+        }
+
+        if let Some(input) = &entry.input {
+            let data = parse_hex(input)?;
+            if enet.is_none() {
+                enet = connect_enet(enet_server, options.enet_channels);
+                if enet.is_none() {
+                    eprintln!("No input server at {}, skipping input.", enet_server);
+                    continue;
+                }
+            }
+            let (host, peer_id) = enet.as_mut().unwrap();
+            let packet = if entry.reliable {
+                enet::Packet::reliable(&data)
+            } else {
+                enet::Packet::unreliable(&data)
+            };
+            let sent = host
+                .get_peer_mut(*peer_id)
+                .map_or(false, |peer| peer.send(entry.channel, &packet).is_ok());
+            host.flush();
+            println!(
+                "[{:>8} ms] -> input {} ({} bytes){}",
+                elapsed,
+                input,
+                data.len(),
+                if sent { "" } else { ", not sent" }
+            );
+        }
+    }
+
+    let done = Instant::now();
+    while done.elapsed() < LINGER {
+        if let Some((host, _)) = enet.as_mut() {
+            let _ = host.service();
+        }
+        task::sleep(SERVICE_INTERVAL).await;
+    }
+    if let Some((mut host, peer_id)) = enet {
+        if let Some(peer) = host.get_peer_mut(peer_id) {
+            peer.disconnect(0);
+        }
+        host.flush();
+    }
+    let _ = outgoing.send(Message::Close(None)).await;
+    Ok(())
+}
+
+fn main() {
+    let result = parse_options().and_then(|options| task::block_on(replay(options)));
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
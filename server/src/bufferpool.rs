@@ -0,0 +1,89 @@
+use gst::prelude::*;
+use gstreamer as gst;
+use gstreamer_video as gst_video;
+use log::info;
+
+// Capture sources of the pipeline, the second only with stereo capture.
+const CAPTURE_ELEMENTS: [&str; 2] = ["capture", "capture_right"];
+
+/// Sizes the frame pools of the capture sources. The sources allocate textures from the pool
+/// downstream proposes and get them back once the encoder is done with a frame, so frames are
+/// reused instead of allocated. A pool too small for a 4K stream at a high frame rate makes
+/// capture wait for a free frame, which shows as a hiccup. 0 keeps what downstream proposes,
+/// for the maximum 0 means no limit.
+pub fn attach(pipeline: &gst::Pipeline, min_buffers: u32, max_buffers: u32) {
+    if min_buffers == 0 && max_buffers == 0 {
+        return;
+    }
+
+    for name in CAPTURE_ELEMENTS {
+        let Some(pad) = pipeline
+            .by_name(name)
+            .and_then(|capture| capture.static_pad("src"))
+        else {
+            continue;
+        };
+
+        // Queries pass a probe twice, the answer of downstream comes back pulled.
+        pad.add_probe(gst::PadProbeType::QUERY_DOWNSTREAM, move |_, info| {
+            if !info.mask.contains(gst::PadProbeType::PULL) {
+                return gst::PadProbeReturn::Ok;
+            }
+            if let Some(gst::PadProbeData::Query(ref mut query)) = info.data {
+                if let gst::QueryViewMut::Allocation(allocation) = query.make_mut().view_mut() {
+                    resize_pools(allocation, min_buffers, max_buffers);
+                }
+            }
+            gst::PadProbeReturn::Ok
+        });
+    }
+}
+
+fn resize_pools(allocation: &mut gst::query::Allocation, min_buffers: u32, max_buffers: u32) {
+    let pools = allocation.allocation_pools();
+    if pools.is_empty() {
+        // Nothing proposed, the source makes a pool of its own with these sizes.
+        let (caps, _) = allocation.get();
+        let size = caps
+            .and_then(|caps| gst_video::VideoInfo::from_caps(caps).ok())
+            .map_or(0, |info| info.size() as u32);
+        allocation.add_allocation_pool(None::<&gst::BufferPool>, size, min_buffers, max_buffers);
+        info!(
+            "Capture pool of {} to {} frames.",
+            min_buffers,
+            max_or_unlimited(max_buffers)
+        );
+        return;
+    }
+
+    for (index, (pool, size, proposed_min, proposed_max)) in pools.into_iter().enumerate() {
+        let min = if min_buffers > 0 {
+            min_buffers
+        } else {
+            proposed_min
+        };
+        let max = if max_buffers > 0 {
+            max_buffers
+        } else {
+            proposed_max
+        };
+        // Never below the minimum, a pool that cannot hold it fails to activate. 0 is no limit.
+        let max = if max == 0 { 0 } else { max.max(min) };
+        allocation.set_nth_allocation_pool(index as u32, pool.as_ref(), size, min, max);
+        info!(
+            "Capture pool of {} to {} frames, proposed were {} to {}.",
+            min,
+            max_or_unlimited(max),
+            proposed_min,
+            max_or_unlimited(proposed_max)
+        );
+    }
+}
+
+fn max_or_unlimited(max: u32) -> String {
+    if max == 0 {
+        "any number of".to_string()
+    } else {
+        max.to_string()
+    }
+}
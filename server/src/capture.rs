@@ -0,0 +1,330 @@
+use crate::rtp::{RTCP_PSFB, RTCP_RR, RTCP_RTPFB, RTCP_SR};
+use crate::stream::pipeline_element;
+use chrono::Utc;
+use gst::prelude::*;
+use gstreamer as gst;
+use log::info;
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+pub const MAX_CAPTURE_SECONDS: u32 = 60;
+// About 40 MB of records. Very high bitrates fill it before the capture ends.
+const MAX_CAPTURE_RECORDS: usize = 1_000_000;
+
+// Tees fanning out the outgoing RTP streams to the clients, and the stream name written to the CSV.
+const RTP_TEES: [(&str, &str); 2] = [("videotee", "video"), ("audiotee", "audio")];
+// Sources of the RTCP feedback clients send about each stream.
+const RTCP_SOURCES: [(&str, &str); 2] = [("videortcpsrc", "video"), ("audiortcpsrc", "audio")];
+// Feedback formats, see RFC 4585 section 6.
+const FMT_NACK: u8 = 1;
+const FMT_PLI: u8 = 1;
+
+struct RtpRecord {
+    stream: &'static str,
+    send_time_us: i64,
+    sequence: u16,
+    timestamp: u32,
+    ssrc: u32,
+    payload_type: u8,
+    marker: bool,
+    size: usize,
+}
+
+enum RtcpReport {
+    // A report block of a receiver or sender report, RFC 3550 section 6.4.
+    Reception {
+        fraction_lost: u8,
+        cumulative_lost: u32,
+        // In RTP timestamp units.
+        jitter: u32,
+    },
+    // One packet asked for again, and how many of the 16 after it too.
+    Nack {
+        sequence: u16,
+        following: u32,
+    },
+    Pli,
+}
+
+struct RtcpRecord {
+    stream: &'static str,
+    receive_time_us: i64,
+    // The media source the feedback is about.
+    ssrc: u32,
+    report: RtcpReport,
+    size: usize,
+}
+
+enum Record {
+    Rtp(RtpRecord),
+    Rtcp(RtcpRecord),
+}
+
+// Reads the fixed RTP header, see RFC 3550 section 5.1.
+fn parse_rtp_header(stream: &'static str, data: &[u8], send_time_us: i64) -> Option<RtpRecord> {
+    if data.len() < 12 || data[0] >> 6 != 2 {
+        return None;
+    }
+
+    Some(RtpRecord {
+        stream,
+        send_time_us,
+        sequence: u16::from_be_bytes([data[2], data[3]]),
+        timestamp: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+        ssrc: u32::from_be_bytes([data[8], data[9], data[10], data[11]]),
+        payload_type: data[1] & 0x7f,
+        marker: data[1] & 0x80 != 0,
+        size: data.len(),
+    })
+}
+
+// Reads the reports and feedback of a compound RTCP packet, see RFC 3550 section 6.4 and
+// RFC 4585 section 6.
+fn parse_rtcp(stream: &'static str, data: &[u8], receive_time_us: i64) -> Vec<RtcpRecord> {
+    let read_u32 = |at: usize| {
+        data.get(at..at + 4)
+            .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+    let record = |ssrc, report| RtcpRecord {
+        stream,
+        receive_time_us,
+        ssrc,
+        report,
+        size: data.len(),
+    };
+
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset + 4 <= data.len() && data[offset] >> 6 == 2 {
+        // The report count, or the feedback format.
+        let count = data[offset] & 0x1f;
+        let packet_type = data[offset + 1];
+        let length = (u16::from_be_bytes([data[offset + 2], data[offset + 3]]) as usize + 1) * 4;
+        let end = (offset + length).min(data.len());
+
+        match packet_type {
+            RTCP_SR | RTCP_RR => {
+                let blocks = offset + if packet_type == RTCP_SR { 28 } else { 8 };
+                for at in (0..count as usize).map(|block| blocks + block * 24) {
+                    let (Some(ssrc), Some(lost), Some(jitter)) =
+                        (read_u32(at), read_u32(at + 4), read_u32(at + 12))
+                    else {
+                        break;
+                    };
+                    records.push(record(
+                        ssrc,
+                        RtcpReport::Reception {
+                            fraction_lost: (lost >> 24) as u8,
+                            cumulative_lost: lost & 0x00ff_ffff,
+                            jitter,
+                        },
+                    ));
+                }
+            }
+            RTCP_RTPFB if count == FMT_NACK => {
+                let Some(ssrc) = read_u32(offset + 8) else {
+                    break;
+                };
+                for fci in (offset + 12..end).step_by(4).filter_map(read_u32) {
+                    records.push(record(
+                        ssrc,
+                        RtcpReport::Nack {
+                            sequence: (fci >> 16) as u16,
+                            following: (fci & 0xffff).count_ones(),
+                        },
+                    ));
+                }
+            }
+            RTCP_PSFB if count == FMT_PLI => {
+                records.extend(read_u32(offset + 8).map(|ssrc| record(ssrc, RtcpReport::Pli)));
+            }
+            _ => {}
+        }
+
+        offset += length;
+    }
+    records
+}
+
+fn record_buffer(
+    stream: &'static str,
+    incoming: bool,
+    buffer: &gst::BufferRef,
+    records: &Mutex<Vec<Record>>,
+) {
+    let Ok(map) = buffer.map_readable() else {
+        return;
+    };
+
+    let time_us = Utc::now().timestamp_micros();
+    let new_records: Vec<Record> = if incoming {
+        parse_rtcp(stream, &map, time_us)
+            .into_iter()
+            .map(Record::Rtcp)
+            .collect()
+    } else {
+        parse_rtp_header(stream, &map, time_us)
+            .map(Record::Rtp)
+            .into_iter()
+            .collect()
+    };
+
+    let mut records = records.lock().unwrap();
+    let room = MAX_CAPTURE_RECORDS.saturating_sub(records.len());
+    records.extend(new_records.into_iter().take(room));
+}
+
+/// Records the metadata of outgoing RTP packets and of the RTCP feedback coming back for
+/// `seconds` and saves it as CSV. Returns the file path and the number of rows. Blocking.
+pub fn capture_rtp(seconds: u32) -> std::io::Result<(PathBuf, usize)> {
+    if seconds == 0 || seconds > MAX_CAPTURE_SECONDS {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Capture length must be 1 to {} seconds",
+                MAX_CAPTURE_SECONDS
+            ),
+        ));
+    }
+
+    let records = Arc::new(Mutex::new(Vec::new()));
+    let mut probes = Vec::new();
+
+    let pads = RTP_TEES
+        .into_iter()
+        .map(|(element_name, stream)| (element_name, "sink", stream, false))
+        .chain(
+            RTCP_SOURCES
+                .into_iter()
+                .map(|(element_name, stream)| (element_name, "src", stream, true)),
+        );
+    for (element_name, pad_name, stream, incoming) in pads {
+        let Some(pad) =
+            pipeline_element(element_name).and_then(|element| element.static_pad(pad_name))
+        else {
+            continue;
+        };
+
+        let records = records.clone();
+        // Payloaders push fragmented frames as buffer lists.
+        let probe = pad.add_probe(
+            gst::PadProbeType::BUFFER | gst::PadProbeType::BUFFER_LIST,
+            move |_, info| {
+                match info.data.as_ref() {
+                    Some(gst::PadProbeData::Buffer(buffer)) => {
+                        record_buffer(stream, incoming, buffer, &records);
+                    }
+                    Some(gst::PadProbeData::BufferList(list)) => {
+                        for buffer in list.iter() {
+                            record_buffer(stream, incoming, buffer, &records);
+                        }
+                    }
+                    _ => {}
+                }
+                gst::PadProbeReturn::Ok
+            },
+        );
+
+        if let Some(probe) = probe {
+            probes.push((pad, probe));
+        }
+    }
+
+    if probes.is_empty() {
+        return Err(Error::new(ErrorKind::NotConnected, "No stream is running"));
+    }
+
+    info!(
+        "Capturing outgoing RTP and incoming RTCP for {} seconds.",
+        seconds
+    );
+    thread::sleep(Duration::from_secs(seconds as u64));
+
+    for (pad, probe) in probes {
+        pad.remove_probe(probe);
+    }
+
+    let records = std::mem::take(&mut *records.lock().unwrap());
+
+    let path = PathBuf::from(format!(
+        "rtp_capture_{}.csv",
+        Utc::now().format("%Y%m%d_%H%M%S")
+    ));
+    let mut writer = BufWriter::new(File::create(&path)?);
+
+    // Incoming rows leave out what only RTP has, outgoing rows what only RTCP has.
+    writeln!(
+        writer,
+        "direction,stream,time_us,kind,sequence,rtp_timestamp,ssrc,payload_type,marker,size,\
+        fraction_lost,cumulative_lost,jitter,nacked_following"
+    )?;
+    for record in &records {
+        match record {
+            Record::Rtp(record) => writeln!(
+                writer,
+                "out,{},{},rtp,{},{},{},{},{},{},,,,",
+                record.stream,
+                record.send_time_us,
+                record.sequence,
+                record.timestamp,
+                record.ssrc,
+                record.payload_type,
+                record.marker as u8,
+                record.size
+            )?,
+            Record::Rtcp(record) => {
+                let (kind, sequence, reception, following) = match &record.report {
+                    RtcpReport::Reception {
+                        fraction_lost,
+                        cumulative_lost,
+                        jitter,
+                    } => (
+                        "rr",
+                        String::new(),
+                        format!("{},{},{}", fraction_lost, cumulative_lost, jitter),
+                        String::new(),
+                    ),
+                    RtcpReport::Nack {
+                        sequence,
+                        following,
+                    } => (
+                        "nack",
+                        sequence.to_string(),
+                        ",,".to_string(),
+                        following.to_string(),
+                    ),
+                    RtcpReport::Pli => ("pli", String::new(), ",,".to_string(), String::new()),
+                };
+                writeln!(
+                    writer,
+                    "in,{},{},{},{},,{},,,{},{},{}",
+                    record.stream,
+                    record.receive_time_us,
+                    kind,
+                    sequence,
+                    record.ssrc,
+                    record.size,
+                    reception,
+                    following
+                )?
+            }
+        }
+    }
+    writer.flush()?;
+
+    let feedback = records
+        .iter()
+        .filter(|record| matches!(record, Record::Rtcp(_)))
+        .count();
+    info!(
+        "Saved {} RTP packets and {} RTCP reports to {}.",
+        records.len() - feedback,
+        feedback,
+        path.display()
+    );
+    Ok((path, records.len()))
+}
@@ -0,0 +1,161 @@
+use gstreamer_video as gst_video;
+use serde::Serialize;
+
+// Below this height the stream is standard definition, which decoders assume is BT.601.
+const HD_HEIGHT: u32 = 720;
+
+/// The range of the encoded luma and chroma values.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorRange {
+    // Limited, what decoders assume when they ignore the signaled range.
+    Auto,
+    // 16 to 235, the broadcast range.
+    Limited,
+    // 0 to 255, like the desktop. Decoders ignoring the flag show it washed out.
+    Full,
+}
+
+impl ColorRange {
+    pub const ALL: [ColorRange; 3] = [ColorRange::Auto, ColorRange::Limited, ColorRange::Full];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ColorRange::Auto => "auto",
+            ColorRange::Limited => "limited",
+            ColorRange::Full => "full",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "auto" => Some(ColorRange::Auto),
+            "limited" => Some(ColorRange::Limited),
+            "full" => Some(ColorRange::Full),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ColorRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColorRange::Auto => write!(f, "Automatic"),
+            ColorRange::Limited => write!(f, "Limited (16-235)"),
+            ColorRange::Full => write!(f, "Full (0-255)"),
+        }
+    }
+}
+
+/// The matrix, transfer function and primaries of the encoded video.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorMatrix {
+    // BT.601 below 720p and BT.709 from there, like decoders guess without a signal.
+    Auto,
+    Bt601,
+    Bt709,
+    Bt2020,
+}
+
+impl ColorMatrix {
+    pub const ALL: [ColorMatrix; 4] = [
+        ColorMatrix::Auto,
+        ColorMatrix::Bt601,
+        ColorMatrix::Bt709,
+        ColorMatrix::Bt2020,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ColorMatrix::Auto => "auto",
+            ColorMatrix::Bt601 => "bt601",
+            ColorMatrix::Bt709 => "bt709",
+            ColorMatrix::Bt2020 => "bt2020",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "auto" => Some(ColorMatrix::Auto),
+            "bt601" => Some(ColorMatrix::Bt601),
+            "bt709" => Some(ColorMatrix::Bt709),
+            "bt2020" => Some(ColorMatrix::Bt2020),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ColorMatrix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColorMatrix::Auto => write!(f, "Automatic"),
+            ColorMatrix::Bt601 => write!(f, "BT.601"),
+            ColorMatrix::Bt709 => write!(f, "BT.709"),
+            ColorMatrix::Bt2020 => write!(f, "BT.2020"),
+        }
+    }
+}
+
+/// The colorimetry an SDR stream is converted to and signaled with, in the caps the encoder
+/// writes into the bitstream and in the session sent to clients. Never automatic.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Colorimetry {
+    pub range: ColorRange,
+    pub matrix: ColorMatrix,
+}
+
+impl Colorimetry {
+    /// Resolves the automatic settings for a stream `height` pixels high.
+    pub fn resolve(range: ColorRange, matrix: ColorMatrix, height: u32) -> Self {
+        let range = match range {
+            ColorRange::Auto => ColorRange::Limited,
+            range => range,
+        };
+        let matrix = match matrix {
+            ColorMatrix::Auto if height < HD_HEIGHT => ColorMatrix::Bt601,
+            ColorMatrix::Auto => ColorMatrix::Bt709,
+            matrix => matrix,
+        };
+        Colorimetry { range, matrix }
+    }
+
+    /// The caps field, e.g. for the capsfilter in front of the encoder.
+    pub fn caps_field(&self) -> String {
+        let range = match self.range {
+            ColorRange::Full => gst_video::VideoColorRange::Range0_255,
+            _ => gst_video::VideoColorRange::Range16_235,
+        };
+        let (matrix, transfer, primaries) = match self.matrix {
+            // SMPTE 170M uses the transfer function of BT.709.
+            ColorMatrix::Bt601 => (
+                gst_video::VideoColorMatrix::Bt601,
+                gst_video::VideoTransferFunction::Bt709,
+                gst_video::VideoColorPrimaries::Smpte170m,
+            ),
+            ColorMatrix::Bt2020 => (
+                gst_video::VideoColorMatrix::Bt2020,
+                gst_video::VideoTransferFunction::Bt202010,
+                gst_video::VideoColorPrimaries::Bt2020,
+            ),
+            _ => (
+                gst_video::VideoColorMatrix::Bt709,
+                gst_video::VideoTransferFunction::Bt709,
+                gst_video::VideoColorPrimaries::Bt709,
+            ),
+        };
+        format!(
+            ",colorimetry={}",
+            gst_video::VideoColorimetry::new(range, matrix, transfer, primaries)
+        )
+    }
+}
+
+impl Default for Colorimetry {
+    fn default() -> Self {
+        Colorimetry {
+            range: ColorRange::Limited,
+            matrix: ColorMatrix::Bt709,
+        }
+    }
+}
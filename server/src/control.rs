@@ -0,0 +1,595 @@
+use crate::accessibility::{self, AccessibilityState};
+use crate::artwork::{self, DEFAULT_THUMBNAIL_WIDTH};
+use crate::audio::AudioCodec;
+use crate::audiostats::{self, AudioStats};
+use crate::capture;
+use crate::display;
+use crate::encoder::VideoCodec;
+use crate::error::{report_error, ErrorCategory, ErrorCode};
+use crate::input;
+use crate::latency::{self, LatencyPreset};
+use crate::launcher;
+use crate::library::{self, GameEntry};
+use crate::monitor::{self, MonitorInfo};
+use crate::network::{self, LinkClass};
+use crate::pairing;
+use crate::ports::Ports;
+use crate::rtp::RtpSession;
+use crate::session::ConsoleState;
+use crate::stats::{self, VideoStats};
+use crate::stereo::StereoMode;
+use crate::stream::{self, StreamMedia, STREAMING_STATE_GUARD};
+use crate::timeline::SessionSummary;
+use crate::trust::Permission;
+use crate::view::{self, ViewRegion, ViewportHint};
+use crate::watchdog::AppExitReason;
+use crate::webrtc;
+use async_tungstenite::tungstenite::protocol::Message;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+
+/// Commands an authenticated client can send over the WebSocket.
+/// Encoded as JSON tagged by `cmd`, e.g. `{"cmd":"set_hdr","enabled":true}`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum ControlCommand {
+    SetDisplayPower {
+        on: bool,
+    },
+    SetDisplayMode {
+        width: u32,
+        height: u32,
+        refresh_rate: Option<u32>,
+    },
+    SetRefreshRate {
+        hz: u32,
+    },
+    SetHdr {
+        enabled: bool,
+    },
+    GetLauncherInfo,
+    LaunchBigPicture,
+    LaunchSteamGame {
+        app_id: u32,
+    },
+    LaunchExecutable {
+        path: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+    ListGames {
+        #[serde(default)]
+        refresh: bool,
+    },
+    GetArtwork {
+        id: String,
+        max_width: Option<u32>,
+    },
+    SetLatencyPreset {
+        preset: LatencyPreset,
+    },
+    SetAutoLatencyPreset {
+        enabled: bool,
+    },
+    CaptureRtp {
+        seconds: u32,
+    },
+    SetViewport(ViewportHint),
+    SetZoom {
+        zoom: f32,
+        center_x: Option<f32>,
+        center_y: Option<f32>,
+    },
+    Pan {
+        dx: f32,
+        dy: f32,
+    },
+    GetAccessibility,
+    SetMagnifier {
+        enabled: bool,
+    },
+    SetHighContrast {
+        enabled: bool,
+    },
+    // Changes the video bitrate without restarting the stream.
+    SetBitrate {
+        mbps: u32,
+    },
+    // Asks for a keyframe, e.g. after the client saw corruption.
+    ForceKeyframe,
+    // Limits the frame rate sent to this client, 0 for the rate of its stream config.
+    SetMaxFps {
+        fps: u32,
+    },
+    // Sets the frame rate of the stream for everyone, 0 for the rate of the stream config.
+    SetFramerate {
+        fps: u32,
+    },
+    ListMonitors,
+    // Frame timing of the running stream, e.g. for an overlay on the client.
+    GetStats,
+    // Captures another monitor, none for the primary one.
+    SelectMonitor {
+        index: Option<u32>,
+    },
+    // Lets go of every injected key, button and stick, for when remote input misbehaves.
+    ReleaseAllInput {
+        #[serde(default)]
+        detach_gamepad: bool,
+    },
+    // The only command accepted before authentication, so clients without the PIN can pair.
+    RequestPairingCode,
+    // Stops sending video, audio or both to this client until it resumes, without rebuilding
+    // the pipeline.
+    PauseStream {
+        #[serde(default)]
+        media: StreamMedia,
+    },
+    ResumeStream {
+        #[serde(default)]
+        media: StreamMedia,
+    },
+    // The client's reply to a `webrtc_offer` event.
+    WebrtcAnswer {
+        sdp: String,
+    },
+    // A way the client may be reached over WebRTC.
+    IceCandidate {
+        sdp_m_line_index: u32,
+        candidate: String,
+    },
+    // Moves the session to this client, e.g. from a phone to a PC, see `stream::hand_over_session`.
+    TakeOver,
+}
+
+impl ControlCommand {
+    fn name(&self) -> &'static str {
+        match self {
+            ControlCommand::SetDisplayPower { .. } => "set_display_power",
+            ControlCommand::SetDisplayMode { .. } => "set_display_mode",
+            ControlCommand::SetRefreshRate { .. } => "set_refresh_rate",
+            ControlCommand::SetHdr { .. } => "set_hdr",
+            ControlCommand::GetLauncherInfo => "get_launcher_info",
+            ControlCommand::LaunchBigPicture => "launch_big_picture",
+            ControlCommand::LaunchSteamGame { .. } => "launch_steam_game",
+            ControlCommand::LaunchExecutable { .. } => "launch_executable",
+            ControlCommand::ListGames { .. } => "list_games",
+            ControlCommand::GetArtwork { .. } => "get_artwork",
+            ControlCommand::SetLatencyPreset { .. } => "set_latency_preset",
+            ControlCommand::SetAutoLatencyPreset { .. } => "set_auto_latency_preset",
+            ControlCommand::CaptureRtp { .. } => "capture_rtp",
+            ControlCommand::SetViewport(_) => "set_viewport",
+            ControlCommand::SetZoom { .. } => "set_zoom",
+            ControlCommand::Pan { .. } => "pan",
+            ControlCommand::GetAccessibility => "get_accessibility",
+            ControlCommand::SetMagnifier { .. } => "set_magnifier",
+            ControlCommand::SetHighContrast { .. } => "set_high_contrast",
+            ControlCommand::SetBitrate { .. } => "set_bitrate",
+            ControlCommand::ForceKeyframe => "force_keyframe",
+            ControlCommand::SetMaxFps { .. } => "set_max_fps",
+            ControlCommand::SetFramerate { .. } => "set_framerate",
+            ControlCommand::ListMonitors => "list_monitors",
+            ControlCommand::GetStats => "get_stats",
+            ControlCommand::SelectMonitor { .. } => "select_monitor",
+            ControlCommand::ReleaseAllInput { .. } => "release_all_input",
+            ControlCommand::RequestPairingCode => "request_pairing_code",
+            ControlCommand::PauseStream { .. } => "pause_stream",
+            ControlCommand::ResumeStream { .. } => "resume_stream",
+            ControlCommand::WebrtcAnswer { .. } => "webrtc_answer",
+            ControlCommand::IceCandidate { .. } => "ice_candidate",
+            ControlCommand::TakeOver => "take_over",
+        }
+    }
+
+    // What the client must be allowed to send this, None for commands every client may use.
+    fn permission(&self) -> Option<Permission> {
+        match self {
+            ControlCommand::SetDisplayPower { .. }
+            | ControlCommand::SetDisplayMode { .. }
+            | ControlCommand::SetRefreshRate { .. }
+            | ControlCommand::SetHdr { .. } => Some(Permission::Power),
+            ControlCommand::LaunchBigPicture
+            | ControlCommand::LaunchSteamGame { .. }
+            | ControlCommand::LaunchExecutable { .. } => Some(Permission::AppLaunch),
+            ControlCommand::TakeOver => Some(Permission::View),
+            _ => None,
+        }
+    }
+
+    // Whether this changes the shared stream or the host for everyone, which spectators may not.
+    fn changes_host(&self) -> bool {
+        matches!(
+            self,
+            ControlCommand::SetLatencyPreset { .. }
+                | ControlCommand::SetAutoLatencyPreset { .. }
+                | ControlCommand::CaptureRtp { .. }
+                | ControlCommand::SetViewport(_)
+                | ControlCommand::SetZoom { .. }
+                | ControlCommand::Pan { .. }
+                | ControlCommand::SetMagnifier { .. }
+                | ControlCommand::SetHighContrast { .. }
+                | ControlCommand::SetBitrate { .. }
+                | ControlCommand::SetFramerate { .. }
+                | ControlCommand::SelectMonitor { .. }
+                | ControlCommand::ReleaseAllInput { .. }
+        )
+    }
+}
+
+/// Messages sent from the server to clients, tagged by `event`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ControlEvent {
+    CommandResult {
+        cmd: String,
+        ok: bool,
+        message: String,
+    },
+    LauncherInfo {
+        steam_installed: bool,
+        running_app_id: Option<u32>,
+    },
+    GameStarted {
+        app_id: u32,
+    },
+    GameExited {
+        app_id: u32,
+    },
+    GameList {
+        games: Vec<GameEntry>,
+    },
+    MonitorList {
+        monitors: Vec<MonitorInfo>,
+        // Index of the captured monitor, none while capturing the primary one.
+        selected: Option<u32>,
+    },
+    Artwork {
+        id: String,
+        mime: String,
+        data: String,
+    },
+    AppExited {
+        name: String,
+        reason: AppExitReason,
+        exit_code: Option<i32>,
+    },
+    EncoderWarning {
+        message: String,
+    },
+    // Another encoder took over the running stream. The next frame is a keyframe with new
+    // parameter sets, frames in flight until then may be lost.
+    EncoderSwitched {
+        encoder: String,
+    },
+    // Raw frames dropped because the encoder fell behind, sent while it happens and once it stops.
+    FramesDropped {
+        per_second: u32,
+    },
+    LatencyPresetChanged {
+        preset: LatencyPreset,
+    },
+    LinkChanged {
+        link: LinkClass,
+        rtt_ms: f32,
+        jitter_ms: f32,
+        loss: f32,
+    },
+    RtpCaptureSaved {
+        path: String,
+        packets: usize,
+    },
+    RtpSession(RtpSession),
+    // Where the host sends the stream of this client. Bundled audio goes to the video port.
+    MediaPorts {
+        video_port: u16,
+        audio_port: u16,
+    },
+    AudioStats(AudioStats),
+    // The reply to `get_stats`, None for what is not streamed.
+    Stats {
+        video: Option<VideoStats>,
+        audio: Option<AudioStats>,
+    },
+    Accessibility(AccessibilityState),
+    ViewChanged {
+        region: ViewRegion,
+    },
+    StreamLayout {
+        layout: StereoMode,
+        eye_width: u32,
+        eye_height: u32,
+    },
+    // Sent once the WebSocket connects, before authentication.
+    ServerCapabilities {
+        video_codecs: Vec<VideoCodec>,
+        audio_codecs: Vec<AudioCodec>,
+        // Where the host listens, which may differ from the defaults.
+        ports: Ports,
+        // Most audio channels clients get, more than 2 if the host does surround.
+        audio_channels: u32,
+        // Whether clients asking for HDR get it, with the current monitor and settings.
+        hdr: bool,
+        // Whether clients can ask for the stream over WebRTC.
+        webrtc: bool,
+        // Whether clients can ask for MPEG-TS instead of RTP.
+        mpegts: bool,
+        // Whether clients asking for 4:4:4 chroma get it.
+        chroma_444: bool,
+        // B-frames in a row HEVC and AV1 streams get for clients that reorder them, 0 for none.
+        b_frames: u32,
+    },
+    // The host shows a one-time code that the client sends as its PIN.
+    PairingCodeShown {
+        expires_in_seconds: u64,
+    },
+    // The id and secret the host issued a client that authenticated without them. The client
+    // sends both in its stream configs from then on, otherwise it pairs as a new client.
+    Paired {
+        client_id: String,
+        client_secret: String,
+    },
+    // Capture pauses while the host session is not on the console, e.g. during RDP.
+    ConsoleSession {
+        state: ConsoleState,
+        // Who is on the console now, if anyone is signed in.
+        user: Option<String>,
+    },
+    // All injected input was released, from the host hotkey or a command.
+    InputReleased {
+        gamepad_detached: bool,
+    },
+    // The capture turned black, most likely protected content that Windows masks out. Video
+    // holds the last frame meanwhile if the host chose to pause it.
+    ProtectedContent {
+        active: bool,
+        paused: bool,
+    },
+    // The pipeline kept failing and fell back to conservative settings, or left them again.
+    SafeMode {
+        active: bool,
+        description: String,
+    },
+    // The stream offered to a client that asked for WebRTC, answered by `webrtc_answer`.
+    WebrtcOffer {
+        sdp: String,
+    },
+    // A way the host may be reached over WebRTC.
+    IceCandidate {
+        sdp_m_line_index: u32,
+        candidate: String,
+    },
+    // Sent before the host closes the connection, e.g. when disconnecting the client.
+    SessionSummary(SessionSummary),
+    // Another client took the session over, this one is disconnected right after.
+    SessionTakenOver {
+        by: String,
+    },
+    // The stream failed while playing and restarts after `retry_in_ms`. Clients keep their
+    // connection and get the stream description again once it plays.
+    StreamRecovering {
+        attempt: u32,
+        max_attempts: u32,
+        retry_in_ms: u64,
+    },
+    // The stream was rebuilt for another client's settings, which this client did not ask for or
+    // cannot decode. It gets no stream until it sends a stream config again, e.g. these settings.
+    StreamRenegotiate {
+        video_width: u32,
+        video_height: u32,
+        framerate: u32,
+        codec: VideoCodec,
+    },
+    // A network interface of the host went away. Clients connected through it lose the session
+    // unless they reconnect to one of these addresses in time.
+    HostAddressesChanged {
+        addresses: Vec<String>,
+        websocket_port: u16,
+    },
+    Error {
+        code: ErrorCode,
+        category: ErrorCategory,
+        message: String,
+        hint: String,
+    },
+}
+
+// Blocking, run it via `task::spawn_blocking`.
+pub(crate) fn handle_command(command: ControlCommand, addr: SocketAddr) {
+    info!("Control command from {}: {:?}", addr, command);
+
+    let name = command.name();
+
+    let permissions = stream::peer_permissions(addr);
+    let denied = match command.permission() {
+        Some(permission) if !permissions.allows(permission) => {
+            Some(format!("{} is not allowed for this client.", permission))
+        }
+        _ if command.changes_host() && permissions.is_view_only() => {
+            Some("Spectators may not change the stream or the host.".to_string())
+        }
+        _ => None,
+    };
+    if let Some(message) = denied {
+        report_error(addr, ErrorCode::PermissionDenied, message.clone());
+        send_event(
+            addr,
+            &ControlEvent::CommandResult {
+                cmd: name.into(),
+                ok: false,
+                message,
+            },
+        );
+        return;
+    }
+
+    let result = match command {
+        ControlCommand::SetDisplayPower { on } => display::set_display_power(on),
+        ControlCommand::SetDisplayMode {
+            width,
+            height,
+            refresh_rate,
+        } => display::set_display_mode(Some((width, height)), refresh_rate),
+        ControlCommand::SetRefreshRate { hz } => display::set_display_mode(None, Some(hz)),
+        ControlCommand::SetHdr { enabled } => display::set_hdr(enabled),
+        ControlCommand::GetLauncherInfo => {
+            send_event(addr, &launcher::launcher_info());
+            return;
+        }
+        ControlCommand::LaunchBigPicture => launcher::launch_big_picture(),
+        ControlCommand::LaunchSteamGame { app_id } => launcher::launch_steam_game(app_id),
+        ControlCommand::LaunchExecutable { path, args } => {
+            launcher::launch_executable(&path, &args)
+        }
+        ControlCommand::ListGames { refresh } => {
+            let games = library::list_games(refresh);
+            send_event(addr, &ControlEvent::GameList { games });
+            return;
+        }
+        ControlCommand::GetArtwork { id, max_width } => {
+            let width = max_width.unwrap_or(DEFAULT_THUMBNAIL_WIDTH);
+            match artwork::thumbnail_base64(&id, width) {
+                Ok(data) => {
+                    let mime = "image/jpeg".into();
+                    send_event(addr, &ControlEvent::Artwork { id, mime, data });
+                    return;
+                }
+                Err(e) => Err(e),
+            }
+        }
+        ControlCommand::SetLatencyPreset { preset } => {
+            latency::pin_preset(preset);
+            Ok(())
+        }
+        ControlCommand::SetAutoLatencyPreset { enabled } => {
+            {
+                let mut guard = STREAMING_STATE_GUARD.lock().unwrap();
+                if let Some(state) = guard.as_mut() {
+                    state.auto_latency_preset = enabled;
+                }
+            }
+            // Catch up with the link right away instead of waiting for its next change.
+            if enabled {
+                if let Some((link, _)) = network::current_link() {
+                    latency::apply_preset(link.preset());
+                }
+            }
+            Ok(())
+        }
+        ControlCommand::CaptureRtp { seconds } => match capture::capture_rtp(seconds) {
+            Ok((path, packets)) => {
+                let path = path.display().to_string();
+                send_event(addr, &ControlEvent::RtpCaptureSaved { path, packets });
+                return;
+            }
+            Err(e) => Err(e),
+        },
+        ControlCommand::SetViewport(hint) => view::set_viewport_hint(hint),
+        ControlCommand::SetZoom {
+            zoom,
+            center_x,
+            center_y,
+        } => view::set_zoom(zoom, center_x.zip(center_y)),
+        ControlCommand::Pan { dx, dy } => view::pan(dx, dy),
+        ControlCommand::GetAccessibility => match accessibility::accessibility_state() {
+            Ok(state) => {
+                send_event(addr, &ControlEvent::Accessibility(state));
+                return;
+            }
+            Err(e) => Err(e),
+        },
+        ControlCommand::SetMagnifier { enabled } => accessibility::set_magnifier(enabled),
+        ControlCommand::SetHighContrast { enabled } => accessibility::set_high_contrast(enabled),
+        ControlCommand::SetBitrate { mbps } => stream::set_bitrate(mbps),
+        ControlCommand::ForceKeyframe => stream::force_keyframe(),
+        ControlCommand::SetMaxFps { fps } => stream::set_peer_max_fps(addr, fps),
+        ControlCommand::SetFramerate { fps } => stream::set_framerate(fps),
+        ControlCommand::GetStats => {
+            let video = stats::current_stats();
+            let audio = audiostats::current_stats();
+            send_event(addr, &ControlEvent::Stats { video, audio });
+            return;
+        }
+        ControlCommand::ListMonitors => {
+            let monitors = monitor::list_monitors();
+            let selected = monitor::selected_monitor().map(|monitor| monitor.index);
+            send_event(addr, &ControlEvent::MonitorList { monitors, selected });
+            return;
+        }
+        ControlCommand::SelectMonitor { index } => monitor::select_monitor(index),
+        ControlCommand::ReleaseAllInput { detach_gamepad } => {
+            input::release_all_input(detach_gamepad);
+            Ok(())
+        }
+        ControlCommand::RequestPairingCode => match pairing::request_code(addr) {
+            Ok(lifetime) => {
+                send_event(
+                    addr,
+                    &ControlEvent::PairingCodeShown {
+                        expires_in_seconds: lifetime.as_secs(),
+                    },
+                );
+                return;
+            }
+            Err(e) => Err(e),
+        },
+        ControlCommand::PauseStream { media } => stream::set_stream_paused(addr, media, true),
+        ControlCommand::ResumeStream { media } => stream::set_stream_paused(addr, media, false),
+        ControlCommand::WebrtcAnswer { sdp } => webrtc::set_answer(addr, &sdp),
+        ControlCommand::IceCandidate {
+            sdp_m_line_index,
+            candidate,
+        } => webrtc::add_ice_candidate(addr, sdp_m_line_index, &candidate),
+        ControlCommand::TakeOver => stream::hand_over_session(addr),
+    };
+
+    let event = match result {
+        Ok(()) => ControlEvent::CommandResult {
+            cmd: name.into(),
+            ok: true,
+            message: String::new(),
+        },
+        Err(e) => {
+            warn!("Command {} from {} failed: {}", name, addr, e);
+            ControlEvent::CommandResult {
+                cmd: name.into(),
+                ok: false,
+                message: e.to_string(),
+            }
+        }
+    };
+
+    send_event(addr, &event);
+}
+
+pub(crate) fn send_event(addr: SocketAddr, event: &ControlEvent) {
+    let text = serde_json::to_string(event).unwrap();
+
+    let guard = STREAMING_STATE_GUARD.lock().unwrap();
+    if let Some(peer) = guard.as_ref().and_then(|state| state.peers.get(&addr)) {
+        if let Err(e) = peer.tx.unbounded_send(Message::Text(text.into())) {
+            error!("Failed to send event to {}: {}", addr, e);
+        }
+    }
+    drop(guard);
+
+    // Most events follow a state change the GUI shows too.
+    crate::gui::request_repaint();
+}
+
+// Sends an event to every authenticated peer.
+pub(crate) fn broadcast_event(event: &ControlEvent) {
+    let text = serde_json::to_string(event).unwrap();
+
+    let guard = STREAMING_STATE_GUARD.lock().unwrap();
+    if let Some(state) = guard.as_ref() {
+        for (addr, peer) in state.peers.iter().filter(|(_, peer)| peer.authenticated) {
+            if let Err(e) = peer.tx.unbounded_send(Message::Text(text.clone().into())) {
+                error!("Failed to send event to {}: {}", addr, e);
+            }
+        }
+    }
+    drop(guard);
+
+    crate::gui::request_repaint();
+}
@@ -0,0 +1,267 @@
+use crate::timeline::SessionSummary;
+use chrono::Utc;
+use log::{info, warn, LevelFilter};
+use serde::Serialize;
+use serde_json::Value;
+use std::fs::{self, File};
+use std::io::{BufWriter, Error, ErrorKind, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+const BUNDLE_DIRECTORY: &str = "diagnostics";
+// Opened with the bundle path filled in when no upload endpoint is configured.
+const NEW_ISSUE_URL: &str = "https://github.com/floppyhammer/rstream/issues/new";
+const UPLOAD_TIMEOUT_SECONDS: u32 = 60;
+
+/// Everything a bug report needs, in one JSON file. The PIN, client ids and addresses are left
+/// out of the config and replaced in the log, so the bundle can be shared as is.
+#[derive(Serialize)]
+struct Bundle {
+    created_at: String,
+    version: &'static str,
+    os: &'static str,
+    arch: &'static str,
+    // config.json without the PIN and the WebRTC server, which may hold TURN credentials.
+    config: Value,
+    gpu_adapters: Vec<String>,
+    video_encoders: Vec<String>,
+    hardware_encoder_blocked: bool,
+    safe_mode: bool,
+    preflight_issues: Vec<String>,
+    sessions: Vec<SessionSummary>,
+    log: Vec<String>,
+}
+
+struct Settings {
+    // Empty to open a pre-filled issue instead of uploading.
+    endpoint: String,
+    // Whether a bundle is sent on its own when the stream keeps failing.
+    automatic: bool,
+}
+
+static SETTINGS: Mutex<Settings> = Mutex::new(Settings {
+    endpoint: String::new(),
+    automatic: false,
+});
+static SENDING: AtomicBool = AtomicBool::new(false);
+// What the last send came to, for the GUI.
+static STATUS: Mutex<Option<String>> = Mutex::new(None);
+
+/// Where bundles are uploaded, empty for none, and whether failures send one without asking.
+pub fn configure(endpoint: &str, automatic: bool) {
+    *SETTINGS.lock().unwrap() = Settings {
+        endpoint: endpoint.trim().to_string(),
+        automatic,
+    };
+}
+
+pub fn is_sending() -> bool {
+    SENDING.load(Ordering::Relaxed)
+}
+
+pub fn status() -> Option<String> {
+    STATUS.lock().unwrap().clone()
+}
+
+/// Writes a bundle to the diagnostics folder.
+pub fn create_bundle() -> std::io::Result<PathBuf> {
+    let mut config = fs::read_to_string(crate::gui::CONFIG_FILE)
+        .ok()
+        .and_then(|contents| serde_json::from_str::<Value>(&contents).ok())
+        .unwrap_or(Value::Null);
+    // Whole words of the log that give away the host or its clients.
+    let mut secrets: Vec<String> = crate::trust::trusted_clients()
+        .into_iter()
+        .map(|client| client.id)
+        .collect();
+    if let Some(config) = config.as_object_mut() {
+        if let Some(pin) = config
+            .remove("pin")
+            .and_then(|pin| pin.as_str().map(str::to_string))
+        {
+            secrets.push(pin);
+        }
+        config.remove("webrtc_ice_server");
+    }
+
+    let bundle = Bundle {
+        created_at: Utc::now().to_rfc3339(),
+        version: env!("CARGO_PKG_VERSION"),
+        os: std::env::consts::OS,
+        arch: std::env::consts::ARCH,
+        config,
+        gpu_adapters: crate::gpu::list_adapters()
+            .into_iter()
+            .map(|adapter| adapter.name)
+            .collect(),
+        video_encoders: crate::encoder::VideoEncoder::ALL
+            .iter()
+            .flat_map(|encoder| {
+                crate::encoder::supported_codecs()
+                    .into_iter()
+                    .filter(|codec| encoder.is_available(*codec))
+                    .map(move |codec| format!("{} {}", encoder, codec))
+            })
+            .collect(),
+        hardware_encoder_blocked: crate::encoder::hardware_encoder_blocked(),
+        safe_mode: crate::safemode::is_active(),
+        preflight_issues: crate::preflight::issues()
+            .iter()
+            .map(ToString::to_string)
+            .collect(),
+        sessions: crate::timeline::history(),
+        log: crate::logging::viewer_lines(LevelFilter::Trace, "")
+            .iter()
+            .map(|line| redact(&line.to_string(), &secrets))
+            .collect(),
+    };
+
+    fs::create_dir_all(BUNDLE_DIRECTORY)?;
+    let path = Path::new(BUNDLE_DIRECTORY).join(format!(
+        "diagnostics_{}.json",
+        Utc::now().format("%Y%m%d_%H%M%S")
+    ));
+    let mut writer = BufWriter::new(File::create(&path)?);
+    serde_json::to_writer_pretty(&mut writer, &bundle)?;
+    writer.flush()?;
+
+    info!("Saved a diagnostics bundle to {}.", path.display());
+    Ok(path)
+}
+
+/// Packages a bundle and uploads it to the configured endpoint, or opens a pre-filled issue
+/// naming the file to attach. Only ever runs when the host asked for it, by hand or by turning
+/// on automatic sending. Blocking.
+pub fn send(reason: &str) {
+    if SENDING.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    let endpoint = SETTINGS.lock().unwrap().endpoint.clone();
+
+    let result = create_bundle().and_then(|path| {
+        if endpoint.is_empty() {
+            open_issue(&path, reason)?;
+            Ok(format!(
+                "Opened a new issue, please attach {}.",
+                path.display()
+            ))
+        } else {
+            upload(&path, &endpoint)?;
+            Ok(format!("Sent {}.", path.display()))
+        }
+    });
+
+    let status = match result {
+        Ok(status) => status,
+        Err(e) => {
+            warn!("Failed to send diagnostics: {}", e);
+            format!("Failed to send diagnostics: {}", e)
+        }
+    };
+    *STATUS.lock().unwrap() = Some(status);
+    SENDING.store(false, Ordering::Relaxed);
+    crate::gui::request_repaint();
+}
+
+/// Sends a bundle on its own after a failure, if the host opted in and set an endpoint. A
+/// browser is never opened without asking.
+pub fn report_failure(reason: &str) {
+    {
+        let settings = SETTINGS.lock().unwrap();
+        if !settings.automatic || settings.endpoint.is_empty() {
+            return;
+        }
+    }
+    info!("Sending diagnostics: {}.", reason);
+    let reason = reason.to_string();
+    thread::spawn(move || send(&reason));
+}
+
+// Posts the bundle as a multipart form with curl, which Windows ships since 10 1803.
+fn upload(path: &Path, endpoint: &str) -> std::io::Result<()> {
+    let output = Command::new("curl")
+        .args(["--fail", "--silent", "--show-error", "--max-time"])
+        .arg(UPLOAD_TIMEOUT_SECONDS.to_string())
+        .arg("--form")
+        .arg(format!("bundle=@{}", path.display()))
+        .arg(endpoint)
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::new(
+            ErrorKind::Other,
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    info!("Uploaded {} to {}.", path.display(), endpoint);
+    Ok(())
+}
+
+fn open_issue(path: &Path, reason: &str) -> std::io::Result<()> {
+    let path = fs::canonicalize(path).unwrap_or(path.to_path_buf());
+    let body = format!(
+        "**What happened**\n\n{}\n\n**Diagnostics**\n\nPlease attach {} to this issue.\n\n\
+        Version {}",
+        reason,
+        path.display(),
+        env!("CARGO_PKG_VERSION")
+    );
+    let url = format!(
+        "{}?title={}&body={}",
+        NEW_ISSUE_URL,
+        url_encode("Diagnostics report"),
+        url_encode(&body)
+    );
+    // Unlike `cmd /C start`, this leaves the `&` of the query alone.
+    Command::new("rundll32")
+        .args(["url.dll,FileProtocolHandler", &url])
+        .spawn()?;
+    Ok(())
+}
+
+// Replaces IP addresses, with or without a port, and the `secrets` in a log line. Only whole
+// words are replaced, a PIN of 1234 leaves 12345 alone.
+fn redact(line: &str, secrets: &[String]) -> String {
+    let is_word_char = |c: char| c.is_ascii_alphanumeric() || "._:-[]%".contains(c);
+    let mut redacted = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find(is_word_char) {
+        redacted.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find(|c: char| !is_word_char(c)).unwrap_or(rest.len());
+        let word = &rest[..end];
+        // Punctuation ending a sentence or a label is no part of the address.
+        let trimmed = word.trim_end_matches(['.', ':', '-']);
+        let replacement = if secrets.iter().any(|secret| secret == trimmed) {
+            Some("<redacted>")
+        } else if trimmed.parse::<SocketAddr>().is_ok() || trimmed.parse::<IpAddr>().is_ok() {
+            Some("<address>")
+        } else {
+            None
+        };
+        match replacement {
+            Some(replacement) => {
+                redacted.push_str(replacement);
+                redacted.push_str(&word[trimmed.len()..]);
+            }
+            None => redacted.push_str(word),
+        }
+        rest = &rest[end..];
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+fn url_encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
@@ -5,13 +5,27 @@ use std::io::Error as IoError;
 use std::net::{Ipv4Addr, UdpSocket};
 use std::thread;
 use std::time::Duration;
-use log::info;
+use log::{info, warn};
 
 const BROADCAST_PORT: u16 = 55555;
 // Standard broadcast address for the local network.
 const BROADCAST_ADDRESS: Ipv4Addr = Ipv4Addr::new(255, 255, 255, 255);
 const ANNOUNCE_INTERVAL_SECONDS: u64 = 2;
 
+// "<hostname>:<port>", followed by ";sha256=<hex>" with the certificate fingerprint when the
+// WebSocket uses TLS, so clients can verify the host before trusting it.
+fn announcement(hostname: &str) -> String {
+    match crate::tls::fingerprint() {
+        Some(fingerprint) => format!(
+            "{}:{};sha256={}",
+            hostname,
+            crate::ports::current().websocket,
+            fingerprint.replace(':', "")
+        ),
+        None => format!("{}:{}", hostname, crate::ports::current().websocket),
+    }
+}
+
 pub(crate) async fn run_announcer(local_ip: String) -> Result<(), IoError> {
     task::spawn_blocking(move || -> io::Result<()> {
         // 1. Create a UDP socket and bind it to a local address (0.0.0.0 for all interfaces)
@@ -25,29 +39,37 @@ pub(crate) async fn run_announcer(local_ip: String) -> Result<(), IoError> {
         let broadcast_target = (BROADCAST_ADDRESS, BROADCAST_PORT);
 
         let hostname = gethostname::gethostname();
-        let message = format!("{}:5600", hostname.to_str().unwrap());
 
         info!(
             "Broadcasting '{}' every {} seconds from {} to {}:{}",
-            message, ANNOUNCE_INTERVAL_SECONDS, local_ip, BROADCAST_ADDRESS, BROADCAST_PORT
+            announcement(hostname.to_str().unwrap()),
+            ANNOUNCE_INTERVAL_SECONDS,
+            local_ip,
+            BROADCAST_ADDRESS,
+            BROADCAST_PORT
         );
 
-        let message_bytes = message.as_bytes();
+        // The address goes away with its network interface, see `failover`.
+        while crate::failover::has_address(&local_ip) {
+            // TLS may be switched on and off while announcing.
+            let message = announcement(hostname.to_str().unwrap());
 
-        loop {
-            match socket.send_to(message_bytes, broadcast_target) {
+            match socket.send_to(message.as_bytes(), broadcast_target) {
                 Ok(_bytes_sent) => {
                     let _now_utc = Utc::now();
                     // println!("[{}] Sent {} bytes.", now_utc, DISCOVERY_MESSAGE);
                 }
                 Err(e) => {
-                    eprintln!("Error sending broadcast: {}", e);
+                    warn!("Error sending broadcast: {}", e);
                 }
             }
 
             // Wait before sending the next announcement.
             thread::sleep(Duration::from_secs(ANNOUNCE_INTERVAL_SECONDS));
         }
+
+        info!("Stopped broadcasting from {}, it is gone.", local_ip);
+        Ok(())
     })
     .await
     .expect("TODO: panic message");
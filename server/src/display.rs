@@ -0,0 +1,212 @@
+use log::{info, warn};
+use std::io::{Error, ErrorKind};
+use std::sync::Mutex;
+use windows::core::PCWSTR;
+use windows::Win32::Devices::Display::{
+    DisplayConfigGetDeviceInfo, DisplayConfigSetDeviceInfo, GetDisplayConfigBufferSizes,
+    QueryDisplayConfig, DISPLAYCONFIG_DEVICE_INFO_GET_ADVANCED_COLOR_INFO,
+    DISPLAYCONFIG_DEVICE_INFO_SET_ADVANCED_COLOR_STATE, DISPLAYCONFIG_GET_ADVANCED_COLOR_INFO,
+    DISPLAYCONFIG_MODE_INFO, DISPLAYCONFIG_PATH_INFO, DISPLAYCONFIG_SET_ADVANCED_COLOR_STATE,
+    QDC_ONLY_ACTIVE_PATHS,
+};
+use windows::Win32::Foundation::{HWND, LPARAM, WPARAM};
+use windows::Win32::Graphics::Gdi::{
+    ChangeDisplaySettingsExW, EnumDisplaySettingsW, CDS_TYPE, DEVMODEW, DEVMODE_FIELD_FLAGS,
+    DISP_CHANGE_SUCCESSFUL, DM_DISPLAYFREQUENCY, DM_PELSHEIGHT, DM_PELSWIDTH,
+    ENUM_CURRENT_SETTINGS,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    PostMessageW, HWND_BROADCAST, SC_MONITORPOWER, WM_SYSCOMMAND,
+};
+
+// Whether the display mode was changed during the current session.
+// Dynamic mode changes are not written to the registry, so restoring is just a reset.
+static MODE_CHANGED: Mutex<bool> = Mutex::new(false);
+
+// The HDR state before the first HDR toggle of the current session.
+static ORIGINAL_HDR: Mutex<Option<bool>> = Mutex::new(None);
+
+/// Turns the host display(s) off or on.
+pub fn set_display_power(on: bool) -> std::io::Result<()> {
+    // See SC_MONITORPOWER: -1 powers on, 2 powers off.
+    let power: isize = if on { -1 } else { 2 };
+
+    unsafe {
+        PostMessageW(
+            HWND_BROADCAST,
+            WM_SYSCOMMAND,
+            WPARAM(SC_MONITORPOWER as usize),
+            LPARAM(power),
+        )?;
+    }
+
+    info!("Display power set to {}.", if on { "on" } else { "off" });
+    Ok(())
+}
+
+/// Changes the resolution and/or refresh rate of the primary display for this session only.
+pub fn set_display_mode(
+    resolution: Option<(u32, u32)>,
+    refresh_rate: Option<u32>,
+) -> std::io::Result<()> {
+    let mut mode = DEVMODEW {
+        dmSize: size_of::<DEVMODEW>() as u16,
+        ..Default::default()
+    };
+
+    unsafe { EnumDisplaySettingsW(PCWSTR::null(), ENUM_CURRENT_SETTINGS, &mut mode) }.ok()?;
+
+    mode.dmFields = DEVMODE_FIELD_FLAGS(0);
+
+    if let Some((width, height)) = resolution {
+        mode.dmPelsWidth = width;
+        mode.dmPelsHeight = height;
+        mode.dmFields |= DM_PELSWIDTH | DM_PELSHEIGHT;
+    }
+
+    if let Some(hz) = refresh_rate {
+        mode.dmDisplayFrequency = hz;
+        mode.dmFields |= DM_DISPLAYFREQUENCY;
+    }
+
+    if mode.dmFields == DEVMODE_FIELD_FLAGS(0) {
+        return Ok(());
+    }
+
+    // Flags of 0 make the change dynamic, i.e. not persisted to the registry.
+    let result = unsafe {
+        ChangeDisplaySettingsExW(PCWSTR::null(), Some(&mode), HWND(0), CDS_TYPE(0), None)
+    };
+    if result != DISP_CHANGE_SUCCESSFUL {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!("ChangeDisplaySettingsEx failed ({})", result.0),
+        ));
+    }
+
+    *MODE_CHANGED.lock().unwrap() = true;
+
+    info!(
+        "Display mode changed to {}x{}@{}Hz.",
+        mode.dmPelsWidth, mode.dmPelsHeight, mode.dmDisplayFrequency
+    );
+    Ok(())
+}
+
+fn query_active_paths() -> std::io::Result<Vec<DISPLAYCONFIG_PATH_INFO>> {
+    let mut path_count = 0;
+    let mut mode_count = 0;
+
+    unsafe {
+        GetDisplayConfigBufferSizes(QDC_ONLY_ACTIVE_PATHS, &mut path_count, &mut mode_count)?;
+    }
+
+    let mut paths = vec![DISPLAYCONFIG_PATH_INFO::default(); path_count as usize];
+    let mut modes = vec![DISPLAYCONFIG_MODE_INFO::default(); mode_count as usize];
+
+    unsafe {
+        QueryDisplayConfig(
+            QDC_ONLY_ACTIVE_PATHS,
+            &mut path_count,
+            paths.as_mut_ptr(),
+            &mut mode_count,
+            modes.as_mut_ptr(),
+            None,
+        )?;
+    }
+
+    paths.truncate(path_count as usize);
+    Ok(paths)
+}
+
+/// Returns whether HDR (advanced color) is enabled on any active display.
+pub fn is_hdr_enabled() -> std::io::Result<bool> {
+    for path in query_active_paths()? {
+        let mut info = DISPLAYCONFIG_GET_ADVANCED_COLOR_INFO::default();
+        info.header.r#type = DISPLAYCONFIG_DEVICE_INFO_GET_ADVANCED_COLOR_INFO;
+        info.header.size = size_of::<DISPLAYCONFIG_GET_ADVANCED_COLOR_INFO>() as u32;
+        info.header.adapterId = path.targetInfo.adapterId;
+        info.header.id = path.targetInfo.id;
+
+        if unsafe { DisplayConfigGetDeviceInfo(&mut info.header) } != 0 {
+            continue;
+        }
+
+        // Bit 1 is advancedColorEnabled.
+        if unsafe { info.Anonymous.value } & 0b10 != 0 {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Toggles HDR (advanced color) on every active display that supports it.
+pub fn set_hdr(enabled: bool) -> std::io::Result<()> {
+    {
+        let mut original = ORIGINAL_HDR.lock().unwrap();
+        if original.is_none() {
+            *original = Some(is_hdr_enabled()?);
+        }
+    }
+
+    let mut changed_any = false;
+
+    for path in query_active_paths()? {
+        let mut state = DISPLAYCONFIG_SET_ADVANCED_COLOR_STATE::default();
+        state.header.r#type = DISPLAYCONFIG_DEVICE_INFO_SET_ADVANCED_COLOR_STATE;
+        state.header.size = size_of::<DISPLAYCONFIG_SET_ADVANCED_COLOR_STATE>() as u32;
+        state.header.adapterId = path.targetInfo.adapterId;
+        state.header.id = path.targetInfo.id;
+        // Bit 0 is enableAdvancedColor.
+        state.Anonymous.value = enabled as u32;
+
+        let result = unsafe { DisplayConfigSetDeviceInfo(&state.header) };
+        if result == 0 {
+            changed_any = true;
+        } else {
+            warn!(
+                "Display {} rejected HDR change ({}).",
+                path.targetInfo.id, result
+            );
+        }
+    }
+
+    if !changed_any {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            "No active display accepted the HDR change",
+        ));
+    }
+
+    info!("HDR {}.", if enabled { "enabled" } else { "disabled" });
+    Ok(())
+}
+
+/// Undoes all display changes made during the session. Called when the last client leaves.
+pub fn restore_display_settings() {
+    {
+        let mut mode_changed = MODE_CHANGED.lock().unwrap();
+        if *mode_changed {
+            // Passing no mode reverts to the settings stored in the registry.
+            let result = unsafe {
+                ChangeDisplaySettingsExW(PCWSTR::null(), None, HWND(0), CDS_TYPE(0), None)
+            };
+            if result == DISP_CHANGE_SUCCESSFUL {
+                info!("Display mode restored.");
+            } else {
+                warn!("Failed to restore display mode ({}).", result.0);
+            }
+            *mode_changed = false;
+        }
+    }
+
+    let original_hdr = ORIGINAL_HDR.lock().unwrap().take();
+    if let Some(original) = original_hdr {
+        if let Err(e) = set_hdr(original) {
+            warn!("Failed to restore HDR state: {}", e);
+        }
+        // set_hdr() records the state again, clear it so the next session starts fresh.
+        ORIGINAL_HDR.lock().unwrap().take();
+    }
+}
@@ -0,0 +1,106 @@
+use log::{info, warn};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+use windows::core::w;
+use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::HiDpi::{
+    SetProcessDpiAwarenessContext, DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateWindowExW, DefWindowProcW, DispatchMessageW, GetMessageW, RegisterClassW, MSG,
+    WINDOW_EX_STYLE, WM_DISPLAYCHANGE, WM_DPICHANGED, WM_SETTINGCHANGE, WNDCLASSW,
+    WS_OVERLAPPEDWINDOW,
+};
+
+// Changes come in bursts, e.g. one message per monitor, which one refresh covers.
+const REFRESH_DELAY: Duration = Duration::from_millis(500);
+
+static REFRESH_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Makes every thread see physical pixels, like capture does. Otherwise Windows scales the
+/// cursor positions of input on scaled monitors and clicks land off target. Call before any
+/// window is created.
+pub fn init() {
+    if let Err(e) =
+        unsafe { SetProcessDpiAwarenessContext(DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2) }
+    {
+        warn!("Failed to make the process DPI aware: {}", e);
+    }
+}
+
+unsafe extern "system" fn watcher_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    if matches!(msg, WM_DISPLAYCHANGE | WM_DPICHANGED | WM_SETTINGCHANGE)
+        && !REFRESH_PENDING.swap(true, Ordering::Relaxed)
+    {
+        thread::spawn(|| {
+            thread::sleep(REFRESH_DELAY);
+            REFRESH_PENDING.store(false, Ordering::Relaxed);
+            crate::monitor::refresh_capture_area();
+        });
+    }
+    DefWindowProcW(hwnd, msg, wparam, lparam)
+}
+
+/// Follows scaling and layout changes of the monitors while streaming, which move the captured
+/// area on the virtual desktop. Only top-level windows hear about them, so a hidden one listens.
+pub fn start_watcher() {
+    thread::spawn(|| unsafe {
+        let instance = match GetModuleHandleW(None) {
+            Ok(instance) => instance,
+            Err(e) => {
+                warn!("Failed to watch display changes: {}", e);
+                return;
+            }
+        };
+        let class_name = w!("RStreamDisplayWatcher");
+        let class = WNDCLASSW {
+            lpfnWndProc: Some(watcher_proc),
+            hInstance: instance.into(),
+            lpszClassName: class_name,
+            ..Default::default()
+        };
+        if RegisterClassW(&class) == 0 {
+            warn!(
+                "Failed to watch display changes: {}",
+                windows::core::Error::from_win32()
+            );
+            return;
+        }
+
+        // Never shown.
+        let hwnd = CreateWindowExW(
+            WINDOW_EX_STYLE::default(),
+            class_name,
+            w!(""),
+            WS_OVERLAPPEDWINDOW,
+            0,
+            0,
+            0,
+            0,
+            None,
+            None,
+            instance,
+            None,
+        );
+        if hwnd.0 == 0 {
+            warn!(
+                "Failed to watch display changes: {}",
+                windows::core::Error::from_win32()
+            );
+            return;
+        }
+        info!("Watching display scaling and layout changes.");
+
+        let mut msg = MSG::default();
+        while GetMessageW(&mut msg, HWND(0), 0, 0).as_bool() {
+            DispatchMessageW(&msg);
+        }
+    });
+}
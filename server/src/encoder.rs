@@ -0,0 +1,743 @@
+use crate::control::{broadcast_event, ControlEvent};
+use crate::latency::PresetParams;
+use crate::pipeline::BuildError;
+use crate::stream::{check_factory_exists, is_pipeline_running, STREAMING_STATE_GUARD};
+use gstreamer as gst;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const THERMAL_POLL_INTERVAL_SECONDS: u64 = 10;
+// How often clients hear about dropped frames while the encoder keeps falling behind.
+const DROP_REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+// Status codes in hardware encoder errors that mean the GPU ran out of encode sessions. NVENC
+// refuses a session over the limit of consumer GPUs with these, AMF and Media SDK fail to
+// allocate one.
+const SESSION_LIMIT_PATTERNS: [&str; 5] = [
+    "nv_enc_err_out_of_memory",
+    "nv_enc_err_incompatible_client_key",
+    "nv_enc_err_no_encode_device",
+    "amf_out_of_memory",
+    "mfx_err_memory_alloc",
+];
+
+/// Video codecs the stream can be encoded with.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VideoCodec {
+    H264,
+    H265,
+    Av1,
+}
+
+impl VideoCodec {
+    pub const ALL: [VideoCodec; 3] = [VideoCodec::H264, VideoCodec::H265, VideoCodec::Av1];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "h264",
+            VideoCodec::H265 => "h265",
+            VideoCodec::Av1 => "av1",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "h264" => Some(VideoCodec::H264),
+            "h265" => Some(VideoCodec::H265),
+            "av1" => Some(VideoCodec::Av1),
+            _ => None,
+        }
+    }
+
+    /// Parser that frames the encoded stream for a muxer, see `recording`.
+    pub fn parser(&self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "h264parse",
+            VideoCodec::H265 => "h265parse",
+            VideoCodec::Av1 => "av1parse",
+        }
+    }
+
+    /// Caps of the encoded stream, fixing the profile clients have to decode.
+    pub fn caps_str(&self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "video/x-h264,profile=baseline",
+            VideoCodec::H265 => "video/x-h265,profile=main",
+            VideoCodec::Av1 => "video/x-av1,profile=main",
+        }
+    }
+
+    /// Caps of a 10-bit stream for HDR.
+    pub fn hdr_caps_str(&self) -> &'static str {
+        match self {
+            // Not chosen for HDR, see `VideoEncoder::encodes_10bit`.
+            VideoCodec::H264 => "video/x-h264,profile=high-10",
+            VideoCodec::H265 => "video/x-h265,profile=main-10",
+            // Main covers 10 bits already.
+            VideoCodec::Av1 => "video/x-av1,profile=main",
+        }
+    }
+
+    /// Caps of a stream without chroma subsampling, for sharp text.
+    pub fn caps_444_str(&self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "video/x-h264,profile=high-4:4:4",
+            VideoCodec::H265 => "video/x-h265,profile=main-444",
+            VideoCodec::Av1 => "video/x-av1,profile=high",
+        }
+    }
+
+    pub fn payloader(&self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "rtph264pay",
+            VideoCodec::H265 => "rtph265pay",
+            // From the Rust plugins, not every GStreamer install has it.
+            VideoCodec::Av1 => "rtpav1pay",
+        }
+    }
+
+    /// Sets the payloader properties on top of MTU, SSRC and payload type.
+    pub fn payloader_options<'a>(
+        &self,
+        payloader: gst::ElementBuilder<'a>,
+    ) -> gst::ElementBuilder<'a> {
+        match self {
+            // Resend parameter sets with every keyframe so clients can join at any time.
+            VideoCodec::H264 | VideoCodec::H265 => payloader
+                .property("config-interval", -1i32)
+                .property_from_str("aggregate-mode", "zero-latency"),
+            // AV1 carries the sequence header in keyframes anyway.
+            VideoCodec::Av1 => payloader,
+        }
+    }
+
+    /// The `encoding-name` of the RTP caps.
+    pub fn encoding_name(&self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "H264",
+            VideoCodec::H265 => "H265",
+            VideoCodec::Av1 => "AV1",
+        }
+    }
+
+    /// Whether the codec can be payloaded and any encoder for it can be used.
+    pub fn is_available(&self) -> bool {
+        check_factory_exists(self.payloader())
+            && VideoEncoder::ALL
+                .iter()
+                .any(|encoder| encoder.is_available(*self))
+    }
+}
+
+impl std::fmt::Display for VideoCodec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VideoCodec::H264 => write!(f, "H.264"),
+            VideoCodec::H265 => write!(f, "H.265 (HEVC)"),
+            VideoCodec::Av1 => write!(f, "AV1"),
+        }
+    }
+}
+
+/// What the encoder is tuned for. Either way it keeps zero latency.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentTune {
+    // Motion, as in games and video.
+    Game,
+    // Mostly still desktops with text and sharp edges.
+    Text,
+}
+
+impl ContentTune {
+    pub const ALL: [ContentTune; 2] = [ContentTune::Game, ContentTune::Text];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentTune::Game => "game",
+            ContentTune::Text => "text",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "game" => Some(ContentTune::Game),
+            "text" => Some(ContentTune::Text),
+            _ => None,
+        }
+    }
+
+    /// Whether the encoder has screen content tools for `codec`, only the software ones do.
+    pub fn is_supported_by(&self, encoder: VideoEncoder, codec: VideoCodec) -> bool {
+        *self == ContentTune::Game
+            || (encoder == VideoEncoder::Software && codec != VideoCodec::H265)
+    }
+}
+
+impl std::fmt::Display for ContentTune {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContentTune::Game => write!(f, "Game"),
+            ContentTune::Text => write!(f, "Desktop/Text"),
+        }
+    }
+}
+
+/// Strongest film grain SVT-AV1 synthesizes.
+pub const MAX_FILM_GRAIN: u32 = 50;
+
+/// Most B-frames in a row HEVC and AV1 streams may have. Each one delays frames by one more.
+pub const MAX_B_FRAMES: u32 = 4;
+
+/// AV1 tools that keep low-bitrate streams from looking flat or banded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Av1Tuning {
+    // Strength of the grain the decoder adds back, 0 for none. Only SVT-AV1 signals it.
+    pub film_grain: u32,
+    // Whether rate control favors flat areas like gradients and dark scenes, which band first.
+    pub deband: bool,
+}
+
+impl Av1Tuning {
+    pub const DEFAULT: Av1Tuning = Av1Tuning {
+        film_grain: 0,
+        deband: false,
+    };
+
+    /// Whether the encoder applies all of the settings.
+    pub fn is_supported_by(&self, encoder: VideoEncoder) -> bool {
+        match encoder {
+            VideoEncoder::Software => true,
+            VideoEncoder::Nvenc => self.film_grain == 0,
+            _ => *self == Av1Tuning::DEFAULT,
+        }
+    }
+
+    // Options appended to the SVT-AV1 parameters string.
+    fn svtav1_params(&self) -> String {
+        let mut params = String::new();
+        // Grain is modelled on the source as is, denoising it first costs too much in real time.
+        if self.film_grain > 0 {
+            params += &format!(
+                ":film-grain={}:film-grain-denoise=0",
+                self.film_grain.min(MAX_FILM_GRAIN)
+            );
+        }
+        if self.deband {
+            params += ":enable-variance-boost=1";
+        }
+        params
+    }
+}
+
+/// Encoder backends the pipeline can drive, in order of preference.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VideoEncoder {
+    Nvenc,
+    Qsv,
+    Amf,
+    D3d11,
+    // x264, x265 or SVT-AV1 on the CPU.
+    Software,
+}
+
+impl VideoEncoder {
+    pub const ALL: [VideoEncoder; 5] = [
+        VideoEncoder::Nvenc,
+        VideoEncoder::Qsv,
+        VideoEncoder::Amf,
+        VideoEncoder::D3d11,
+        VideoEncoder::Software,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VideoEncoder::Nvenc => "nvenc",
+            VideoEncoder::Qsv => "qsv",
+            VideoEncoder::Amf => "amf",
+            VideoEncoder::D3d11 => "d3d11",
+            VideoEncoder::Software => "software",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "nvenc" => Some(VideoEncoder::Nvenc),
+            "qsv" => Some(VideoEncoder::Qsv),
+            "amf" => Some(VideoEncoder::Amf),
+            "d3d11" => Some(VideoEncoder::D3d11),
+            // Written by versions that only encoded H.264.
+            "software" | "x264" => Some(VideoEncoder::Software),
+            _ => None,
+        }
+    }
+
+    pub fn factory_name(&self, codec: VideoCodec) -> &'static str {
+        match (self, codec) {
+            (VideoEncoder::Nvenc, VideoCodec::H264) => "nvh264enc",
+            (VideoEncoder::Nvenc, VideoCodec::H265) => "nvh265enc",
+            (VideoEncoder::Nvenc, VideoCodec::Av1) => "nvav1enc",
+            (VideoEncoder::Qsv, VideoCodec::H264) => "qsvh264enc",
+            (VideoEncoder::Qsv, VideoCodec::H265) => "qsvh265enc",
+            (VideoEncoder::Qsv, VideoCodec::Av1) => "qsvav1enc",
+            (VideoEncoder::Amf, VideoCodec::H264) => "amfh264enc",
+            (VideoEncoder::Amf, VideoCodec::H265) => "amfh265enc",
+            (VideoEncoder::Amf, VideoCodec::Av1) => "amfav1enc",
+            (VideoEncoder::D3d11, VideoCodec::H264) => "d3d11h264enc",
+            (VideoEncoder::D3d11, VideoCodec::H265) => "d3d11h265enc",
+            (VideoEncoder::D3d11, VideoCodec::Av1) => "d3d11av1enc",
+            (VideoEncoder::Software, VideoCodec::H264) => "x264enc",
+            (VideoEncoder::Software, VideoCodec::H265) => "x265enc",
+            (VideoEncoder::Software, VideoCodec::Av1) => "svtav1enc",
+        }
+    }
+
+    pub fn is_hardware(&self) -> bool {
+        *self != VideoEncoder::Software
+    }
+
+    /// Whether the encoder takes the D3D11 textures from capture without a copy to system memory.
+    /// NVENC still converts on the GPU but needs the frames downloaded.
+    pub fn takes_d3d11_memory(&self) -> bool {
+        matches!(
+            self,
+            VideoEncoder::Qsv | VideoEncoder::Amf | VideoEncoder::D3d11
+        )
+    }
+
+    /// Whether the encoder takes 10-bit frames for `codec` and signals HDR metadata. GPU encoders
+    /// have no 10-bit H.264, the software ones are too slow at 10 bits.
+    pub fn encodes_10bit(&self, codec: VideoCodec) -> bool {
+        codec != VideoCodec::H264
+            && matches!(
+                self,
+                VideoEncoder::Nvenc | VideoEncoder::Qsv | VideoEncoder::Amf
+            )
+    }
+
+    /// Whether the encoder takes full-resolution chroma for `codec`. Of the GPU encoders only
+    /// NVENC does.
+    pub fn encodes_444(&self, codec: VideoCodec) -> bool {
+        codec != VideoCodec::Av1 && matches!(self, VideoEncoder::Nvenc | VideoEncoder::Software)
+    }
+
+    /// Whether the encoder takes a B-frame count for `codec`. H.264 streams never get B-frames,
+    /// SVT-AV1 already uses its low-delay prediction structure.
+    pub fn takes_b_frames(&self, codec: VideoCodec) -> bool {
+        matches!(
+            (self, codec),
+            (VideoEncoder::Nvenc, VideoCodec::H265 | VideoCodec::Av1)
+                | (VideoEncoder::Qsv, VideoCodec::H265)
+                | (VideoEncoder::Software, VideoCodec::H265)
+        )
+    }
+
+    /// Whether the element for `codec` is installed and not blocked after a failure.
+    pub fn is_available(&self, codec: VideoCodec) -> bool {
+        check_factory_exists(self.factory_name(codec)) && !encoder_blocked(*self)
+    }
+
+    /// The encoder element, named `enc`, with rate control for the stream. `factory_name` picks
+    /// the element of a hardware encoder on a specific GPU, `x264_options` only apply to x264 and
+    /// `av1` to AV1. `tune` only changes the software encoders, see `ContentTune::is_supported_by`,
+    /// and `b_frames` only those that take them, see `takes_b_frames`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn make_element(
+        &self,
+        factory_name: &str,
+        codec: VideoCodec,
+        preset: &PresetParams,
+        bitrate_kbps: u32,
+        intra_refresh: bool,
+        x264_options: &str,
+        av1: &Av1Tuning,
+        tune: ContentTune,
+        b_frames: u32,
+    ) -> Result<gst::Element, BuildError> {
+        let b_frames = if self.takes_b_frames(codec) {
+            b_frames.min(MAX_B_FRAMES)
+        } else {
+            0
+        };
+        // The GOP length is signed for some elements and unsigned for others.
+        let gop_size = preset.key_int_max.to_string();
+        let encoder = crate::pipeline::make(factory_name)?.name("enc");
+        let encoder = match (self, codec) {
+            // The AV1 encoder only exists in the newer NVENC API, with its own presets.
+            (VideoEncoder::Nvenc, VideoCodec::Av1) => encoder
+                .property_from_str("preset", preset.nvenc_av1_preset)
+                .property_from_str("tune", "ultra-low-latency")
+                .property_from_str("rc-mode", "cbr")
+                .property("bframes", b_frames)
+                .property("bitrate", bitrate_kbps)
+                .property_from_str("gop-size", &gop_size)
+                .property("spatial-aq", av1.deband)
+                .property("temporal-aq", av1.deband),
+            (VideoEncoder::Nvenc, _) => encoder
+                .property_from_str("preset", preset.nvenc_preset)
+                .property_from_str("rc-mode", "cbr")
+                .property("zerolatency", true)
+                .property("bframes", b_frames)
+                .property("bitrate", bitrate_kbps)
+                .property_from_str("gop-size", &gop_size),
+            (VideoEncoder::Qsv, _) => encoder
+                .property("target-usage", preset.qsv_target_usage)
+                .property_from_str("rate-control", "cbr")
+                .property("b-frames", b_frames)
+                .property("bitrate", bitrate_kbps)
+                .property_from_str("gop-size", &gop_size),
+            (VideoEncoder::Amf, _) => encoder
+                .property_from_str("preset", preset.amf_preset)
+                .property_from_str("usage", preset.amf_usage)
+                .property_from_str("rate-control", "cbr")
+                .property("bitrate", bitrate_kbps)
+                .property_from_str("gop-size", &gop_size),
+            (VideoEncoder::D3d11, _) => encoder
+                .property_from_str("rate-control", "cbr")
+                .property("bitrate", bitrate_kbps)
+                .property_from_str("gop-size", &gop_size),
+            (VideoEncoder::Software, VideoCodec::H264) => encoder
+                .property_from_str(
+                    "tune",
+                    if tune == ContentTune::Text {
+                        "zerolatency+stillimage"
+                    } else {
+                        "zerolatency"
+                    },
+                )
+                .property("sliced-threads", true)
+                .property_from_str("speed-preset", preset.x264_speed_preset)
+                .property("bframes", 0u32)
+                .property("bitrate", bitrate_kbps)
+                .property_from_str("key-int-max", &gop_size)
+                .property("intra-refresh", intra_refresh)
+                .property("option-string", x264_options),
+            // x265 shares the speed presets of x264 but takes B-frames as a raw option.
+            (VideoEncoder::Software, VideoCodec::H265) => encoder
+                .property_from_str("tune", "zerolatency")
+                .property_from_str("speed-preset", preset.x264_speed_preset)
+                .property("bitrate", bitrate_kbps)
+                .property_from_str("key-int-max", &gop_size)
+                .property("option-string", format!("bframes={}", b_frames)),
+            // The low-delay prediction structure has no frames referencing the future.
+            (VideoEncoder::Software, VideoCodec::Av1) => encoder
+                .property("preset", preset.svtav1_preset)
+                .property("target-bitrate", bitrate_kbps)
+                .property_from_str("intra-period-length", &gop_size)
+                .property(
+                    "parameters-string",
+                    format!(
+                        "pred-struct=1{}{}",
+                        av1.svtav1_params(),
+                        // Palette and intra block copy, for text and UI.
+                        if tune == ContentTune::Text {
+                            ":scm=1"
+                        } else {
+                            ""
+                        }
+                    ),
+                ),
+        };
+        Ok(encoder.build()?)
+    }
+}
+
+impl std::fmt::Display for VideoEncoder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VideoEncoder::Nvenc => write!(f, "NVIDIA NVENC"),
+            VideoEncoder::Qsv => write!(f, "Intel Quick Sync"),
+            VideoEncoder::Amf => write!(f, "AMD AMF"),
+            VideoEncoder::D3d11 => write!(f, "Direct3D 11"),
+            VideoEncoder::Software => write!(f, "Software"),
+        }
+    }
+}
+
+/// The preferred encoder if it can encode `codec`, otherwise the first available one.
+fn select_encoder(codec: VideoCodec, preferred: Option<VideoEncoder>) -> Option<VideoEncoder> {
+    if let Some(preferred) = preferred {
+        if preferred.is_available(codec) {
+            return Some(preferred);
+        }
+        warn!(
+            "{} cannot encode {}, picking another encoder.",
+            preferred, codec
+        );
+    }
+
+    VideoEncoder::ALL
+        .into_iter()
+        .find(|encoder| encoder.is_available(codec))
+}
+
+/// The encoder for a 4:4:4 stream of `codec`: `selected` if it can, otherwise the first one that
+/// can, None if none is available.
+pub fn select_444_encoder(codec: VideoCodec, selected: VideoEncoder) -> Option<VideoEncoder> {
+    if selected.encodes_444(codec) {
+        return Some(selected);
+    }
+    VideoEncoder::ALL
+        .into_iter()
+        .find(|encoder| encoder.encodes_444(codec) && encoder.is_available(codec))
+}
+
+/// Whether clients asking for 4:4:4 can get it with any of the codecs they may decode.
+pub fn supports_444() -> bool {
+    supported_codecs().into_iter().any(|codec| {
+        VideoEncoder::ALL
+            .iter()
+            .any(|encoder| encoder.encodes_444(codec) && encoder.is_available(codec))
+    })
+}
+
+/// Codecs the server can encode, for clients to negotiate with. H.264 always works thanks to x264.
+pub fn supported_codecs() -> Vec<VideoCodec> {
+    VideoCodec::ALL
+        .into_iter()
+        .filter(|codec| *codec == VideoCodec::H264 || codec.is_available())
+        .collect()
+}
+
+/// The codec and encoder for a stream: the configured codec if the client decodes it and it can
+/// be encoded, otherwise H.264. Clients that send no codecs only decode H.264.
+pub fn select_codec(
+    configured: VideoCodec,
+    client_codecs: &[VideoCodec],
+    preferred: Option<VideoEncoder>,
+) -> (VideoCodec, VideoEncoder) {
+    if configured != VideoCodec::H264 {
+        if !client_codecs.contains(&configured) {
+            info!("The client does not decode {}, using H.264.", configured);
+        } else if !check_factory_exists(configured.payloader()) {
+            warn!(
+                "{} is not installed, using H.264 instead of {}.",
+                configured.payloader(),
+                configured
+            );
+        } else if let Some(encoder) = select_encoder(configured, preferred) {
+            return (configured, encoder);
+        } else {
+            warn!("No encoder for {} is available, using H.264.", configured);
+        }
+    }
+
+    // x264 is the last resort and assumed to be installed.
+    let encoder = select_encoder(VideoCodec::H264, preferred).unwrap_or(VideoEncoder::Software);
+    (VideoCodec::H264, encoder)
+}
+
+// Hardware encoders that failed in this process and may no longer be used, and why.
+static BLOCKED_ENCODERS: Mutex<Vec<(VideoEncoder, String)>> = Mutex::new(Vec::new());
+static THERMAL_MONITOR_RUNNING: Mutex<bool> = Mutex::new(false);
+// Whether the GPU temperature could be watched, None until the monitor ran.
+static THERMAL_MONITORING: Mutex<Option<bool>> = Mutex::new(None);
+
+// Raw frames the encoder queue dropped since the last stats tick.
+static DROPPED_FRAMES: AtomicU32 = AtomicU32::new(0);
+
+struct DropReport {
+    per_second: u32,
+    // When clients were last told, and whether frames were being dropped then.
+    last_report: Option<(Instant, bool)>,
+}
+
+static DROP_REPORT: Mutex<DropReport> = Mutex::new(DropReport {
+    per_second: 0,
+    last_report: None,
+});
+
+/// Whether any hardware encoder failed in this process.
+pub fn hardware_encoder_blocked() -> bool {
+    !BLOCKED_ENCODERS.lock().unwrap().is_empty()
+}
+
+pub fn encoder_blocked(encoder: VideoEncoder) -> bool {
+    BLOCKED_ENCODERS
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|(blocked, _)| *blocked == encoder)
+}
+
+/// Whether a hardware encoder error means the GPU has no free encode sessions, which another
+/// preset does not fix.
+pub fn is_session_limit_error(error: &str, debug: Option<&str>) -> bool {
+    let text = format!("{} {}", error, debug.unwrap_or("")).to_lowercase();
+    SESSION_LIMIT_PATTERNS
+        .iter()
+        .any(|pattern| text.contains(pattern))
+}
+
+/// Turns an encoder error into a message users can act on.
+pub fn describe_encoder_error(error: &str, debug: Option<&str>) -> String {
+    if is_session_limit_error(error, debug) {
+        "The GPU encoder has no free sessions (other apps may be recording or streaming)."
+            .to_string()
+    } else {
+        format!("The GPU encoder failed ({}).", error)
+    }
+}
+
+/// Stops using `encoder` for the rest of this run and tells everyone why.
+pub fn block_encoder(encoder: VideoEncoder, reason: String) {
+    warn!("{}", reason);
+
+    BLOCKED_ENCODERS
+        .lock()
+        .unwrap()
+        .push((encoder, reason.clone()));
+    set_encoder_warning(reason);
+}
+
+/// Shows `message` in the GUI and tells clients, until another warning replaces it.
+pub fn set_encoder_warning(message: String) {
+    {
+        let mut guard = STREAMING_STATE_GUARD.lock().unwrap();
+        if let Some(state) = guard.as_mut() {
+            state.encoder_warning = Some(message.clone());
+        }
+    }
+
+    broadcast_event(&ControlEvent::EncoderWarning { message });
+}
+
+// Asks nvidia-smi whether the GPU is currently slowed down because of heat.
+// None when there is no NVIDIA GPU or the query failed.
+fn gpu_thermal_throttling() -> Option<bool> {
+    let output = Command::new("nvidia-smi")
+        .args([
+            "--query-gpu=clocks_throttle_reasons.hw_thermal_slowdown,clocks_throttle_reasons.sw_thermal_slowdown",
+            "--format=csv,noheader",
+        ])
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Some(stdout.split(',').any(|reason| reason.trim() == "Active"))
+}
+
+/// Watches for GPU thermal throttling while the pipeline is running.
+pub fn start_thermal_monitor() {
+    {
+        let mut running = THERMAL_MONITOR_RUNNING.lock().unwrap();
+        if *running {
+            return;
+        }
+        *running = true;
+    }
+
+    thread::spawn(|| {
+        let mut throttling = false;
+
+        let mut first = true;
+        while is_pipeline_running() {
+            let polled = gpu_thermal_throttling();
+            if first {
+                first = false;
+                *THERMAL_MONITORING.lock().unwrap() = Some(polled.is_some());
+                if polled.is_none() {
+                    info!("GPU thermal throttling is not watched, it needs nvidia-smi.");
+                }
+            }
+            match polled {
+                Some(now_throttling) if now_throttling != throttling => {
+                    throttling = now_throttling;
+                    if throttling {
+                        set_encoder_warning(
+                            "The GPU is thermal throttling, expect lower frame rates. \
+                            Consider lowering resolution or bitrate."
+                                .to_string(),
+                        );
+                    } else {
+                        let mut guard = STREAMING_STATE_GUARD.lock().unwrap();
+                        if let Some(state) = guard.as_mut() {
+                            state.encoder_warning = BLOCKED_ENCODERS
+                                .lock()
+                                .unwrap()
+                                .last()
+                                .map(|(_, reason)| reason.clone());
+                        }
+                    }
+                }
+                None => break,
+                _ => {}
+            }
+
+            thread::sleep(Duration::from_secs(THERMAL_POLL_INTERVAL_SECONDS));
+        }
+
+        *THERMAL_MONITOR_RUNNING.lock().unwrap() = false;
+    });
+}
+
+/// Whether the GPU temperature is watched, for the GUI. Only NVIDIA GPUs report throttling,
+/// None before a hardware encoder ran.
+pub fn thermal_monitoring() -> Option<bool> {
+    *THERMAL_MONITORING.lock().unwrap()
+}
+
+/// Counts a raw frame thrown away because the encoder fell behind. Called from the streaming thread.
+pub fn record_dropped_frame() {
+    DROPPED_FRAMES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Takes the frames dropped since the last call and tells clients if the encoder is overloaded.
+/// Called once per second while streaming.
+pub fn collect_dropped_frames() -> u32 {
+    let dropped = DROPPED_FRAMES.swap(0, Ordering::Relaxed);
+    let dropping = dropped > 0;
+
+    let report = {
+        let mut report = DROP_REPORT.lock().unwrap();
+        report.per_second = dropped;
+
+        let due = match report.last_report {
+            Some((at, was_dropping)) => {
+                was_dropping != dropping || (dropping && at.elapsed() >= DROP_REPORT_INTERVAL)
+            }
+            None => dropping,
+        };
+        if due {
+            report.last_report = Some((Instant::now(), dropping));
+        }
+        due
+    };
+
+    if report {
+        if dropping {
+            warn!(
+                "Encoder overloaded, dropped {} frame(s) in the last second.",
+                dropped
+            );
+        }
+        broadcast_event(&ControlEvent::FramesDropped {
+            per_second: dropped,
+        });
+    }
+    dropped
+}
+
+/// Frames dropped in front of the encoder during the last second, for the GUI.
+pub fn dropped_frames_per_second() -> u32 {
+    DROP_REPORT.lock().unwrap().per_second
+}
+
+/// Starts counting from zero for a new session.
+pub fn reset_dropped_frames() {
+    DROPPED_FRAMES.store(0, Ordering::Relaxed);
+    *DROP_REPORT.lock().unwrap() = DropReport {
+        per_second: 0,
+        last_report: None,
+    };
+}
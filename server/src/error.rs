@@ -0,0 +1,88 @@
+use crate::control::{broadcast_event, send_event, ControlEvent};
+use log::warn;
+use serde::Serialize;
+use std::net::SocketAddr;
+
+/// Broad area a failure belongs to, so clients can group their UI.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+    Auth,
+    Encoder,
+    Capture,
+    Network,
+    Protocol,
+    Pipeline,
+}
+
+/// Stable identifiers of failures reported to clients.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    AuthFailed,
+    PermissionDenied,
+    EncoderMissing,
+    EncoderFailed,
+    CaptureDenied,
+    PortBusy,
+    InvalidMessage,
+    PipelineFailed,
+}
+
+impl ErrorCode {
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            ErrorCode::AuthFailed | ErrorCode::PermissionDenied => ErrorCategory::Auth,
+            ErrorCode::EncoderMissing | ErrorCode::EncoderFailed => ErrorCategory::Encoder,
+            ErrorCode::CaptureDenied => ErrorCategory::Capture,
+            ErrorCode::PortBusy => ErrorCategory::Network,
+            ErrorCode::InvalidMessage => ErrorCategory::Protocol,
+            ErrorCode::PipelineFailed => ErrorCategory::Pipeline,
+        }
+    }
+
+    /// What the user can do about it.
+    pub fn hint(&self) -> &'static str {
+        match self {
+            ErrorCode::AuthFailed => "Check the PIN shown in the server window.",
+            ErrorCode::PermissionDenied => {
+                "Ask the host to allow it under Trusted clients in the server window."
+            }
+            ErrorCode::EncoderMissing => {
+                "Install the GStreamer plugins for your GPU, or the x264 plugin for software encoding."
+            }
+            ErrorCode::EncoderFailed => {
+                "Update the GPU driver, or close other apps that record or stream the screen."
+            }
+            ErrorCode::CaptureDenied => {
+                "Unlock the host and make sure no secure desktop (UAC prompt, lock screen) is shown."
+            }
+            ErrorCode::PortBusy => {
+                "Another program uses the streaming ports. Close it or restart the server."
+            }
+            ErrorCode::InvalidMessage => "Update the client to a version matching the server.",
+            ErrorCode::PipelineFailed => "See the server log for details.",
+        }
+    }
+
+    fn event(&self, message: String) -> ControlEvent {
+        ControlEvent::Error {
+            code: *self,
+            category: self.category(),
+            message,
+            hint: self.hint().to_string(),
+        }
+    }
+}
+
+/// Tells one client why something failed.
+pub fn report_error(addr: SocketAddr, code: ErrorCode, message: String) {
+    warn!("Reporting {:?} to {}: {}", code, addr, message);
+    send_event(addr, &code.event(message));
+}
+
+/// Tells every streaming client why something failed.
+pub fn broadcast_error(code: ErrorCode, message: String) {
+    warn!("Reporting {:?} to all clients: {}", code, message);
+    broadcast_event(&code.event(message));
+}
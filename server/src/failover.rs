@@ -0,0 +1,103 @@
+use crate::control::{broadcast_event, ControlEvent};
+use crate::discovery::run_announcer;
+use crate::timeline::EndReason;
+use async_std::task;
+use local_ip_address::list_afinet_netifas;
+use log::{info, warn};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+// Addresses of the network interfaces that are up, IPv4 only like discovery.
+static ADDRESSES: Mutex<Vec<IpAddr>> = Mutex::new(Vec::new());
+
+/// Whether the host still has `ip`, so an announcer bound to it keeps going.
+pub fn has_address(ip: &str) -> bool {
+    ADDRESSES
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|address| address.to_string() == ip)
+}
+
+/// Announces the host on every local network and follows interfaces coming and going, so a
+/// session survives e.g. Ethernet being unplugged while Wi-Fi is up. The WebSocket, input and
+/// media sockets are bound to all interfaces and need no rebinding, only the announcers are tied
+/// to one.
+pub fn start() {
+    thread::spawn(|| {
+        let mut first = true;
+        loop {
+            let current = list_addresses();
+            let previous = std::mem::replace(&mut *ADDRESSES.lock().unwrap(), current.clone());
+
+            for ip in current.iter().filter(|ip| !previous.contains(ip)) {
+                if !first {
+                    info!("The network interface with {} came up.", ip);
+                }
+                if is_announced(ip) {
+                    task::spawn(run_announcer(ip.to_string()));
+                }
+            }
+
+            let lost: Vec<IpAddr> = previous
+                .into_iter()
+                .filter(|ip| !current.contains(ip))
+                .collect();
+            if !lost.is_empty() {
+                fail_over(&lost, &current);
+            }
+
+            first = false;
+            thread::sleep(POLL_INTERVAL);
+        }
+    });
+}
+
+fn list_addresses() -> Vec<IpAddr> {
+    match list_afinet_netifas() {
+        Ok(interfaces) => interfaces
+            .into_iter()
+            .map(|(_, ip)| ip)
+            .filter(|ip| ip.is_ipv4() && !ip.is_loopback())
+            .collect(),
+        Err(e) => {
+            warn!("Failed to list the network interfaces: {}", e);
+            ADDRESSES.lock().unwrap().clone()
+        }
+    }
+}
+
+// Home and office networks, where clients look for the host.
+fn is_announced(ip: &IpAddr) -> bool {
+    let ip = ip.to_string();
+    ip.starts_with("192.168.") || ip.starts_with("10.11.")
+}
+
+// Tells clients where the host is now and drops those that reached it through a lost interface.
+// Their connections cannot recover, and the session waits for them to find the host again.
+fn fail_over(lost: &[IpAddr], current: &[IpAddr]) {
+    for ip in lost {
+        warn!("The network interface with {} went away.", ip);
+    }
+    if current.is_empty() {
+        warn!("No network interface is left, clients reconnect once one is back.");
+    }
+
+    broadcast_event(&ControlEvent::HostAddressesChanged {
+        addresses: current.iter().map(ToString::to_string).collect(),
+        websocket_port: crate::ports::current().websocket,
+    });
+
+    let stranded = crate::stream::peers_reached_through(lost);
+    for addr in &stranded {
+        info!(
+            "{} reached the host through a lost interface, waiting for it to reconnect.",
+            addr
+        );
+        crate::stream::disconnect_peer(*addr, EndReason::NetworkChanged);
+    }
+}
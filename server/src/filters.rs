@@ -0,0 +1,269 @@
+use crate::pipeline::{capsfilter, make, BuildError};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use gstreamer_video as gst_video;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// Points per side of the 3D table pixels are looked up in, LUT files are resampled to it.
+const LUT_TABLE_SIZE: usize = 64;
+
+/// Post-processing applied to the capture before encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterKind {
+    Denoise,
+    Sharpen,
+    Gamma,
+    ColorLut,
+}
+
+impl FilterKind {
+    pub const ALL: [FilterKind; 4] = [
+        FilterKind::Denoise,
+        FilterKind::Sharpen,
+        FilterKind::Gamma,
+        FilterKind::ColorLut,
+    ];
+
+    /// Range of `Filter::amount`, None if the filter has none.
+    pub fn amount_range(&self) -> Option<std::ops::RangeInclusive<f32>> {
+        match self {
+            // Sigma of the gaussian, negative values sharpen.
+            FilterKind::Sharpen => Some(0.1..=2.0),
+            FilterKind::Gamma => Some(0.5..=2.5),
+            FilterKind::Denoise | FilterKind::ColorLut => None,
+        }
+    }
+
+    fn default_amount(&self) -> f32 {
+        match self {
+            FilterKind::Sharpen => 0.5,
+            FilterKind::Gamma => 1.0,
+            FilterKind::Denoise | FilterKind::ColorLut => 0.0,
+        }
+    }
+}
+
+impl std::fmt::Display for FilterKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FilterKind::Denoise => write!(f, "Denoise"),
+            FilterKind::Sharpen => write!(f, "Sharpen"),
+            FilterKind::Gamma => write!(f, "Gamma"),
+            FilterKind::ColorLut => write!(f, "Color LUT"),
+        }
+    }
+}
+
+/// One stage of the filter chain, applied in list order.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Filter {
+    pub kind: FilterKind,
+    pub enabled: bool,
+    #[serde(default)]
+    pub amount: f32,
+    // A .cube file, for the color LUT.
+    #[serde(default)]
+    pub lut_path: String,
+}
+
+/// Every filter once, all disabled.
+pub fn default_filters() -> Vec<Filter> {
+    FilterKind::ALL
+        .into_iter()
+        .map(|kind| Filter {
+            kind,
+            enabled: false,
+            amount: kind.default_amount(),
+            lut_path: String::new(),
+        })
+        .collect()
+}
+
+/// The elements of the enabled filters in link order, empty if none is. They work on frames in
+/// system memory, so capture hands over frames the GPU could otherwise keep.
+pub fn make_filters(filters: &[Filter]) -> Result<Vec<gst::Element>, BuildError> {
+    let mut elements = Vec::new();
+    for filter in filters.iter().filter(|filter| filter.enabled) {
+        let filter_elements = match filter.kind {
+            FilterKind::Denoise => vec![make("videomedian")?
+                .property_from_str("filtersize", "5")
+                .build()?],
+            FilterKind::Sharpen => vec![make("gaussianblur")?
+                .property("sigma", -filter.amount as f64)
+                .build()?],
+            FilterKind::Gamma => vec![make("gamma")?
+                .property("gamma", filter.amount as f64)
+                .build()?],
+            FilterKind::ColorLut if filter.lut_path.is_empty() => continue,
+            // Applied by a probe, see `attach_lut`.
+            FilterKind::ColorLut => vec![
+                capsfilter(None, "video/x-raw,format=BGRx")?,
+                make("identity")?.name("colorlut").build()?,
+            ],
+        };
+        elements.push(make("videoconvert")?.build()?);
+        elements.extend(filter_elements);
+    }
+
+    if elements.is_empty() {
+        return Ok(elements);
+    }
+    elements.push(make("videoconvert")?.build()?);
+    info!(
+        "Filtering the capture with {}.",
+        crate::pipeline::describe(&elements)
+    );
+    Ok(elements)
+}
+
+/// A color lookup table resampled to a cube of `LUT_TABLE_SIZE` points per side.
+struct Lut {
+    // RGB outputs, red varying fastest like in .cube files.
+    table: Vec<[u8; 3]>,
+}
+
+impl Lut {
+    // Reads a .cube file, 1D or 3D.
+    fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+
+        let mut size_1d = None;
+        let mut size_3d = None;
+        let mut points = Vec::new();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(size) = line.strip_prefix("LUT_1D_SIZE") {
+                size_1d = size.trim().parse::<usize>().ok();
+            } else if let Some(size) = line.strip_prefix("LUT_3D_SIZE") {
+                size_3d = size.trim().parse::<usize>().ok();
+            } else if line.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.') {
+                let values: Vec<f32> = line
+                    .split_whitespace()
+                    .filter_map(|value| value.parse().ok())
+                    .collect();
+                if let [r, g, b] = values[..] {
+                    points.push([r, g, b]);
+                }
+            }
+            // TITLE, DOMAIN_MIN and DOMAIN_MAX are left at their defaults.
+        }
+
+        let sample: Box<dyn Fn([f32; 3]) -> [f32; 3]> = match (size_1d, size_3d) {
+            (Some(size), None) if size >= 2 && points.len() == size => {
+                Box::new(move |rgb: [f32; 3]| {
+                    std::array::from_fn(|channel| {
+                        let position = rgb[channel].clamp(0.0, 1.0) * (size - 1) as f32;
+                        let low = (position as usize).min(size - 2);
+                        let t = position - low as f32;
+                        points[low][channel] * (1.0 - t) + points[low + 1][channel] * t
+                    })
+                })
+            }
+            (None, Some(size)) if size >= 2 && points.len() == size * size * size => {
+                Box::new(move |rgb: [f32; 3]| trilinear(&points, size, rgb))
+            }
+            _ => return Err("not a valid 1D or 3D .cube LUT".to_string()),
+        };
+
+        let step = 1.0 / (LUT_TABLE_SIZE - 1) as f32;
+        let mut table = Vec::with_capacity(LUT_TABLE_SIZE.pow(3));
+        for b in 0..LUT_TABLE_SIZE {
+            for g in 0..LUT_TABLE_SIZE {
+                for r in 0..LUT_TABLE_SIZE {
+                    let out = sample([r as f32 * step, g as f32 * step, b as f32 * step]);
+                    table.push(out.map(|value| (value.clamp(0.0, 1.0) * 255.0).round() as u8));
+                }
+            }
+        }
+        Ok(Lut { table })
+    }
+
+    fn apply(&self, r: u8, g: u8, b: u8) -> [u8; 3] {
+        let index = |value: u8| value as usize * (LUT_TABLE_SIZE - 1) / 255;
+        self.table[index(r) + LUT_TABLE_SIZE * (index(g) + LUT_TABLE_SIZE * index(b))]
+    }
+}
+
+// Interpolates a 3D LUT of `size` points per side.
+fn trilinear(points: &[[f32; 3]], size: usize, rgb: [f32; 3]) -> [f32; 3] {
+    let position = rgb.map(|value| value.clamp(0.0, 1.0) * (size - 1) as f32);
+    let low = position.map(|value| (value as usize).min(size - 2));
+    let t = [0, 1, 2].map(|channel| position[channel] - low[channel] as f32);
+    let point = |r: usize, g: usize, b: usize| points[r + size * (g + size * b)];
+
+    let mut out = [0.0; 3];
+    for corner in 0..8 {
+        let (dr, dg, db) = (corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+        let weight = [dr, dg, db]
+            .iter()
+            .zip(t)
+            .map(|(&offset, t)| if offset == 1 { t } else { 1.0 - t })
+            .product::<f32>();
+        let value = point(low[0] + dr, low[1] + dg, low[2] + db);
+        for (out, value) in out.iter_mut().zip(value) {
+            *out += value * weight;
+        }
+    }
+    out
+}
+
+/// Loads the color LUT of `filters` and applies it to the frames passing the `colorlut` element.
+pub fn attach_lut(pipeline: &gst::Pipeline, filters: &[Filter]) {
+    let Some(pad) = pipeline
+        .by_name("colorlut")
+        .and_then(|identity| identity.static_pad("src"))
+    else {
+        return;
+    };
+    let Some(path) = filters
+        .iter()
+        .find(|filter| filter.enabled && filter.kind == FilterKind::ColorLut)
+        .map(|filter| filter.lut_path.clone())
+    else {
+        return;
+    };
+
+    let lut = match Lut::load(&path) {
+        Ok(lut) => Arc::new(lut),
+        Err(e) => {
+            warn!("Failed to load the LUT {}: {}", path, e);
+            return;
+        }
+    };
+    info!("Applying the color LUT {}.", path);
+
+    pad.add_probe(gst::PadProbeType::BUFFER, move |pad, info| {
+        let Some(video_info) = pad
+            .current_caps()
+            .and_then(|caps| gst_video::VideoInfo::from_caps(&caps).ok())
+        else {
+            return gst::PadProbeReturn::Ok;
+        };
+        if let Some(gst::PadProbeData::Buffer(ref mut buffer)) = info.data {
+            let buffer = buffer.make_mut();
+            if let Ok(mut frame) =
+                gst_video::VideoFrameRef::from_buffer_ref_writable(buffer, &video_info)
+            {
+                let stride = frame.plane_stride()[0] as usize;
+                let (width, height) = (video_info.width() as usize, video_info.height() as usize);
+                if let Ok(data) = frame.plane_data_mut(0) {
+                    for row in data.chunks_mut(stride).take(height) {
+                        for pixel in row[..width * 4].chunks_exact_mut(4) {
+                            // BGRx
+                            let [r, g, b] = lut.apply(pixel[2], pixel[1], pixel[0]);
+                            pixel[0] = b;
+                            pixel[1] = g;
+                            pixel[2] = r;
+                        }
+                    }
+                }
+            }
+        }
+        gst::PadProbeReturn::Ok
+    });
+}
@@ -0,0 +1,62 @@
+use crate::input::is_gamepad_active;
+use crate::stream::STREAMING_STATE_GUARD;
+use crate::watchdog::watched_pids;
+use crate::window::{find_main_window, focus_window, foreground_window};
+use log::{info, warn};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+const FOCUS_CHECK_INTERVAL_MILLIS: u64 = 500;
+
+static ENFORCER_RUNNING: Mutex<bool> = Mutex::new(false);
+
+/// Keeps the launched app's window in the foreground while a virtual gamepad is plugged in,
+/// since gamepad input only reaches the focused window.
+pub fn start_focus_enforcer() {
+    {
+        let mut running = ENFORCER_RUNNING.lock().unwrap();
+        if *running {
+            return;
+        }
+        *running = true;
+    }
+
+    thread::spawn(|| {
+        info!("Focus enforcer started.");
+
+        while is_gamepad_active() {
+            thread::sleep(Duration::from_millis(FOCUS_CHECK_INTERVAL_MILLIS));
+
+            let enabled = {
+                let guard = STREAMING_STATE_GUARD.lock().unwrap();
+                guard.as_ref().map_or(false, |state| state.enforce_focus)
+            };
+            if !enabled {
+                continue;
+            }
+
+            let pids = watched_pids();
+            if pids.is_empty() {
+                continue;
+            }
+
+            let Some(target) = find_main_window(&pids) else {
+                continue;
+            };
+
+            if foreground_window() != target.hwnd {
+                info!(
+                    "\"{}\" ({}) lost focus, bringing it back.",
+                    target.title, target.process_name
+                );
+                if !focus_window(target.hwnd) {
+                    warn!("Failed to bring \"{}\" to the foreground.", target.title);
+                }
+            }
+        }
+
+        info!("Focus enforcer stopped.");
+        *ENFORCER_RUNNING.lock().unwrap() = false;
+    });
+}
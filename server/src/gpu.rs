@@ -0,0 +1,160 @@
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use log::{info, warn};
+use std::sync::Mutex;
+use windows::core::{s, w};
+use windows::Win32::Foundation::HANDLE;
+use windows::Win32::Graphics::Dxgi::{
+    CreateDXGIFactory1, IDXGIFactory1, DXGI_ADAPTER_FLAG_SOFTWARE,
+};
+use windows::Win32::System::LibraryLoader::{GetProcAddress, LoadLibraryW};
+use windows::Win32::System::Threading::GetCurrentProcess;
+
+// Hardware encoders register one element per GPU beyond the first, e.g. qsvh264device1enc.
+const MAX_DEVICE_ELEMENTS: u32 = 8;
+
+// D3DKMT_SCHEDULINGPRIORITYCLASS values. Realtime needs a privilege most users lack.
+const SCHEDULING_PRIORITY_NORMAL: i32 = 2;
+const SCHEDULING_PRIORITY_HIGH: i32 = 4;
+
+type SetSchedulingPriorityClass = unsafe extern "system" fn(HANDLE, i32) -> i32;
+
+/// A GPU that can convert and encode the stream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GpuAdapter {
+    // DXGI enumeration order, which the D3D11 elements take as `adapter`.
+    pub index: u32,
+    pub name: String,
+    // Stays the same until the next reboot, unlike the order.
+    pub luid: i64,
+}
+
+// Encoder elements found per (default element, adapter LUID).
+static ENCODER_FACTORIES: Mutex<Vec<((&'static str, i64), String)>> = Mutex::new(Vec::new());
+
+/// The hardware adapters, without the software renderer.
+pub fn list_adapters() -> Vec<GpuAdapter> {
+    let factory: IDXGIFactory1 = match unsafe { CreateDXGIFactory1() } {
+        Ok(factory) => factory,
+        Err(e) => {
+            warn!("Failed to create a DXGI factory: {}", e);
+            return Vec::new();
+        }
+    };
+
+    let mut adapters = Vec::new();
+    // Enumeration ends with DXGI_ERROR_NOT_FOUND.
+    for index in 0.. {
+        let Ok(adapter) = (unsafe { factory.EnumAdapters1(index) }) else {
+            break;
+        };
+        let Ok(desc) = (unsafe { adapter.GetDesc1() }) else {
+            continue;
+        };
+        if desc.Flags & DXGI_ADAPTER_FLAG_SOFTWARE.0 as u32 != 0 {
+            continue;
+        }
+
+        let name_len = desc
+            .Description
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(desc.Description.len());
+        adapters.push(GpuAdapter {
+            index,
+            name: String::from_utf16_lossy(&desc.Description[..name_len]),
+            // Packed like GStreamer's adapter-luid property.
+            luid: ((desc.AdapterLuid.HighPart as i64) << 32) | desc.AdapterLuid.LowPart as i64,
+        });
+    }
+    adapters
+}
+
+/// Finds the chosen adapter, by name if its LUID changed with a reboot.
+pub fn find_adapter(luid: i64, name: &str) -> Option<GpuAdapter> {
+    let adapters = list_adapters();
+    adapters
+        .iter()
+        .find(|adapter| adapter.luid == luid)
+        .or_else(|| adapters.iter().find(|adapter| adapter.name == name))
+        .cloned()
+}
+
+/// The element of a hardware encoder that runs on the adapter with `luid`, falling back to
+/// `default_factory` if none reports that adapter.
+pub fn encoder_factory(default_factory: &'static str, luid: i64) -> String {
+    let key = (default_factory, luid);
+    if let Some((_, factory)) = ENCODER_FACTORIES
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(cached, _)| *cached == key)
+    {
+        return factory.clone();
+    }
+
+    let prefix = default_factory
+        .strip_suffix("enc")
+        .unwrap_or(default_factory);
+    let candidates = std::iter::once(default_factory.to_string())
+        .chain((1..MAX_DEVICE_ELEMENTS).map(|device| format!("{}device{}enc", prefix, device)));
+
+    let factory = candidates
+        .filter_map(|name| {
+            gst::ElementFactory::make(&name)
+                .build()
+                .ok()
+                .map(|e| (name, e))
+        })
+        .find(|(_, element)| {
+            element.find_property("adapter-luid").is_some()
+                && element.property::<i64>("adapter-luid") == luid
+        })
+        .map(|(name, _)| name)
+        .unwrap_or_else(|| {
+            warn!(
+                "No {} element runs on the chosen GPU, using the default one.",
+                default_factory
+            );
+            default_factory.to_string()
+        });
+
+    ENCODER_FACTORIES
+        .lock()
+        .unwrap()
+        .push((key, factory.clone()));
+    factory
+}
+
+/// Asks the GPU scheduler to favor this process, so a game saturating the GPU cannot starve
+/// capture and encoding.
+pub fn set_high_priority(high: bool) {
+    let priority = if high {
+        SCHEDULING_PRIORITY_HIGH
+    } else {
+        SCHEDULING_PRIORITY_NORMAL
+    };
+
+    // Not in the import libraries, only exported by gdi32.
+    let result = unsafe {
+        LoadLibraryW(w!("gdi32.dll"))
+            .ok()
+            .and_then(|gdi32| GetProcAddress(gdi32, s!("D3DKMTSetProcessSchedulingPriorityClass")))
+    }
+    .map(|function| unsafe {
+        let set_priority: SetSchedulingPriorityClass = std::mem::transmute(function);
+        set_priority(GetCurrentProcess(), priority)
+    });
+
+    match result {
+        Some(0) => info!(
+            "GPU scheduling priority: {}.",
+            if high { "high" } else { "normal" }
+        ),
+        Some(status) => warn!(
+            "Failed to set the GPU scheduling priority: NTSTATUS {:#x}",
+            status
+        ),
+        None => warn!("GPU scheduling priority is not supported on this system."),
+    }
+}
@@ -0,0 +1,184 @@
+use crate::gpu::{self, GpuAdapter};
+use crate::stream::{is_pipeline_running, STREAMING_STATE_GUARD};
+use log::info;
+use serde::Serialize;
+use std::process::Command;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+// Busier than this, an engine holds the stream back.
+const BUSY_PERCENT: f32 = 90.0;
+
+// Windows counts engine time per process and engine. Unlike AMD ADL, which only reports the
+// whole GPU, this works the same for NVENC, AMF and Quick Sync.
+const ENCODE_COUNTER: &str = r"\GPU Engine(*engtype_VideoEncode)\Utilization Percentage";
+const RENDER_COUNTER: &str = r"\GPU Engine(*engtype_3D)\Utilization Percentage";
+
+/// How busy the GPU the stream is encoded on is, to tell the encoder and the game apart as the
+/// bottleneck.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct GpuStats {
+    // Percent of the time the video encode engine was busy, all processes together.
+    pub encoder_utilization: Option<f32>,
+    // Percent of the time the 3D engine was busy, mostly with the game.
+    pub render_utilization: Option<f32>,
+    // Average time per frame NVENC reports through NVML, other vendors expose none.
+    pub encode_latency_ms: Option<f32>,
+}
+
+impl GpuStats {
+    /// What holds the stream back, if anything, for the GUI.
+    pub fn bottleneck(&self) -> Option<&'static str> {
+        if self
+            .encoder_utilization
+            .is_some_and(|busy| busy >= BUSY_PERCENT)
+        {
+            Some("The encoder is at its limit, lower the resolution, frame rate or preset.")
+        } else if self
+            .render_utilization
+            .is_some_and(|busy| busy >= BUSY_PERCENT)
+        {
+            Some("The game keeps the GPU busy, capping its frame rate leaves room to encode.")
+        } else {
+            None
+        }
+    }
+}
+
+static MONITOR_RUNNING: Mutex<bool> = Mutex::new(false);
+// Present while a hardware encoder streams and the counters could be read.
+static STATS: Mutex<Option<GpuStats>> = Mutex::new(None);
+
+/// Stats of the running stream, for the GUI.
+pub fn current_stats() -> Option<GpuStats> {
+    *STATS.lock().unwrap()
+}
+
+/// Samples the GPU while the pipeline is running.
+pub fn start_monitor() {
+    {
+        let mut running = MONITOR_RUNNING.lock().unwrap();
+        if *running {
+            return;
+        }
+        *running = true;
+    }
+
+    thread::spawn(|| {
+        while is_pipeline_running() {
+            let adapter = {
+                let guard = STREAMING_STATE_GUARD.lock().unwrap();
+                guard.as_ref().and_then(|state| state.gpu_adapter.clone())
+            }
+            // D3D11 picks the first adapter unless told otherwise.
+            .or_else(|| gpu::list_adapters().into_iter().next());
+            let Some(adapter) = adapter else {
+                break;
+            };
+
+            let stats = sample(&adapter);
+            if stats == GpuStats::default() {
+                info!("No GPU engine counters are available, GPU stats are off.");
+                break;
+            }
+            *STATS.lock().unwrap() = Some(stats);
+            crate::gui::request_repaint();
+
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        *STATS.lock().unwrap() = None;
+        *MONITOR_RUNNING.lock().unwrap() = false;
+    });
+}
+
+fn sample(adapter: &GpuAdapter) -> GpuStats {
+    let (encoder_utilization, render_utilization) = engine_utilization(adapter.luid);
+    let encode_latency_ms = if adapter.name.contains("NVIDIA") {
+        nvenc_latency_ms()
+    } else {
+        None
+    };
+    GpuStats {
+        encoder_utilization,
+        render_utilization,
+        encode_latency_ms,
+    }
+}
+
+// Sums the engines of the adapter with `luid` over all processes. Takes about a second, which
+// typeperf spends measuring.
+fn engine_utilization(luid: i64) -> (Option<f32>, Option<f32>) {
+    let Some(output) = Command::new("typeperf")
+        .args([ENCODE_COUNTER, RENDER_COUNTER, "-sc", "1"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+    else {
+        return (None, None);
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    // A CSV of counter paths, then one of values. Instance names hold the LUID as two hex parts.
+    let mut lines = stdout
+        .lines()
+        .filter(|line| line.starts_with('"'))
+        .map(|line| line.trim_matches('"').split("\",\"").collect::<Vec<_>>());
+    let (Some(paths), Some(values)) = (lines.next(), lines.next()) else {
+        return (None, None);
+    };
+    let luid = format!("luid_0x{:08x}_0x{:08x}", (luid >> 32) as u32, luid as u32);
+
+    let mut encode = None;
+    let mut render = None;
+    // The first column is the time of the sample.
+    for (path, value) in paths.iter().zip(values.iter()).skip(1) {
+        let path = path.to_lowercase();
+        let Ok(value) = value.trim().parse::<f32>() else {
+            continue;
+        };
+        if !path.contains(&luid) {
+            continue;
+        }
+        let total = if path.contains("engtype_videoencode") {
+            &mut encode
+        } else {
+            &mut render
+        };
+        *total = Some(total.unwrap_or(0.0) + value);
+    }
+    (
+        encode.map(|busy: f32| busy.min(100.0)),
+        render.map(|busy: f32| busy.min(100.0)),
+    )
+}
+
+// The average of the GPU with running encoder sessions, in ms.
+fn nvenc_latency_ms() -> Option<f32> {
+    let output = Command::new("nvidia-smi")
+        .args([
+            "--query-gpu=encoder.stats.sessionCount,encoder.stats.averageLatency",
+            "--format=csv,noheader,nounits",
+        ])
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout.lines().find_map(|line| {
+        let (sessions, latency_us) = line.split_once(',')?;
+        if sessions.trim().parse::<u32>().ok()? == 0 {
+            return None;
+        }
+        latency_us
+            .trim()
+            .parse::<f32>()
+            .ok()
+            .map(|latency_us| latency_us / 1000.0)
+    })
+}
@@ -1,12 +1,52 @@
-use log::debug;
+use crate::audio::{AudioCodec, AUDIO_FRAME_SIZES, MAX_AUDIO_BITRATE_KBPS, MIN_AUDIO_BITRATE_KBPS};
+use crate::color::{ColorMatrix, ColorRange};
+use crate::encoder::{
+    Av1Tuning, ContentTune, VideoCodec, VideoEncoder, MAX_B_FRAMES, MAX_FILM_GRAIN,
+};
+use crate::filters::{default_filters, Filter, FilterKind};
+use crate::input::{EnetTuning, ENET_MAX_CHANNELS};
+use crate::latency::{LatencyPreset, QueueLeaky};
+use crate::logging::DEFAULT_VIEWER_LINES;
+use crate::monitor::{CaptureRegion, MIN_REGION_SIZE};
+use crate::recording::RecordingFormat;
+use crate::stereo::StereoMode;
+use crate::stream::{
+    CLIENT_AUDIO_PORT, CLIENT_VIDEO_PORT, DEFAULT_MAX_CLIENTS, DEFAULT_MAX_SLICE_SIZE,
+    DEFAULT_VIDEO_FEC_PERCENTAGE, MAX_FRAMERATE, RTP_MTU,
+};
+use crate::timeline::DEFAULT_TIMELINE_MINUTES;
+use crate::trust::Permissions;
+use crate::watchdog::AppExitAction;
+use log::{debug, warn};
 use serde_json::{json, Value};
 use std::fs::File;
 use std::io::prelude::*;
+use std::ops::RangeInclusive;
+use std::path::Path;
 
-const CONFIG_FILE: &str = "config.json";
+pub const CONFIG_FILE: &str = "config.json";
+
+/// Digits of a generated PIN, and the fewest a PIN may have.
+pub const PIN_LENGTH: usize = 4;
+
+// Ranges the GUI offers, a loaded config is held to them too.
+pub const SLICE_COUNT_RANGE: RangeInclusive<u32> = 0..=16;
+pub const VIDEO_FEC_PERCENTAGE_RANGE: RangeInclusive<u32> = 1..=100;
+pub const AUDIO_LOSS_PERCENTAGE_RANGE: RangeInclusive<u32> = 0..=100;
+pub const QUEUE_MAX_BUFFERS_RANGE: RangeInclusive<u32> = 0..=60;
+pub const QUEUE_MAX_TIME_MS_RANGE: RangeInclusive<u32> = 0..=1000;
+pub const CAPTURE_POOL_BUFFERS_RANGE: RangeInclusive<u32> = 0..=32;
+pub const MAX_CLIENTS_RANGE: RangeInclusive<u32> = 1..=64;
+pub const SPECTATOR_DELAY_SECONDS_RANGE: RangeInclusive<u32> = 0..=300;
+// Below 1024 ports need admin rights on many clients.
+pub const MEDIA_PORT_RANGE: RangeInclusive<u32> = 1024..=65535;
 
 use rand::Rng;
 
+fn is_valid_pin(pin: &str) -> bool {
+    pin.len() >= PIN_LENGTH && pin.chars().all(|c| c.is_ascii_digit())
+}
+
 pub(crate) fn generate_pin(length: usize) -> String {
     let mut rng = rand::thread_rng();
     let mut pin = String::new();
@@ -18,20 +58,211 @@ pub(crate) fn generate_pin(length: usize) -> String {
     pin
 }
 
+/// A setting that would not work as configured, shown next to it in the GUI.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigIssue {
+    // Key of the setting in the config file.
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl std::fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
 pub struct AppConfig {
     pub dark_mode: bool,
     pub pin: String,
     pub auto_start: bool,
+    pub app_exit_action: AppExitAction,
+    pub enforce_focus: bool,
+    pub stream_audio_device: String,
+    // Endpoint ID of the device audio is captured from, empty for the stream audio device.
+    pub audio_source_device: String,
+    // Its name, shown while the device list is not loaded.
+    pub audio_source_name: String,
+    // Output device client microphones play into, e.g. a virtual cable. Empty for none.
+    pub mic_device: String,
+    pub performance_mode: bool,
+    pub latency_preset: LatencyPreset,
+    pub auto_latency_preset: bool,
+    pub intra_refresh: bool,
+    pub slice_count: u32,
+    pub max_slice_size: u32,
+    pub stereo_mode: StereoMode,
+    pub audio_codec: AudioCodec,
+    pub audio_bitrate_kbps: u32,
+    pub surround: bool,
+    // Opus frame duration in ms, 0 to follow the latency preset.
+    pub audio_frame_size: u32,
+    pub audio_fec: bool,
+    pub audio_dtx: bool,
+    pub audio_loss_percentage: u32,
+    pub allow_viewport_crop: bool,
+    pub queue_max_buffers: u32,
+    pub queue_max_time_ms: u32,
+    pub queue_leaky: QueueLeaky,
+    // Frames in the pool of the capture source, 0 for what downstream proposes.
+    pub capture_pool_min_buffers: u32,
+    pub capture_pool_max_buffers: u32,
+    pub preferred_encoder: Option<VideoEncoder>,
+    pub video_codec: VideoCodec,
+    pub tls: bool,
+    pub video_fec: bool,
+    pub video_fec_percentage: u32,
+    pub max_clients: u32,
+    // Where clients receive video and audio unless they ask for other ports.
+    pub client_video_port: u32,
+    pub client_audio_port: u32,
+    // Seconds view-only clients lag behind, 0 for none.
+    pub spectator_delay_seconds: u32,
+    pub log_viewer_lines: u32,
+    // Where diagnostics bundles are uploaded, empty to open a GitHub issue instead.
+    pub diagnostics_endpoint: String,
+    // Whether a bundle is uploaded on its own when the stream keeps failing to start.
+    pub auto_send_diagnostics: bool,
+    pub timeline_minutes: u32,
+    // Core lists like "0-3", empty for any core.
+    pub stream_cores: String,
+    pub input_cores: String,
+    pub raise_thread_priority: bool,
+    // None for the default GPU. The name finds it again when the LUID changes with a reboot.
+    pub gpu_adapter_luid: Option<i64>,
+    pub gpu_adapter_name: String,
+    pub high_gpu_priority: bool,
+    pub pause_on_protected_content: bool,
+    // Device name of the captured monitor, empty for the primary one.
+    pub monitor: String,
+    // Streamed rectangle of the monitor or window in pixels, 0 width or height for all of it.
+    pub crop_x: u32,
+    pub crop_y: u32,
+    pub crop_width: u32,
+    pub crop_height: u32,
+    // Whether the panic hotkey also unplugs the virtual controller.
+    pub panic_detach_gamepad: bool,
+    // Post-processing of the capture, in order.
+    pub filters: Vec<Filter>,
+    // Whether clients that display HDR get it from a monitor in HDR mode.
+    pub hdr: bool,
+    // Whether clients that ask for it get full-resolution chroma, for sharp text.
+    pub chroma_444: bool,
+    // What SDR video is converted to and signaled as.
+    pub color_range: ColorRange,
+    pub color_matrix: ColorMatrix,
+    // Film grain strength of AV1 streams, 0 for none.
+    pub av1_film_grain: u32,
+    // Whether AV1 rate control favors areas prone to banding.
+    pub av1_deband: bool,
+    // B-frames in a row for HEVC and AV1 clients that reorder them, 0 for none.
+    pub b_frames: u32,
+    // What the encoder is tuned for, clients may ask for the other.
+    pub content_tune: ContentTune,
+    // Whether clients on this machine can take the stream from shared memory, see `local`.
+    pub local_mode: bool,
+    // Frame rate streamed whatever clients ask for, 0 for their choice.
+    pub framerate: u32,
+    // Where recordings are saved, empty for a folder next to the server.
+    pub recording_directory: String,
+    pub recording_format: RecordingFormat,
+    // Input transport, see `input::EnetTuning`. Bandwidths in KB/s, 0 for no limit.
+    pub enet_channel_limit: u32,
+    pub enet_incoming_bandwidth: u32,
+    pub enet_outgoing_bandwidth: u32,
+    pub enet_throttle_interval_ms: u32,
+    pub enet_throttle_acceleration: u32,
+    pub enet_throttle_deceleration: u32,
+    // Full paths of the executables clients may launch, see `launcher::launch_executable`.
+    pub launch_executables: Vec<String>,
+    // STUN or TURN server of WebRTC clients, empty to only offer host candidates.
+    pub webrtc_ice_server: String,
+    // What clients get when they pair, see `trust::pair`.
+    pub new_client_permissions: Permissions,
 }
 
 impl AppConfig {
     pub fn new() -> Self {
-        let pin = generate_pin(4);
+        let pin = generate_pin(PIN_LENGTH);
 
         Self {
             dark_mode: true,
             pin,
             auto_start: false,
+            app_exit_action: AppExitAction::StopStream,
+            enforce_focus: false,
+            stream_audio_device: String::new(),
+            audio_source_device: String::new(),
+            audio_source_name: String::new(),
+            mic_device: String::new(),
+            performance_mode: false,
+            latency_preset: LatencyPreset::UltraLow,
+            auto_latency_preset: true,
+            intra_refresh: false,
+            slice_count: 0,
+            max_slice_size: DEFAULT_MAX_SLICE_SIZE,
+            stereo_mode: StereoMode::Mono,
+            audio_codec: AudioCodec::Opus,
+            audio_bitrate_kbps: MIN_AUDIO_BITRATE_KBPS,
+            surround: false,
+            audio_frame_size: 0,
+            audio_fec: true,
+            audio_dtx: false,
+            audio_loss_percentage: 0,
+            allow_viewport_crop: true,
+            queue_max_buffers: 0,
+            capture_pool_min_buffers: 0,
+            capture_pool_max_buffers: 0,
+            queue_max_time_ms: 0,
+            queue_leaky: QueueLeaky::Downstream,
+            preferred_encoder: None,
+            video_codec: VideoCodec::H264,
+            tls: false,
+            video_fec: false,
+            video_fec_percentage: DEFAULT_VIDEO_FEC_PERCENTAGE,
+            max_clients: DEFAULT_MAX_CLIENTS,
+            client_video_port: CLIENT_VIDEO_PORT as u32,
+            client_audio_port: CLIENT_AUDIO_PORT as u32,
+            spectator_delay_seconds: 0,
+            log_viewer_lines: DEFAULT_VIEWER_LINES,
+            diagnostics_endpoint: String::new(),
+            auto_send_diagnostics: false,
+            timeline_minutes: DEFAULT_TIMELINE_MINUTES,
+            stream_cores: String::new(),
+            input_cores: String::new(),
+            raise_thread_priority: false,
+            gpu_adapter_luid: None,
+            gpu_adapter_name: String::new(),
+            high_gpu_priority: false,
+            pause_on_protected_content: false,
+            monitor: String::new(),
+            crop_x: 0,
+            crop_y: 0,
+            crop_width: 0,
+            crop_height: 0,
+            panic_detach_gamepad: false,
+            filters: default_filters(),
+            hdr: false,
+            chroma_444: true,
+            color_range: ColorRange::Auto,
+            color_matrix: ColorMatrix::Auto,
+            av1_film_grain: Av1Tuning::DEFAULT.film_grain,
+            av1_deband: Av1Tuning::DEFAULT.deband,
+            b_frames: 0,
+            content_tune: ContentTune::Game,
+            local_mode: false,
+            framerate: 0,
+            recording_directory: String::new(),
+            recording_format: RecordingFormat::Mp4,
+            enet_channel_limit: EnetTuning::DEFAULT.channel_limit as u32,
+            enet_incoming_bandwidth: 0,
+            enet_outgoing_bandwidth: 0,
+            enet_throttle_interval_ms: EnetTuning::DEFAULT.throttle_interval_ms,
+            enet_throttle_acceleration: EnetTuning::DEFAULT.throttle_acceleration,
+            enet_throttle_deceleration: EnetTuning::DEFAULT.throttle_deceleration,
+            launch_executables: Vec::new(),
+            webrtc_ice_server: String::new(),
+            new_client_permissions: Permissions::default(),
         }
     }
 
@@ -55,15 +286,527 @@ impl AppConfig {
         self.pin = String::from(json_value["pin"].as_str().unwrap_or(""));
         self.dark_mode = json_value["dark_mode"].as_bool().unwrap_or(true);
         self.auto_start = json_value["auto_start"].as_bool().unwrap_or(false);
+        self.app_exit_action =
+            AppExitAction::from_str(json_value["app_exit_action"].as_str().unwrap_or(""))
+                .unwrap_or(AppExitAction::StopStream);
+        self.enforce_focus = json_value["enforce_focus"].as_bool().unwrap_or(false);
+        self.stream_audio_device =
+            String::from(json_value["stream_audio_device"].as_str().unwrap_or(""));
+        self.audio_source_device =
+            String::from(json_value["audio_source_device"].as_str().unwrap_or(""));
+        self.audio_source_name =
+            String::from(json_value["audio_source_name"].as_str().unwrap_or(""));
+        self.mic_device = String::from(json_value["mic_device"].as_str().unwrap_or(""));
+        self.performance_mode = json_value["performance_mode"].as_bool().unwrap_or(false);
+        self.latency_preset =
+            LatencyPreset::from_str(json_value["latency_preset"].as_str().unwrap_or(""))
+                .unwrap_or(LatencyPreset::UltraLow);
+        self.auto_latency_preset = json_value["auto_latency_preset"].as_bool().unwrap_or(true);
+        self.intra_refresh = json_value["intra_refresh"].as_bool().unwrap_or(false);
+        self.slice_count = json_value["slice_count"].as_u64().unwrap_or(0) as u32;
+        self.max_slice_size = json_value["max_slice_size"]
+            .as_u64()
+            .unwrap_or(DEFAULT_MAX_SLICE_SIZE as u64) as u32;
+        self.stereo_mode = StereoMode::from_str(json_value["stereo_mode"].as_str().unwrap_or(""))
+            .unwrap_or(StereoMode::Mono);
+        self.audio_codec = AudioCodec::from_str(json_value["audio_codec"].as_str().unwrap_or(""))
+            .unwrap_or(AudioCodec::Opus);
+        self.audio_bitrate_kbps = json_value["audio_bitrate_kbps"]
+            .as_u64()
+            .unwrap_or(MIN_AUDIO_BITRATE_KBPS as u64) as u32;
+        self.surround = json_value["surround"].as_bool().unwrap_or(false);
+        self.audio_frame_size = json_value["audio_frame_size"].as_u64().unwrap_or(0) as u32;
+        self.audio_fec = json_value["audio_fec"].as_bool().unwrap_or(true);
+        self.audio_dtx = json_value["audio_dtx"].as_bool().unwrap_or(false);
+        self.audio_loss_percentage =
+            json_value["audio_loss_percentage"].as_u64().unwrap_or(0) as u32;
+        self.allow_viewport_crop = json_value["allow_viewport_crop"].as_bool().unwrap_or(true);
+        self.queue_max_buffers = json_value["queue_max_buffers"].as_u64().unwrap_or(0) as u32;
+        self.queue_max_time_ms = json_value["queue_max_time_ms"].as_u64().unwrap_or(0) as u32;
+        self.queue_leaky = QueueLeaky::from_str(json_value["queue_leaky"].as_str().unwrap_or(""))
+            .unwrap_or(QueueLeaky::Downstream);
+        self.capture_pool_min_buffers =
+            json_value["capture_pool_min_buffers"].as_u64().unwrap_or(0) as u32;
+        self.capture_pool_max_buffers =
+            json_value["capture_pool_max_buffers"].as_u64().unwrap_or(0) as u32;
+        // "auto" or anything unknown picks the best encoder available.
+        self.preferred_encoder =
+            VideoEncoder::from_str(json_value["preferred_encoder"].as_str().unwrap_or(""));
+        self.video_codec = VideoCodec::from_str(json_value["video_codec"].as_str().unwrap_or(""))
+            .unwrap_or(VideoCodec::H264);
+        self.tls = json_value["tls"].as_bool().unwrap_or(false);
+        self.video_fec = json_value["video_fec"].as_bool().unwrap_or(false);
+        self.video_fec_percentage = json_value["video_fec_percentage"]
+            .as_u64()
+            .unwrap_or(DEFAULT_VIDEO_FEC_PERCENTAGE as u64)
+            as u32;
+        self.max_clients = json_value["max_clients"]
+            .as_u64()
+            .unwrap_or(DEFAULT_MAX_CLIENTS as u64) as u32;
+        self.client_video_port = json_value["client_video_port"]
+            .as_u64()
+            .unwrap_or(CLIENT_VIDEO_PORT as u64) as u32;
+        self.client_audio_port = json_value["client_audio_port"]
+            .as_u64()
+            .unwrap_or(CLIENT_AUDIO_PORT as u64) as u32;
+        self.spectator_delay_seconds =
+            json_value["spectator_delay_seconds"].as_u64().unwrap_or(0) as u32;
+        self.log_viewer_lines = json_value["log_viewer_lines"]
+            .as_u64()
+            .unwrap_or(DEFAULT_VIEWER_LINES as u64) as u32;
+        self.diagnostics_endpoint =
+            String::from(json_value["diagnostics_endpoint"].as_str().unwrap_or(""));
+        self.auto_send_diagnostics = json_value["auto_send_diagnostics"]
+            .as_bool()
+            .unwrap_or(false);
+        self.timeline_minutes = json_value["timeline_minutes"]
+            .as_u64()
+            .unwrap_or(DEFAULT_TIMELINE_MINUTES as u64) as u32;
+        self.stream_cores = String::from(json_value["stream_cores"].as_str().unwrap_or(""));
+        self.input_cores = String::from(json_value["input_cores"].as_str().unwrap_or(""));
+        self.raise_thread_priority = json_value["raise_thread_priority"]
+            .as_bool()
+            .unwrap_or(false);
+        self.gpu_adapter_luid = json_value["gpu_adapter_luid"].as_i64();
+        self.gpu_adapter_name = String::from(json_value["gpu_adapter_name"].as_str().unwrap_or(""));
+        self.high_gpu_priority = json_value["high_gpu_priority"].as_bool().unwrap_or(false);
+        self.pause_on_protected_content = json_value["pause_on_protected_content"]
+            .as_bool()
+            .unwrap_or(false);
+        self.monitor = String::from(json_value["monitor"].as_str().unwrap_or(""));
+        self.crop_x = json_value["crop_x"].as_u64().unwrap_or(0) as u32;
+        self.crop_y = json_value["crop_y"].as_u64().unwrap_or(0) as u32;
+        self.crop_width = json_value["crop_width"].as_u64().unwrap_or(0) as u32;
+        self.crop_height = json_value["crop_height"].as_u64().unwrap_or(0) as u32;
+        self.panic_detach_gamepad = json_value["panic_detach_gamepad"]
+            .as_bool()
+            .unwrap_or(false);
+        self.filters = serde_json::from_value(json_value["filters"].clone())
+            .unwrap_or_else(|_| default_filters());
+        self.hdr = json_value["hdr"].as_bool().unwrap_or(false);
+        self.chroma_444 = json_value["chroma_444"].as_bool().unwrap_or(true);
+        self.color_range = ColorRange::from_str(json_value["color_range"].as_str().unwrap_or(""))
+            .unwrap_or(ColorRange::Auto);
+        self.color_matrix =
+            ColorMatrix::from_str(json_value["color_matrix"].as_str().unwrap_or(""))
+                .unwrap_or(ColorMatrix::Auto);
+        self.av1_film_grain = json_value["av1_film_grain"]
+            .as_u64()
+            .unwrap_or(Av1Tuning::DEFAULT.film_grain as u64) as u32;
+        self.av1_deband = json_value["av1_deband"]
+            .as_bool()
+            .unwrap_or(Av1Tuning::DEFAULT.deband);
+        self.b_frames = json_value["b_frames"].as_u64().unwrap_or(0) as u32;
+        self.content_tune =
+            ContentTune::from_str(json_value["content_tune"].as_str().unwrap_or(""))
+                .unwrap_or(ContentTune::Game);
+        self.local_mode = json_value["local_mode"].as_bool().unwrap_or(false);
+        self.framerate = json_value["framerate"].as_u64().unwrap_or(0) as u32;
+        self.recording_directory =
+            String::from(json_value["recording_directory"].as_str().unwrap_or(""));
+        self.recording_format =
+            RecordingFormat::from_str(json_value["recording_format"].as_str().unwrap_or(""))
+                .unwrap_or(RecordingFormat::Mp4);
+        self.enet_channel_limit = json_value["enet_channel_limit"]
+            .as_u64()
+            .unwrap_or(EnetTuning::DEFAULT.channel_limit as u64)
+            as u32;
+        self.enet_incoming_bandwidth =
+            json_value["enet_incoming_bandwidth"].as_u64().unwrap_or(0) as u32;
+        self.enet_outgoing_bandwidth =
+            json_value["enet_outgoing_bandwidth"].as_u64().unwrap_or(0) as u32;
+        self.enet_throttle_interval_ms = json_value["enet_throttle_interval_ms"]
+            .as_u64()
+            .unwrap_or(EnetTuning::DEFAULT.throttle_interval_ms as u64)
+            as u32;
+        self.enet_throttle_acceleration = json_value["enet_throttle_acceleration"]
+            .as_u64()
+            .unwrap_or(EnetTuning::DEFAULT.throttle_acceleration as u64)
+            as u32;
+        self.enet_throttle_deceleration = json_value["enet_throttle_deceleration"]
+            .as_u64()
+            .unwrap_or(EnetTuning::DEFAULT.throttle_deceleration as u64)
+            as u32;
+        self.launch_executables =
+            serde_json::from_value(json_value["launch_executables"].clone()).unwrap_or_default();
+        self.new_client_permissions =
+            serde_json::from_value(json_value["new_client_permissions"].clone())
+                .unwrap_or_default();
+        self.webrtc_ice_server =
+            String::from(json_value["webrtc_ice_server"].as_str().unwrap_or(""));
+
+        for issue in self.validate() {
+            warn!("Invalid setting {}", issue);
+        }
+        self.repair();
 
         Ok(())
     }
 
+    // Numbers the pipeline takes as is, with their allowed ranges.
+    fn ranged_values(&self) -> [(&'static str, u32, RangeInclusive<u32>); 15] {
+        [
+            ("framerate", self.framerate, 0..=MAX_FRAMERATE),
+            ("slice_count", self.slice_count, SLICE_COUNT_RANGE),
+            ("max_slice_size", self.max_slice_size, 0..=RTP_MTU),
+            (
+                "audio_bitrate_kbps",
+                self.audio_bitrate_kbps,
+                MIN_AUDIO_BITRATE_KBPS..=MAX_AUDIO_BITRATE_KBPS,
+            ),
+            (
+                "audio_loss_percentage",
+                self.audio_loss_percentage,
+                AUDIO_LOSS_PERCENTAGE_RANGE,
+            ),
+            (
+                "video_fec_percentage",
+                self.video_fec_percentage,
+                VIDEO_FEC_PERCENTAGE_RANGE,
+            ),
+            ("av1_film_grain", self.av1_film_grain, 0..=MAX_FILM_GRAIN),
+            ("b_frames", self.b_frames, 0..=MAX_B_FRAMES),
+            (
+                "queue_max_buffers",
+                self.queue_max_buffers,
+                QUEUE_MAX_BUFFERS_RANGE,
+            ),
+            (
+                "queue_max_time_ms",
+                self.queue_max_time_ms,
+                QUEUE_MAX_TIME_MS_RANGE,
+            ),
+            (
+                "capture_pool_min_buffers",
+                self.capture_pool_min_buffers,
+                CAPTURE_POOL_BUFFERS_RANGE,
+            ),
+            (
+                "capture_pool_max_buffers",
+                self.capture_pool_max_buffers,
+                CAPTURE_POOL_BUFFERS_RANGE,
+            ),
+            ("max_clients", self.max_clients, MAX_CLIENTS_RANGE),
+            (
+                "client_video_port",
+                self.client_video_port,
+                MEDIA_PORT_RANGE,
+            ),
+            (
+                "client_audio_port",
+                self.client_audio_port,
+                MEDIA_PORT_RANGE,
+            ),
+            (
+                "spectator_delay_seconds",
+                self.spectator_delay_seconds,
+                SPECTATOR_DELAY_SECONDS_RANGE,
+            ),
+        ]
+    }
+
+    /// Everything that would keep the settings from working as configured. Cheap enough to run
+    /// on every frame of the GUI.
+    pub fn validate(&self) -> Vec<ConfigIssue> {
+        let mut issues = Vec::new();
+        let mut issue =
+            |field: &'static str, message: String| issues.push(ConfigIssue { field, message });
+
+        if !is_valid_pin(&self.pin) {
+            issue(
+                "pin",
+                format!(
+                    "The PIN needs at least {} digits and nothing else.",
+                    PIN_LENGTH
+                ),
+            );
+        }
+
+        for (field, value, range) in self.ranged_values() {
+            if !range.contains(&value) {
+                issue(
+                    field,
+                    format!("{} is outside {} to {}.", value, range.start(), range.end()),
+                );
+            }
+        }
+        let channel_limit = 1..=ENET_MAX_CHANNELS as u32;
+        if !channel_limit.contains(&self.enet_channel_limit) {
+            issue(
+                "enet_channel_limit",
+                format!("Clients may open 1 to {} channels.", ENET_MAX_CHANNELS),
+            );
+        }
+
+        if self.audio_frame_size != 0 && !AUDIO_FRAME_SIZES.contains(&self.audio_frame_size) {
+            issue(
+                "audio_frame_size",
+                format!("Opus has no {} ms frames.", self.audio_frame_size),
+            );
+        } else if self.audio_frame_size != 0 && self.audio_codec == AudioCodec::Aac {
+            issue(
+                "audio_frame_size",
+                "AAC frames are always 21 ms, the frame size only applies to Opus.".to_string(),
+            );
+        }
+
+        if let Some(encoder) = self.preferred_encoder {
+            if !self.content_tune.is_supported_by(encoder, self.video_codec) {
+                issue(
+                    "content_tune",
+                    format!(
+                        "{} has no {} tune for {}, it streams tuned for games.",
+                        encoder, self.content_tune, self.video_codec
+                    ),
+                );
+            }
+        }
+
+        if (self.crop_width == 0) != (self.crop_height == 0) {
+            issue(
+                "crop",
+                "A region needs both a width and a height, the whole area is streamed.".to_string(),
+            );
+        } else if self
+            .capture_region()
+            .is_some_and(|region| region.width < MIN_REGION_SIZE || region.height < MIN_REGION_SIZE)
+        {
+            issue(
+                "crop",
+                format!(
+                    "A region must be at least {}x{} pixels.",
+                    MIN_REGION_SIZE, MIN_REGION_SIZE
+                ),
+            );
+        }
+
+        for (field, cores) in [
+            ("stream_cores", &self.stream_cores),
+            ("input_cores", &self.input_cores),
+        ] {
+            if let Err(e) = crate::affinity::parse_cores(cores) {
+                issue(field, format!("{}.", e));
+            }
+        }
+
+        for filter in self
+            .filters
+            .iter()
+            .filter(|filter| filter.enabled && filter.kind == FilterKind::ColorLut)
+        {
+            if filter.lut_path.is_empty() {
+                issue("filters", "The color LUT needs a .cube file.".to_string());
+            } else if !Path::new(&filter.lut_path).is_file() {
+                issue(
+                    "filters",
+                    format!("The color LUT {} does not exist.", filter.lut_path),
+                );
+            }
+        }
+
+        if self.client_video_port == self.client_audio_port {
+            issue(
+                "client_audio_port",
+                "Video and audio need ports of their own, or clients that bundle them.".to_string(),
+            );
+        }
+
+        if self.capture_pool_max_buffers != 0
+            && self.capture_pool_max_buffers < self.capture_pool_min_buffers
+        {
+            issue(
+                "capture_pool_max_buffers",
+                "The capture pool cannot hold fewer frames than its minimum, it keeps the minimum."
+                    .to_string(),
+            );
+        }
+
+        let endpoint = self.diagnostics_endpoint.trim();
+        if !endpoint.is_empty()
+            && !endpoint.starts_with("https://")
+            && !endpoint.starts_with("http://")
+        {
+            issue(
+                "diagnostics_endpoint",
+                "The diagnostics endpoint needs to be an http:// or https:// URL.".to_string(),
+            );
+        } else if self.auto_send_diagnostics && endpoint.is_empty() {
+            issue(
+                "diagnostics_endpoint",
+                "Diagnostics are only sent on their own to an endpoint.".to_string(),
+            );
+        }
+
+        let ice_server = self.webrtc_ice_server.trim();
+        if !ice_server.is_empty()
+            && !["stun://", "turn://", "turns://"]
+                .iter()
+                .any(|scheme| ice_server.starts_with(scheme))
+        {
+            issue(
+                "webrtc_ice_server",
+                "The WebRTC server needs to be a stun://, turn:// or turns:// URL.".to_string(),
+            );
+        }
+
+        issues
+    }
+
+    // Brings loaded values back to what the GUI could have set, so they never reach the
+    // pipeline. Thread cores and LUT files are left alone, they fail with an error later.
+    fn repair(&mut self) {
+        let clamp = |value: &mut u32, range: RangeInclusive<u32>| {
+            *value = (*value).clamp(*range.start(), *range.end());
+        };
+        clamp(&mut self.framerate, 0..=MAX_FRAMERATE);
+        clamp(&mut self.slice_count, SLICE_COUNT_RANGE);
+        clamp(&mut self.max_slice_size, 0..=RTP_MTU);
+        clamp(
+            &mut self.audio_bitrate_kbps,
+            MIN_AUDIO_BITRATE_KBPS..=MAX_AUDIO_BITRATE_KBPS,
+        );
+        clamp(&mut self.audio_loss_percentage, AUDIO_LOSS_PERCENTAGE_RANGE);
+        clamp(&mut self.video_fec_percentage, VIDEO_FEC_PERCENTAGE_RANGE);
+        clamp(&mut self.av1_film_grain, 0..=MAX_FILM_GRAIN);
+        clamp(&mut self.b_frames, 0..=MAX_B_FRAMES);
+        clamp(&mut self.queue_max_buffers, QUEUE_MAX_BUFFERS_RANGE);
+        clamp(&mut self.queue_max_time_ms, QUEUE_MAX_TIME_MS_RANGE);
+        clamp(
+            &mut self.capture_pool_min_buffers,
+            CAPTURE_POOL_BUFFERS_RANGE,
+        );
+        clamp(
+            &mut self.capture_pool_max_buffers,
+            CAPTURE_POOL_BUFFERS_RANGE,
+        );
+        clamp(&mut self.max_clients, MAX_CLIENTS_RANGE);
+        clamp(&mut self.client_video_port, MEDIA_PORT_RANGE);
+        clamp(&mut self.client_audio_port, MEDIA_PORT_RANGE);
+        clamp(
+            &mut self.spectator_delay_seconds,
+            SPECTATOR_DELAY_SECONDS_RANGE,
+        );
+        clamp(&mut self.enet_channel_limit, 1..=ENET_MAX_CHANNELS as u32);
+
+        if !AUDIO_FRAME_SIZES.contains(&self.audio_frame_size) {
+            self.audio_frame_size = 0;
+        }
+        if !is_valid_pin(&self.pin) {
+            self.pin = generate_pin(PIN_LENGTH);
+        }
+        if self.capture_region().is_none() {
+            self.crop_width = 0;
+            self.crop_height = 0;
+        }
+    }
+
+    pub fn enet_tuning(&self) -> EnetTuning {
+        EnetTuning {
+            channel_limit: self.enet_channel_limit as usize,
+            incoming_bandwidth: self.enet_incoming_bandwidth * 1024,
+            outgoing_bandwidth: self.enet_outgoing_bandwidth * 1024,
+            throttle_interval_ms: self.enet_throttle_interval_ms,
+            throttle_acceleration: self.enet_throttle_acceleration,
+            throttle_deceleration: self.enet_throttle_deceleration,
+        }
+    }
+
+    pub fn av1_tuning(&self) -> Av1Tuning {
+        Av1Tuning {
+            film_grain: self.av1_film_grain,
+            deband: self.av1_deband,
+        }
+    }
+
+    /// The streamed region, None for the whole monitor or window.
+    pub fn capture_region(&self) -> Option<CaptureRegion> {
+        (self.crop_width > 0 && self.crop_height > 0).then_some(CaptureRegion {
+            x: self.crop_x,
+            y: self.crop_y,
+            width: self.crop_width,
+            height: self.crop_height,
+        })
+    }
+
     pub fn write(&mut self) -> std::io::Result<()> {
         let json_value = json!({
             "dark_mode": self.dark_mode,
             "pin": self.pin,
             "auto_start": self.auto_start,
+            "app_exit_action": self.app_exit_action.as_str(),
+            "enforce_focus": self.enforce_focus,
+            "stream_audio_device": self.stream_audio_device,
+            "audio_source_device": self.audio_source_device,
+            "audio_source_name": self.audio_source_name,
+            "mic_device": self.mic_device,
+            "performance_mode": self.performance_mode,
+            "latency_preset": self.latency_preset.as_str(),
+            "auto_latency_preset": self.auto_latency_preset,
+            "intra_refresh": self.intra_refresh,
+            "slice_count": self.slice_count,
+            "max_slice_size": self.max_slice_size,
+            "stereo_mode": self.stereo_mode.as_str(),
+            "audio_codec": self.audio_codec.as_str(),
+            "audio_bitrate_kbps": self.audio_bitrate_kbps,
+            "surround": self.surround,
+            "audio_frame_size": self.audio_frame_size,
+            "audio_fec": self.audio_fec,
+            "audio_dtx": self.audio_dtx,
+            "audio_loss_percentage": self.audio_loss_percentage,
+            "allow_viewport_crop": self.allow_viewport_crop,
+            "queue_max_buffers": self.queue_max_buffers,
+            "capture_pool_min_buffers": self.capture_pool_min_buffers,
+            "capture_pool_max_buffers": self.capture_pool_max_buffers,
+            "queue_max_time_ms": self.queue_max_time_ms,
+            "queue_leaky": self.queue_leaky.as_str(),
+            "preferred_encoder": self.preferred_encoder.map_or("auto", |encoder| encoder.as_str()),
+            "video_codec": self.video_codec.as_str(),
+            "tls": self.tls,
+            "video_fec": self.video_fec,
+            "video_fec_percentage": self.video_fec_percentage,
+            "max_clients": self.max_clients,
+            "client_video_port": self.client_video_port,
+            "client_audio_port": self.client_audio_port,
+            "spectator_delay_seconds": self.spectator_delay_seconds,
+            "log_viewer_lines": self.log_viewer_lines,
+            "diagnostics_endpoint": self.diagnostics_endpoint,
+            "auto_send_diagnostics": self.auto_send_diagnostics,
+            "timeline_minutes": self.timeline_minutes,
+            "stream_cores": self.stream_cores,
+            "input_cores": self.input_cores,
+            "raise_thread_priority": self.raise_thread_priority,
+            "gpu_adapter_luid": self.gpu_adapter_luid,
+            "gpu_adapter_name": self.gpu_adapter_name,
+            "high_gpu_priority": self.high_gpu_priority,
+            "pause_on_protected_content": self.pause_on_protected_content,
+            "monitor": self.monitor,
+            "crop_x": self.crop_x,
+            "crop_y": self.crop_y,
+            "crop_width": self.crop_width,
+            "crop_height": self.crop_height,
+            "panic_detach_gamepad": self.panic_detach_gamepad,
+            "filters": self.filters,
+            "hdr": self.hdr,
+            "chroma_444": self.chroma_444,
+            "color_range": self.color_range.as_str(),
+            "color_matrix": self.color_matrix.as_str(),
+            "av1_film_grain": self.av1_film_grain,
+            "av1_deband": self.av1_deband,
+            "b_frames": self.b_frames,
+            "content_tune": self.content_tune.as_str(),
+            "local_mode": self.local_mode,
+            "framerate": self.framerate,
+            "recording_directory": self.recording_directory,
+            "recording_format": self.recording_format.as_str(),
+            "enet_channel_limit": self.enet_channel_limit,
+            "enet_incoming_bandwidth": self.enet_incoming_bandwidth,
+            "enet_outgoing_bandwidth": self.enet_outgoing_bandwidth,
+            "enet_throttle_interval_ms": self.enet_throttle_interval_ms,
+            "enet_throttle_acceleration": self.enet_throttle_acceleration,
+            "enet_throttle_deceleration": self.enet_throttle_deceleration,
+            "launch_executables": self.launch_executables,
+            "webrtc_ice_server": self.webrtc_ice_server,
+            "new_client_permissions": self.new_client_permissions,
         });
 
         let json_string = serde_json::to_string_pretty(&json_value).unwrap();
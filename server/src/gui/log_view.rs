@@ -0,0 +1,93 @@
+use crate::logging::{self, LogLine};
+use eframe::egui;
+use eframe::egui::{Color32, ComboBox, RichText, ScrollArea, TextEdit, TextStyle};
+use log::{Level, LevelFilter};
+
+const LOG_VIEW_HEIGHT: f32 = 240.0;
+
+/// Recent log lines with level filtering and search.
+pub struct LogView {
+    level: LevelFilter,
+    search: String,
+    // Keeps the newest line in view while scrolled to the bottom.
+    auto_scroll: bool,
+}
+
+impl Default for LogView {
+    fn default() -> Self {
+        Self {
+            level: LevelFilter::Info,
+            search: String::new(),
+            auto_scroll: true,
+        }
+    }
+}
+
+fn level_color(level: Level) -> Color32 {
+    match level {
+        Level::Error => Color32::LIGHT_RED,
+        Level::Warn => Color32::YELLOW,
+        _ => Color32::GRAY,
+    }
+}
+
+impl LogView {
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        let lines = logging::viewer_lines(self.level, &self.search);
+
+        ui.horizontal(|ui| {
+            ComboBox::from_id_source("log_level")
+                .selected_text(self.level.to_string())
+                .show_ui(ui, |ui| {
+                    for level in [LevelFilter::Error, LevelFilter::Warn, LevelFilter::Info] {
+                        ui.selectable_value(&mut self.level, level, level.to_string());
+                    }
+                });
+
+            ui.add(
+                TextEdit::singleline(&mut self.search)
+                    .hint_text("Search")
+                    .desired_width(140.0),
+            );
+
+            ui.checkbox(&mut self.auto_scroll, "Auto-scroll");
+
+            if ui
+                .button("Copy")
+                .on_hover_text("Copy the lines shown")
+                .clicked()
+            {
+                let text = lines
+                    .iter()
+                    .map(LogLine::to_string)
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                ui.output_mut(|output| output.copied_text = text);
+            }
+
+            if ui.button("Clear").clicked() {
+                logging::clear_viewer_lines();
+            }
+        });
+
+        if lines.is_empty() {
+            ui.label("No log lines");
+            return;
+        }
+
+        let row_height = ui.text_style_height(&TextStyle::Monospace);
+        ScrollArea::both()
+            .max_height(LOG_VIEW_HEIGHT)
+            .auto_shrink([false, true])
+            .stick_to_bottom(self.auto_scroll)
+            .show_rows(ui, row_height, lines.len(), |ui, rows| {
+                for line in &lines[rows] {
+                    ui.label(
+                        RichText::new(line.to_string())
+                            .monospace()
+                            .color(level_color(line.level)),
+                    );
+                }
+            });
+    }
+}
@@ -1,2 +1,57 @@
 pub mod app;
-mod config;
\ No newline at end of file
+mod config;
+mod log_view;
+
+pub(crate) use config::{generate_pin, CONFIG_FILE};
+
+use crate::platform::NativeWindow;
+use eframe::egui;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+// How often the window refreshes on its own, for stats that change without an event.
+const STATS_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+static REPAINT_CONTEXT: Mutex<Option<egui::Context>> = Mutex::new(None);
+static MAIN_WINDOW: Mutex<Option<NativeWindow>> = Mutex::new(None);
+
+/// Asks the GUI to show a state change made outside of it, e.g. a client connecting.
+pub fn request_repaint() {
+    if let Some(ctx) = REPAINT_CONTEXT.lock().unwrap().as_ref() {
+        ctx.request_repaint();
+    }
+}
+
+/// Repaints the window once per interval while it is visible or shows a pairing code,
+/// and enables `request_repaint()`.
+/// A timer thread is used because `request_repaint_after()` panics when used along with rfd.
+pub fn start_repaint_timer(ctx: egui::Context) {
+    *REPAINT_CONTEXT.lock().unwrap() = Some(ctx);
+
+    thread::spawn(|| loop {
+        thread::sleep(STATS_REFRESH_INTERVAL);
+        if *crate::VISIBLE.lock().unwrap() || crate::pairing::visible_code().is_some() {
+            request_repaint();
+        }
+    });
+}
+
+/// Remembers the native main window, so `quit()` can bring it back before closing.
+pub fn set_main_window(window: NativeWindow) {
+    *MAIN_WINDOW.lock().unwrap() = Some(window);
+}
+
+/// Closes the app like Quit in the tray menu, so the config is saved on the way out.
+pub fn quit() {
+    *crate::ALLOW_EXIT.lock().unwrap() = true;
+
+    // Show hidden window before sending quit command
+    if let Some(window) = MAIN_WINDOW.lock().unwrap().as_ref() {
+        window.show();
+    }
+    if let Some(ctx) = REPAINT_CONTEXT.lock().unwrap().as_ref() {
+        ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+        ctx.request_repaint();
+    }
+}
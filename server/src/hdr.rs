@@ -0,0 +1,168 @@
+use crate::encoder::{supported_codecs, VideoEncoder};
+use crate::pipeline::{capsfilter, make, BuildError};
+use crate::stream::STREAMING_STATE_GUARD;
+use gstreamer as gst;
+use log::warn;
+use windows::core::ComInterface;
+use windows::Win32::Graphics::Dxgi::Common::DXGI_COLOR_SPACE_RGB_FULL_G2084_NONE_P2020;
+use windows::Win32::Graphics::Dxgi::{
+    CreateDXGIFactory1, IDXGIFactory1, IDXGIOutput6, DXGI_OUTPUT_DESC1,
+};
+
+// Units of the SEI messages: chromaticity in 0.00002, luminance in 0.0001 cd/m².
+const CHROMATICITY_UNITS: f32 = 50_000.0;
+const LUMINANCE_UNITS: f32 = 10_000.0;
+
+/// What the encoder signals about an HDR display, as GStreamer caps take it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HdrMetadata {
+    // Primaries, white point and luminance range of the display.
+    pub mastering_display_info: String,
+    // Brightest pixel and brightest frame average, in cd/m².
+    pub content_light_level: String,
+}
+
+impl HdrMetadata {
+    fn from_desc(desc: &DXGI_OUTPUT_DESC1) -> Self {
+        let chromaticity = |[x, y]: [f32; 2]| {
+            format!(
+                "{}:{}",
+                (x * CHROMATICITY_UNITS) as u32,
+                (y * CHROMATICITY_UNITS) as u32
+            )
+        };
+        HdrMetadata {
+            // Red, green and blue, unlike the order of the SEI.
+            mastering_display_info: format!(
+                "{}:{}:{}:{}:{}:{}",
+                chromaticity(desc.RedPrimary),
+                chromaticity(desc.GreenPrimary),
+                chromaticity(desc.BluePrimary),
+                chromaticity(desc.WhitePoint),
+                (desc.MaxLuminance * LUMINANCE_UNITS) as u32,
+                (desc.MinLuminance * LUMINANCE_UNITS) as u32
+            ),
+            content_light_level: format!(
+                "{}:{}",
+                desc.MaxLuminance as u32, desc.MaxFullFrameLuminance as u32
+            ),
+        }
+    }
+
+    /// Caps fields of HDR10 video: BT.2020 primaries, PQ transfer and the display metadata.
+    pub fn caps_fields(&self) -> String {
+        format!(
+            ",colorimetry=bt2100-pq,mastering-display-info=(string){},\
+            content-light-level=(string){}",
+            self.mastering_display_info, self.content_light_level
+        )
+    }
+}
+
+/// The metadata of a monitor showing HDR, by its device name or the primary one for None.
+/// None if the monitor shows SDR.
+pub fn display_hdr(monitor_name: Option<&str>) -> Option<HdrMetadata> {
+    let factory: IDXGIFactory1 = match unsafe { CreateDXGIFactory1() } {
+        Ok(factory) => factory,
+        Err(e) => {
+            warn!("Failed to create a DXGI factory: {}", e);
+            return None;
+        }
+    };
+
+    // Both enumerations end with DXGI_ERROR_NOT_FOUND.
+    for adapter_index in 0.. {
+        let Ok(adapter) = (unsafe { factory.EnumAdapters1(adapter_index) }) else {
+            break;
+        };
+        for output_index in 0.. {
+            let Ok(output) = (unsafe { adapter.EnumOutputs(output_index) }) else {
+                break;
+            };
+            // Older systems lack IDXGIOutput6, and HDR with it.
+            let Ok(desc) = output
+                .cast::<IDXGIOutput6>()
+                .and_then(|output| unsafe { output.GetDesc1() })
+            else {
+                continue;
+            };
+            if !desc.AttachedToDesktop.as_bool() {
+                continue;
+            }
+
+            let name_len = desc
+                .DeviceName
+                .iter()
+                .position(|&c| c == 0)
+                .unwrap_or(desc.DeviceName.len());
+            let is_chosen = match monitor_name {
+                Some(name) => String::from_utf16_lossy(&desc.DeviceName[..name_len]) == name,
+                None => desc.DesktopCoordinates.left == 0 && desc.DesktopCoordinates.top == 0,
+            };
+            if is_chosen {
+                return (desc.ColorSpace == DXGI_COLOR_SPACE_RGB_FULL_G2084_NONE_P2020)
+                    .then(|| HdrMetadata::from_desc(&desc));
+            }
+        }
+    }
+    None
+}
+
+/// The metadata of the captured monitor, if the host allows HDR and the monitor shows it.
+pub fn captured_display_hdr() -> Option<HdrMetadata> {
+    let (enabled, monitor_name) = captured_monitor();
+    if !enabled {
+        return None;
+    }
+    display_hdr(monitor_name.as_deref())
+}
+
+/// Whether the captured monitor shows HDR, whatever clients get.
+pub fn captured_display_is_hdr() -> bool {
+    let (_, monitor_name) = captured_monitor();
+    display_hdr(monitor_name.as_deref()).is_some()
+}
+
+// Whether the host allows HDR, and the device name of the captured monitor.
+fn captured_monitor() -> (bool, Option<String>) {
+    let guard = STREAMING_STATE_GUARD.lock().unwrap();
+    guard.as_ref().map_or((false, None), |state| {
+        (
+            state.hdr,
+            state.monitor.as_ref().map(|monitor| monitor.name.clone()),
+        )
+    })
+}
+
+/// Maps the HDR capture of the monitor to SDR on the GPU, for a stream that is not HDR.
+/// Captured as 8 bits, an HDR desktop comes out washed out. The output is 8-bit sRGB, in GPU
+/// or system memory as the elements after it take it. Converts on the GPU `adapter`, None for
+/// the default one.
+pub fn make_tone_map(adapter: Option<u32>) -> Result<Vec<gst::Element>, BuildError> {
+    let mut tone_map = make("d3d11convert")?.name("tonemap");
+    if let Some(adapter) = adapter {
+        tone_map = tone_map.property("adapter", adapter as i32);
+    }
+    Ok(vec![
+        capsfilter(
+            None,
+            "video/x-raw(memory:D3D11Memory),format=BGR10A2_LE,colorimetry=bt2100-pq",
+        )?,
+        tone_map.build()?,
+        capsfilter(
+            None,
+            "video/x-raw(memory:D3D11Memory),format=BGRA,colorimetry=sRGB;\
+            video/x-raw,format=BGRA,colorimetry=sRGB",
+        )?,
+    ])
+}
+
+/// Whether clients that display HDR can get it now, for the capability exchange.
+pub fn is_available() -> bool {
+    let encodes_10bit = supported_codecs().into_iter().any(|codec| {
+        VideoEncoder::ALL
+            .iter()
+            .any(|encoder| encoder.encodes_10bit(codec) && encoder.is_available(codec))
+    });
+    encodes_10bit && captured_display_hdr().is_some()
+}
@@ -0,0 +1,40 @@
+use log::{info, warn};
+use std::thread;
+use windows::Win32::Foundation::HWND;
+use windows::Win32::UI::Input::KeyboardAndMouse::{
+    RegisterHotKey, MOD_ALT, MOD_CONTROL, MOD_NOREPEAT, MOD_SHIFT, VK_Q,
+};
+use windows::Win32::UI::WindowsAndMessaging::{GetMessageW, MSG, WM_HOTKEY};
+
+const PANIC_HOTKEY_ID: i32 = 1;
+
+/// Shown wherever the hotkey is explained.
+pub const PANIC_HOTKEY_NAME: &str = "Ctrl+Alt+Shift+Q";
+
+/// Registers the host hotkey that releases all remote input, see `input::panic_release`.
+/// It works whatever window has the focus, e.g. a game a stuck key is wrecking.
+pub fn start_panic_hotkey() {
+    thread::spawn(|| unsafe {
+        // Without a window the hotkey is posted to this thread's queue.
+        if let Err(e) = RegisterHotKey(
+            HWND(0),
+            PANIC_HOTKEY_ID,
+            MOD_CONTROL | MOD_ALT | MOD_SHIFT | MOD_NOREPEAT,
+            VK_Q.0 as u32,
+        ) {
+            warn!(
+                "Failed to register the panic hotkey {}: {}",
+                PANIC_HOTKEY_NAME, e
+            );
+            return;
+        }
+        info!("Panic hotkey {} registered.", PANIC_HOTKEY_NAME);
+
+        let mut msg = MSG::default();
+        while GetMessageW(&mut msg, HWND(0), 0, 0).as_bool() {
+            if msg.message == WM_HOTKEY && msg.wParam.0 == PANIC_HOTKEY_ID as usize {
+                crate::input::panic_release();
+            }
+        }
+    });
+}
@@ -1,43 +1,142 @@
+use crate::control::{broadcast_event, ControlEvent};
+use crate::network;
 use crate::stream::STREAMING_STATE_GUARD;
+use crate::trust::{Permission, Permissions};
+use crate::view;
 use async_std::task;
 use byteorder::{LittleEndian, ReadBytesExt};
-use enigo::Coordinate::Abs;
+use enigo::Coordinate::{Abs, Rel};
 use enigo::Direction::{Click, Press, Release};
 use enigo::{Button, Direction, Enigo, Key, Keyboard, Mouse, Settings};
 use rusty_enet as enet;
 use std::io::Cursor;
 use std::io::Error as IoError;
-use std::net::{SocketAddr, UdpSocket};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::process::Command;
 use std::str::FromStr;
-use std::sync::{Mutex, Once};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
 use vigem_client::{self as vigem, Client, TargetId, XGamepad, Xbox360Wired};
 
 // --- ENet Configuration ---
-const ENET_PORT: u16 = 7777; // Dedicated ENet port for input
-                             // const ENET_CHANNEL_INPUT: u8 = 0; // Channel 0 for reliable input commands
+pub(crate) const ENET_PORT: u16 = 7777; // Dedicated ENet port for input, unless it is busy
+                                        // const ENET_CHANNEL_INPUT: u8 = 0; // Channel 0 for reliable input commands
+
+/// How the input transport uses the link. Takes effect right away, channels for the next
+/// connection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EnetTuning {
+    // Most channels a client may open, e.g. to keep rumble or clipboard traffic from queuing
+    // behind input. Clients pick which channel carries what.
+    pub channel_limit: usize,
+    // In bytes per second, 0 for no limit. ENet shares them out between its peers.
+    pub incoming_bandwidth: u32,
+    pub outgoing_bandwidth: u32,
+    // How often, and by how much, ENet adjusts how many unreliable packets it drops when
+    // the link congests. Lower values react faster.
+    pub throttle_interval_ms: u32,
+    pub throttle_acceleration: u32,
+    pub throttle_deceleration: u32,
+}
+
+impl EnetTuning {
+    // ENet's own defaults, with one channel for input and one spare.
+    pub const DEFAULT: EnetTuning = EnetTuning {
+        channel_limit: 2,
+        incoming_bandwidth: 0,
+        outgoing_bandwidth: 0,
+        throttle_interval_ms: 5000,
+        throttle_acceleration: 2,
+        throttle_deceleration: 2,
+    };
+}
+
+// Channels an ENet connection can have at most.
+pub(crate) const ENET_MAX_CHANNELS: usize = 255;
+
+static ENET_TUNING: Mutex<EnetTuning> = Mutex::new(EnetTuning::DEFAULT);
+// Bumped on every change, so the ENet loop knows to apply it.
+static ENET_TUNING_GENERATION: AtomicU32 = AtomicU32::new(0);
+
+// How often the input link is measured for network classification.
+const LINK_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+// How often the ENet loop looks up what its client may send, e.g. after the host changed it.
+const PERMISSION_REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+
+const VIGEM_DOWNLOAD_URL: &str = "https://github.com/nefarius/ViGEmBus/releases/latest";
+
+// How far the cursor moves when the head turns, for games using mouse look.
+const HEAD_POSE_PIXELS_PER_RADIAN: f32 = 1000.0;
 
 // A thread-safe global container for the Enigo instance.
 // Mutex: Ensures exclusive access when a thread is using Enigo.
 // Option: Allows Enigo to be initialized later (Lazy initialization).
 pub(crate) static ENIGO_GUARD: Mutex<Option<Enigo>> = Mutex::new(None);
-static ENIGO_INIT: Once = Once::new();
 
 static VIGEM_GUARD: Mutex<Option<Xbox360Wired<Client>>> = Mutex::new(None);
+// Whether the ViGEmBus driver answered the last check, None before the first one.
+static VIGEM_DRIVER_FOUND: Mutex<Option<bool>> = Mutex::new(None);
 static GAMEPAD_GUARD: Mutex<Option<XGamepad>> = Mutex::new(None);
 
-// A function to initialize Enigo exactly once.
-pub fn init_enigo() {
-    ENIGO_INIT.call_once(|| {
-        let enigo = Enigo::new(&Settings::default()).expect("Failed to initialize Enigo");
-        *ENIGO_GUARD.lock().unwrap() = Some(enigo);
-        log::info!("Enigo initialized.");
-    });
+// Where the client that took the session over connects from, the ENet loop lets go of any
+// other one.
+static INPUT_HANDOVER: Mutex<Option<IpAddr>> = Mutex::new(None);
+
+// Last head yaw and pitch, head pose packets are turned into relative mouse movement.
+static HEAD_POSE_GUARD: Mutex<Option<(f32, f32)>> = Mutex::new(None);
+
+// Whether the left mouse button is held down by a client, so a state sync can release it.
+static LEFT_BUTTON_DOWN: AtomicBool = AtomicBool::new(false);
+
+// Whether the panic hotkey unplugs the virtual controller too.
+static PANIC_DETACHES_GAMEPAD: AtomicBool = AtomicBool::new(false);
+
+// Bits of the mouse buttons in a state sync.
+const SYNC_MOUSE_LEFT: u8 = 1;
+
+// Initializes Enigo unless it already is. Can be retried after a failure.
+pub fn init_enigo() -> Result<(), String> {
+    let mut enigo_lock = ENIGO_GUARD.lock().unwrap();
+    if enigo_lock.is_some() {
+        return Ok(());
+    }
+
+    let enigo = Enigo::new(&Settings::default()).map_err(|e| e.to_string())?;
+    *enigo_lock = Some(enigo);
+    log::info!("Enigo initialized.");
+    Ok(())
+}
+
+/// Checks whether the ViGEmBus driver is installed. Without it gamepad input is ignored
+/// and mouse and keyboard still work.
+pub fn check_vigem_driver() -> bool {
+    let found = vigem::Client::connect().is_ok();
+    if !found {
+        log::warn!("ViGEmBus driver not found, gamepad input is disabled.");
+    }
+    *VIGEM_DRIVER_FOUND.lock().unwrap() = Some(found);
+    found
+}
+
+/// Whether gamepads can be emulated, as far as the last check knows.
+pub fn is_vigem_driver_found() -> bool {
+    VIGEM_DRIVER_FOUND.lock().unwrap().unwrap_or(true)
+}
+
+/// Opens the ViGEmBus release page in the default browser.
+pub fn open_vigem_download_page() -> std::io::Result<()> {
+    // The empty argument is the window title `start` expects before the target.
+    Command::new("cmd")
+        .args(["/C", "start", "", VIGEM_DOWNLOAD_URL])
+        .spawn()?;
+    Ok(())
 }
 
 // A function to initialize Vigem.
 pub fn init_vigem() {
     let mut vigem_lock = VIGEM_GUARD.lock().unwrap();
-    if vigem_lock.is_some() {
+    if vigem_lock.is_some() || !is_vigem_driver_found() {
         return;
     }
 
@@ -46,6 +145,7 @@ pub fn init_vigem() {
         Ok(c) => c,
         Err(e) => {
             log::error!("Failed to connect to ViGEmBus: {:?}", e);
+            *VIGEM_DRIVER_FOUND.lock().unwrap() = Some(false);
             return;
         }
     };
@@ -78,6 +178,12 @@ pub fn init_vigem() {
     });
 
     log::info!("Controller is ready.");
+
+    crate::focus::start_focus_enforcer();
+}
+
+pub fn is_gamepad_active() -> bool {
+    VIGEM_GUARD.lock().unwrap().is_some()
 }
 
 // A function to deinitialize Vigem.
@@ -92,17 +198,98 @@ pub fn deinit_vigem() {
     *gamepad_lock = None;
 }
 
+pub fn set_panic_detaches_gamepad(detach: bool) {
+    PANIC_DETACHES_GAMEPAD.store(detach, Ordering::Relaxed);
+}
+
+/// Gives input to the client at `ip` after it took the session over. Whatever the previous client
+/// held down is released and its ENet connection dropped, so the new one can connect.
+pub(crate) fn hand_over(ip: IpAddr) {
+    release_all_input(false);
+    *INPUT_HANDOVER.lock().unwrap() = Some(ip);
+}
+
+/// Releases everything a client may hold down: mouse buttons, modifier keys and the gamepad.
+/// With `detach_gamepad` the virtual controller is unplugged until the client reconnects.
+pub fn release_all_input(detach_gamepad: bool) {
+    if let Some(enigo) = ENIGO_GUARD.lock().unwrap().as_mut() {
+        for button in [Button::Left, Button::Right, Button::Middle] {
+            let _ = enigo.button(button, Release);
+        }
+        for key in [Key::Shift, Key::Control, Key::Alt, Key::Meta] {
+            let _ = enigo.key(key, Release);
+        }
+    }
+    LEFT_BUTTON_DOWN.store(false, Ordering::Relaxed);
+    *HEAD_POSE_GUARD.lock().unwrap() = None;
+
+    if detach_gamepad {
+        deinit_vigem();
+    } else if let Some(gamepad) = GAMEPAD_GUARD.lock().unwrap().as_mut() {
+        *gamepad = XGamepad::default();
+        if let Some(vigem) = VIGEM_GUARD.lock().unwrap().as_mut() {
+            if let Err(e) = vigem.update(gamepad) {
+                log::error!("Failed to update ViGEm target: {:?}", e);
+            }
+        }
+    }
+
+    log::warn!(
+        "Released all input{}.",
+        if detach_gamepad {
+            " and unplugged the virtual controller"
+        } else {
+            ""
+        }
+    );
+    broadcast_event(&ControlEvent::InputReleased {
+        gamepad_detached: detach_gamepad,
+    });
+}
+
+/// Changes how the input transport uses the link, see `EnetTuning`.
+pub fn set_enet_tuning(tuning: EnetTuning) {
+    let mut current = ENET_TUNING.lock().unwrap();
+    if *current == tuning {
+        return;
+    }
+    *current = tuning;
+    ENET_TUNING_GENERATION.fetch_add(1, Ordering::Relaxed);
+}
+
+// Limits of 0 are no limits to ENet as well.
+fn bandwidth_limit(bytes_per_second: u32) -> Option<u32> {
+    (bytes_per_second > 0).then_some(bytes_per_second)
+}
+
+fn apply_peer_tuning(peer: &mut enet::Peer<UdpSocket>, tuning: &EnetTuning) {
+    peer.set_throttle(
+        Duration::from_millis(tuning.throttle_interval_ms as u64),
+        tuning.throttle_acceleration,
+        tuning.throttle_deceleration,
+    );
+}
+
+/// Releases all input from the panic hotkey, see `hotkey`.
+pub fn panic_release() {
+    release_all_input(PANIC_DETACHES_GAMEPAD.load(Ordering::Relaxed));
+}
+
 // Function to start the ENet server host
 fn start_enet_server() -> enet::Host<UdpSocket> {
-    let socket =
-        UdpSocket::bind(SocketAddr::from_str(format!("0.0.0.0:{}", ENET_PORT).as_str()).unwrap())
-            .unwrap();
+    let socket = UdpSocket::bind(
+        SocketAddr::from_str(format!("0.0.0.0:{}", crate::ports::current().enet).as_str()).unwrap(),
+    )
+    .unwrap();
 
+    let tuning = *ENET_TUNING.lock().unwrap();
     let host = enet::Host::new(
         socket,
         enet::HostSettings {
             peer_limit: 1,
-            channel_limit: 2,
+            channel_limit: tuning.channel_limit,
+            incoming_bandwidth_limit: bandwidth_limit(tuning.incoming_bandwidth),
+            outgoing_bandwidth_limit: bandwidth_limit(tuning.outgoing_bandwidth),
             ..Default::default()
         },
     )
@@ -117,10 +304,51 @@ pub async fn run_enet_server() -> Result<(), IoError> {
     task::spawn_blocking(|| -> () {
         let mut host = start_enet_server();
         let mut received_events = false;
+        let mut connected_peer = None;
+        let mut last_link_sample = Instant::now();
+        // What the connected client may send, None until it authenticated over the WebSocket.
+        let mut permissions = None;
+        let mut last_permission_check = Instant::now();
+        // The host was created with the current tuning.
+        let mut tuning_generation = ENET_TUNING_GENERATION.load(Ordering::Relaxed);
 
         log::info!("Starting ENet loop.");
 
         loop {
+            crate::affinity::tune_input_thread();
+
+            let generation = ENET_TUNING_GENERATION.load(Ordering::Relaxed);
+            if generation != tuning_generation {
+                tuning_generation = generation;
+                let tuning = *ENET_TUNING.lock().unwrap();
+                host.set_channel_limit(tuning.channel_limit);
+                host.set_bandwidth_limit(
+                    bandwidth_limit(tuning.incoming_bandwidth),
+                    bandwidth_limit(tuning.outgoing_bandwidth),
+                );
+                if let Some(peer) = connected_peer.and_then(|id| host.get_peer_mut(id)) {
+                    apply_peer_tuning(peer, &tuning);
+                }
+                log::info!("ENet tuning changed: {:?}", tuning);
+            }
+
+            if let Some(ip) = INPUT_HANDOVER.lock().unwrap().take() {
+                if let Some(peer) = connected_peer.and_then(|id| host.get_peer_mut(id)) {
+                    if peer.address().map_or(false, |addr| addr.ip() != ip) {
+                        log::info!("Letting go of the input of {:?}.", peer.address());
+                        peer.disconnect(0);
+                    }
+                }
+            }
+
+            if last_permission_check.elapsed() >= PERMISSION_REFRESH_INTERVAL {
+                last_permission_check = Instant::now();
+                permissions = connected_peer
+                    .and_then(|id| host.get_peer(id))
+                    .and_then(|peer| peer.address())
+                    .and_then(|addr| crate::stream::permissions_from_ip(addr.ip()));
+            }
+
             while let Some(event) = host.service().unwrap() {
                 match event {
                     enet::Event::Connect { peer, .. } => {
@@ -129,6 +357,11 @@ pub async fn run_enet_server() -> Result<(), IoError> {
                             peer.id().0,
                             peer.address().unwrap()
                         );
+                        apply_peer_tuning(peer, &ENET_TUNING.lock().unwrap());
+                        connected_peer = Some(peer.id());
+                        permissions = peer
+                            .address()
+                            .and_then(|addr| crate::stream::permissions_from_ip(addr.ip()));
                         init_vigem();
                     }
                     enet::Event::Disconnect { peer, .. } => {
@@ -137,20 +370,48 @@ pub async fn run_enet_server() -> Result<(), IoError> {
                             peer.id().0,
                             peer.address().unwrap()
                         );
+                        connected_peer = None;
+                        permissions = None;
+                        network::reset();
+                        *HEAD_POSE_GUARD.lock().unwrap() = None;
                         deinit_vigem();
                     }
                     enet::Event::Receive {
-                        peer: _,
-                        channel_id: _,
+                        peer,
+                        channel_id,
                         packet,
                     } => {
-                        handle_enet_packet(&packet);
+                        crate::replay::record_input(
+                            packet.data(),
+                            channel_id,
+                            matches!(packet.kind(), enet::PacketKind::Reliable),
+                        );
+                        match permissions {
+                            Some(permissions) if is_input_allowed(packet.data(), permissions) => {
+                                let received = SystemTime::now();
+                                crate::photon::record_input();
+                                handle_input_packet(packet.data());
+                                crate::telemetry::record_input(received);
+                            }
+                            Some(_) => log::debug!("Dropping input the client may not send."),
+                            None => log::debug!(
+                                "Dropping input from {:?}, which did not authenticate.",
+                                peer.address()
+                            ),
+                        }
 
                         received_events = true;
                     }
                 }
             }
 
+            if last_link_sample.elapsed() >= LINK_SAMPLE_INTERVAL {
+                last_link_sample = Instant::now();
+                if let Some(peer) = connected_peer.and_then(|id| host.get_peer(id)) {
+                    network::record_sample(peer.round_trip_time(), peer.packet_loss());
+                }
+            }
+
             // Only sleep if no events were processed in the last cycle,
             // allowing fast reaction when traffic is high.
             if !received_events {
@@ -215,6 +476,27 @@ enum InputType {
     GamepadButtonStart = 20,
     GamepadButtonSelect = 21,
     KeyboardSuper = 22,
+    // Yaw and pitch in radians, from VR clients.
+    HeadPose = 23,
+    // Everything the client holds, sent periodically, see `InputState`.
+    StateSync = 24,
+}
+
+impl InputType {
+    // What a client must be allowed to send this. Head pose moves the cursor.
+    fn permission(&self) -> Permission {
+        match self {
+            InputType::CursorLeftDown
+            | InputType::CursorLeftUp
+            | InputType::CursorLeftClick
+            | InputType::CursorRightClick
+            | InputType::CursorMove
+            | InputType::CursorScroll
+            | InputType::HeadPose => Permission::Mouse,
+            InputType::KeyboardSuper => Permission::Keyboard,
+            _ => Permission::Gamepad,
+        }
+    }
 }
 
 impl TryFrom<u8> for InputType {
@@ -245,23 +527,141 @@ impl TryFrom<u8> for InputType {
             20 => Ok(InputType::GamepadButtonStart),
             21 => Ok(InputType::GamepadButtonSelect),
             22 => Ok(InputType::KeyboardSuper),
+            23 => Ok(InputType::HeadPose),
+            24 => Ok(InputType::StateSync),
             _ => Err("Invalid integer for MyEnum"),
         }
     }
 }
 
+/// The full input state of a client. Sent periodically so a lost release can't leave anything
+/// held down. Packed little-endian after the input type byte, in XInput units.
+#[derive(Debug)]
+struct InputState {
+    // XInput button bits, as in `XButtons`.
+    buttons: u16,
+    left_trigger: u8,
+    right_trigger: u8,
+    thumb_lx: i16,
+    thumb_ly: i16,
+    thumb_rx: i16,
+    thumb_ry: i16,
+    // See SYNC_MOUSE_LEFT.
+    mouse_buttons: u8,
+}
+
+// Input type byte plus the fields of `InputState`.
+const STATE_SYNC_SIZE: usize = 14;
+
+fn read_state_from_cursor(cursor: &mut Cursor<&[u8]>) -> Result<InputState, std::io::Error> {
+    Ok(InputState {
+        buttons: cursor.read_u16::<LittleEndian>()?,
+        left_trigger: cursor.read_u8()?,
+        right_trigger: cursor.read_u8()?,
+        thumb_lx: cursor.read_i16::<LittleEndian>()?,
+        thumb_ly: cursor.read_i16::<LittleEndian>()?,
+        thumb_rx: cursor.read_i16::<LittleEndian>()?,
+        thumb_ry: cursor.read_i16::<LittleEndian>()?,
+        mouse_buttons: cursor.read_u8()?,
+    })
+}
+
+// Makes the injected state match what the client holds. The gamepad takes the state as is,
+// the mouse button is only released, as pressing it late would click wherever the cursor is.
+fn handle_state_sync(packet_data: &[u8]) {
+    if packet_data.len() != STATE_SYNC_SIZE {
+        log::warn!(
+            "State sync size mismatch! Expected {} bytes, got {}",
+            STATE_SYNC_SIZE,
+            packet_data.len()
+        );
+        return;
+    }
+
+    let mut cursor = Cursor::new(&packet_data[1..]);
+    let state = match read_state_from_cursor(&mut cursor) {
+        Ok(state) => state,
+        Err(e) => {
+            log::warn!("Failed to read a state sync: {}", e);
+            return;
+        }
+    };
+
+    if state.mouse_buttons & SYNC_MOUSE_LEFT == 0 && LEFT_BUTTON_DOWN.swap(false, Ordering::Relaxed)
+    {
+        log::warn!("State sync: releasing the left mouse button.");
+        if let Some(enigo) = ENIGO_GUARD.lock().unwrap().as_mut() {
+            let _ = enigo.button(Button::Left, Release);
+        }
+    }
+
+    let mut gamepad_lock = GAMEPAD_GUARD.lock().unwrap();
+    let Some(gamepad) = gamepad_lock.as_mut() else {
+        return;
+    };
+
+    let synced = XGamepad {
+        buttons: vigem::XButtons { raw: state.buttons },
+        left_trigger: state.left_trigger,
+        right_trigger: state.right_trigger,
+        thumb_lx: state.thumb_lx,
+        thumb_ly: state.thumb_ly,
+        thumb_rx: state.thumb_rx,
+        thumb_ry: state.thumb_ry,
+    };
+    if *gamepad == synced {
+        return;
+    }
+    if gamepad.buttons.raw != synced.buttons.raw {
+        log::warn!(
+            "State sync: gamepad buttons {:#06x} -> {:#06x}.",
+            gamepad.buttons.raw,
+            synced.buttons.raw
+        );
+    }
+    *gamepad = synced;
+
+    if let Some(vigem) = VIGEM_GUARD.lock().unwrap().as_mut() {
+        if let Err(e) = vigem.update(gamepad) {
+            log::error!("Failed to update ViGEm target: {:?}", e);
+        }
+    }
+}
+
+// Maps an angle difference to [-PI, PI], so turning past the yaw seam is a small step.
+fn wrap_angle(angle: f32) -> f32 {
+    use std::f32::consts::{PI, TAU};
+    (angle + PI).rem_euclid(TAU) - PI
+}
+
+// Whether a client with `permissions` may send this packet. Malformed packets pass, they are
+// rejected when handled.
+fn is_input_allowed(packet_data: &[u8], permissions: Permissions) -> bool {
+    packet_data
+        .first()
+        .and_then(|&input_type| InputType::try_from(input_type).ok())
+        .map_or(true, |input_type| {
+            permissions.allows(input_type.permission())
+        })
+}
+
 // --- ENet Input Handling Function ---
-fn handle_enet_packet(packet: &enet::Packet) {
+/// Applies one input packet, from ENet or the local input pipe.
+pub(crate) fn handle_input_packet(packet_data: &[u8]) {
     // 1. Check if the packet size matches the struct size.
-    let packet_data = packet.data();
+    if packet_data.first() == Some(&(InputType::StateSync as u8)) {
+        handle_state_sync(packet_data);
+        return;
+    }
     if packet_data.len() != size_of::<InputCommand>() {
-        eprintln!(
+        log::warn!(
             "Received packet size mismatch! Expected {} bytes, got {}",
             size_of::<InputCommand>(),
             packet_data.len()
         );
         return;
     }
+    crate::timeline::record_input_event();
 
     // println!("Received packet data: {:?}", packet_data);
 
@@ -281,12 +681,13 @@ fn handle_enet_packet(packet: &enet::Packet) {
     let command = match read_command_from_cursor(&mut cursor) {
         Ok(c) => c,
         Err(e) => {
-            eprintln!("Failed to deserialize packet with byte order: {}", e);
+            log::warn!("Failed to deserialize packet with byte order: {}", e);
             return;
         }
     };
 
     let native_resolution;
+    let capture_origin;
     let stream_resolution;
     {
         let state_lock = STREAMING_STATE_GUARD.lock().unwrap();
@@ -294,6 +695,7 @@ fn handle_enet_packet(packet: &enet::Packet) {
             .as_ref()
             .expect("Streaming state was not initialized!");
         native_resolution = state.native_resolution;
+        capture_origin = state.capture_origin;
         if let Some(config) = state.stream_config.as_ref() {
             stream_resolution = config.resolution;
         } else {
@@ -304,8 +706,14 @@ fn handle_enet_packet(packet: &enet::Packet) {
     let x: f32 = f32::from_bits(command.data0);
     let y: f32 = f32::from_bits(command.data1);
 
-    let x_coord = x / stream_resolution.0 as f32 * native_resolution.0 as f32;
-    let y_coord = y / stream_resolution.1 as f32 * native_resolution.1 as f32;
+    // The stream may show only part of the desktop.
+    let (x_capture, y_capture) = view::map_to_capture(
+        x / stream_resolution.0 as f32,
+        y / stream_resolution.1 as f32,
+    );
+    // Positions are on the virtual desktop, where other monitors and windows are offset.
+    let x_coord = capture_origin.0 as f32 + x_capture * native_resolution.0 as f32;
+    let y_coord = capture_origin.1 as f32 + y_capture * native_resolution.1 as f32;
 
     // println!("Received input type: {:?}", command.input_type);
     // println!("Received input position: {:?}, {:?}", x, y);
@@ -313,7 +721,10 @@ fn handle_enet_packet(packet: &enet::Packet) {
     let input_type = InputType::try_from(command.input_type).unwrap();
 
     let mut enigo_lock = ENIGO_GUARD.lock().unwrap();
-    let enigo = enigo_lock.as_mut().expect("Enigo was not initialized!");
+    // The preflight reports why, see `preflight`.
+    let Some(enigo) = enigo_lock.as_mut() else {
+        return;
+    };
 
     let mut gamepad_lock = GAMEPAD_GUARD.lock().unwrap();
 
@@ -326,6 +737,7 @@ fn handle_enet_packet(packet: &enet::Packet) {
                 .move_mouse(x_coord as i32, y_coord as i32, Abs)
                 .unwrap();
             enigo.button(Button::Left, Press).unwrap();
+            LEFT_BUTTON_DOWN.store(true, Ordering::Relaxed);
             log::debug!("CursorLeftDown pos {},{}", x_coord as i32, y_coord as i32);
         }
         InputType::CursorLeftUp => {
@@ -333,6 +745,7 @@ fn handle_enet_packet(packet: &enet::Packet) {
                 .move_mouse(x_coord as i32, y_coord as i32, Abs)
                 .unwrap();
             enigo.button(Button::Left, Release).unwrap();
+            LEFT_BUTTON_DOWN.store(false, Ordering::Relaxed);
             log::debug!("CursorLeftUp pos {},{}", x_coord as i32, y_coord as i32);
         }
         InputType::CursorMove => {
@@ -373,6 +786,20 @@ fn handle_enet_packet(packet: &enet::Packet) {
 
             enigo.key(Key::Meta, Direction::Click).unwrap();
         }
+        InputType::HeadPose => {
+            let mut head_pose = HEAD_POSE_GUARD.lock().unwrap();
+            if let Some((last_yaw, last_pitch)) = *head_pose {
+                let dx = wrap_angle(x - last_yaw) * HEAD_POSE_PIXELS_PER_RADIAN;
+                let dy = wrap_angle(y - last_pitch) * HEAD_POSE_PIXELS_PER_RADIAN;
+                if dx.abs() >= 1.0 || dy.abs() >= 1.0 {
+                    enigo.move_mouse(dx as i32, -dy as i32, Rel).unwrap();
+                } else {
+                    // Keep sub-pixel turns accumulating.
+                    return;
+                }
+            }
+            *head_pose = Some((x, y));
+        }
         _ => {
             // Gamepad inputs
             if let Some(gamepad) = gamepad_lock.as_mut() {
@@ -478,7 +905,7 @@ fn handle_enet_packet(packet: &enet::Packet) {
                     // Update the target
                     let result = vigem.update(&gamepad);
                     if let Err(e) = result {
-                        eprintln!("Failed to update ViGEm target: {:?}", e);
+                        log::error!("Failed to update ViGEm target: {:?}", e);
                     }
                 }
             }
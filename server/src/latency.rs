@@ -0,0 +1,281 @@
+use crate::control::{broadcast_event, ControlEvent};
+use crate::stream::{is_pipeline_running, restart_gstreamer_pipeline, STREAMING_STATE_GUARD};
+use gst::prelude::*;
+use gstreamer as gst;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+/// One-click tradeoffs between latency and picture quality,
+/// so users don't have to tune every pipeline knob themselves.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LatencyPreset {
+    UltraLow,
+    Balanced,
+    Quality,
+}
+
+/// Pipeline settings coordinated by a preset.
+pub struct PresetParams {
+    // Maximum GOP length in frames.
+    pub key_int_max: u32,
+    pub x264_speed_preset: &'static str,
+    pub amf_preset: &'static str,
+    pub amf_usage: &'static str,
+    pub nvenc_preset: &'static str,
+    // p1 is the fastest, p7 the best quality.
+    pub nvenc_av1_preset: &'static str,
+    // 1 is the best quality, 7 the fastest.
+    pub qsv_target_usage: u32,
+    // 0 is the best quality, 13 the fastest.
+    pub svtav1_preset: u32,
+    // How many raw frames may wait in front of the encoder.
+    pub queue_max_buffers: u32,
+    // How many captured frames may wait for conversion while the next one is captured.
+    pub convert_queue_max_buffers: u32,
+    // Opus frame duration in ms.
+    pub audio_frame_size: u32,
+    // Most delay the host pipeline may add before it is overridden, in ms.
+    pub max_pipeline_latency_ms: u64,
+}
+
+impl LatencyPreset {
+    pub const ALL: [LatencyPreset; 3] = [
+        LatencyPreset::UltraLow,
+        LatencyPreset::Balanced,
+        LatencyPreset::Quality,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LatencyPreset::UltraLow => "ultra_low",
+            LatencyPreset::Balanced => "balanced",
+            LatencyPreset::Quality => "quality",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "ultra_low" => Some(LatencyPreset::UltraLow),
+            "balanced" => Some(LatencyPreset::Balanced),
+            "quality" => Some(LatencyPreset::Quality),
+            _ => None,
+        }
+    }
+
+    /// The next preset towards lower latency, which also asks less of the encoder.
+    pub fn faster(&self) -> Option<LatencyPreset> {
+        match self {
+            LatencyPreset::UltraLow => None,
+            LatencyPreset::Balanced => Some(LatencyPreset::UltraLow),
+            LatencyPreset::Quality => Some(LatencyPreset::Balanced),
+        }
+    }
+
+    pub fn params(&self) -> PresetParams {
+        match self {
+            LatencyPreset::UltraLow => PresetParams {
+                key_int_max: 30,
+                x264_speed_preset: "ultrafast",
+                amf_preset: "speed",
+                amf_usage: "ultra-low-latency",
+                nvenc_preset: "low-latency-hp",
+                nvenc_av1_preset: "p1",
+                qsv_target_usage: 7,
+                svtav1_preset: 12,
+                queue_max_buffers: 1,
+                convert_queue_max_buffers: 1,
+                audio_frame_size: 10,
+                max_pipeline_latency_ms: 50,
+            },
+            LatencyPreset::Balanced => PresetParams {
+                key_int_max: 60,
+                x264_speed_preset: "superfast",
+                amf_preset: "balanced",
+                amf_usage: "low-latency",
+                nvenc_preset: "low-latency",
+                nvenc_av1_preset: "p4",
+                qsv_target_usage: 4,
+                svtav1_preset: 10,
+                queue_max_buffers: 2,
+                convert_queue_max_buffers: 1,
+                audio_frame_size: 10,
+                max_pipeline_latency_ms: 100,
+            },
+            LatencyPreset::Quality => PresetParams {
+                key_int_max: 120,
+                x264_speed_preset: "veryfast",
+                amf_preset: "quality",
+                amf_usage: "low-latency",
+                nvenc_preset: "low-latency-hq",
+                nvenc_av1_preset: "p6",
+                qsv_target_usage: 1,
+                svtav1_preset: 8,
+                queue_max_buffers: 4,
+                convert_queue_max_buffers: 2,
+                audio_frame_size: 20,
+                max_pipeline_latency_ms: 200,
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for LatencyPreset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LatencyPreset::UltraLow => write!(f, "Ultra-low latency"),
+            LatencyPreset::Balanced => write!(f, "Balanced"),
+            LatencyPreset::Quality => write!(f, "Quality"),
+        }
+    }
+}
+
+/// Which buffers a full queue throws away.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueueLeaky {
+    // Block upstream instead, capture waits for the encoder.
+    No,
+    // Drop the newest frames.
+    Upstream,
+    // Drop the oldest frames, keeping latency lowest.
+    Downstream,
+}
+
+impl QueueLeaky {
+    pub const ALL: [QueueLeaky; 3] = [QueueLeaky::No, QueueLeaky::Upstream, QueueLeaky::Downstream];
+
+    /// The value of the `leaky` property of `queue`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QueueLeaky::No => "no",
+            QueueLeaky::Upstream => "upstream",
+            QueueLeaky::Downstream => "downstream",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "no" => Some(QueueLeaky::No),
+            "upstream" => Some(QueueLeaky::Upstream),
+            "downstream" => Some(QueueLeaky::Downstream),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for QueueLeaky {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueueLeaky::No => write!(f, "Wait for the encoder"),
+            QueueLeaky::Upstream => write!(f, "Drop new frames"),
+            QueueLeaky::Downstream => write!(f, "Drop old frames"),
+        }
+    }
+}
+
+/// Selects a preset by hand, which stops automatic switching. Blocking.
+pub fn pin_preset(preset: LatencyPreset) {
+    {
+        let mut guard = STREAMING_STATE_GUARD.lock().unwrap();
+        if let Some(state) = guard.as_mut() {
+            state.auto_latency_preset = false;
+        }
+    }
+
+    apply_preset(preset);
+}
+
+/// Switches the active preset and rebuilds a running pipeline with it. Blocking.
+pub fn apply_preset(preset: LatencyPreset) {
+    {
+        let mut guard = STREAMING_STATE_GUARD.lock().unwrap();
+        let Some(state) = guard.as_mut() else {
+            return;
+        };
+        if state.latency_preset == preset {
+            return;
+        }
+        state.latency_preset = preset;
+    }
+
+    info!("Latency preset switched to {}.", preset);
+    broadcast_event(&ControlEvent::LatencyPresetChanged { preset });
+
+    if is_pipeline_running() {
+        restart_gstreamer_pipeline();
+    }
+}
+
+/// What the last latency query of the pipeline reported.
+#[derive(Debug, Clone, Copy)]
+pub struct PipelineLatency {
+    pub live: bool,
+    pub min: gst::ClockTime,
+    pub max: Option<gst::ClockTime>,
+    // Whether the pipeline runs with a lower latency than it asked for.
+    pub capped: bool,
+}
+
+static PIPELINE_LATENCY: Mutex<Option<PipelineLatency>> = Mutex::new(None);
+
+/// Queries how much delay the elements of the pipeline add and configures the pipeline with it,
+/// capped by the latency preset. Called whenever an element reports a latency change.
+pub fn update_pipeline_latency(pipeline: &gst::Pipeline) {
+    if let Err(e) = pipeline.recalculate_latency() {
+        warn!("Failed to recalculate the pipeline latency: {}", e);
+    }
+
+    let mut query = gst::query::Latency::new();
+    if !pipeline.query(&mut query) {
+        warn!("The pipeline did not answer the latency query.");
+        return;
+    }
+    let (live, min, max) = query.result();
+
+    let cap = {
+        let guard = STREAMING_STATE_GUARD.lock().unwrap();
+        guard
+            .as_ref()
+            .map_or(LatencyPreset::UltraLow, |state| state.latency_preset)
+    }
+    .params()
+    .max_pipeline_latency_ms;
+    let cap = gst::ClockTime::from_mseconds(cap);
+
+    let capped = live && min > cap;
+    let latency = if capped { Some(cap) } else { None };
+    if pipeline.latency() != latency {
+        if capped {
+            warn!(
+                "The pipeline asks for {} ms of latency, capping it at {} ms.",
+                min.mseconds(),
+                cap.mseconds()
+            );
+        }
+        pipeline.set_latency(latency);
+    }
+
+    info!(
+        "Pipeline latency: live {}, min {} ms, max {:?} ms.",
+        live,
+        min.mseconds(),
+        max.map(|max| max.mseconds())
+    );
+    *PIPELINE_LATENCY.lock().unwrap() = Some(PipelineLatency {
+        live,
+        min,
+        max,
+        capped,
+    });
+    crate::gui::request_repaint();
+}
+
+/// The host-side latency of the running pipeline, for the GUI.
+pub fn pipeline_latency() -> Option<PipelineLatency> {
+    *PIPELINE_LATENCY.lock().unwrap()
+}
+
+pub fn reset_pipeline_latency() {
+    *PIPELINE_LATENCY.lock().unwrap() = None;
+}
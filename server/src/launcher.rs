@@ -0,0 +1,158 @@
+use crate::audio;
+use crate::control::{broadcast_event, ControlEvent};
+use crate::stream::STREAMING_STATE_GUARD;
+use crate::watchdog;
+use log::info;
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+const STEAM_REG_KEY: &str = "HKEY_CURRENT_USER\\Software\\Valve\\Steam";
+const GAME_POLL_INTERVAL_SECONDS: u64 = 2;
+
+// Whether the Steam game watcher thread is running.
+static WATCHER_RUNNING: Mutex<bool> = Mutex::new(false);
+// Executables the host lets clients launch.
+static ALLOWED_EXECUTABLES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+// Reads a value under the Steam registry key.
+// `reg query` prints lines like "    SteamExe    REG_SZ    c:/program files (x86)/steam/steam.exe".
+fn query_steam_value(name: &str) -> Option<String> {
+    let output = Command::new("reg")
+        .args(["query", STEAM_REG_KEY, "/v", name])
+        .output()
+        .ok()?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout.lines().find_map(|line| {
+        let rest = line.trim().strip_prefix(name)?;
+        let (_value_type, value) = rest.trim_start().split_once(char::is_whitespace)?;
+        Some(value.trim().to_string())
+    })
+}
+
+/// Path to steam.exe, if Steam is installed for the current user.
+pub fn steam_exe() -> Option<PathBuf> {
+    let path = PathBuf::from(query_steam_value("SteamExe")?);
+    path.exists().then_some(path)
+}
+
+/// Steam installation directory, if Steam is installed for the current user.
+pub fn steam_dir() -> Option<PathBuf> {
+    let path = PathBuf::from(query_steam_value("SteamPath")?);
+    path.is_dir().then_some(path)
+}
+
+/// App ID of the game Steam reports as running.
+pub fn running_steam_app() -> Option<u32> {
+    let value = query_steam_value("RunningAppID")?;
+    let app_id = u32::from_str_radix(value.trim_start_matches("0x"), 16).ok()?;
+    (app_id != 0).then_some(app_id)
+}
+
+fn open_steam_url(url: &str) -> std::io::Result<()> {
+    let exe = steam_exe().ok_or(Error::new(ErrorKind::NotFound, "Steam is not installed"))?;
+    Command::new(exe).arg(url).spawn()?;
+    info!("Opened {}", url);
+    Ok(())
+}
+
+pub fn launch_big_picture() -> std::io::Result<()> {
+    open_steam_url("steam://open/bigpicture")?;
+    start_game_watcher();
+    Ok(())
+}
+
+pub fn launch_steam_game(app_id: u32) -> std::io::Result<()> {
+    open_steam_url(&format!("steam://rungameid/{}", app_id))?;
+    start_game_watcher();
+    watchdog::watch_steam_app(app_id);
+    Ok(())
+}
+
+/// Sets which executables clients may launch, by full path.
+pub fn allow_executables(paths: &[String]) {
+    *ALLOWED_EXECUTABLES.lock().unwrap() = paths.iter().map(|path| normalize_path(path)).collect();
+}
+
+// Windows paths ignore case and take either slash.
+fn normalize_path(path: &str) -> String {
+    path.trim().replace('/', "\\").to_lowercase()
+}
+
+/// Starts an executable the host allowed as the session app.
+pub fn launch_executable(path: &str, args: &[String]) -> std::io::Result<()> {
+    if !ALLOWED_EXECUTABLES
+        .lock()
+        .unwrap()
+        .contains(&normalize_path(path))
+    {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("{} is not among the executables clients may launch", path),
+        ));
+    }
+    let child = Command::new(path).args(args).spawn()?;
+    info!("Launched {} (pid {}).", path, child.id());
+    audio::route_to_stream_device(child.id());
+    watchdog::watch_process(path.to_string(), child);
+    Ok(())
+}
+
+pub fn launcher_info() -> ControlEvent {
+    ControlEvent::LauncherInfo {
+        steam_installed: steam_exe().is_some(),
+        running_app_id: running_steam_app(),
+    }
+}
+
+// Polls Steam for the running game and reports start/exit to clients.
+// The thread exits once all clients are gone.
+fn start_game_watcher() {
+    {
+        let mut running = WATCHER_RUNNING.lock().unwrap();
+        if *running {
+            return;
+        }
+        *running = true;
+    }
+
+    thread::spawn(|| {
+        let mut current_app = running_steam_app();
+
+        loop {
+            thread::sleep(Duration::from_secs(GAME_POLL_INTERVAL_SECONDS));
+
+            let has_peers = {
+                let guard = STREAMING_STATE_GUARD.lock().unwrap();
+                guard
+                    .as_ref()
+                    .map_or(false, |state| !state.peers.is_empty())
+            };
+            if !has_peers {
+                break;
+            }
+
+            let app = running_steam_app();
+            if app == current_app {
+                continue;
+            }
+
+            if let Some(app_id) = current_app {
+                info!("Steam game {} exited.", app_id);
+                broadcast_event(&ControlEvent::GameExited { app_id });
+            }
+            if let Some(app_id) = app {
+                info!("Steam game {} started.", app_id);
+                broadcast_event(&ControlEvent::GameStarted { app_id });
+            }
+            current_app = app;
+        }
+
+        info!("No clients left, stopping Steam game watcher.");
+        *WATCHER_RUNNING.lock().unwrap() = false;
+    });
+}
@@ -0,0 +1,232 @@
+use crate::launcher::steam_dir;
+use log::{info, warn};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const EPIC_MANIFEST_DIR: &str = "C:\\ProgramData\\Epic\\EpicGamesLauncher\\Data\\Manifests";
+const XBOX_GAMES_DIR: &str = "C:\\XboxGames";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GameSource {
+    Steam,
+    Epic,
+    Xbox,
+}
+
+impl std::fmt::Display for GameSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GameSource::Steam => write!(f, "Steam"),
+            GameSource::Epic => write!(f, "Epic"),
+            GameSource::Xbox => write!(f, "Xbox"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GameEntry {
+    pub source: GameSource,
+    pub id: String,
+    pub name: String,
+    pub artwork_path: Option<PathBuf>,
+}
+
+// Result of the last scan, shared by the GUI and the control channel.
+pub static GAME_LIBRARY: Mutex<Vec<GameEntry>> = Mutex::new(Vec::new());
+
+/// Rescans all launchers and replaces the shared library. Blocking.
+pub fn refresh() {
+    let mut games = Vec::new();
+    games.extend(scan_steam());
+    games.extend(scan_epic());
+    games.extend(scan_xbox());
+    games.sort_by_key(|game| game.name.to_lowercase());
+
+    info!("Game library scan found {} titles.", games.len());
+
+    *GAME_LIBRARY.lock().unwrap() = games;
+}
+
+/// Returns the scanned games, scanning first if nothing has been scanned yet.
+pub fn list_games(force_refresh: bool) -> Vec<GameEntry> {
+    if force_refresh || GAME_LIBRARY.lock().unwrap().is_empty() {
+        refresh();
+    }
+    GAME_LIBRARY.lock().unwrap().clone()
+}
+
+// Extracts the quoted key/value pair from a VDF/ACF line such as `"name"		"Portal 2"`.
+fn vdf_pair(line: &str) -> Option<(&str, &str)> {
+    let mut parts = line.split('"').skip(1).step_by(2);
+    Some((parts.next()?, parts.next()?))
+}
+
+fn steam_library_dirs(steam_dir: &Path) -> Vec<PathBuf> {
+    let mut dirs = vec![steam_dir.to_path_buf()];
+
+    let vdf = steam_dir.join("steamapps").join("libraryfolders.vdf");
+    if let Ok(contents) = fs::read_to_string(vdf) {
+        for (key, value) in contents.lines().filter_map(vdf_pair) {
+            if key == "path" {
+                // Backslashes are escaped in VDF.
+                let dir = PathBuf::from(value.replace("\\\\", "\\"));
+                if !dirs.contains(&dir) {
+                    dirs.push(dir);
+                }
+            }
+        }
+    }
+
+    dirs
+}
+
+fn scan_steam() -> Vec<GameEntry> {
+    let Some(steam_dir) = steam_dir() else {
+        return Vec::new();
+    };
+
+    let mut games = Vec::new();
+
+    for library in steam_library_dirs(&steam_dir) {
+        let Ok(entries) = fs::read_dir(library.join("steamapps")) else {
+            continue;
+        };
+
+        for entry in entries.flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if !file_name.starts_with("appmanifest_") || !file_name.ends_with(".acf") {
+                continue;
+            }
+
+            let Ok(contents) = fs::read_to_string(entry.path()) else {
+                continue;
+            };
+
+            let mut id = None;
+            let mut name = None;
+            for (key, value) in contents.lines().filter_map(vdf_pair) {
+                match key {
+                    "appid" if id.is_none() => id = Some(value.to_string()),
+                    "name" if name.is_none() => name = Some(value.to_string()),
+                    _ => {}
+                }
+            }
+
+            let (Some(id), Some(name)) = (id, name) else {
+                warn!("Skipping malformed Steam manifest {}", file_name);
+                continue;
+            };
+
+            // Steamworks Common Redistributables is not a game.
+            if id == "228980" {
+                continue;
+            }
+
+            let artwork = steam_dir
+                .join("appcache")
+                .join("librarycache")
+                .join(format!("{}_library_600x900.jpg", id));
+
+            games.push(GameEntry {
+                source: GameSource::Steam,
+                id,
+                name,
+                artwork_path: artwork.exists().then_some(artwork),
+            });
+        }
+    }
+
+    games
+}
+
+fn scan_epic() -> Vec<GameEntry> {
+    let Ok(entries) = fs::read_dir(EPIC_MANIFEST_DIR) else {
+        return Vec::new();
+    };
+
+    let mut games = Vec::new();
+
+    for entry in entries.flatten() {
+        if entry.path().extension().map_or(true, |ext| ext != "item") {
+            continue;
+        }
+
+        let Ok(contents) = fs::read_to_string(entry.path()) else {
+            continue;
+        };
+        let Ok(json_value) = serde_json::from_str::<serde_json::Value>(&contents) else {
+            continue;
+        };
+
+        let (Some(id), Some(name)) = (
+            json_value["AppName"].as_str(),
+            json_value["DisplayName"].as_str(),
+        ) else {
+            continue;
+        };
+
+        games.push(GameEntry {
+            source: GameSource::Epic,
+            id: id.to_string(),
+            name: name.to_string(),
+            artwork_path: None,
+        });
+    }
+
+    games
+}
+
+// Returns the text between `<tag>` and `</tag>`.
+fn xml_element<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{}>", tag))? + tag.len() + 2;
+    let end = start + xml[start..].find(&format!("</{}>", tag))?;
+    Some(xml[start..end].trim())
+}
+
+// Returns the value of `attribute` on the first `<element ...>`.
+fn xml_attribute<'a>(xml: &'a str, element: &str, attribute: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{} ", element))?;
+    let tag = &xml[start..start + xml[start..].find('>')?];
+    let value_start = tag.find(&format!("{}=\"", attribute))? + attribute.len() + 2;
+    let value_end = value_start + tag[value_start..].find('"')?;
+    Some(&tag[value_start..value_end])
+}
+
+fn scan_xbox() -> Vec<GameEntry> {
+    let Ok(entries) = fs::read_dir(XBOX_GAMES_DIR) else {
+        return Vec::new();
+    };
+
+    let mut games = Vec::new();
+
+    for entry in entries.flatten() {
+        let content_dir = entry.path().join("Content");
+        let Ok(manifest) = fs::read_to_string(content_dir.join("appxmanifest.xml")) else {
+            continue;
+        };
+
+        let Some(id) = xml_attribute(&manifest, "Identity", "Name") else {
+            continue;
+        };
+
+        // Display names may be resource references like "ms-resource:AppName", use the folder name then.
+        let name = match xml_element(&manifest, "DisplayName") {
+            Some(name) if !name.starts_with("ms-resource:") => name.to_string(),
+            _ => entry.file_name().to_string_lossy().to_string(),
+        };
+
+        let artwork = xml_element(&manifest, "Logo").map(|logo| content_dir.join(logo));
+
+        games.push(GameEntry {
+            source: GameSource::Xbox,
+            id: id.to_string(),
+            name,
+            artwork_path: artwork.filter(|path| path.exists()),
+        });
+    }
+
+    games
+}
@@ -0,0 +1,134 @@
+use crate::pipeline::{add_chain, make, BuildError};
+use gst::prelude::*;
+use gstreamer as gst;
+use log::{info, warn};
+use std::fs::File;
+use std::io::Read;
+use std::os::windows::io::FromRawHandle;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use windows::core::HSTRING;
+use windows::Win32::Foundation::ERROR_MORE_DATA;
+use windows::Win32::Storage::FileSystem::PIPE_ACCESS_INBOUND;
+use windows::Win32::System::Pipes::{
+    ConnectNamedPipe, CreateNamedPipeW, PIPE_READMODE_MESSAGE, PIPE_REJECT_REMOTE_CLIENTS,
+    PIPE_TYPE_MESSAGE, PIPE_WAIT,
+};
+
+/// Where local clients read raw frames, with `win32ipcvideosrc pipe-name=...`.
+pub const VIDEO_PIPE_NAME: &str = r"\\.\pipe\rstream.video";
+/// Where local clients write input, one packet of the ENet input protocol per message.
+pub const INPUT_PIPE_NAME: &str = r"\\.\pipe\rstream.input";
+
+// Larger than any input packet, a longer message is cut off and ignored.
+const MAX_MESSAGE_SIZE: usize = 64;
+// How long the pipe server waits before checking again whether local mode got enabled.
+const IDLE_INTERVAL: Duration = Duration::from_secs(1);
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Whether streams are offered through shared memory to clients on this machine.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Adds the branch that hands the stream to local clients before it is encoded to `bin`, NV12
+/// frames at the stream size in system memory. Returns the tee it splits off from, for capture to
+/// link in front of the encoder. None unless local mode is enabled.
+pub fn add_video_branch(
+    bin: &gst::Bin,
+    d3d11_memory: bool,
+) -> Result<Option<gst::Element>, BuildError> {
+    if !is_enabled() {
+        return Ok(None);
+    }
+    let tee = make("tee")?.name("rawtee").build()?;
+    // A local client that stops reading must not stall the stream.
+    let mut branch = vec![make("queue")?
+        .property("max-size-buffers", 2u32)
+        .property("max-size-bytes", 0u32)
+        .property("max-size-time", 0u64)
+        .property_from_str("leaky", "downstream")
+        .build()?];
+    if d3d11_memory {
+        branch.push(make("d3d11download")?.build()?);
+    }
+    branch.push(
+        make("win32ipcvideosink")?
+            .name("localsink")
+            .property("sync", false)
+            .property("pipe-name", VIDEO_PIPE_NAME)
+            .build()?,
+    );
+    bin.add(&tee)?;
+    add_chain(bin, Some(&tee), &branch)?;
+    Ok(Some(tee))
+}
+
+/// Serves the input pipe, so local clients can send input without a network round trip.
+pub fn start_input_pipe() {
+    thread::spawn(|| loop {
+        if !is_enabled() {
+            thread::sleep(IDLE_INTERVAL);
+            continue;
+        }
+
+        let pipe = unsafe {
+            CreateNamedPipeW(
+                &HSTRING::from(INPUT_PIPE_NAME),
+                PIPE_ACCESS_INBOUND,
+                PIPE_TYPE_MESSAGE | PIPE_READMODE_MESSAGE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                1,
+                0,
+                MAX_MESSAGE_SIZE as u32,
+                0,
+                None,
+            )
+        };
+        if pipe.is_invalid() {
+            warn!(
+                "Failed to create the input pipe {}: {}",
+                INPUT_PIPE_NAME,
+                windows::core::Error::from_win32()
+            );
+            thread::sleep(IDLE_INTERVAL);
+            continue;
+        }
+
+        // Fails with ERROR_PIPE_CONNECTED if the client was quicker, which is fine.
+        let _ = unsafe { ConnectNamedPipe(pipe, None) };
+        // Owns the handle from here on and closes it.
+        let mut pipe = unsafe { File::from_raw_handle(pipe.0 as _) };
+        info!("Local client connected to {}.", INPUT_PIPE_NAME);
+        crate::input::init_vigem();
+
+        let mut message = [0u8; MAX_MESSAGE_SIZE];
+        // Whether the rest of an oversized message is still to come.
+        let mut truncated = false;
+        loop {
+            match pipe.read(&mut message) {
+                Ok(0) => break,
+                Ok(_) if truncated => truncated = false,
+                Ok(length) => {
+                    if !is_enabled() {
+                        break;
+                    }
+                    let received = SystemTime::now();
+                    crate::input::handle_input_packet(&message[..length]);
+                    crate::telemetry::record_input(received);
+                }
+                // A message that did not fit, its rest follows in the next reads.
+                Err(e) if e.raw_os_error() == Some(ERROR_MORE_DATA.0 as i32) => truncated = true,
+                Err(_) => break,
+            }
+        }
+
+        info!("Local client disconnected from {}.", INPUT_PIPE_NAME);
+        crate::input::deinit_vigem();
+    });
+}
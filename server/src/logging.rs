@@ -0,0 +1,221 @@
+use chrono::Local;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Identical lines within this window are collapsed into one summary.
+const REPEAT_WINDOW: Duration = Duration::from_secs(10);
+// Distinct lines tracked at once. Beyond that, lines pass through as they are.
+const MAX_TRACKED_LINES: usize = 256;
+// Lines kept for the log viewer by default, the oldest are dropped first.
+pub const DEFAULT_VIEWER_LINES: u32 = 2000;
+// The viewer shows this crate's lines down to info, whatever `RUST_LOG` says.
+const VIEWER_TARGET: &str = env!("CARGO_CRATE_NAME");
+
+/// A line for the log viewer.
+#[derive(Debug, Clone)]
+pub struct LogLine {
+    pub time: String,
+    pub level: Level,
+    // Module path within the crate.
+    pub target: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for LogLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {:<5} {}: {}",
+            self.time, self.level, self.target, self.message
+        )
+    }
+}
+
+static VIEWER_LINES: Mutex<VecDeque<LogLine>> = Mutex::new(VecDeque::new());
+static VIEWER_CAPACITY: AtomicUsize = AtomicUsize::new(DEFAULT_VIEWER_LINES as usize);
+
+struct Repeat {
+    first_seen: Instant,
+    // Occurrences after the first, which were held back.
+    suppressed: u32,
+}
+
+fn shows_in_viewer(metadata: &Metadata<'_>) -> bool {
+    metadata.level() <= Level::Info && metadata.target().starts_with(VIEWER_TARGET)
+}
+
+/// Passes each line once per window and summarizes the repeats when the window ends, so a
+/// failing call in a loop cannot flood the log.
+struct DedupLogger {
+    inner: env_logger::Logger,
+    repeats: Mutex<HashMap<(Level, String, String), Repeat>>,
+}
+
+impl DedupLogger {
+    // Forgets the lines whose window ended, returning those that were repeated.
+    fn take_expired(
+        repeats: &mut HashMap<(Level, String, String), Repeat>,
+    ) -> Vec<((Level, String, String), u32)> {
+        let mut summaries = Vec::new();
+        repeats.retain(|key, repeat| {
+            if repeat.first_seen.elapsed() < REPEAT_WINDOW {
+                return true;
+            }
+            if repeat.suppressed > 0 {
+                summaries.push((key.clone(), repeat.suppressed));
+            }
+            false
+        });
+        summaries
+    }
+
+    // Writes a line that made it past deduplication to the console and the viewer.
+    fn output(&self, record: &Record<'_>) {
+        self.inner.log(record);
+
+        if shows_in_viewer(record.metadata()) {
+            let target = record.target();
+            let mut lines = VIEWER_LINES.lock().unwrap();
+            while lines.len() >= VIEWER_CAPACITY.load(Ordering::Relaxed) {
+                lines.pop_front();
+            }
+            lines.push_back(LogLine {
+                time: Local::now().format("%H:%M:%S").to_string(),
+                level: record.level(),
+                target: target
+                    .strip_prefix(VIEWER_TARGET)
+                    .map_or(target, |path| path.trim_start_matches("::"))
+                    .to_string(),
+                message: record.args().to_string(),
+            });
+        }
+    }
+
+    fn log_summaries(&self, summaries: Vec<((Level, String, String), u32)>) {
+        for ((level, target, message), suppressed) in summaries {
+            self.output(
+                &Record::builder()
+                    .level(level)
+                    .target(&target)
+                    .args(format_args!(
+                        "{} x{} in last {}s",
+                        message,
+                        suppressed + 1,
+                        REPEAT_WINDOW.as_secs()
+                    ))
+                    .build(),
+            );
+        }
+    }
+}
+
+impl Log for DedupLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.inner.enabled(metadata) || shows_in_viewer(metadata)
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let key = (
+            record.level(),
+            record.target().to_string(),
+            record.args().to_string(),
+        );
+
+        let (summaries, repeated) = {
+            let mut repeats = self.repeats.lock().unwrap();
+            let summaries = Self::take_expired(&mut repeats);
+
+            let repeated = match repeats.get_mut(&key) {
+                Some(repeat) => {
+                    repeat.suppressed += 1;
+                    true
+                }
+                None => {
+                    if repeats.len() < MAX_TRACKED_LINES {
+                        repeats.insert(
+                            key,
+                            Repeat {
+                                first_seen: Instant::now(),
+                                suppressed: 0,
+                            },
+                        );
+                    }
+                    false
+                }
+            };
+            (summaries, repeated)
+        };
+
+        self.log_summaries(summaries);
+        if !repeated {
+            self.output(record);
+        }
+    }
+
+    fn flush(&self) {
+        let summaries = Self::take_expired(&mut self.repeats.lock().unwrap());
+        self.log_summaries(summaries);
+        self.inner.flush();
+    }
+}
+
+/// Sets up logging, configured by `RUST_LOG` as before, with repeated lines collapsed and
+/// recent ones kept for the log viewer.
+pub fn init() {
+    let inner = env_logger::Builder::from_default_env().build();
+    let max_level = inner.filter().max(LevelFilter::Info);
+
+    let logger = DedupLogger {
+        inner,
+        repeats: Mutex::new(HashMap::new()),
+    };
+    if log::set_boxed_logger(Box::new(logger)).is_err() {
+        return;
+    }
+    log::set_max_level(max_level);
+
+    // Summarizes bursts that ended with no other line to trigger it.
+    thread::spawn(|| loop {
+        thread::sleep(REPEAT_WINDOW);
+        log::logger().flush();
+    });
+}
+
+/// The lines kept for the viewer at `level` or more severe, containing `search` if not empty.
+pub fn viewer_lines(level: LevelFilter, search: &str) -> Vec<LogLine> {
+    let search = search.to_lowercase();
+    VIEWER_LINES
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|line| line.level <= level)
+        .filter(|line| {
+            search.is_empty()
+                || line.message.to_lowercase().contains(&search)
+                || line.target.to_lowercase().contains(&search)
+        })
+        .cloned()
+        .collect()
+}
+
+/// Changes how many lines the viewer keeps, dropping the oldest ones beyond that.
+pub fn set_viewer_capacity(lines: u32) {
+    let capacity = lines.max(1) as usize;
+    VIEWER_CAPACITY.store(capacity, Ordering::Relaxed);
+
+    let mut viewer_lines = VIEWER_LINES.lock().unwrap();
+    let excess = viewer_lines.len().saturating_sub(capacity);
+    viewer_lines.drain(..excess);
+}
+
+pub fn clear_viewer_lines() {
+    VIEWER_LINES.lock().unwrap().clear();
+}
@@ -4,10 +4,66 @@
 // Hide the console window.
 // #![windows_subsystem = "windows"]
 
+mod accessibility;
+mod affinity;
+mod artwork;
+mod audio;
+mod audiostats;
+mod bench;
+mod bufferpool;
+mod capture;
+mod color;
+mod control;
+mod diagnostics;
 mod discovery;
+mod display;
+mod dpi;
+mod encoder;
+mod error;
+mod failover;
+mod filters;
+mod focus;
+mod gpu;
+mod gpustats;
 mod gui;
+mod hdr;
+mod hotkey;
 mod input;
+mod latency;
+mod launcher;
+mod library;
+mod local;
+mod logging;
+mod mic;
+mod monitor;
+mod mpegts;
+mod network;
+mod pairing;
+mod photon;
+mod pipeline;
+mod platform;
+mod ports;
+mod power;
+mod preflight;
+mod process;
+mod protected;
+mod recording;
+mod replay;
+mod rtp;
+mod safemode;
+mod selftest;
+mod session;
+mod stats;
+mod stereo;
 mod stream;
+mod telemetry;
+mod timeline;
+mod tls;
+mod trust;
+mod view;
+mod watchdog;
+mod webrtc;
+mod window;
 
 use eframe::egui;
 use eframe::egui::{Style, Visuals};
@@ -15,9 +71,7 @@ use std::env;
 use std::sync::Mutex;
 use tray_icon::menu::{Menu, MenuItem};
 use tray_icon::{Icon, MouseButton, MouseButtonState, TrayIconBuilder, TrayIconEvent};
-use windows::Win32::Foundation::HWND;
-use windows::Win32::UI::WindowsAndMessaging::{ShowWindow, SW_HIDE, SW_SHOWDEFAULT};
-use winit::raw_window_handle::{HasWindowHandle, RawWindowHandle};
+use winit::raw_window_handle::HasWindowHandle;
 
 #[allow(dead_code)]
 const NAME: &str = env!("CARGO_PKG_NAME");
@@ -28,11 +82,35 @@ pub static VISIBLE: Mutex<bool> = Mutex::new(true);
 pub static ALLOW_EXIT: Mutex<bool> = Mutex::new(false);
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    env_logger::init();
+    logging::init();
+    telemetry::init();
+    dpi::init();
 
     let args: Vec<String> = env::args().collect();
+
+    if args.get(1).map(String::as_str) == Some("bench") {
+        bench::run_bench();
+        return Ok(());
+    }
+
+    // Set when another instance hands the console session over to this one.
+    if let Some(pid) = args
+        .iter()
+        .position(|arg| arg == "--wait-for")
+        .and_then(|i| args.get(i + 1))
+        .and_then(|pid| pid.parse().ok())
+    {
+        session::wait_for_predecessor(pid);
+    }
+
     let start_minimized = args.iter().any(|arg| arg == "--minimized");
 
+    if args.iter().any(|arg| arg == "--record-protocol") {
+        if let Err(e) = replay::start() {
+            log::error!("Failed to start recording the protocol capture: {}", e);
+        }
+    }
+
     if start_minimized {
         let mut visible = VISIBLE.lock()?;
         *visible = false;
@@ -87,40 +165,26 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             };
             cc.egui_ctx.set_style(style);
 
-            let RawWindowHandle::Win32(handle) = cc.window_handle().unwrap().as_raw() else {
-                panic!("Unsupported platform");
-            };
+            // Repaint on events and a slow timer instead of every frame to save CPU when idle.
+            gui::start_repaint_timer(cc.egui_ctx.clone());
+
+            let native_window =
+                platform::NativeWindow::new(cc.window_handle().ok().map(|handle| handle.as_raw()));
+            gui::set_main_window(native_window);
 
-            let context_menu = cc.egui_ctx.clone();
             let quit_id_cloned = quit_id.clone();
-            let handle_hwnd = handle.hwnd;
 
             tray_icon::menu::MenuEvent::set_event_handler(Some(move |event: tray_icon::menu::MenuEvent| {
                 if event.id() == &quit_id_cloned {
                     log::info!("Tray Menu Event: Quit selected. Shutting down.");
-
-                    {
-                        let mut allow_exit = ALLOW_EXIT.lock().unwrap();
-                        *allow_exit = true;
-                    }
-
-                    // Show hidden window before sending quit command
-                    let window_handle = HWND(handle_hwnd.into());
-                    unsafe {
-                        ShowWindow(window_handle, SW_SHOWDEFAULT);
-                    }
-                    context_menu.send_viewport_cmd(egui::ViewportCommand::Close);
-                    context_menu.request_repaint();
+                    gui::quit();
                 }
             }));
 
             {
                 let visible = VISIBLE.lock().unwrap();
                 if !*visible {
-                    let window_handle = HWND(handle.hwnd.into());
-                    unsafe {
-                        ShowWindow(window_handle, SW_HIDE);
-                    }
+                    native_window.hide();
                 }
             }
 
@@ -134,10 +198,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     } => {
                         let mut visible = VISIBLE.lock().unwrap();
                         if !*visible {
-                            let window_handle = HWND(handle.hwnd.into());
-                            unsafe {
-                                ShowWindow(window_handle, SW_SHOWDEFAULT);
-                            }
+                            native_window.show();
                             context_tray.send_viewport_cmd(egui::ViewportCommand::Visible(true));
                             context_tray.send_viewport_cmd(egui::ViewportCommand::Focus);
                             *visible = true;
@@ -0,0 +1,94 @@
+use crate::pipeline::{make, BuildError};
+use crate::rtp::MIC_PAYLOAD_TYPE;
+use crate::stream::STREAMING_STATE_GUARD;
+use gst::prelude::*;
+use gstreamer as gst;
+use log::{info, warn};
+
+/// Where clients send their microphone, Opus in RTP, unless the port is busy.
+pub const MIC_PORT: u16 = 5605;
+
+// Jitter the receiver smooths out, in ms. Voice chat tolerates more delay than the picture.
+const MIC_JITTER_MS: u32 = 60;
+
+/// The WASAPI endpoint ID of the device client microphones play into, e.g. the input of a
+/// virtual cable whose output games record from. None if passthrough is off or the device is
+/// gone. GStreamer must be initialized.
+pub fn device_id() -> Option<String> {
+    let device = {
+        let guard = STREAMING_STATE_GUARD.lock().unwrap();
+        guard
+            .as_ref()
+            .map_or(String::new(), |state| state.mic_device.clone())
+    };
+
+    if device.is_empty() {
+        return None;
+    }
+    let id = crate::audio::find_render_device_id(&device);
+    match &id {
+        Some(_) => info!("Playing client microphones into {}.", device),
+        None => warn!(
+            "Microphone device {} not found, client microphones are off.",
+            device
+        ),
+    }
+    id
+}
+
+/// The branch that receives client microphones and plays them into `device_id`, in link order.
+pub fn make_branch(device_id: &str) -> Result<Vec<gst::Element>, BuildError> {
+    let caps = format!(
+        "application/x-rtp,media=audio,encoding-name=OPUS,clock-rate=48000,payload={}",
+        MIC_PAYLOAD_TYPE
+    )
+    .parse::<gst::Caps>()
+    .map_err(|_| gst::glib::bool_error!("Invalid microphone caps"))?;
+    // Lost packets are concealed rather than waited for.
+    Ok(vec![
+        make("udpsrc")?
+            .name("micsrc")
+            .property("port", crate::ports::current().mic as i32)
+            .property("caps", caps)
+            .build()?,
+        make("rtpjitterbuffer")?
+            .property("latency", MIC_JITTER_MS)
+            .property("drop-on-latency", true)
+            .build()?,
+        make("rtpopusdepay")?.build()?,
+        make("opusdec")?.property("plc", true).build()?,
+        make("audioconvert")?.build()?,
+        make("audioresample")?.build()?,
+        make("wasapi2sink")?
+            .name("micsink")
+            .property("device", device_id)
+            .property("low-latency", true)
+            .property("sync", false)
+            .build()?,
+    ])
+}
+
+/// Only lets the microphone of this session through, so nobody else on the network can speak
+/// into the host.
+pub fn attach_ssrc_filter(pipeline: &gst::Pipeline, ssrc: u32) {
+    let Some(pad) = pipeline
+        .by_name("micsrc")
+        .and_then(|src| src.static_pad("src"))
+    else {
+        return;
+    };
+
+    pad.add_probe(gst::PadProbeType::BUFFER, move |_, info| {
+        if let Some(gst::PadProbeData::Buffer(ref buffer)) = info.data {
+            if let Ok(map) = buffer.map_readable() {
+                let packet_ssrc = map
+                    .get(8..12)
+                    .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+                if packet_ssrc != Some(ssrc) {
+                    return gst::PadProbeReturn::Drop;
+                }
+            }
+        }
+        gst::PadProbeReturn::Ok
+    });
+}
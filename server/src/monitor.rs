@@ -0,0 +1,398 @@
+use crate::stream::{is_pipeline_running, restart_gstreamer_pipeline, STREAMING_STATE_GUARD};
+use crate::window::{self, WindowInfo};
+use gstreamer as gst;
+use log::{info, warn};
+use serde::Serialize;
+use std::io::{Error, ErrorKind};
+use windows::Win32::Graphics::Dxgi::{CreateDXGIFactory1, IDXGIFactory1};
+
+/// A display that can be captured.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MonitorInfo {
+    // What d3d11screencapturesrc takes as `monitor-index`: outputs attached to the desktop,
+    // adapter by adapter.
+    pub index: u32,
+    // GDI device name like \\.\DISPLAY2, which survives other monitors being unplugged.
+    pub name: String,
+    // Position on the virtual desktop, the primary monitor is at 0,0.
+    pub left: i32,
+    pub top: i32,
+    pub width: u32,
+    pub height: u32,
+    pub primary: bool,
+}
+
+impl std::fmt::Display for MonitorInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({}x{}){}",
+            self.name.trim_start_matches(r"\\.\"),
+            self.width,
+            self.height,
+            if self.primary { ", primary" } else { "" }
+        )
+    }
+}
+
+// Smallest region side in pixels, encoders reject tinier pictures.
+pub const MIN_REGION_SIZE: u32 = 64;
+
+/// A rectangle of the captured monitor or window that is streamed instead of all of it, in
+/// pixels from its top-left corner.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CaptureRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl CaptureRegion {
+    // The part of the region inside an area of `size`, None if too little is left.
+    fn clipped(self, size: (u32, u32)) -> Option<Self> {
+        let x = self.x.min(size.0);
+        let y = self.y.min(size.1);
+        let width = self.width.min(size.0 - x);
+        let height = self.height.min(size.1 - y);
+        (width >= MIN_REGION_SIZE && height >= MIN_REGION_SIZE).then_some(CaptureRegion {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+}
+
+impl std::fmt::Display for CaptureRegion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{} at {},{}", self.width, self.height, self.x, self.y)
+    }
+}
+
+/// The monitors in the order capture counts them.
+pub fn list_monitors() -> Vec<MonitorInfo> {
+    let factory: IDXGIFactory1 = match unsafe { CreateDXGIFactory1() } {
+        Ok(factory) => factory,
+        Err(e) => {
+            warn!("Failed to create a DXGI factory: {}", e);
+            return Vec::new();
+        }
+    };
+
+    let mut monitors = Vec::new();
+    // Both enumerations end with DXGI_ERROR_NOT_FOUND.
+    for adapter_index in 0.. {
+        let Ok(adapter) = (unsafe { factory.EnumAdapters1(adapter_index) }) else {
+            break;
+        };
+        for output_index in 0.. {
+            let Ok(output) = (unsafe { adapter.EnumOutputs(output_index) }) else {
+                break;
+            };
+            let Ok(desc) = (unsafe { output.GetDesc() }) else {
+                continue;
+            };
+            if !desc.AttachedToDesktop.as_bool() {
+                continue;
+            }
+
+            let name_len = desc
+                .DeviceName
+                .iter()
+                .position(|&c| c == 0)
+                .unwrap_or(desc.DeviceName.len());
+            let rect = desc.DesktopCoordinates;
+            monitors.push(MonitorInfo {
+                index: monitors.len() as u32,
+                name: String::from_utf16_lossy(&desc.DeviceName[..name_len]),
+                left: rect.left,
+                top: rect.top,
+                width: (rect.right - rect.left) as u32,
+                height: (rect.bottom - rect.top) as u32,
+                primary: rect.left == 0 && rect.top == 0,
+            });
+        }
+    }
+    monitors
+}
+
+/// Finds a monitor by its device name, None for the primary one or if it is gone.
+pub fn find_monitor(name: &str) -> Option<MonitorInfo> {
+    if name.is_empty() {
+        return None;
+    }
+    list_monitors()
+        .into_iter()
+        .find(|monitor| monitor.name == name)
+}
+
+/// The monitor being captured, None for the primary one.
+pub fn selected_monitor() -> Option<MonitorInfo> {
+    let guard = STREAMING_STATE_GUARD.lock().unwrap();
+    guard.as_ref().and_then(|state| state.monitor.clone())
+}
+
+/// The window captured instead of a monitor, if any.
+pub fn capture_window() -> Option<WindowInfo> {
+    let guard = STREAMING_STATE_GUARD.lock().unwrap();
+    guard
+        .as_ref()
+        .and_then(|state| state.capture_window.clone())
+}
+
+/// The streamed rectangle of the capture, None for all of it.
+pub fn capture_region() -> Option<CaptureRegion> {
+    let guard = STREAMING_STATE_GUARD.lock().unwrap();
+    guard.as_ref().and_then(|state| state.capture_region)
+}
+
+/// Size of what is captured before any region is cut out, for picking a region.
+pub fn capture_area_size() -> Option<(u32, u32)> {
+    if let Some(rect) = capture_window().and_then(|window| window::window_bounds(window.hwnd)) {
+        return Some((
+            (rect.right - rect.left) as u32,
+            (rect.bottom - rect.top) as u32,
+        ));
+    }
+    selected_monitor()
+        .or_else(|| list_monitors().into_iter().find(|monitor| monitor.primary))
+        .map(|monitor| (monitor.width, monitor.height))
+}
+
+/// Streams only `region` of the monitor or window, None for all of it. A running stream
+/// switches over. Blocking.
+pub fn select_region(region: Option<CaptureRegion>) {
+    {
+        let mut guard = STREAMING_STATE_GUARD.lock().unwrap();
+        let Some(state) = guard.as_mut() else {
+            return;
+        };
+        if state.capture_region == region {
+            return;
+        }
+        state.capture_region = region;
+    }
+
+    match &region {
+        Some(region) => info!("Capturing the region {}.", region),
+        None => info!("Capturing the whole area again."),
+    }
+
+    crate::view::reset();
+    if is_pipeline_running() {
+        restart_gstreamer_pipeline();
+    }
+    crate::gui::request_repaint();
+}
+
+/// Captures another monitor, None for the primary one, instead of a window. A running stream
+/// switches over. Blocking.
+pub fn select_monitor(index: Option<u32>) -> std::io::Result<()> {
+    let monitor = match index {
+        Some(index) => Some(
+            list_monitors()
+                .into_iter()
+                .find(|monitor| monitor.index == index)
+                .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("No monitor {}", index)))?,
+        ),
+        None => None,
+    };
+
+    {
+        let mut guard = STREAMING_STATE_GUARD.lock().unwrap();
+        let Some(state) = guard.as_mut() else {
+            return Ok(());
+        };
+        if state.monitor == monitor && state.capture_window.is_none() {
+            return Ok(());
+        }
+        if let Some(monitor) = &monitor {
+            state.native_resolution = (monitor.width, monitor.height);
+        }
+        state.capture_origin = monitor
+            .as_ref()
+            .map_or((0, 0), |monitor| (monitor.left, monitor.top));
+        state.monitor = monitor.clone();
+        state.capture_window = None;
+    }
+
+    match &monitor {
+        Some(monitor) => info!("Capturing monitor {}.", monitor),
+        None => info!("Capturing the primary monitor."),
+    }
+
+    // A crop of the previous monitor means nothing on this one.
+    crate::view::reset();
+    if is_pipeline_running() {
+        restart_gstreamer_pipeline();
+    }
+    crate::gui::request_repaint();
+    Ok(())
+}
+
+/// Captures just one window, or the monitor again if None. Window capture uses Windows
+/// Graphics Capture, which keeps working while the window is covered. Blocking.
+pub fn select_window(capture_window: Option<WindowInfo>) {
+    {
+        let mut guard = STREAMING_STATE_GUARD.lock().unwrap();
+        let Some(state) = guard.as_mut() else {
+            return;
+        };
+        if state.capture_window.as_ref().map(|window| window.hwnd)
+            == capture_window.as_ref().map(|window| window.hwnd)
+        {
+            return;
+        }
+        state.capture_window = capture_window.clone();
+    }
+
+    match &capture_window {
+        Some(capture_window) => info!(
+            "Capturing the window \"{}\" of {}.",
+            capture_window.title, capture_window.process_name
+        ),
+        None => info!("Capturing the monitor again."),
+    }
+
+    crate::view::reset();
+    if is_pipeline_running() {
+        restart_gstreamer_pipeline();
+    }
+    crate::gui::request_repaint();
+}
+
+/// Picks up where the captured area is now, after the display scaling or layout changed, so
+/// input keeps landing where clients point. A running stream restarts if the size changed.
+/// Blocking.
+pub fn refresh_capture_area() {
+    let area = || {
+        let guard = STREAMING_STATE_GUARD.lock().unwrap();
+        guard
+            .as_ref()
+            .map(|state| (state.capture_origin, state.native_resolution))
+    };
+    let previous = area();
+    capture_target();
+    let current = area();
+    if current == previous {
+        return;
+    }
+
+    info!("The captured area changed to {:?}.", current);
+    if previous.map(|(_, size)| size) != current.map(|(_, size)| size) && is_pipeline_running() {
+        restart_gstreamer_pipeline();
+    }
+}
+
+/// What capture picks, a window or a monitor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaptureTarget {
+    Window(u64),
+    Monitor(u32),
+    Primary,
+}
+
+impl CaptureTarget {
+    /// Sets the properties that make `d3d11screencapturesrc` capture this.
+    pub fn configure<'a>(&self, source: gst::ElementBuilder<'a>) -> gst::ElementBuilder<'a> {
+        match *self {
+            CaptureTarget::Window(hwnd) => source
+                .property_from_str("capture-api", "wgc")
+                .property("window-handle", hwnd),
+            CaptureTarget::Monitor(index) => source.property("monitor-index", index as i32),
+            CaptureTarget::Primary => source,
+        }
+    }
+}
+
+/// Pixels cut off the left, top, right and bottom of the capture.
+pub type Crop = (i32, i32, i32, i32);
+
+/// Brings the captured area up to date before a pipeline starts, since monitors change modes
+/// and windows move. Returns what capture picks, and what to cut off to leave the chosen region.
+pub fn capture_target() -> (CaptureTarget, Option<Crop>) {
+    let (monitor, capture_window, region) = {
+        let guard = STREAMING_STATE_GUARD.lock().unwrap();
+        guard.as_ref().map_or((None, None, None), |state| {
+            (
+                state.monitor.clone(),
+                state.capture_window.clone(),
+                state.capture_region,
+            )
+        })
+    };
+
+    let monitor = monitor.map(|chosen| find_monitor(&chosen.name).unwrap_or(chosen));
+    let window_bounds = capture_window.as_ref().and_then(|capture_window| {
+        let bounds = window::window_bounds(capture_window.hwnd);
+        if bounds.is_none() {
+            warn!(
+                "The window \"{}\" is gone, capturing the monitor.",
+                capture_window.title
+            );
+        }
+        bounds
+    });
+    let capture_window = capture_window.filter(|_| window_bounds.is_some());
+
+    // Left, top, width and height on the virtual desktop.
+    let area = match (window_bounds, &monitor) {
+        (Some(rect), _) => Some((
+            rect.left,
+            rect.top,
+            (rect.right - rect.left) as u32,
+            (rect.bottom - rect.top) as u32,
+        )),
+        (None, Some(monitor)) => Some((monitor.left, monitor.top, monitor.width, monitor.height)),
+        // Only looked up for a region, the GUI keeps the size of the primary monitor otherwise.
+        (None, None) if region.is_some() => list_monitors()
+            .into_iter()
+            .find(|monitor| monitor.primary)
+            .map(|monitor| (monitor.left, monitor.top, monitor.width, monitor.height)),
+        (None, None) => None,
+    };
+
+    let region = region.and_then(|region| {
+        let clipped = area.and_then(|(_, _, width, height)| region.clipped((width, height)));
+        if clipped.is_none() {
+            warn!(
+                "The region {} is outside the captured area, capturing all of it.",
+                region
+            );
+        }
+        clipped
+    });
+
+    if let Some(state) = STREAMING_STATE_GUARD.lock().unwrap().as_mut() {
+        state.monitor = monitor.clone();
+        state.capture_window = capture_window.clone();
+        state.capture_origin = area.map_or((0, 0), |(left, top, _, _)| (left, top));
+        if let Some((_, _, width, height)) = area {
+            state.native_resolution = (width, height);
+        }
+        // Clients only ever see the region, so it is what they point into.
+        if let Some(region) = region {
+            state.capture_origin.0 += region.x as i32;
+            state.capture_origin.1 += region.y as i32;
+            state.native_resolution = (region.width, region.height);
+        }
+    }
+
+    let crop = match (region, area) {
+        (Some(region), Some((_, _, width, height))) => Some((
+            region.x as i32,
+            region.y as i32,
+            (width - region.x - region.width) as i32,
+            (height - region.y - region.height) as i32,
+        )),
+        _ => None,
+    };
+
+    let target = match (capture_window, monitor) {
+        (Some(capture_window), _) => CaptureTarget::Window(capture_window.hwnd.0 as u64),
+        (None, Some(monitor)) => CaptureTarget::Monitor(monitor.index),
+        (None, None) => CaptureTarget::Primary,
+    };
+    (target, crop)
+}
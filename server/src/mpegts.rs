@@ -0,0 +1,41 @@
+use crate::pipeline::{add_chain, make, BuildError};
+use crate::rtp::RtpSession;
+use crate::stream::{check_factory_exists, make_udpsink};
+use gst::prelude::*;
+use gstreamer as gst;
+
+/// Whether GStreamer can mux MPEG-TS, for the capability exchange.
+pub fn is_available() -> bool {
+    check_factory_exists("mpegtsmux")
+}
+
+/// Adds the sink of a client that only plays MPEG-TS, e.g. a TV or a set-top box, to `bin` and
+/// returns it. Video and audio are muxed into one transport stream on the video port, without
+/// RTP, so there is no RTCP, FEC or retransmission. Fed by the valves behind the queues the tees
+/// of the encoded streams link to, the muxer would wait forever for audio that has no valve.
+pub fn add_branch(
+    bin: &gst::Bin,
+    video_valve: &gst::Element,
+    audio_valve: Option<&gst::Element>,
+    host: &str,
+    port: u16,
+    session: &RtpSession,
+) -> Result<gst::Element, BuildError> {
+    // Seven packets of 188 bytes fill a UDP datagram without fragmenting it.
+    let tsmux = make("mpegtsmux")?
+        .name("tsmux")
+        .property("alignment", 7i32)
+        .build()?;
+    let sink = make_udpsink("videosink", host, port)?;
+    add_chain(bin, None, &[tsmux.clone(), sink.clone()])?;
+
+    let video_parser = make(session.video_codec.parser())?.build()?;
+    add_chain(bin, Some(video_valve), std::slice::from_ref(&video_parser))?;
+    video_parser.link(&tsmux)?;
+    if let Some(audio_valve) = audio_valve {
+        let audio_parser = make(session.audio_codec.parser())?.build()?;
+        add_chain(bin, Some(audio_valve), std::slice::from_ref(&audio_parser))?;
+        audio_parser.link(&tsmux)?;
+    }
+    Ok(sink)
+}
@@ -0,0 +1,200 @@
+use crate::control::{broadcast_event, ControlEvent};
+use crate::latency::{self, LatencyPreset};
+use crate::stream::STREAMING_STATE_GUARD;
+use log::info;
+use serde::Serialize;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// A new class must be measured this many times in a row before it is adopted.
+const STABLE_SAMPLES: u32 = 5;
+// Minimum time between two class changes, so a flaky link can't make the pipeline restart over and over.
+const MIN_SWITCH_INTERVAL: Duration = Duration::from_secs(30);
+// Weight of a new sample in the moving averages.
+const SMOOTHING: f32 = 0.2;
+
+/// Rough kind of link between the server and the client.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkClass {
+    Lan,
+    Wifi,
+    Wan,
+}
+
+impl LinkClass {
+    /// The preset picked for this link when presets are not pinned.
+    pub fn preset(&self) -> LatencyPreset {
+        match self {
+            LinkClass::Lan => LatencyPreset::UltraLow,
+            LinkClass::Wifi => LatencyPreset::Balanced,
+            LinkClass::Wan => LatencyPreset::Quality,
+        }
+    }
+}
+
+impl std::fmt::Display for LinkClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkClass::Lan => write!(f, "LAN"),
+            LinkClass::Wifi => write!(f, "Wi-Fi"),
+            LinkClass::Wan => write!(f, "WAN"),
+        }
+    }
+}
+
+/// Smoothed link statistics.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LinkStats {
+    pub rtt_ms: f32,
+    pub jitter_ms: f32,
+    // Fraction of lost packets, 0 to 1.
+    pub loss: f32,
+}
+
+impl LinkStats {
+    fn classify(&self) -> LinkClass {
+        if self.rtt_ms < 5.0 && self.jitter_ms < 2.0 && self.loss < 0.005 {
+            LinkClass::Lan
+        } else if self.rtt_ms < 40.0 && self.jitter_ms < 10.0 && self.loss < 0.02 {
+            LinkClass::Wifi
+        } else {
+            LinkClass::Wan
+        }
+    }
+}
+
+struct Classifier {
+    stats: LinkStats,
+    last_rtt_ms: Option<f32>,
+    class: Option<LinkClass>,
+    candidate: Option<LinkClass>,
+    candidate_count: u32,
+    last_switch: Option<Instant>,
+}
+
+impl Classifier {
+    fn new() -> Self {
+        Self {
+            stats: LinkStats::default(),
+            last_rtt_ms: None,
+            class: None,
+            candidate: None,
+            candidate_count: 0,
+            last_switch: None,
+        }
+    }
+
+    // Returns the new class when the link class changes.
+    fn update(&mut self, rtt_ms: f32, loss: f32) -> Option<LinkClass> {
+        match self.last_rtt_ms {
+            Some(last_rtt_ms) => {
+                // Jitter as the smoothed RTT delta between samples, similar to RFC 3550.
+                let delta = (rtt_ms - last_rtt_ms).abs();
+                self.stats.rtt_ms += SMOOTHING * (rtt_ms - self.stats.rtt_ms);
+                self.stats.jitter_ms += SMOOTHING * (delta - self.stats.jitter_ms);
+                self.stats.loss += SMOOTHING * (loss - self.stats.loss);
+            }
+            None => {
+                self.stats.rtt_ms = rtt_ms;
+                self.stats.loss = loss;
+            }
+        }
+        self.last_rtt_ms = Some(rtt_ms);
+
+        let measured = self.stats.classify();
+        if Some(measured) == self.class {
+            self.candidate = None;
+            self.candidate_count = 0;
+            return None;
+        }
+
+        if self.candidate == Some(measured) {
+            self.candidate_count += 1;
+        } else {
+            self.candidate = Some(measured);
+            self.candidate_count = 1;
+        }
+
+        let settled = self
+            .last_switch
+            .map_or(true, |time| time.elapsed() >= MIN_SWITCH_INTERVAL);
+        if self.candidate_count < STABLE_SAMPLES || !settled {
+            return None;
+        }
+
+        self.class = Some(measured);
+        self.candidate = None;
+        self.candidate_count = 0;
+        self.last_switch = Some(Instant::now());
+        Some(measured)
+    }
+}
+
+static CLASSIFIER: Mutex<Option<Classifier>> = Mutex::new(None);
+
+/// Feeds one measurement of the input link. Called periodically while a client is connected.
+pub fn record_sample(rtt: Duration, loss: f32) {
+    let rtt_ms = rtt.as_secs_f32() * 1000.0;
+
+    let (class, stats) = {
+        let mut guard = CLASSIFIER.lock().unwrap();
+        let classifier = guard.get_or_insert_with(Classifier::new);
+        let Some(class) = classifier.update(rtt_ms, loss) else {
+            return;
+        };
+        (class, classifier.stats)
+    };
+
+    info!(
+        "Link classified as {} (RTT {:.1} ms, jitter {:.1} ms, loss {:.1}%).",
+        class,
+        stats.rtt_ms,
+        stats.jitter_ms,
+        stats.loss * 100.0
+    );
+
+    broadcast_event(&ControlEvent::LinkChanged {
+        link: class,
+        rtt_ms: stats.rtt_ms,
+        jitter_ms: stats.jitter_ms,
+        loss: stats.loss,
+    });
+
+    let auto_preset = {
+        let guard = STREAMING_STATE_GUARD.lock().unwrap();
+        guard
+            .as_ref()
+            .map_or(false, |state| state.auto_latency_preset)
+    };
+    if !auto_preset {
+        info!("Latency preset is pinned, not switching.");
+        return;
+    }
+
+    let preset = class.preset();
+    info!("Switching to the {} preset for the {} link.", preset, class);
+    // This may restart the pipeline, keep it off the caller's thread.
+    thread::spawn(move || latency::apply_preset(preset));
+}
+
+/// The current link class and statistics, once a class has been settled on.
+pub fn current_link() -> Option<(LinkClass, LinkStats)> {
+    let guard = CLASSIFIER.lock().unwrap();
+    let classifier = guard.as_ref()?;
+    Some((classifier.class?, classifier.stats))
+}
+
+/// The smoothed measurements so far, also before a class has been settled on.
+pub fn latest_stats() -> Option<LinkStats> {
+    let guard = CLASSIFIER.lock().unwrap();
+    let classifier = guard.as_ref()?;
+    classifier.last_rtt_ms?;
+    Some(classifier.stats)
+}
+
+/// Forgets the measurements, e.g. when the client disconnects.
+pub fn reset() {
+    *CLASSIFIER.lock().unwrap() = None;
+}
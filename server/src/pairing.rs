@@ -0,0 +1,115 @@
+use crate::gui::generate_pin;
+use log::{info, warn};
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const CODE_LENGTH: usize = 6;
+const CODE_LIFETIME: Duration = Duration::from_secs(120);
+// How long an IP has to wait between codes, so unauthenticated clients cannot keep the host busy.
+const REQUEST_INTERVAL: Duration = Duration::from_secs(10);
+
+struct PairingCode {
+    addr: SocketAddr,
+    code: String,
+    expires: Instant,
+}
+
+// The code shown on the host, if a client asked for one.
+static ACTIVE_CODE: Mutex<Option<PairingCode>> = Mutex::new(None);
+// When each IP last asked for a code, within the request interval.
+static LAST_REQUESTS: Mutex<Vec<(IpAddr, Instant)>> = Mutex::new(Vec::new());
+
+/// Shows a one-time code on the host for `addr` to echo back instead of the PIN.
+/// Returns how long the code is valid. Fails while another client's code is valid or when the
+/// IP asked too recently.
+pub fn request_code(addr: SocketAddr) -> std::io::Result<Duration> {
+    let now = Instant::now();
+    {
+        let mut last_requests = LAST_REQUESTS.lock().unwrap();
+        last_requests.retain(|(_, at)| now.duration_since(*at) < REQUEST_INTERVAL);
+        if last_requests.iter().any(|(ip, _)| *ip == addr.ip()) {
+            warn!("{} asked for pairing codes too often.", addr);
+            return Err(Error::new(
+                ErrorKind::WouldBlock,
+                format!(
+                    "Wait {} seconds between pairing code requests",
+                    REQUEST_INTERVAL.as_secs()
+                ),
+            ));
+        }
+        last_requests.push((addr.ip(), now));
+    }
+
+    let mut active = ACTIVE_CODE.lock().unwrap();
+    if active
+        .as_ref()
+        .map_or(false, |active| active.addr != addr && now < active.expires)
+    {
+        warn!(
+            "Not showing a pairing code for {}, another client is pairing.",
+            addr
+        );
+        return Err(Error::new(
+            ErrorKind::AddrInUse,
+            "Another client is pairing, try again later",
+        ));
+    }
+
+    info!("Showing a pairing code for {}.", addr);
+
+    *active = Some(PairingCode {
+        addr,
+        code: generate_pin(CODE_LENGTH),
+        expires: now + CODE_LIFETIME,
+    });
+    crate::gui::request_repaint();
+
+    Ok(CODE_LIFETIME)
+}
+
+/// Whether `code` is the valid code shown for `addr`. A matching code is used up.
+pub fn take_code(addr: SocketAddr, code: &str) -> bool {
+    let mut active = ACTIVE_CODE.lock().unwrap();
+    let matches = active.as_ref().map_or(false, |active| {
+        active.addr == addr && active.code == code && Instant::now() < active.expires
+    });
+
+    if matches {
+        info!("{} paired with a one-time code.", addr);
+        *active = None;
+        crate::gui::request_repaint();
+    }
+    matches
+}
+
+/// The code to show and how long it stays valid, for the GUI.
+pub fn visible_code() -> Option<(String, Duration)> {
+    let mut active = ACTIVE_CODE.lock().unwrap();
+    let remaining = active
+        .as_ref()?
+        .expires
+        .checked_duration_since(Instant::now());
+
+    match remaining {
+        Some(remaining) => active
+            .as_ref()
+            .map(|active| (active.code.clone(), remaining)),
+        None => {
+            *active = None;
+            None
+        }
+    }
+}
+
+/// Hides the code, e.g. when the host dismisses it or the client leaves.
+pub fn cancel(addr: Option<SocketAddr>) {
+    let mut active = ACTIVE_CODE.lock().unwrap();
+    if active.as_ref().map_or(false, |active| {
+        addr.map_or(true, |addr| active.addr == addr)
+    }) {
+        *active = None;
+        crate::gui::request_repaint();
+    }
+}
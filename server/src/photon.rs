@@ -0,0 +1,104 @@
+use crate::pipeline::{capsfilter, make, BuildError};
+use crate::stream::{is_pipeline_running, restart_gstreamer_pipeline};
+use gst::prelude::*;
+use gstreamer as gst;
+use gstreamer_video as gst_video;
+use log::info;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Side of the square in the top-left corner, in pixels of the captured frame.
+const MARKER_SIZE: usize = 64;
+// How long the marker stays white after an input packet, so a 30 FPS stream shows it for at
+// least two frames.
+const FLASH_DURATION: Duration = Duration::from_millis(100);
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static LAST_INPUT: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Whether the input-to-photon marker is drawn on the stream.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Draws a square in the top-left corner of the stream that turns white the moment an input
+/// packet arrives and black again shortly after. Filming the input device and the client screen
+/// with a fast camera then gives the latency from input to photon. A running stream restarts.
+/// Blocking.
+pub fn set_enabled(enabled: bool) {
+    if ENABLED.swap(enabled, Ordering::Relaxed) == enabled {
+        return;
+    }
+    if enabled {
+        info!("Drawing the input-to-photon marker.");
+    } else {
+        info!("No longer drawing the input-to-photon marker.");
+    }
+    if is_pipeline_running() {
+        restart_gstreamer_pipeline();
+    }
+}
+
+/// Lights the marker, called as soon as input reaches the host.
+pub fn record_input() {
+    if is_enabled() {
+        *LAST_INPUT.lock().unwrap() = Some(Instant::now());
+    }
+}
+
+/// The elements around the one the marker is drawn at in link order, none while it is off. Like
+/// the filters, it works on 8-bit frames in system memory.
+pub fn make_marker() -> Result<Vec<gst::Element>, BuildError> {
+    if !is_enabled() {
+        return Ok(Vec::new());
+    }
+    Ok(vec![
+        make("videoconvert")?.build()?,
+        capsfilter(None, "video/x-raw,format=BGRx")?,
+        make("identity")?.name("photonmarker").build()?,
+        make("videoconvert")?.build()?,
+    ])
+}
+
+/// Draws the marker on the frames passing the `photonmarker` element.
+pub fn attach(pipeline: &gst::Pipeline) {
+    let Some(pad) = pipeline
+        .by_name("photonmarker")
+        .and_then(|identity| identity.static_pad("src"))
+    else {
+        return;
+    };
+
+    pad.add_probe(gst::PadProbeType::BUFFER, |pad, info| {
+        let Some(video_info) = pad
+            .current_caps()
+            .and_then(|caps| gst_video::VideoInfo::from_caps(&caps).ok())
+        else {
+            return gst::PadProbeReturn::Ok;
+        };
+        let lit = LAST_INPUT
+            .lock()
+            .unwrap()
+            .is_some_and(|received| received.elapsed() < FLASH_DURATION);
+        let value = if lit { 255 } else { 0 };
+
+        if let Some(gst::PadProbeData::Buffer(ref mut buffer)) = info.data {
+            let buffer = buffer.make_mut();
+            if let Ok(mut frame) =
+                gst_video::VideoFrameRef::from_buffer_ref_writable(buffer, &video_info)
+            {
+                let stride = frame.plane_stride()[0] as usize;
+                let width = MARKER_SIZE.min(video_info.width() as usize);
+                let height = MARKER_SIZE.min(video_info.height() as usize);
+                if let Ok(data) = frame.plane_data_mut(0) {
+                    for row in data.chunks_mut(stride).take(height) {
+                        // BGRx
+                        row[..width * 4].fill(value);
+                    }
+                }
+            }
+        }
+        gst::PadProbeReturn::Ok
+    });
+}
@@ -0,0 +1,181 @@
+use gst::prelude::*;
+use gstreamer as gst;
+
+/// Why the stream pipeline could not be built.
+#[derive(Debug)]
+pub enum BuildError {
+    // GStreamer plugins that are not installed, by element name.
+    MissingElements(Vec<String>),
+    Failed(gst::glib::BoolError),
+}
+
+impl From<gst::glib::BoolError> for BuildError {
+    fn from(e: gst::glib::BoolError) -> Self {
+        BuildError::Failed(e)
+    }
+}
+
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildError::MissingElements(missing) => {
+                write!(f, "Missing GStreamer element(s): {}", missing.join(", "))
+            }
+            BuildError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// The elements of the stream pipeline that are controlled while it runs.
+#[derive(Debug, Clone)]
+pub struct PipelineHandles {
+    // Takes bitrate changes.
+    pub video_encoder: gst::Element,
+    // Where outgoing video is counted.
+    pub video_payloader: gst::Element,
+    // Takes FEC and DTX settings, None without audio.
+    pub audio_encoder: Option<gst::Element>,
+}
+
+/// Builds a pipeline element by element, so it can be changed while it runs: client branches
+/// link to the tees and encoder settings change through `PipelineHandles`.
+pub struct PipelineBuilder {
+    pipeline: gst::Pipeline,
+}
+
+impl PipelineBuilder {
+    pub fn new() -> Self {
+        PipelineBuilder {
+            pipeline: gst::Pipeline::new(),
+        }
+    }
+
+    /// Builds `element`, started with `make`, and adds it.
+    pub fn add(&self, element: gst::ElementBuilder) -> Result<gst::Element, BuildError> {
+        let element = element.build()?;
+        self.pipeline.add(&element)?;
+        Ok(element)
+    }
+
+    /// Adds a capsfilter that lets `caps` through.
+    pub fn capsfilter(&self, name: Option<&str>, caps: &str) -> Result<gst::Element, BuildError> {
+        let element = capsfilter(name, caps)?;
+        self.pipeline.add(&element)?;
+        Ok(element)
+    }
+
+    /// Adds a part built on its own, e.g. capture or the encoder.
+    pub fn add_bin(&self, bin: &gst::Bin) -> Result<(), BuildError> {
+        self.pipeline.add(bin)?;
+        Ok(())
+    }
+
+    /// Adds elements and links them one after another.
+    pub fn add_chain(&self, elements: &[gst::Element]) -> Result<(), BuildError> {
+        add_chain(self.pipeline.upcast_ref(), None, elements)
+    }
+
+    /// Links elements one after another.
+    pub fn chain(&self, elements: &[&gst::Element]) -> Result<(), BuildError> {
+        gst::Element::link_many(elements)?;
+        Ok(())
+    }
+
+    /// Links `src` to the request pad `pad_name` of `sink`, e.g. `send_rtp_sink_0` of rtpbin.
+    pub fn link_to_request(
+        &self,
+        src: &gst::Element,
+        sink: &gst::Element,
+        pad_name: &str,
+    ) -> Result<(), BuildError> {
+        let sink_pad = sink
+            .request_pad_simple(pad_name)
+            .ok_or_else(|| gst::glib::bool_error!("{} has no pad {}", sink.name(), pad_name))?;
+        let src_pad = src
+            .static_pad("src")
+            .ok_or_else(|| gst::glib::bool_error!("{} has no src pad", src.name()))?;
+        src_pad
+            .link(&sink_pad)
+            .map_err(|e| gst::glib::bool_error!("Failed to link {}: {:?}", pad_name, e))?;
+        Ok(())
+    }
+
+    /// Links the pad `pad_name` that `src` made for a request pad, e.g. `send_rtp_src_0` of
+    /// rtpbin, to `sink`.
+    pub fn link_from(
+        &self,
+        src: &gst::Element,
+        pad_name: &str,
+        sink: &gst::Element,
+    ) -> Result<(), BuildError> {
+        src.link_pads(Some(pad_name), sink, None)?;
+        Ok(())
+    }
+
+    pub fn build(self) -> gst::Pipeline {
+        self.pipeline
+    }
+}
+
+impl Default for PipelineBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Starts an element of `factory`, its name and properties are set on the builder. A factory
+/// that is not installed fails with `BuildError::MissingElements`.
+pub fn make(factory: &str) -> Result<gst::ElementBuilder<'_>, BuildError> {
+    if gst::ElementFactory::find(factory).is_none() {
+        return Err(BuildError::MissingElements(vec![factory.to_string()]));
+    }
+    Ok(gst::ElementFactory::make(factory))
+}
+
+/// A capsfilter that lets `caps` through.
+pub fn capsfilter(name: Option<&str>, caps: &str) -> Result<gst::Element, BuildError> {
+    let caps = caps
+        .parse::<gst::Caps>()
+        .map_err(|_| gst::glib::bool_error!("Invalid caps: {}", caps))?;
+    let mut element = make("capsfilter")?.property("caps", caps);
+    if let Some(name) = name {
+        element = element.name(name);
+    }
+    Ok(element.build()?)
+}
+
+/// Adds `elements` to `bin` and links them one after another, behind `from` if given.
+pub fn add_chain(
+    bin: &gst::Bin,
+    from: Option<&gst::Element>,
+    elements: &[gst::Element],
+) -> Result<(), BuildError> {
+    bin.add_many(elements)?;
+    gst::Element::link_many(from.into_iter().chain(elements))?;
+    Ok(())
+}
+
+/// Exposes the pad `pad_name` of `element` on `bin`, so the bin links like an element.
+pub fn ghost_pad(bin: &gst::Bin, element: &gst::Element, pad_name: &str) -> Result<(), BuildError> {
+    let target = element
+        .static_pad(pad_name)
+        .ok_or_else(|| gst::glib::bool_error!("{} has no pad {}", element.name(), pad_name))?;
+    let ghost_pad = gst::GhostPad::with_target(&target)?;
+    ghost_pad.set_active(true)?;
+    bin.add_pad(&ghost_pad)?;
+    Ok(())
+}
+
+/// The factories of `elements` with their names, in the order they link, for the log.
+pub fn describe(elements: &[gst::Element]) -> String {
+    elements
+        .iter()
+        .map(|element| {
+            let factory = element
+                .factory()
+                .map_or(String::new(), |factory| factory.name().to_string());
+            format!("{} name={}", factory, element.name())
+        })
+        .collect::<Vec<_>>()
+        .join(" ! ")
+}
@@ -0,0 +1,60 @@
+use log::warn;
+use winit::raw_window_handle::RawWindowHandle;
+
+#[cfg(target_os = "windows")]
+use windows::Win32::Foundation::HWND;
+#[cfg(target_os = "windows")]
+use windows::Win32::UI::WindowsAndMessaging::{ShowWindow, SW_HIDE, SW_SHOWDEFAULT};
+
+/// The main window as the OS sees it, for hiding it to the tray.
+/// Where the window cannot be hidden natively, egui's `Visible` viewport command is all there is.
+#[derive(Debug, Clone, Copy)]
+pub struct NativeWindow {
+    #[cfg(target_os = "windows")]
+    hwnd: Option<isize>,
+}
+
+impl NativeWindow {
+    pub fn new(handle: Option<RawWindowHandle>) -> Self {
+        #[cfg(target_os = "windows")]
+        {
+            let hwnd = match handle {
+                Some(RawWindowHandle::Win32(handle)) => Some(handle.hwnd.get()),
+                other => {
+                    warn!(
+                        "Unexpected window handle {:?}, hiding to the tray is limited.",
+                        other
+                    );
+                    None
+                }
+            };
+            Self { hwnd }
+        }
+
+        #[cfg(not(target_os = "windows"))]
+        {
+            if handle.is_none() {
+                warn!("No native window handle available.");
+            }
+            Self {}
+        }
+    }
+
+    pub fn show(&self) {
+        #[cfg(target_os = "windows")]
+        if let Some(hwnd) = self.hwnd {
+            unsafe {
+                ShowWindow(HWND(hwnd), SW_SHOWDEFAULT);
+            }
+        }
+    }
+
+    pub fn hide(&self) {
+        #[cfg(target_os = "windows")]
+        if let Some(hwnd) = self.hwnd {
+            unsafe {
+                ShowWindow(HWND(hwnd), SW_HIDE);
+            }
+        }
+    }
+}
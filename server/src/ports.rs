@@ -0,0 +1,99 @@
+use log::{info, warn};
+use serde::Serialize;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, UdpSocket};
+use std::sync::Mutex;
+
+// How many ports above a busy one are tried before any free port is taken.
+const SEARCH_RANGE: u16 = 100;
+
+/// Ports the host listens on. Clients learn them from the discovery announcement and the
+/// capability exchange, since any of them may have moved off its default.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Ports {
+    // WebSocket control, TCP.
+    pub websocket: u16,
+    // ENet input, UDP.
+    pub enet: u16,
+    // Where clients send RTCP about the video and the audio.
+    pub video_rtcp: u16,
+    pub audio_rtcp: u16,
+    // Where clients send their microphone, see `mic`.
+    pub mic: u16,
+}
+
+impl Ports {
+    pub const DEFAULT: Ports = Ports {
+        websocket: 5600,
+        enet: crate::input::ENET_PORT,
+        video_rtcp: 5603,
+        audio_rtcp: 5604,
+        mic: crate::mic::MIC_PORT,
+    };
+}
+
+impl std::fmt::Display for Ports {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "WebSocket {}, input {}, RTCP {}/{}, microphone {}",
+            self.websocket, self.enet, self.video_rtcp, self.audio_rtcp, self.mic
+        )
+    }
+}
+
+static PORTS: Mutex<Ports> = Mutex::new(Ports::DEFAULT);
+
+/// The ports in use, the defaults until `assign` ran.
+pub fn current() -> Ports {
+    *PORTS.lock().unwrap()
+}
+
+/// Whether any port had to move off its default.
+pub fn any_moved() -> bool {
+    current() != Ports::DEFAULT
+}
+
+/// Moves the ports another program holds to free ones nearby, so the server starts instead of
+/// failing to bind. Call once on startup, before anything listens.
+pub fn assign() -> Ports {
+    let mut taken = Vec::new();
+    let mut pick = |name: &str, preferred: u16, tcp: bool| {
+        let port = free_port(preferred, tcp, &taken);
+        if port != preferred {
+            warn!("The {} port {} is in use, using {}.", name, preferred, port);
+        }
+        taken.push(port);
+        port
+    };
+
+    let ports = Ports {
+        websocket: pick("WebSocket", Ports::DEFAULT.websocket, true),
+        enet: pick("input", Ports::DEFAULT.enet, false),
+        video_rtcp: pick("video RTCP", Ports::DEFAULT.video_rtcp, false),
+        audio_rtcp: pick("audio RTCP", Ports::DEFAULT.audio_rtcp, false),
+        mic: pick("microphone", Ports::DEFAULT.mic, false),
+    };
+    info!("Listening on ports: {}.", ports);
+    *PORTS.lock().unwrap() = ports;
+    ports
+}
+
+// Binds and closes right away. Another program may still grab the port in between, which then
+// shows up as a bind error like before.
+fn bind(port: u16, tcp: bool) -> Option<SocketAddr> {
+    let addr = (Ipv4Addr::UNSPECIFIED, port);
+    if tcp {
+        TcpListener::bind(addr).and_then(|listener| listener.local_addr())
+    } else {
+        UdpSocket::bind(addr).and_then(|socket| socket.local_addr())
+    }
+    .ok()
+}
+
+// `preferred` if free, else the next free one, else one the OS picks.
+fn free_port(preferred: u16, tcp: bool, taken: &[u16]) -> u16 {
+    (preferred..preferred.saturating_add(SEARCH_RANGE))
+        .find(|port| !taken.contains(port) && bind(*port, tcp).is_some())
+        .or_else(|| bind(0, tcp).map(|addr| addr.port()))
+        .unwrap_or(preferred)
+}
@@ -0,0 +1,112 @@
+use log::{info, warn};
+use std::process::Command;
+use std::sync::Mutex;
+
+const HIGH_PERFORMANCE_SCHEME: &str = "8c5e7fda-e8bf-4a96-9a85-a6e23a8c635c";
+const GAME_BAR_REG_KEY: &str = "HKEY_CURRENT_USER\\Software\\Microsoft\\GameBar";
+const GAME_MODE_REG_VALUE: &str = "AutoGameModeEnabled";
+
+struct SavedPowerState {
+    scheme: Option<String>,
+    // None if the value did not exist before.
+    game_mode: Option<u32>,
+}
+
+// What to restore once the session ends. Some while performance mode is active.
+static SAVED_STATE: Mutex<Option<SavedPowerState>> = Mutex::new(None);
+
+// `powercfg /getactivescheme` prints "Power Scheme GUID: <guid>  (Balanced)".
+fn active_scheme() -> Option<String> {
+    let output = Command::new("powercfg")
+        .arg("/getactivescheme")
+        .output()
+        .ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (_, rest) = stdout.split_once(':')?;
+    rest.split_whitespace().next().map(str::to_string)
+}
+
+fn set_scheme(guid: &str) -> std::io::Result<()> {
+    Command::new("powercfg")
+        .args(["/setactive", guid])
+        .output()?;
+    Ok(())
+}
+
+fn game_mode() -> Option<u32> {
+    let output = Command::new("reg")
+        .args(["query", GAME_BAR_REG_KEY, "/v", GAME_MODE_REG_VALUE])
+        .output()
+        .ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let value = stdout
+        .lines()
+        .find_map(|line| line.trim().strip_prefix(GAME_MODE_REG_VALUE))?
+        .split_whitespace()
+        .last()?;
+    u32::from_str_radix(value.trim_start_matches("0x"), 16).ok()
+}
+
+fn set_game_mode(value: Option<u32>) -> std::io::Result<()> {
+    match value {
+        Some(value) => Command::new("reg")
+            .args([
+                "add",
+                GAME_BAR_REG_KEY,
+                "/v",
+                GAME_MODE_REG_VALUE,
+                "/t",
+                "REG_DWORD",
+                "/d",
+                &value.to_string(),
+                "/f",
+            ])
+            .output()?,
+        None => Command::new("reg")
+            .args(["delete", GAME_BAR_REG_KEY, "/v", GAME_MODE_REG_VALUE, "/f"])
+            .output()?,
+    };
+    Ok(())
+}
+
+/// Switches to the High Performance power plan and enables Game Mode,
+/// remembering the previous settings. Called when a session starts.
+pub fn enter_performance_mode() {
+    let mut saved = SAVED_STATE.lock().unwrap();
+    if saved.is_some() {
+        return;
+    }
+
+    let state = SavedPowerState {
+        scheme: active_scheme(),
+        game_mode: game_mode(),
+    };
+
+    if let Err(e) = set_scheme(HIGH_PERFORMANCE_SCHEME) {
+        warn!("Failed to switch to the High Performance power plan: {}", e);
+    }
+    if let Err(e) = set_game_mode(Some(1)) {
+        warn!("Failed to enable Game Mode: {}", e);
+    }
+
+    info!("Performance mode enabled.");
+    *saved = Some(state);
+}
+
+/// Restores the power plan and Game Mode saved by `enter_performance_mode()`.
+pub fn leave_performance_mode() {
+    let Some(state) = SAVED_STATE.lock().unwrap().take() else {
+        return;
+    };
+
+    if let Some(scheme) = state.scheme {
+        if let Err(e) = set_scheme(&scheme) {
+            warn!("Failed to restore power plan {}: {}", scheme, e);
+        }
+    }
+    if let Err(e) = set_game_mode(state.game_mode) {
+        warn!("Failed to restore Game Mode: {}", e);
+    }
+
+    info!("Performance mode disabled.");
+}
@@ -0,0 +1,182 @@
+use crate::input::init_enigo;
+use crate::stream::{init_gstreamer, is_pipeline_running};
+use gst::prelude::*;
+use gstreamer as gst;
+use log::{info, warn};
+use std::sync::Mutex;
+use std::thread;
+use windows::Win32::Foundation::HANDLE;
+use windows::Win32::System::StationsAndDesktops::{
+    CloseDesktop, GetUserObjectInformationW, OpenInputDesktop, DESKTOP_CONTROL_FLAGS,
+    DESKTOP_READOBJECTS, UOI_NAME,
+};
+use windows::Win32::UI::WindowsAndMessaging::{GetSystemMetrics, SM_REMOTESESSION};
+
+// Long enough for the first frame of a freshly started capture.
+const CAPTURE_CHECK_TIMEOUT_SECONDS: u64 = 3;
+
+/// A reason streaming or input would not work, found before a client runs into it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PreflightIssue {
+    InputUnavailable(String),
+    SecureDesktop,
+    RemoteSession,
+    CaptureFailed(String),
+}
+
+impl PreflightIssue {
+    /// What the user can do about it.
+    pub fn guidance(&self) -> &'static str {
+        match self {
+            PreflightIssue::InputUnavailable(_) => {
+                "Run the server in the signed-in user's session, not as a service, \
+                and as administrator to control elevated apps."
+            }
+            PreflightIssue::SecureDesktop => {
+                "Unlock the host or answer the UAC prompt on it. Windows hides the \
+                lock screen and UAC prompts from other apps."
+            }
+            PreflightIssue::RemoteSession => {
+                "Capture does not work inside Remote Desktop. Disconnect with \
+                'tscon' to the console session instead of closing the RDP window."
+            }
+            PreflightIssue::CaptureFailed(_) => {
+                "Update the GPU driver and make sure a display is connected and turned on, \
+                or use a dummy display plug on headless hosts."
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for PreflightIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PreflightIssue::InputUnavailable(e) => write!(f, "Input injection unavailable: {}", e),
+            PreflightIssue::SecureDesktop => write!(f, "A secure desktop is shown"),
+            PreflightIssue::RemoteSession => write!(f, "Running in a Remote Desktop session"),
+            PreflightIssue::CaptureFailed(e) => write!(f, "Screen capture failed: {}", e),
+        }
+    }
+}
+
+static PREFLIGHT_RUNNING: Mutex<bool> = Mutex::new(false);
+static ISSUES: Mutex<Vec<PreflightIssue>> = Mutex::new(Vec::new());
+
+pub fn is_preflight_running() -> bool {
+    *PREFLIGHT_RUNNING.lock().unwrap()
+}
+
+/// Issues found by the last preflight, for the GUI.
+pub fn issues() -> Vec<PreflightIssue> {
+    ISSUES.lock().unwrap().clone()
+}
+
+/// Checks input and capture on a background thread, unless a check is running already.
+pub fn start_preflight() {
+    {
+        let mut running = PREFLIGHT_RUNNING.lock().unwrap();
+        if *running {
+            return;
+        }
+        *running = true;
+    }
+
+    thread::spawn(|| {
+        let issues = run_preflight();
+        if issues.is_empty() {
+            info!("Preflight passed.");
+        }
+        for issue in issues.iter() {
+            warn!("Preflight: {}. {}", issue, issue.guidance());
+        }
+
+        *ISSUES.lock().unwrap() = issues;
+        *PREFLIGHT_RUNNING.lock().unwrap() = false;
+        crate::gui::request_repaint();
+    });
+}
+
+fn run_preflight() -> Vec<PreflightIssue> {
+    let mut issues = Vec::new();
+
+    if let Err(e) = init_enigo() {
+        issues.push(PreflightIssue::InputUnavailable(e));
+    }
+    if is_remote_session() {
+        issues.push(PreflightIssue::RemoteSession);
+    }
+    if is_secure_desktop() {
+        issues.push(PreflightIssue::SecureDesktop);
+    }
+
+    // A running stream already proves capture works, and a second capture could disturb it.
+    if !is_pipeline_running() {
+        if let Err(e) = check_capture() {
+            issues.push(PreflightIssue::CaptureFailed(e));
+        }
+    }
+
+    issues
+}
+
+fn is_remote_session() -> bool {
+    unsafe { GetSystemMetrics(SM_REMOTESESSION) != 0 }
+}
+
+// The input desktop is "Default" unless the lock screen or a UAC prompt ("Winlogon") is up.
+// Other apps are not even allowed to open the secure desktop.
+fn is_secure_desktop() -> bool {
+    unsafe {
+        let Ok(desktop) = OpenInputDesktop(DESKTOP_CONTROL_FLAGS(0), false, DESKTOP_READOBJECTS)
+        else {
+            return true;
+        };
+
+        let mut name = [0u16; 64];
+        let result = GetUserObjectInformationW(
+            HANDLE(desktop.0),
+            UOI_NAME,
+            Some(name.as_mut_ptr() as *mut _),
+            (name.len() * size_of::<u16>()) as u32,
+            None,
+        );
+        let _ = CloseDesktop(desktop);
+
+        if result.is_err() {
+            return false;
+        }
+
+        let len = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+        !String::from_utf16_lossy(&name[..len]).eq_ignore_ascii_case("Default")
+    }
+}
+
+// Grabs a single frame, the way the stream would.
+fn check_capture() -> Result<(), String> {
+    init_gstreamer();
+
+    let pipeline = gst::parse::launch("d3d11screencapturesrc num-buffers=1 ! fakesink")
+        .map_err(|e| e.to_string())?
+        .downcast::<gst::Pipeline>()
+        .unwrap();
+
+    pipeline
+        .set_state(gst::State::Playing)
+        .map_err(|e| e.to_string())?;
+
+    let result = match pipeline.bus().and_then(|bus| {
+        bus.timed_pop_filtered(
+            gst::ClockTime::from_seconds(CAPTURE_CHECK_TIMEOUT_SECONDS),
+            &[gst::MessageType::Eos, gst::MessageType::Error],
+        )
+    }) {
+        Some(msg) => match msg.view() {
+            gst::MessageView::Error(err) => Err(err.error().to_string()),
+            _ => Ok(()),
+        },
+        None => Err("No frame within the timeout".to_string()),
+    };
+
+    let _ = pipeline.set_state(gst::State::Null);
+    result
+}
@@ -0,0 +1,133 @@
+use crate::control::{broadcast_event, ControlEvent};
+use gstreamer as gst;
+use gstreamer_video as gst_video;
+use log::{info, warn};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Reading a captured frame back from the GPU costs a copy, so only one is checked per interval.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+// Black for this long before it counts, so fades and loading screens don't.
+const BLACK_DURATION: Duration = Duration::from_secs(3);
+// Brightest channel value still taken as black. Protected content is masked with pure black.
+const BLACK_LEVEL: u8 = 8;
+// Pixels checked per row and per column.
+const SAMPLE_GRID: u32 = 32;
+
+struct Detector {
+    last_check: Option<Instant>,
+    black_since: Option<Instant>,
+    // Whether the black frames were reported as protected content.
+    detected: bool,
+}
+
+// Present while a stream is running.
+static DETECTOR: Mutex<Option<Detector>> = Mutex::new(None);
+// Whether video is held on the last frame while protected content is captured.
+static PAUSE_ON_DETECT: AtomicBool = AtomicBool::new(false);
+
+/// Starts watching the capture of a new stream.
+pub fn start() {
+    *DETECTOR.lock().unwrap() = Some(Detector {
+        last_check: None,
+        black_since: None,
+        detected: false,
+    });
+}
+
+pub fn stop() {
+    *DETECTOR.lock().unwrap() = None;
+}
+
+pub fn set_pause_on_detect(pause: bool) {
+    PAUSE_ON_DETECT.store(pause, Ordering::Relaxed);
+}
+
+/// Whether the capture currently shows protected content, for the GUI.
+pub fn is_detected() -> bool {
+    DETECTOR
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map_or(false, |detector| detector.detected)
+}
+
+// Samples a grid of pixels, the capture delivers BGRA.
+fn is_black(buffer: &gst::BufferRef, info: &gst_video::VideoInfo) -> Option<bool> {
+    if info.format() != gst_video::VideoFormat::Bgra {
+        return None;
+    }
+    let frame = gst_video::VideoFrameRef::from_buffer_ref_readable(buffer, info).ok()?;
+    let data = frame.plane_data(0).ok()?;
+    let stride = frame.plane_stride()[0] as usize;
+
+    let (width, height) = (info.width(), info.height());
+    let black = (0..SAMPLE_GRID).all(|row| {
+        let y = (row * height / SAMPLE_GRID) as usize;
+        (0..SAMPLE_GRID).all(|column| {
+            let x = (column * width / SAMPLE_GRID) as usize;
+            let offset = y * stride + x * 4;
+            data.get(offset..offset + 3)
+                .map_or(true, |bgr| bgr.iter().all(|&value| value <= BLACK_LEVEL))
+        })
+    });
+    Some(black)
+}
+
+/// Checks a captured frame for the black picture Windows substitutes for DRM-protected content.
+/// Returns whether the frame should be dropped. Called from the streaming thread of capture.
+pub fn check_frame(buffer: &gst::BufferRef, caps: Option<&gst::CapsRef>) -> bool {
+    let now = Instant::now();
+    let pause = PAUSE_ON_DETECT.load(Ordering::Relaxed);
+
+    let changed = {
+        let mut guard = DETECTOR.lock().unwrap();
+        let Some(detector) = guard.as_mut() else {
+            return false;
+        };
+        if detector
+            .last_check
+            .map_or(false, |last| now.duration_since(last) < CHECK_INTERVAL)
+        {
+            return detector.detected && pause;
+        }
+        detector.last_check = Some(now);
+
+        let Some(black) = caps
+            .and_then(|caps| gst_video::VideoInfo::from_caps(caps).ok())
+            .and_then(|info| is_black(buffer, &info))
+        else {
+            return false;
+        };
+
+        detector.black_since = if black {
+            detector.black_since.or(Some(now))
+        } else {
+            None
+        };
+        let detected = detector
+            .black_since
+            .map_or(false, |since| now.duration_since(since) >= BLACK_DURATION);
+
+        let changed = detected != detector.detected;
+        detector.detected = detected;
+        changed.then_some(detected)
+    };
+
+    // Sent without holding the detector, broadcasting locks the streaming state.
+    if let Some(detected) = changed {
+        if detected {
+            warn!("Capture has been black for a while, likely protected content.");
+        } else {
+            info!("Capture shows content again.");
+        }
+        broadcast_event(&ControlEvent::ProtectedContent {
+            active: detected,
+            paused: detected && pause,
+        });
+        crate::gui::request_repaint();
+    }
+
+    is_detected() && pause
+}
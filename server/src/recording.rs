@@ -0,0 +1,316 @@
+use crate::rtp::{RtpSession, CURRENT_SESSION};
+use crate::stream::pipeline_element;
+use chrono::Utc;
+use gst::prelude::*;
+use gstreamer as gst;
+use log::{error, info, warn};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::time::Duration;
+
+// How long the muxer gets to write its index once the streams end.
+const FINALIZE_TIMEOUT: Duration = Duration::from_secs(5);
+// Where recordings go unless a directory is configured, relative to the working directory.
+const DEFAULT_DIRECTORY: &str = "recordings";
+// Encoded media the recording may fall behind by before it drops some, rather than stalling the
+// stream on a slow disk.
+const MAX_QUEUED_NS: u64 = 2_000_000_000;
+
+/// Container recordings are written in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordingFormat {
+    Mp4,
+    Mkv,
+}
+
+impl RecordingFormat {
+    pub const ALL: [RecordingFormat; 2] = [RecordingFormat::Mp4, RecordingFormat::Mkv];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RecordingFormat::Mp4 => "mp4",
+            RecordingFormat::Mkv => "mkv",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "mp4" => Some(RecordingFormat::Mp4),
+            "mkv" => Some(RecordingFormat::Mkv),
+            _ => None,
+        }
+    }
+
+    fn muxer(&self) -> &'static str {
+        match self {
+            RecordingFormat::Mp4 => "mp4mux",
+            RecordingFormat::Mkv => "matroskamux",
+        }
+    }
+}
+
+impl std::fmt::Display for RecordingFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecordingFormat::Mp4 => write!(f, "MP4"),
+            RecordingFormat::Mkv => write!(f, "Matroska (MKV)"),
+        }
+    }
+}
+
+struct Output {
+    // Empty for `DEFAULT_DIRECTORY`.
+    directory: String,
+    format: RecordingFormat,
+}
+
+// A file being written from the running pipeline.
+struct Recording {
+    bin: gst::Bin,
+    tee_pads: Vec<(gst::Element, gst::Pad)>,
+    path: PathBuf,
+    // Gets a message once the end of the streams reached the file.
+    finished: mpsc::Receiver<()>,
+}
+
+static OUTPUT: Mutex<Output> = Mutex::new(Output {
+    directory: String::new(),
+    format: RecordingFormat::Mp4,
+});
+// Whether the host wants the stream recorded, kept across pipeline restarts.
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+static ACTIVE: Mutex<Option<Recording>> = Mutex::new(None);
+
+/// Where and how the next recording is written.
+pub fn configure(directory: &str, format: RecordingFormat) {
+    *OUTPUT.lock().unwrap() = Output {
+        directory: directory.to_string(),
+        format,
+    };
+}
+
+/// Whether recording is on, even if no stream is running to record.
+pub fn is_requested() -> bool {
+    REQUESTED.load(Ordering::Relaxed)
+}
+
+/// The file being written, None while nothing is recorded.
+pub fn current_file() -> Option<PathBuf> {
+    ACTIVE
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|recording| recording.path.clone())
+}
+
+/// Turns recording on or off. A running stream starts or finishes its file right away, otherwise
+/// recording starts with the next stream. Blocking while a file is finished.
+pub fn set_recording(enabled: bool) {
+    REQUESTED.store(enabled, Ordering::Relaxed);
+    if !enabled {
+        stop();
+        return;
+    }
+
+    let pipeline = pipeline_element("videoenctee")
+        .and_then(|tee| tee.parent())
+        .and_downcast::<gst::Pipeline>();
+    let session = *CURRENT_SESSION.lock().unwrap();
+    match (pipeline, session) {
+        (Some(pipeline), Some(session)) => start(&pipeline, &session),
+        _ => info!("Recording starts with the next stream."),
+    }
+}
+
+/// Starts a new file if recording is on, once `pipeline` plays. Each pipeline gets its own file,
+/// since the encoder settings may have changed in between.
+pub fn start(pipeline: &gst::Pipeline, session: &RtpSession) {
+    if !is_requested() {
+        return;
+    }
+    let mut active = ACTIVE.lock().unwrap();
+    if active.is_some() {
+        return;
+    }
+
+    match start_file(pipeline, session) {
+        Ok(recording) => {
+            info!("Recording to {}.", recording.path.display());
+            *active = Some(recording);
+        }
+        Err(e) => {
+            error!("Failed to start recording: {}", e);
+            REQUESTED.store(false, Ordering::Relaxed);
+        }
+    }
+    crate::gui::request_repaint();
+}
+
+/// Finishes the file being written so players can open it, e.g. before the pipeline stops.
+/// Blocking until the muxer wrote its index.
+pub fn stop() {
+    let Some(recording) = ACTIVE.lock().unwrap().take() else {
+        return;
+    };
+    finish(recording);
+    crate::gui::request_repaint();
+}
+
+// Records the encoded streams clients get, nothing is encoded twice.
+fn start_file(pipeline: &gst::Pipeline, session: &RtpSession) -> Result<Recording, String> {
+    let (directory, format) = {
+        let output = OUTPUT.lock().unwrap();
+        let directory = if output.directory.is_empty() {
+            DEFAULT_DIRECTORY.to_string()
+        } else {
+            output.directory.clone()
+        };
+        (directory, output.format)
+    };
+    std::fs::create_dir_all(&directory).map_err(|e| format!("{}: {}", directory, e))?;
+    let path = Path::new(&directory).join(format!(
+        "recording_{}.{}",
+        Utc::now().format("%Y%m%d_%H%M%S"),
+        format.as_str()
+    ));
+
+    let queue_str = format!(
+        "queue max-size-buffers=0 max-size-bytes=0 max-size-time={} leaky=downstream",
+        MAX_QUEUED_NS
+    );
+    let audio_tee = pipeline.by_name("audioenctee");
+    let mut description = format!(
+        "{} name=recvideo ! {} ! {} name=recmux ! filesink name=recsink location=\"{}\"",
+        queue_str,
+        session.video_codec.parser(),
+        format.muxer(),
+        path.display().to_string().replace('\\', "\\\\")
+    );
+    if audio_tee.is_some() {
+        description += &format!(
+            " {} name=recaudio ! {} ! recmux.",
+            queue_str,
+            session.audio_codec.parser()
+        );
+    }
+
+    let bin = gst::parse::bin_from_description(&description, false).map_err(|e| e.to_string())?;
+
+    // Frames before the first keyframe cannot be decoded, so the file starts with one.
+    if let Some(pad) = bin
+        .by_name("recvideo")
+        .and_then(|queue| queue.static_pad("src"))
+    {
+        pad.add_probe(gst::PadProbeType::BUFFER, |_, info| {
+            if let Some(gst::PadProbeData::Buffer(ref buffer)) = info.data {
+                if buffer.flags().contains(gst::BufferFlags::DELTA_UNIT) {
+                    return gst::PadProbeReturn::Drop;
+                }
+            }
+            gst::PadProbeReturn::Remove
+        });
+    }
+
+    let (finished_tx, finished) = mpsc::channel();
+    if let Some(pad) = bin
+        .by_name("recsink")
+        .and_then(|sink| sink.static_pad("sink"))
+    {
+        pad.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |_, info| {
+            if let Some(gst::PadProbeData::Event(ref event)) = info.data {
+                if event.type_() == gst::EventType::Eos {
+                    let _ = finished_tx.send(());
+                }
+            }
+            gst::PadProbeReturn::Ok
+        });
+    }
+
+    pipeline.add(&bin).map_err(|e| e.to_string())?;
+    let mut recording = Recording {
+        bin,
+        tee_pads: Vec::new(),
+        path,
+        finished,
+    };
+    if let Err(e) = link_recording(pipeline, &mut recording, audio_tee) {
+        finish(recording);
+        return Err(e.to_string());
+    }
+
+    crate::stream::request_keyframe();
+    Ok(recording)
+}
+
+fn link_recording(
+    pipeline: &gst::Pipeline,
+    recording: &mut Recording,
+    audio_tee: Option<gst::Element>,
+) -> Result<(), gst::glib::BoolError> {
+    recording.bin.sync_state_with_parent()?;
+
+    for (tee, queue_name) in [
+        (pipeline.by_name("videoenctee"), "recvideo"),
+        (audio_tee, "recaudio"),
+    ] {
+        let (Some(tee), Some(queue_pad)) = (
+            tee,
+            recording
+                .bin
+                .by_name(queue_name)
+                .and_then(|queue| queue.static_pad("sink")),
+        ) else {
+            continue;
+        };
+
+        let ghost_pad = gst::GhostPad::with_target(&queue_pad)?;
+        ghost_pad.set_active(true)?;
+        recording.bin.add_pad(&ghost_pad)?;
+
+        let Some(tee_pad) = tee.request_pad_simple("src_%u") else {
+            continue;
+        };
+        if let Err(e) = tee_pad.link(&ghost_pad) {
+            tee.release_request_pad(&tee_pad);
+            return Err(gst::glib::bool_error!(
+                "Failed to link {}: {:?}",
+                queue_name,
+                e
+            ));
+        }
+        recording.tee_pads.push((tee, tee_pad));
+    }
+    Ok(())
+}
+
+// Cuts the recording off the tees and ends its streams, so the muxer writes its index before the
+// branch is removed. The stream to clients goes on.
+fn finish(recording: Recording) {
+    let linked = !recording.tee_pads.is_empty();
+    for (tee, tee_pad) in recording.tee_pads {
+        tee_pad.add_probe(gst::PadProbeType::IDLE, move |pad, _| {
+            if let Some(peer) = pad.peer() {
+                let _ = pad.unlink(&peer);
+                peer.send_event(gst::event::Eos::new());
+            }
+            tee.release_request_pad(pad);
+            gst::PadProbeReturn::Remove
+        });
+    }
+
+    if linked {
+        match recording.finished.recv_timeout(FINALIZE_TIMEOUT) {
+            Ok(()) => info!("Saved the recording {}.", recording.path.display()),
+            Err(_) => warn!(
+                "The recording {} was not finished in time and may not play.",
+                recording.path.display()
+            ),
+        }
+    }
+
+    let _ = recording.bin.set_state(gst::State::Null);
+    if let Some(parent) = recording.bin.parent().and_downcast::<gst::Bin>() {
+        let _ = parent.remove(&recording.bin);
+    }
+}
@@ -0,0 +1,77 @@
+use chrono::Utc;
+use log::{info, warn};
+use serde_json::json;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Instant;
+
+struct Recorder {
+    writer: BufWriter<File>,
+    // Entries are timed from the first one.
+    started: Option<Instant>,
+}
+
+// Present while recording.
+static RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+
+/// Starts recording what clients send to a new file in the working directory, in the capture
+/// format `rstream-replay` plays back. Only with `--record-protocol`, since captures hold
+/// everything typed while they run. The PIN and client secret are left out, fill the PIN in
+/// before replaying.
+pub fn start() -> std::io::Result<()> {
+    let path = PathBuf::from(format!(
+        "protocol_capture_{}.jsonl",
+        Utc::now().format("%Y%m%d_%H%M%S")
+    ));
+    let writer = BufWriter::new(File::create(&path)?);
+    *RECORDER.lock().unwrap() = Some(Recorder {
+        writer,
+        started: None,
+    });
+    info!(
+        "Recording control messages and input to {}.",
+        path.display()
+    );
+    Ok(())
+}
+
+/// Records a control message as the client sent it, unless it is no JSON. The PIN or pairing code
+/// and the client secret of a stream config are replaced.
+pub fn record_control(text: &str) {
+    if let Ok(mut control) = serde_json::from_str::<serde_json::Value>(text) {
+        for key in ["pin", "client_secret"] {
+            if let Some(secret) = control.get_mut(key) {
+                *secret = json!("<redacted>");
+            }
+        }
+        record(json!({ "control": control }));
+    }
+}
+
+/// Records an ENet packet, in hex like the captures take it.
+pub fn record_input(data: &[u8], channel: u8, reliable: bool) {
+    let input = data
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(" ");
+    record(json!({ "input": input, "channel": channel, "reliable": reliable }));
+}
+
+fn record(mut entry: serde_json::Value) {
+    let mut recorder = RECORDER.lock().unwrap();
+    let Some(current) = recorder.as_mut() else {
+        return;
+    };
+    let started = *current.started.get_or_insert_with(Instant::now);
+    entry["at_ms"] = json!(started.elapsed().as_millis() as u64);
+
+    // Flushed on every entry, the server is usually stopped by closing it.
+    let written = writeln!(current.writer, "{}", entry).and_then(|_| current.writer.flush());
+    if let Err(e) = written {
+        warn!("Stopped recording the protocol capture: {}", e);
+        *recorder = None;
+    }
+}
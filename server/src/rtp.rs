@@ -0,0 +1,179 @@
+use crate::audio::AudioCodec;
+use crate::color::Colorimetry;
+use crate::encoder::VideoCodec;
+use log::warn;
+use serde::Serialize;
+use std::sync::Mutex;
+
+pub const VIDEO_PAYLOAD_TYPE: u8 = 96;
+pub const AUDIO_PAYLOAD_TYPE: u8 = 127;
+pub const ULPFEC_PAYLOAD_TYPE: u8 = 122;
+pub const RED_PAYLOAD_TYPE: u8 = 123;
+pub const MIC_PAYLOAD_TYPE: u8 = 111;
+
+// RTCP packet types carrying SSRCs of media we send, see RFC 3550 and RFC 4585.
+pub(crate) const RTCP_SR: u8 = 200;
+pub(crate) const RTCP_RR: u8 = 201;
+pub(crate) const RTCP_RTPFB: u8 = 205;
+pub(crate) const RTCP_PSFB: u8 = 206;
+
+/// Video FEC of a session: ULPFEC packets (RFC 5109) sent along the media in RED (RFC 2198),
+/// all on the video SSRC.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct VideoFec {
+    pub red_payload_type: u8,
+    pub ulpfec_payload_type: u8,
+}
+
+/// Where a client sends its microphone: Opus in RTP to `port` of the host, see `mic`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct MicChannel {
+    pub port: u16,
+    pub payload_type: u8,
+    // Packets with another SSRC are dropped.
+    pub ssrc: u32,
+}
+
+/// RTP identifiers of one streaming session. Sent to clients so their jitter
+/// buffers can tell a new session apart from late packets of the previous one.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct RtpSession {
+    pub video_ssrc: u32,
+    pub video_payload_type: u8,
+    pub video_codec: VideoCodec,
+    pub audio_ssrc: u32,
+    pub audio_payload_type: u8,
+    pub audio_codec: AudioCodec,
+    // 2 for stereo, 6 for 5.1 and 8 for 7.1, in the Vorbis channel order.
+    pub audio_channels: u32,
+    // Whether audio shares the video port, to be told apart by SSRC and payload type.
+    pub bundle: bool,
+    pub video_fec: Option<VideoFec>,
+    // Whether video is HDR10: 10 bits, BT.2020 primaries and the PQ transfer function.
+    pub hdr: bool,
+    // Whether video keeps full-resolution chroma, see `VideoCodec::caps_444_str`.
+    pub chroma_444: bool,
+    // Range and matrix of SDR video, as signaled in its bitstream. None for HDR.
+    pub colorimetry: Option<Colorimetry>,
+    // Set if the host plays client microphones into a device.
+    pub mic: Option<MicChannel>,
+}
+
+impl RtpSession {
+    pub fn new(
+        bundle: bool,
+        video_codec: VideoCodec,
+        video_fec: bool,
+        hdr: bool,
+        chroma_444: bool,
+        mic: bool,
+        audio_codec: AudioCodec,
+        audio_channels: u32,
+    ) -> Self {
+        let video_ssrc = rand::random();
+        // Distinct SSRCs make it obvious which stream a report is about.
+        let mut audio_ssrc = rand::random();
+        while audio_ssrc == video_ssrc {
+            audio_ssrc = rand::random();
+        }
+        let mut mic_ssrc = rand::random();
+        while mic_ssrc == video_ssrc || mic_ssrc == audio_ssrc {
+            mic_ssrc = rand::random();
+        }
+
+        Self {
+            video_ssrc,
+            video_payload_type: VIDEO_PAYLOAD_TYPE,
+            video_codec,
+            audio_ssrc,
+            audio_payload_type: AUDIO_PAYLOAD_TYPE,
+            audio_codec,
+            audio_channels,
+            bundle,
+            video_fec: video_fec.then_some(VideoFec {
+                red_payload_type: RED_PAYLOAD_TYPE,
+                ulpfec_payload_type: ULPFEC_PAYLOAD_TYPE,
+            }),
+            hdr,
+            chroma_444,
+            colorimetry: None,
+            mic: mic.then_some(MicChannel {
+                port: crate::ports::current().mic,
+                payload_type: MIC_PAYLOAD_TYPE,
+                ssrc: mic_ssrc,
+            }),
+        }
+    }
+}
+
+// The session of the running pipeline.
+pub static CURRENT_SESSION: Mutex<Option<RtpSession>> = Mutex::new(None);
+
+// Collects the media SSRCs a compound RTCP packet reports on,
+// with the fraction lost (out of 256) for report blocks.
+fn reported_ssrcs(data: &[u8]) -> Vec<(u32, Option<u8>)> {
+    let read_u32 = |at: usize| {
+        data.get(at..at + 4)
+            .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+
+    let mut ssrcs = Vec::new();
+    let mut offset = 0;
+
+    while offset + 4 <= data.len() {
+        let count = (data[offset] & 0x1f) as usize;
+        let packet_type = data[offset + 1];
+        let length = (u16::from_be_bytes([data[offset + 2], data[offset + 3]]) as usize + 1) * 4;
+
+        match packet_type {
+            // Report blocks follow the sender SSRC (and sender info for SRs).
+            RTCP_SR | RTCP_RR => {
+                let blocks = offset + if packet_type == RTCP_SR { 28 } else { 8 };
+                for block in 0..count {
+                    let at = blocks + block * 24;
+                    if let Some(ssrc) = read_u32(at) {
+                        ssrcs.push((ssrc, data.get(at + 4).copied()));
+                    }
+                }
+            }
+            // Feedback names the media source after the sender SSRC.
+            RTCP_RTPFB | RTCP_PSFB => {
+                ssrcs.extend(read_u32(offset + 8).map(|ssrc| (ssrc, None)));
+            }
+            _ => {}
+        }
+
+        offset += length;
+    }
+
+    ssrcs
+}
+
+/// Whether an incoming RTCP packet is about the given stream of the current session.
+/// Reports from clients still tuned to an old session would confuse rtpbin's statistics.
+pub fn validate_rtcp(data: &[u8], expected_ssrc: u32) -> bool {
+    let ssrcs: Vec<u32> = reported_ssrcs(data)
+        .into_iter()
+        .map(|(ssrc, _)| ssrc)
+        .collect();
+
+    // Packets without reports (e.g. SDES, BYE) are harmless.
+    if ssrcs.is_empty() || ssrcs.contains(&expected_ssrc) {
+        return true;
+    }
+
+    warn!(
+        "Dropping RTCP for unknown SSRC(s) {:08x?}, expected {:08x}.",
+        ssrcs, expected_ssrc
+    );
+    false
+}
+
+/// Fraction (0 to 1) of the given stream's packets an RTCP receiver report says were lost.
+pub fn reported_loss(data: &[u8], ssrc: u32) -> Option<f32> {
+    reported_ssrcs(data)
+        .into_iter()
+        .filter(|(reported, _)| *reported == ssrc)
+        .find_map(|(_, fraction_lost)| fraction_lost)
+        .map(|fraction_lost| fraction_lost as f32 / 256.0)
+}
@@ -0,0 +1,83 @@
+use crate::control::{broadcast_event, ControlEvent};
+use crate::stream::{is_pipeline_running, restart_gstreamer_pipeline};
+use log::{info, warn};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::thread;
+
+/// Failed starts in a row after which the pipeline falls back to safe mode.
+const MAX_FAILED_STARTS: u32 = 3;
+
+/// The stream in safe mode fits in this size.
+pub const SAFE_WIDTH: u32 = 1280;
+pub const SAFE_HEIGHT: u32 = 720;
+pub const SAFE_FRAMERATE: u32 = 30;
+
+/// Shown wherever safe mode is explained.
+pub const SAFE_MODE_DESCRIPTION: &str = "720p30 with software H.264 and no audio";
+
+static FAILED_STARTS: AtomicU32 = AtomicU32::new(0);
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Whether pipelines are built with the conservative settings, see `SAFE_MODE_DESCRIPTION`.
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Counts a pipeline that failed before it played. Enough of them in a row switch to safe mode
+/// and restart the stream with it.
+pub fn record_failed_start() {
+    let failed = FAILED_STARTS.fetch_add(1, Ordering::Relaxed) + 1;
+    if failed < MAX_FAILED_STARTS || is_active() {
+        return;
+    }
+
+    warn!(
+        "The pipeline failed to start {} times in a row, switching to safe mode: {}.",
+        failed, SAFE_MODE_DESCRIPTION
+    );
+    set_active(true);
+    crate::diagnostics::report_failure(&format!(
+        "The pipeline failed to start {} times in a row",
+        failed
+    ));
+}
+
+/// Counts a pipeline that played.
+pub fn record_started() {
+    FAILED_STARTS.store(0, Ordering::Relaxed);
+}
+
+/// Leaves safe mode, e.g. once the host fixed the settings, or enters it by hand. A running
+/// stream restarts with the new settings.
+pub fn set_active(active: bool) {
+    if ACTIVE.swap(active, Ordering::Relaxed) == active {
+        return;
+    }
+    FAILED_STARTS.store(0, Ordering::Relaxed);
+    if !active {
+        info!("Leaving safe mode.");
+    }
+
+    broadcast_event(&ControlEvent::SafeMode {
+        active,
+        description: SAFE_MODE_DESCRIPTION.to_string(),
+    });
+    crate::gui::request_repaint();
+    thread::spawn(|| {
+        if is_pipeline_running() || active {
+            restart_gstreamer_pipeline();
+        }
+    });
+}
+
+/// The largest size with the aspect ratio of `width`x`height` that fits safe mode, in even
+/// pixels as encoders want.
+pub fn fit_size(width: u32, height: u32) -> (u32, u32) {
+    let scale = (SAFE_WIDTH as f32 / width.max(1) as f32)
+        .min(SAFE_HEIGHT as f32 / height.max(1) as f32)
+        .min(1.0);
+    (
+        ((width as f32 * scale) as u32) & !1,
+        ((height as f32 * scale) as u32) & !1,
+    )
+}
@@ -0,0 +1,309 @@
+use crate::encoder::{encoder_blocked, VideoEncoder};
+use crate::stream::{
+    check_factory_exists, init_gstreamer, is_pipeline_running, STREAMING_STATE_GUARD,
+};
+use byteorder::{LittleEndian, WriteBytesExt};
+use gst::prelude::*;
+use gstreamer as gst;
+use log::{info, warn};
+use rusty_enet as enet;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const TEST_SECONDS: u64 = 5;
+// Loopback port for the test stream, separate from the real one.
+const TEST_PORT: u16 = 5611;
+const TEST_WIDTH: usize = 1280;
+const TEST_HEIGHT: usize = 720;
+// Side length of the luma blocks that carry one timestamp bit each.
+const STAMP_BLOCK: usize = 16;
+const MIN_STAMPED_FRAMES: usize = 30;
+// Anything slower is treated as a misread stamp.
+const MAX_PLAUSIBLE_LATENCY_MICROS: u32 = 5_000_000;
+const INPUT_CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub struct SelfTestReport {
+    pub passed: bool,
+    pub frames_decoded: usize,
+    pub average_latency_ms: Option<f32>,
+    pub max_latency_ms: Option<f32>,
+    pub input_ok: bool,
+    pub message: String,
+}
+
+impl std::fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} frames decoded",
+            if self.passed { "Passed" } else { "Failed" },
+            self.frames_decoded
+        )?;
+        if let (Some(average), Some(max)) = (self.average_latency_ms, self.max_latency_ms) {
+            write!(f, ", latency {:.1} ms (max {:.1} ms)", average, max)?;
+        }
+        write!(
+            f,
+            ", input {}",
+            if self.input_ok { "OK" } else { "unreachable" }
+        )?;
+        if !self.message.is_empty() {
+            write!(f, ". {}", self.message)?;
+        }
+        Ok(())
+    }
+}
+
+static SELF_TEST_RUNNING: Mutex<bool> = Mutex::new(false);
+// Whether the last self-test passed, and its summary.
+static LAST_REPORT: Mutex<Option<(bool, String)>> = Mutex::new(None);
+
+pub fn is_self_test_running() -> bool {
+    *SELF_TEST_RUNNING.lock().unwrap()
+}
+
+/// Outcome and summary of the last self-test, for the GUI.
+pub fn last_report() -> Option<(bool, String)> {
+    LAST_REPORT.lock().unwrap().clone()
+}
+
+/// Runs the self-test on a background thread, unless one is running already.
+pub fn start_self_test() {
+    {
+        let mut running = SELF_TEST_RUNNING.lock().unwrap();
+        if *running {
+            return;
+        }
+        *running = true;
+    }
+
+    thread::spawn(|| {
+        let report = run_self_test();
+        info!("Self-test finished. {}", report);
+
+        *LAST_REPORT.lock().unwrap() = Some((report.passed, report.to_string()));
+        *SELF_TEST_RUNNING.lock().unwrap() = false;
+        crate::gui::request_repaint();
+    });
+}
+
+// Writes `value` into the top rows of an NV12 frame as black and white blocks, most significant bit first.
+fn write_stamp(luma: &mut [u8], value: u32) {
+    for bit in 0..32 {
+        let level = if (value >> (31 - bit)) & 1 == 1 {
+            235
+        } else {
+            16
+        };
+        for row in 0..STAMP_BLOCK {
+            let start = row * TEST_WIDTH + bit * STAMP_BLOCK;
+            luma[start..start + STAMP_BLOCK].fill(level);
+        }
+    }
+}
+
+// Reads a stamp back by sampling the centre of each block, which survives compression.
+fn read_stamp(luma: &[u8]) -> u32 {
+    (0..32).fold(0, |value, bit| {
+        let sample = luma[STAMP_BLOCK / 2 * TEST_WIDTH + bit * STAMP_BLOCK + STAMP_BLOCK / 2];
+        (value << 1) | (sample > 128) as u32
+    })
+}
+
+/// Streams the screen to a decoder on this machine and checks the input port, as a client would. Blocking.
+pub fn run_self_test() -> SelfTestReport {
+    let mut report = SelfTestReport {
+        passed: false,
+        frames_decoded: 0,
+        average_latency_ms: None,
+        max_latency_ms: None,
+        input_ok: false,
+        message: String::new(),
+    };
+
+    let has_peers = {
+        let guard = STREAMING_STATE_GUARD.lock().unwrap();
+        guard
+            .as_ref()
+            .map_or(false, |state| !state.peers.is_empty())
+    };
+    if has_peers || is_pipeline_running() {
+        report.message = "Disconnect all clients before running the self-test.".into();
+        return report;
+    }
+
+    report.input_ok = check_input_port();
+
+    let latencies = match run_video_loopback() {
+        Ok(latencies) => latencies,
+        Err(e) => {
+            report.message = e;
+            return report;
+        }
+    };
+
+    report.frames_decoded = latencies.len();
+    if let Some(max) = latencies.iter().max() {
+        let sum: u64 = latencies.iter().map(|&latency| latency as u64).sum();
+        report.average_latency_ms = Some(sum as f32 / latencies.len() as f32 / 1000.0);
+        report.max_latency_ms = Some(*max as f32 / 1000.0);
+    }
+
+    if report.frames_decoded < MIN_STAMPED_FRAMES {
+        report.message = "Too few frames made it through the encoder and decoder.".into();
+    } else if !report.input_ok {
+        report.message = format!(
+            "Nothing answered on input port {}.",
+            crate::ports::current().enet
+        );
+    } else {
+        report.passed = true;
+    }
+
+    report
+}
+
+// Connects to the input server like a client and sends a cursor move.
+// The server ignores input while no stream is configured, so this has no effect on the desktop.
+fn check_input_port() -> bool {
+    let Ok(socket) = UdpSocket::bind("127.0.0.1:0") else {
+        return false;
+    };
+    let Ok(mut host) = enet::Host::new(
+        socket,
+        enet::HostSettings {
+            peer_limit: 1,
+            channel_limit: 2,
+            ..Default::default()
+        },
+    ) else {
+        return false;
+    };
+
+    let server = SocketAddr::from(([127, 0, 0, 1], crate::ports::current().enet));
+    if host.connect(server, 2, 0).is_err() {
+        return false;
+    }
+
+    let started = Instant::now();
+    while started.elapsed() < INPUT_CONNECT_TIMEOUT {
+        match host.service() {
+            Ok(Some(enet::Event::Connect { peer, .. })) => {
+                let mut command = vec![4u8];
+                command.write_f32::<LittleEndian>(0.0).unwrap();
+                command.write_f32::<LittleEndian>(0.0).unwrap();
+
+                let sent = peer.send(0, &enet::Packet::reliable(&command)).is_ok();
+                peer.disconnect(0);
+                host.flush();
+                return sent;
+            }
+            Ok(_) => thread::sleep(Duration::from_millis(10)),
+            Err(e) => {
+                warn!("Self-test input connection failed: {}", e);
+                return false;
+            }
+        }
+    }
+
+    false
+}
+
+// Encodes the screen with a timestamp stamped into each frame, decodes it again
+// and returns the capture-to-decode latency of every frame in microseconds.
+fn run_video_loopback() -> Result<Vec<u32>, String> {
+    init_gstreamer();
+
+    let encoder_str = if check_factory_exists("amfh264enc") && !encoder_blocked(VideoEncoder::Amf) {
+        "amfh264enc preset=speed usage=ultra-low-latency rate-control=cbr bitrate=8000 gop-size=30"
+    } else {
+        "x264enc tune=zerolatency speed-preset=ultrafast bframes=0 bitrate=8000 key-int-max=30"
+    };
+    info!("Self-test using {}", encoder_str);
+
+    let pipeline_str = format!(
+        "d3d11screencapturesrc show-cursor=false ! \
+        d3d11download ! \
+        videoconvert ! \
+        videoscale ! \
+        videorate ! \
+        video/x-raw,width={},height={},format=NV12,framerate=60/1 ! \
+        identity name=stamp ! \
+        {} ! \
+        rtph264pay config-interval=-1 aggregate-mode=zero-latency ! \
+        udpsink host=127.0.0.1 port={} sync=false \
+        udpsrc port={} caps=\"application/x-rtp,media=video,encoding-name=H264,clock-rate=90000,payload=96\" ! \
+        rtph264depay ! \
+        h264parse ! \
+        decodebin ! \
+        videoconvert ! \
+        video/x-raw,width={},height={},format=NV12 ! \
+        fakesink name=sink sync=false",
+        TEST_WIDTH, TEST_HEIGHT, encoder_str, TEST_PORT, TEST_PORT, TEST_WIDTH, TEST_HEIGHT
+    );
+
+    let pipeline = gst::parse::launch(&pipeline_str)
+        .map_err(|e| format!("Failed to build the test pipeline: {}", e))?
+        .downcast::<gst::Pipeline>()
+        .unwrap();
+
+    let start = Instant::now();
+    let latencies = Arc::new(Mutex::new(Vec::new()));
+
+    let stamp_pad = pipeline
+        .by_name("stamp")
+        .and_then(|stamp| stamp.static_pad("src"))
+        .unwrap();
+    stamp_pad.add_probe(gst::PadProbeType::BUFFER, move |_, info| {
+        if let Some(gst::PadProbeData::Buffer(ref mut buffer)) = info.data {
+            let now = start.elapsed().as_micros() as u32;
+            if let Ok(mut map) = buffer.make_mut().map_writable() {
+                write_stamp(&mut map, now);
+            }
+        }
+        gst::PadProbeReturn::Ok
+    });
+
+    let sink_pad = pipeline
+        .by_name("sink")
+        .and_then(|sink| sink.static_pad("sink"))
+        .unwrap();
+    let sink_latencies = latencies.clone();
+    sink_pad.add_probe(gst::PadProbeType::BUFFER, move |_, info| {
+        if let Some(gst::PadProbeData::Buffer(ref buffer)) = info.data {
+            if let Ok(map) = buffer.map_readable() {
+                let now = start.elapsed().as_micros() as u32;
+                let latency = now.wrapping_sub(read_stamp(&map));
+                if latency < MAX_PLAUSIBLE_LATENCY_MICROS {
+                    sink_latencies.lock().unwrap().push(latency);
+                }
+            }
+        }
+        gst::PadProbeReturn::Ok
+    });
+
+    pipeline
+        .set_state(gst::State::Playing)
+        .map_err(|e| format!("Failed to start the test pipeline: {}", e))?;
+
+    thread::sleep(Duration::from_secs(TEST_SECONDS));
+
+    let error = pipeline.bus().and_then(|bus| {
+        bus.pop_filtered(&[gst::MessageType::Error])
+            .and_then(|msg| match msg.view() {
+                gst::MessageView::Error(err) => Some(err.error().to_string()),
+                _ => None,
+            })
+    });
+
+    let _ = pipeline.set_state(gst::State::Null);
+
+    if let Some(error) = error {
+        return Err(format!("The test pipeline failed: {}", error));
+    }
+
+    let latencies = std::mem::take(&mut *latencies.lock().unwrap());
+    Ok(latencies)
+}
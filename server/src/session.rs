@@ -0,0 +1,336 @@
+use crate::control::{broadcast_event, ControlEvent};
+use crate::process::list_processes;
+use crate::stream::{
+    has_authenticated_peer, is_pipeline_running, restart_gstreamer_pipeline,
+    stop_gstreamer_pipeline,
+};
+use log::{info, warn};
+use serde::Serialize;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use windows::core::{w, PCWSTR, PWSTR};
+use windows::Win32::Foundation::{CloseHandle, HANDLE, HWND, LPARAM, LRESULT, WPARAM};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::System::RemoteDesktop::{
+    ProcessIdToSessionId, WTSFreeMemory, WTSGetActiveConsoleSessionId, WTSQuerySessionInformationW,
+    WTSQueryUserToken, WTSRegisterSessionNotification, WTSUserName, NOTIFY_FOR_ALL_SESSIONS,
+    WTS_CURRENT_SERVER_HANDLE,
+};
+use windows::Win32::System::Threading::{
+    CreateProcessAsUserW, PROCESS_CREATION_FLAGS, PROCESS_INFORMATION, STARTUPINFOW,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateWindowExW, DefWindowProcW, DispatchMessageW, GetMessageW, GetSystemMetrics,
+    RegisterClassW, HWND_MESSAGE, MSG, SM_REMOTESESSION, WINDOW_EX_STYLE, WINDOW_STYLE,
+    WM_WTSSESSION_CHANGE, WNDCLASSW, WTS_CONSOLE_CONNECT, WTS_SESSION_LOGON,
+};
+
+// Only used when session notifications are unavailable.
+const SESSION_CHECK_INTERVAL_MILLIS: u64 = 2000;
+const PREDECESSOR_CHECK_INTERVAL_MILLIS: u64 = 200;
+const PREDECESSOR_EXIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Returned by WTSGetActiveConsoleSessionId while the console is switching sessions.
+const NO_CONSOLE_SESSION: u32 = 0xFFFF_FFFF;
+
+/// Where the session of the server is shown. Capture only works while it owns the physical console.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConsoleState {
+    Attached,
+    // Taken over by a Remote Desktop client.
+    Remote,
+    // Another user is on the console, or the session was left without one.
+    Disconnected,
+}
+
+impl std::fmt::Display for ConsoleState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConsoleState::Attached => write!(f, "On the console"),
+            ConsoleState::Remote => write!(f, "Taken over by Remote Desktop"),
+            ConsoleState::Disconnected => write!(f, "Not on the console"),
+        }
+    }
+}
+
+struct Monitor {
+    state: ConsoleState,
+    // Whether a stream was cut off by the detach and should come back with the console.
+    suspended: bool,
+}
+
+static MONITOR: Mutex<Option<Monitor>> = Mutex::new(None);
+
+/// The console state found by the last check, for the GUI.
+pub fn console_state() -> ConsoleState {
+    MONITOR
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map_or(ConsoleState::Attached, |monitor| monitor.state)
+}
+
+fn query_console_state() -> ConsoleState {
+    let mut own_session = 0;
+    if let Err(e) = unsafe { ProcessIdToSessionId(std::process::id(), &mut own_session) } {
+        warn!("Failed to query the session of the server: {}", e);
+        return ConsoleState::Attached;
+    }
+
+    let console_session = unsafe { WTSGetActiveConsoleSessionId() };
+    if console_session == own_session {
+        ConsoleState::Attached
+    } else if unsafe { GetSystemMetrics(SM_REMOTESESSION) } != 0 {
+        ConsoleState::Remote
+    } else {
+        if console_session == NO_CONSOLE_SESSION {
+            info!("The console is switching sessions.");
+        }
+        ConsoleState::Disconnected
+    }
+}
+
+// The user signed in to a session, if any.
+fn session_user(session: u32) -> Option<String> {
+    unsafe {
+        let mut buffer = PWSTR::null();
+        let mut bytes = 0;
+        WTSQuerySessionInformationW(
+            WTS_CURRENT_SERVER_HANDLE,
+            session,
+            WTSUserName,
+            &mut buffer,
+            &mut bytes,
+        )
+        .ok()?;
+
+        let user = buffer.to_string().ok();
+        WTSFreeMemory(buffer.0 as *mut _);
+        user.filter(|user| !user.is_empty())
+    }
+}
+
+/// Watches whether the server's session owns the console, since RDP and fast user switching
+/// leave capture with nothing to show. The stream stops while detached and restarts on return.
+pub fn start_console_monitor() {
+    {
+        let mut monitor = MONITOR.lock().unwrap();
+        if monitor.is_some() {
+            return;
+        }
+        *monitor = Some(Monitor {
+            state: query_console_state(),
+            suspended: false,
+        });
+    }
+
+    thread::spawn(|| {
+        if let Err(e) = run_session_notifications() {
+            warn!(
+                "Session notifications are unavailable ({}), polling instead.",
+                e
+            );
+        }
+
+        loop {
+            thread::sleep(Duration::from_millis(SESSION_CHECK_INTERVAL_MILLIS));
+            check_console();
+        }
+    });
+}
+
+// Blocks on a message-only window that receives WM_WTSSESSION_CHANGE for all sessions.
+fn run_session_notifications() -> windows::core::Result<()> {
+    unsafe {
+        let instance = GetModuleHandleW(None)?;
+        let class_name = w!("RStreamSessionMonitor");
+
+        let class = WNDCLASSW {
+            lpfnWndProc: Some(session_window_proc),
+            hInstance: instance.into(),
+            lpszClassName: class_name,
+            ..Default::default()
+        };
+        if RegisterClassW(&class) == 0 {
+            return Err(windows::core::Error::from_win32());
+        }
+
+        let hwnd = CreateWindowExW(
+            WINDOW_EX_STYLE::default(),
+            class_name,
+            PCWSTR::null(),
+            WINDOW_STYLE::default(),
+            0,
+            0,
+            0,
+            0,
+            HWND_MESSAGE,
+            None,
+            instance,
+            None,
+        );
+        if hwnd.0 == 0 {
+            return Err(windows::core::Error::from_win32());
+        }
+
+        WTSRegisterSessionNotification(hwnd, NOTIFY_FOR_ALL_SESSIONS)?;
+        info!("Watching session changes.");
+
+        let mut msg = MSG::default();
+        while GetMessageW(&mut msg, None, 0, 0).as_bool() {
+            DispatchMessageW(&msg);
+        }
+    }
+    Ok(())
+}
+
+unsafe extern "system" fn session_window_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    if msg != WM_WTSSESSION_CHANGE {
+        return DefWindowProcW(hwnd, msg, wparam, lparam);
+    }
+
+    check_console();
+
+    // Another user took the console: follow them if this process is allowed to.
+    let reason = wparam.0 as u32;
+    if (reason == WTS_CONSOLE_CONNECT || reason == WTS_SESSION_LOGON)
+        && console_state() == ConsoleState::Disconnected
+    {
+        follow_console_session();
+    }
+    LRESULT(0)
+}
+
+fn check_console() {
+    let state = query_console_state();
+    let previous = console_state();
+    if state == previous {
+        return;
+    }
+
+    info!("Console session changed: {} -> {}.", previous, state);
+    on_console_changed(previous, state);
+}
+
+fn on_console_changed(previous: ConsoleState, state: ConsoleState) {
+    let resume = {
+        let mut guard = MONITOR.lock().unwrap();
+        let monitor = guard.as_mut().unwrap();
+        monitor.state = state;
+
+        if previous == ConsoleState::Attached {
+            monitor.suspended = is_pipeline_running();
+            false
+        } else if state == ConsoleState::Attached {
+            std::mem::take(&mut monitor.suspended)
+        } else {
+            false
+        }
+    };
+
+    if previous == ConsoleState::Attached && is_pipeline_running() {
+        // The capture would only fail or freeze, stop it before it floods clients with errors.
+        stop_gstreamer_pipeline();
+    }
+
+    let user = session_user(unsafe { WTSGetActiveConsoleSessionId() });
+    broadcast_event(&ControlEvent::ConsoleSession { state, user });
+
+    if resume && has_authenticated_peer() {
+        info!("Back on the console, restarting capture.");
+        restart_gstreamer_pipeline();
+    }
+    crate::gui::request_repaint();
+}
+
+// Hands the server over to an instance in the session now on the console. Starting a process
+// for another user needs the LocalSystem account, e.g. when launched by a scheduled task
+// running as SYSTEM. Otherwise the stream just stays paused until this session returns.
+fn follow_console_session() {
+    let session = unsafe { WTSGetActiveConsoleSessionId() };
+    if session == NO_CONSOLE_SESSION {
+        return;
+    }
+
+    match launch_in_session(session) {
+        Ok(()) => {
+            info!(
+                "Started the server for {} in session {}, closing this one.",
+                session_user(session).unwrap_or_default(),
+                session
+            );
+            crate::gui::quit();
+        }
+        Err(e) => info!("Not following the console to session {}: {}", session, e),
+    }
+}
+
+fn launch_in_session(session: u32) -> std::io::Result<()> {
+    let exe = std::env::current_exe()?;
+    let mut command_line: Vec<u16> = format!(
+        "\"{}\" --minimized --wait-for {}",
+        exe.display(),
+        std::process::id()
+    )
+    .encode_utf16()
+    .chain(Some(0))
+    .collect();
+    let mut desktop: Vec<u16> = "winsta0\\default".encode_utf16().chain(Some(0)).collect();
+
+    unsafe {
+        let mut token = HANDLE::default();
+        WTSQueryUserToken(session, &mut token)?;
+
+        let startup_info = STARTUPINFOW {
+            cb: size_of::<STARTUPINFOW>() as u32,
+            lpDesktop: PWSTR(desktop.as_mut_ptr()),
+            ..Default::default()
+        };
+        let mut process_info = PROCESS_INFORMATION::default();
+
+        let result = CreateProcessAsUserW(
+            token,
+            PCWSTR::null(),
+            PWSTR(command_line.as_mut_ptr()),
+            None,
+            None,
+            false,
+            PROCESS_CREATION_FLAGS(0),
+            None,
+            PCWSTR::null(),
+            &startup_info,
+            &mut process_info,
+        );
+        let _ = CloseHandle(token);
+        result?;
+
+        let _ = CloseHandle(process_info.hThread);
+        let _ = CloseHandle(process_info.hProcess);
+    }
+    Ok(())
+}
+
+/// Waits for the instance that handed over to this one to release the ports.
+pub fn wait_for_predecessor(pid: u32) {
+    info!("Waiting for the previous server ({}) to exit.", pid);
+
+    let started = Instant::now();
+    while started.elapsed() < PREDECESSOR_EXIT_TIMEOUT {
+        let running = list_processes()
+            .map(|processes| processes.iter().any(|process| process.pid == pid))
+            .unwrap_or(false);
+        if !running {
+            return;
+        }
+        thread::sleep(Duration::from_millis(PREDECESSOR_CHECK_INTERVAL_MILLIS));
+    }
+
+    warn!("The previous server did not exit in time.");
+}
@@ -0,0 +1,258 @@
+use gst::prelude::*;
+use gstreamer as gst;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Frames between capture and sending that are followed, older ones count as dropped.
+const MAX_FRAMES_IN_FLIGHT: usize = 16;
+// Averages follow 1/16 of each new sample, like the RTP jitter estimate of RFC 3550.
+const SMOOTHING: f32 = 16.0;
+// Marks the reference timestamp meta carrying when a frame was captured.
+const CAPTURE_REFERENCE: &str = "timestamp/x-rstream-capture";
+
+/// Timing of the video frames of the running stream, measured on each frame.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct VideoStats {
+    // From capture until the encoder is done with a frame.
+    pub encode_latency_ms: f32,
+    // From capture until the first packet of a frame is sent.
+    pub send_latency_ms: f32,
+    // Between frames being sent.
+    pub frame_interval_ms: f32,
+    // Variation of the send intervals against the capture intervals, see RFC 3550 section 6.4.1.
+    pub frame_jitter_ms: f32,
+    pub frames_sent: u64,
+    // Captured but never sent, e.g. dropped by a full queue in front of the encoder.
+    pub frames_dropped: u64,
+}
+
+struct Frame {
+    pts: gst::ClockTime,
+    captured: Instant,
+    encoded: bool,
+}
+
+struct FrameMonitor {
+    // What capture times in frame metas count from.
+    started: Instant,
+    // How many frames captured earlier may still come after a sent one, with B-frames.
+    reorder_frames: usize,
+    in_flight: VecDeque<Frame>,
+    encode_latency_ms: Option<f32>,
+    send_latency_ms: Option<f32>,
+    last_sent: Option<(Instant, gst::ClockTime)>,
+    frame_interval_ms: Option<f32>,
+    frame_jitter_ms: f32,
+    frames_sent: u64,
+    frames_dropped: u64,
+}
+
+impl FrameMonitor {
+    fn stats(&self) -> Option<VideoStats> {
+        Some(VideoStats {
+            encode_latency_ms: self.encode_latency_ms?,
+            send_latency_ms: self.send_latency_ms?,
+            frame_interval_ms: self.frame_interval_ms.unwrap_or(0.0),
+            frame_jitter_ms: self.frame_jitter_ms,
+            frames_sent: self.frames_sent,
+            frames_dropped: self.frames_dropped,
+        })
+    }
+}
+
+fn smooth(average: Option<f32>, sample: f32) -> f32 {
+    average.map_or(sample, |average| average + (sample - average) / SMOOTHING)
+}
+
+// Present while a video stream is running.
+static MONITOR: Mutex<Option<FrameMonitor>> = Mutex::new(None);
+
+/// Starts measuring the frames of a new pipeline: when they are captured, leave the encoder and
+/// are handed to rtpbin for sending. `reorder_frames` is the B-frame count of `encoder`.
+pub fn attach(pipeline: &gst::Pipeline, encoder: &gst::Element, reorder_frames: usize) {
+    *MONITOR.lock().unwrap() = Some(FrameMonitor {
+        started: Instant::now(),
+        reorder_frames,
+        in_flight: VecDeque::new(),
+        encode_latency_ms: None,
+        send_latency_ms: None,
+        last_sent: None,
+        frame_interval_ms: None,
+        frame_jitter_ms: 0.0,
+        frames_sent: 0,
+        frames_dropped: 0,
+    });
+
+    // videorate puts frames on a grid of its own, so they are only followed by PTS from there
+    // on. When they were captured travels along with them in a meta.
+    if let Some(pad) = pipeline
+        .by_name("capture")
+        .and_then(|capture| capture.static_pad("src"))
+    {
+        stamp_capture_time(&pad);
+    }
+    if let Some(pad) = pipeline
+        .by_name("ratefilter")
+        .and_then(|filter| filter.static_pad("src"))
+    {
+        add_probe(&pad, record_captured);
+    }
+    add_encoder_probe(encoder);
+    if let Some(pad) = pipeline
+        .by_name("videotee")
+        .and_then(|tee| tee.static_pad("sink"))
+    {
+        add_probe(&pad, record_sent);
+    }
+}
+
+/// Follows frames out of `encoder`, also for one that replaced the encoder of the pipeline.
+pub fn add_encoder_probe(encoder: &gst::Element) {
+    if let Some(pad) = encoder.static_pad("src") {
+        add_probe(&pad, record_encoded);
+    }
+}
+
+pub fn stop() {
+    *MONITOR.lock().unwrap() = None;
+}
+
+/// Stats of the running stream, None until frames were sent.
+pub fn current_stats() -> Option<VideoStats> {
+    MONITOR
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(FrameMonitor::stats)
+}
+
+// Adds the time each captured frame left the source, since the monitor started.
+fn stamp_capture_time(pad: &gst::Pad) {
+    let reference = gst::Caps::new_empty_simple(CAPTURE_REFERENCE);
+    pad.add_probe(gst::PadProbeType::BUFFER, move |_, info| {
+        let Some(started) = MONITOR
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|monitor| monitor.started)
+        else {
+            return gst::PadProbeReturn::Ok;
+        };
+        if let Some(gst::PadProbeData::Buffer(ref mut buffer)) = info.data {
+            let since_start = gst::ClockTime::from_nseconds(started.elapsed().as_nanos() as u64);
+            gst::ReferenceTimestampMeta::add(buffer.make_mut(), &reference, since_start, None);
+        }
+        gst::PadProbeReturn::Ok
+    });
+}
+
+// When `buffer` was captured, None if the meta got lost on the way, e.g. in the compositor of
+// stereo capture.
+fn capture_time(buffer: &gst::BufferRef, started: Instant) -> Option<Instant> {
+    buffer
+        .iter_meta::<gst::ReferenceTimestampMeta>()
+        .find(|meta| {
+            meta.reference()
+                .structure(0)
+                .map_or(false, |structure| structure.name() == CAPTURE_REFERENCE)
+        })
+        .map(|meta| started + Duration::from_nanos(meta.timestamp().nseconds()))
+}
+
+// Frames are matched by PTS, packets of a frame share it and only the first one counts.
+fn add_probe(
+    pad: &gst::Pad,
+    record: fn(&mut FrameMonitor, gst::ClockTime, &gst::BufferRef, Instant),
+) {
+    pad.add_probe(
+        gst::PadProbeType::BUFFER | gst::PadProbeType::BUFFER_LIST,
+        move |_, info| {
+            let now = Instant::now();
+            let buffer = match info.data.as_ref() {
+                Some(gst::PadProbeData::Buffer(buffer)) => Some(&**buffer),
+                Some(gst::PadProbeData::BufferList(list)) => list.get(0),
+                _ => None,
+            };
+            let Some(buffer) = buffer else {
+                return gst::PadProbeReturn::Ok;
+            };
+            if let (Some(pts), Some(monitor)) = (buffer.pts(), MONITOR.lock().unwrap().as_mut()) {
+                record(monitor, pts, buffer, now);
+            }
+            gst::PadProbeReturn::Ok
+        },
+    );
+}
+
+// The frame left videorate with the PTS it is followed by, when it was captured is in its meta.
+fn record_captured(
+    monitor: &mut FrameMonitor,
+    pts: gst::ClockTime,
+    buffer: &gst::BufferRef,
+    now: Instant,
+) {
+    let captured = capture_time(buffer, monitor.started).unwrap_or(now);
+    if monitor.in_flight.len() >= MAX_FRAMES_IN_FLIGHT {
+        monitor.in_flight.pop_front();
+        monitor.frames_dropped += 1;
+    }
+    monitor.in_flight.push_back(Frame {
+        pts,
+        captured,
+        encoded: false,
+    });
+}
+
+fn record_encoded(
+    monitor: &mut FrameMonitor,
+    pts: gst::ClockTime,
+    _: &gst::BufferRef,
+    now: Instant,
+) {
+    let Some(frame) = monitor
+        .in_flight
+        .iter_mut()
+        .find(|frame| frame.pts == pts && !frame.encoded)
+    else {
+        return;
+    };
+    frame.encoded = true;
+    let ms = now.duration_since(frame.captured).as_secs_f32() * 1000.0;
+    monitor.encode_latency_ms = Some(smooth(monitor.encode_latency_ms, ms));
+}
+
+fn record_sent(monitor: &mut FrameMonitor, pts: gst::ClockTime, _: &gst::BufferRef, now: Instant) {
+    let Some(index) = monitor.in_flight.iter().position(|frame| frame.pts == pts) else {
+        return;
+    };
+    // Frames are sent in capture order apart from B-frames, those captured before this one that
+    // cannot be B-frames anymore will not come.
+    let missed = index.saturating_sub(monitor.reorder_frames);
+    monitor.frames_dropped += missed as u64;
+    monitor.in_flight.drain(..missed);
+    let frame = monitor.in_flight.remove(index - missed).unwrap();
+    monitor.frames_sent += 1;
+
+    let ms = now.duration_since(frame.captured).as_secs_f32() * 1000.0;
+    monitor.send_latency_ms = Some(smooth(monitor.send_latency_ms, ms));
+
+    // A B-frame sent after a later frame says nothing about the pacing.
+    if monitor
+        .last_sent
+        .map_or(false, |(_, last_pts)| pts < last_pts)
+    {
+        return;
+    }
+    if let Some((last_instant, last_pts)) = monitor.last_sent {
+        let interval_ms = now.duration_since(last_instant).as_secs_f32() * 1000.0;
+        let media_ms = pts.saturating_sub(last_pts).nseconds() as f32 / 1_000_000.0;
+        monitor.frame_interval_ms = Some(smooth(monitor.frame_interval_ms, interval_ms));
+        monitor.frame_jitter_ms = smooth(
+            Some(monitor.frame_jitter_ms),
+            (interval_ms - media_ms).abs(),
+        );
+    }
+    monitor.last_sent = Some((now, pts));
+}
@@ -0,0 +1,103 @@
+use crate::monitor::CaptureTarget;
+use crate::pipeline::{add_chain, make, BuildError};
+use gst::prelude::*;
+use gstreamer as gst;
+use serde::Serialize;
+
+/// How the captured picture is laid out in the stream.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StereoMode {
+    Mono,
+    // The first two monitors packed side by side, left eye first.
+    SideBySide,
+}
+
+impl StereoMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StereoMode::Mono => "mono",
+            StereoMode::SideBySide => "side_by_side",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "mono" => Some(StereoMode::Mono),
+            "side_by_side" => Some(StereoMode::SideBySide),
+            _ => None,
+        }
+    }
+
+    /// Size of the picture of one eye within a stream of the given size.
+    pub fn eye_size(&self, width: u32, height: u32) -> (u32, u32) {
+        match self {
+            StereoMode::Mono => (width, height),
+            StereoMode::SideBySide => (width / 2, height),
+        }
+    }
+
+    /// Adds the video source to `bin` and returns the element the capture chain continues from.
+    /// Mono capture is of `target`, a monitor or window, see `monitor`.
+    pub fn add_video_source(
+        &self,
+        bin: &gst::Bin,
+        width: u32,
+        height: u32,
+        d3d11_output: bool,
+        target: CaptureTarget,
+    ) -> Result<gst::Element, BuildError> {
+        match self {
+            StereoMode::Mono => {
+                let capture = target
+                    .configure(make("d3d11screencapturesrc")?.name("capture"))
+                    .property("show-cursor", true)
+                    .build()?;
+                bin.add(&capture)?;
+                Ok(capture)
+            }
+            StereoMode::SideBySide => {
+                let (eye_width, eye_height) = self.eye_size(width, height);
+                let compositor = make("d3d11compositor")?.name("sbs").build()?;
+                bin.add(&compositor)?;
+
+                for (index, name) in ["capture", "capture_right"].into_iter().enumerate() {
+                    let capture = make("d3d11screencapturesrc")?
+                        .name(name)
+                        .property("monitor-index", index as i32)
+                        .property("show-cursor", true)
+                        .build()?;
+                    let sink_pad = compositor.request_pad_simple("sink_%u").ok_or_else(|| {
+                        gst::glib::bool_error!("The compositor has no pad for {}", name)
+                    })?;
+                    sink_pad.set_property("xpos", index as i32 * eye_width as i32);
+                    sink_pad.set_property("ypos", 0i32);
+                    sink_pad.set_property("width", eye_width as i32);
+                    sink_pad.set_property("height", eye_height as i32);
+                    bin.add(&capture)?;
+                    capture
+                        .static_pad("src")
+                        .ok_or_else(|| gst::glib::bool_error!("{} has no src pad", name))?
+                        .link(&sink_pad)
+                        .map_err(|e| gst::glib::bool_error!("Failed to link {}: {:?}", name, e))?;
+                }
+
+                if d3d11_output {
+                    return Ok(compositor);
+                }
+                let download = make("d3d11download")?.build()?;
+                add_chain(bin, Some(&compositor), std::slice::from_ref(&download))?;
+                Ok(download)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for StereoMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StereoMode::Mono => write!(f, "Off"),
+            StereoMode::SideBySide => write!(f, "Side by side (monitors 1 and 2)"),
+        }
+    }
+}
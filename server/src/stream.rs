@@ -1,10 +1,34 @@
 use gst::prelude::*;
 use gstreamer as gst;
-
+use gstreamer_video as gst_video;
+
+use crate::audio::{AudioCodec, STEREO_CHANNELS};
+use crate::color::{ColorMatrix, ColorRange, Colorimetry};
+use crate::control::{broadcast_event, handle_command, send_event, ControlCommand, ControlEvent};
+use crate::encoder::{
+    block_encoder, describe_encoder_error, is_session_limit_error, select_codec,
+    set_encoder_warning, start_thermal_monitor, supported_codecs, Av1Tuning, ContentTune,
+    VideoCodec, VideoEncoder,
+};
+use crate::error::{broadcast_error, report_error, ErrorCode};
+use crate::filters::Filter;
+use crate::gpu::GpuAdapter;
+use crate::latency::{LatencyPreset, QueueLeaky};
+use crate::monitor::{CaptureRegion, MonitorInfo};
+use crate::pipeline::{
+    add_chain, capsfilter, ghost_pad, make, BuildError, PipelineBuilder, PipelineHandles,
+};
+use crate::rtp::{reported_loss, validate_rtcp, RtpSession, CURRENT_SESSION};
+use crate::stereo::StereoMode;
+use crate::timeline::EndReason;
+use crate::trust::Permissions;
+use crate::watchdog::AppExitAction;
+use crate::window::WindowInfo;
 use async_std::net::{TcpListener, TcpStream};
 use async_std::task;
 use async_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
 use async_tungstenite::tungstenite::protocol::{CloseFrame, Message};
+use async_tungstenite::WebSocketStream;
 use chrono::{SubsecRound, Utc};
 use futures::prelude::*;
 use futures::{
@@ -12,15 +36,19 @@ use futures::{
     channel::oneshot,
     future, pin_mut,
 };
-use gstreamer::glib::ControlFlow;
 use gstreamer::MessageView;
 use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
     io::Error as IoError,
-    net::SocketAddr,
-    sync::{Arc, Mutex, Once},
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, Once,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
 // --- FIXED: Use a thread-safe Mutex for the global pipeline ---
@@ -28,9 +56,161 @@ use std::{
 // `Option<gst::Pipeline>` allows the pipeline to be present or absent (Null state).
 static PIPELINE_GUARD: Mutex<Option<gst::Pipeline>> = Mutex::new(None);
 static PIPELINE_INIT: Once = Once::new();
+// Elements of the running pipeline controlled at runtime, set and cleared along with it.
+static PIPELINE_HANDLES: Mutex<Option<PipelineHandles>> = Mutex::new(None);
+
+// The bin of the video encoder and the caps after it, replaced by `switch_encoder`.
+const ENCODER_BIN: &str = "encoderbin";
+// The capture up to the queue in front of the encoder.
+const CAPTURE_BIN: &str = "capturebin";
+// Errors of a failed encoder and of the capture it stopped arrive for a moment after it failed.
+const ENCODER_SWITCH_GRACE: Duration = Duration::from_secs(2);
+
+// What it takes to make another encoder for the running stream, see `switch_encoder`.
+struct EncoderSetup {
+    encoder: VideoEncoder,
+    codec: VideoCodec,
+    // Caps of the encoded stream, the payloader and clients keep them.
+    encoded_caps: &'static str,
+    hdr: bool,
+    chroma_444: bool,
+    // The preset the encoder settings come from, a faster one after the encoder failed.
+    latency_preset: LatencyPreset,
+    preset_lowered: bool,
+    intra_refresh: bool,
+    x264_options: String,
+    av1_tuning: Av1Tuning,
+    content_tune: ContentTune,
+    b_frames: u32,
+}
+
+static ENCODER_SETUP: Mutex<Option<EncoderSetup>> = Mutex::new(None);
+
+// When a failed encoder is being replaced, with the capture and encoder bins at the time.
+// Their errors are expected then and do not restart the pipeline.
+static ENCODER_SWITCH: Mutex<Option<(Instant, gst::Element, gst::Element)>> = Mutex::new(None);
+
+// The client whose settings the shared capture and encoders of the pipeline follow, so it can be
+// rebuilt. Other clients with the same settings get the stream too, see `StreamBranch`.
+static PIPELINE_TARGET: Mutex<Option<(SocketAddr, StreamConfigMessage)>> = Mutex::new(None);
+
+// Per-client sinks of the running pipeline.
+static STREAM_BRANCHES: Mutex<Vec<StreamBranch>> = Mutex::new(Vec::new());
+
+// Picture layout of the current pipeline.
+static ACTIVE_STEREO_MODE: Mutex<StereoMode> = Mutex::new(StereoMode::Mono);
+
+// When a client last got a keyframe on request.
+static LAST_FORCED_KEYFRAME: Mutex<Option<Instant>> = Mutex::new(None);
+
+const BUS_POLL_INTERVAL_MILLIS: u64 = 100;
+
+// Payload size of one RTP packet, leaving room for IP/UDP/RTP headers within a 1500 byte MTU.
+pub const RTP_MTU: u32 = 1400;
+// Keeps each H.264 slice in a single packet, so a lost packet only corrupts one slice.
+pub const DEFAULT_MAX_SLICE_SIZE: u32 = 1200;
+
+// Enough to repair the scattered losses of a busy Wi-Fi link.
+pub const DEFAULT_VIDEO_FEC_PERCENTAGE: u32 = 20;
+
+// Upper bound for bitrates clients ask for at runtime.
+const MAX_BITRATE_MBPS: u32 = 200;
+
+// Upper bound for the frame rate set on the host or by clients.
+pub const MAX_FRAMERATE: u32 = 240;
+
+// Clients may ask for keyframes on every corrupt frame, one per interval is enough.
+const MIN_FORCED_KEYFRAME_INTERVAL: Duration = Duration::from_millis(250);
+
+// Audio capture buffers this many Opus frames, more only adds latency to a live source.
+const AUDIO_BUFFERED_FRAMES: u32 = 4;
+
+// Enough for a few viewers, without letting stray connections pile up.
+pub const DEFAULT_MAX_CLIENTS: u32 = 8;
+
+// How long the pipeline outlives the last client, so a roaming client can reconnect to it.
+const RECONNECT_GRACE_SECONDS: u64 = 5;
+// The same after a network interface of the host went away. Clients need longer to notice and
+// find the host on another network.
+const FAILOVER_GRACE_SECONDS: u64 = 30;
+
+// A pipeline failing while it plays restarts after this delay, doubled with every attempt that
+// fails again, up to the maximum.
+const RECOVERY_BASE_DELAY: Duration = Duration::from_secs(1);
+const RECOVERY_MAX_DELAY: Duration = Duration::from_secs(30);
+// Restarts in a row before the stream is given up on.
+const MAX_RECOVERY_ATTEMPTS: u32 = 5;
+// Playing this long counts as recovered, the next failure starts over at the base delay.
+const RECOVERY_STABLE_TIME: Duration = Duration::from_secs(60);
+
+// Where clients receive RTP unless their stream config says otherwise.
+pub const CLIENT_VIDEO_PORT: u16 = 5601;
+pub const CLIENT_AUDIO_PORT: u16 = 5602;
+
+// How far a slow client's branch may fall behind before it drops packets, instead of holding
+// back the others.
+const BRANCH_QUEUE_MAX_TIME_MS: u64 = 200;
+
+// Tees fanning the RTP streams out to the clients.
+const RTP_TEES: [&str; 2] = ["videotee", "audiotee"];
+// Tees of the encoded streams, for clients that take them in another container.
+const ENCODED_TEES: [&str; 2] = ["videoenctee", "audioenctee"];
+
+/// The sinks sending the stream to one client, fed by request pads of the tees.
+struct StreamBranch {
+    addr: SocketAddr,
+    bin: gst::Bin,
+    // Where video and audio enter: the queue a tee links to, then the valve that pauses them.
+    // No audio for MPEG-TS without an audio stream.
+    video: (gst::Element, gst::Element),
+    audio: Option<(gst::Element, gst::Element)>,
+    // Fed by the encoded streams instead of RTP, see `mpegts`.
+    encoded: bool,
+    // What sends to the client, none for WebRTC.
+    sinks: Vec<gst::Element>,
+    webrtc: Option<gst::Element>,
+    tee_pads: Vec<(gst::Element, gst::Pad)>,
+}
+
+// Where the streaming session is. A session starts with the first client's stream and ends a
+// grace period after the last client left, tearing everything down so the next one starts over.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SessionState {
+    Idle,
+    // Clients are streaming, or the pipeline is being (re)built for them.
+    Active,
+    // The last client left, the pipeline waits for it to reconnect.
+    Lingering,
+}
+
+struct Session {
+    state: SessionState,
+    // Bumped whenever a client streams, so a grace period that a client interrupted is ignored.
+    generation: u64,
+    // Why the last client went, for the summary once the session ends.
+    end_reason: EndReason,
+}
+
+struct Recovery {
+    // Restarts since the pipeline last played for `RECOVERY_STABLE_TIME`.
+    attempts: u32,
+    // A restart is scheduled, further errors of the failing pipeline are ignored.
+    pending: bool,
+    playing_since: Option<Instant>,
+}
 
-// We'll keep the GstPipelineControl for single-start logic
-type GstPipelineControl = Arc<Once>;
+static RECOVERY: Mutex<Recovery> = Mutex::new(Recovery {
+    attempts: 0,
+    pending: false,
+    playing_since: None,
+});
+
+// Held while a session starts or ends, so the two never interleave.
+static SESSION: Mutex<Session> = Mutex::new(Session {
+    state: SessionState::Idle,
+    generation: 0,
+    end_reason: EndReason::ClientLeft,
+});
 
 type Tx = UnboundedSender<Message>;
 type PeerMap = Arc<Mutex<HashMap<SocketAddr, Tx>>>;
@@ -40,12 +220,29 @@ pub struct Peer {
     pub(crate) time_connected: String,
     pub(crate) tx: Tx,
     pub(crate) shutdown_tx: Option<oneshot::Sender<()>>,
+    pub(crate) authenticated: bool,
+    // The trust store id, once authenticated.
+    pub(crate) client_id: Option<String>,
+    // Frame rate the client asked to be limited to, e.g. to save battery.
+    pub(crate) max_fps: Option<u32>,
+    // What the client asked to receive, once authenticated.
+    pub(crate) stream: Option<StreamConfigMessage>,
+    // Whether the client paused its video or audio, kept across pipeline rebuilds.
+    pub(crate) video_paused: bool,
+    pub(crate) audio_paused: bool,
+    // Why the host disconnects the client, None while it is connected or left on its own.
+    pub(crate) end_reason: Option<EndReason>,
+    // The host address the client connected to, which goes away with its network interface.
+    pub(crate) local_ip: Option<IpAddr>,
+    // Where the client receives video and audio, once it streams. See `assign_media_ports`.
+    pub(crate) media_ports: Option<(u16, u16)>,
 }
 
 pub struct StreamConfig {
     pub(crate) resolution: (u32, u32),
     pub(crate) framerate: u32,
     pub(crate) bitrate: u32,
+    pub(crate) encoder: String,
 }
 
 pub struct StreamingState {
@@ -55,253 +252,2401 @@ pub struct StreamingState {
     pub(crate) stream_config: Option<StreamConfig>,
     pub(crate) connection_status: ConnectionStatus,
     pub(crate) pin: String,
+    pub(crate) app_exit_action: AppExitAction,
+    pub(crate) enforce_focus: bool,
+    // Name of a (virtual) output device to capture instead of the default one. Empty for default.
+    pub(crate) stream_audio_device: String,
+    // Endpoint ID of the device audio is captured from, empty for `stream_audio_device`.
+    pub(crate) audio_source_device: String,
+    // Name of the output device client microphones play into, empty for none.
+    pub(crate) mic_device: String,
+    pub(crate) performance_mode: bool,
+    pub(crate) encoder_warning: Option<String>,
+    pub(crate) latency_preset: LatencyPreset,
+    // Whether the preset follows the measured link, otherwise it is pinned.
+    pub(crate) auto_latency_preset: bool,
+    // Spread keyframes over several frames to avoid bitrate spikes, if the client supports it.
+    pub(crate) intra_refresh: bool,
+    // Slices per frame, 0 lets the encoder decide.
+    pub(crate) slice_count: u32,
+    // Maximum slice size in bytes, 0 for no limit.
+    pub(crate) max_slice_size: u32,
+    pub(crate) stereo_mode: StereoMode,
+    pub(crate) audio_codec: AudioCodec,
+    // Whether the host layout beyond stereo reaches clients that play it.
+    pub(crate) surround: bool,
+    pub(crate) audio_bitrate_kbps: u32,
+    // Opus frame duration in ms, 0 to follow the latency preset.
+    pub(crate) audio_frame_size: u32,
+    pub(crate) audio_fec: bool,
+    pub(crate) audio_dtx: bool,
+    // Loss the audio FEC is planned for at least, in percent.
+    pub(crate) audio_loss_percentage: u32,
+    // Whether clients may crop the stream to the part of the desktop they show.
+    pub(crate) allow_viewport_crop: bool,
+    // Raw frames allowed in front of the encoder, 0 to follow the latency preset.
+    pub(crate) queue_max_buffers: u32,
+    // Maximum duration of queued raw frames in ms, 0 for no limit.
+    pub(crate) queue_max_time_ms: u32,
+    pub(crate) queue_leaky: QueueLeaky,
+    // Frames in the pool of the capture source, 0 for what downstream proposes.
+    pub(crate) capture_pool_buffers: (u32, u32),
+    // None picks the best encoder available.
+    pub(crate) preferred_encoder: Option<VideoEncoder>,
+    // Used when the client decodes it, H.264 otherwise.
+    pub(crate) video_codec: VideoCodec,
+    // Whether clients that support it get ULPFEC, and its overhead in percent.
+    pub(crate) video_fec: bool,
+    pub(crate) video_fec_percentage: u32,
+    // Connections beyond this are refused, authenticated or not.
+    pub(crate) max_clients: u32,
+    // Where clients receive video and audio unless they ask for other ports.
+    pub(crate) media_ports: (u16, u16),
+    // How far clients without any input permission lag behind, 0 for not at all.
+    pub(crate) spectator_delay_seconds: u32,
+    // Converts and encodes on this GPU, None for the default one.
+    pub(crate) gpu_adapter: Option<GpuAdapter>,
+    // The captured monitor, None for the primary one.
+    pub(crate) monitor: Option<MonitorInfo>,
+    // A single window captured instead of the monitor.
+    pub(crate) capture_window: Option<WindowInfo>,
+    // Part of the monitor or window that is streamed, None for all of it.
+    pub(crate) capture_region: Option<CaptureRegion>,
+    // Top-left of the captured area on the virtual desktop, for placing the cursor.
+    pub(crate) capture_origin: (i32, i32),
+    // Whether clients that display HDR get it while the captured monitor shows HDR.
+    pub(crate) hdr: bool,
+    // Whether clients that ask for 4:4:4 get it, see `encoder::select_444_encoder`.
+    pub(crate) chroma_444: bool,
+    // What SDR video is converted to and signaled as, see `color::Colorimetry`.
+    pub(crate) color_range: ColorRange,
+    pub(crate) color_matrix: ColorMatrix,
+    // Film grain and debanding of AV1 streams.
+    pub(crate) av1_tuning: Av1Tuning,
+    // B-frames in a row for HEVC and AV1 clients that decode them, 0 for none.
+    pub(crate) b_frames: u32,
+    // What the encoder is tuned for, unless the client asks otherwise.
+    pub(crate) content_tune: ContentTune,
+    // Post-processing of the capture, in order, see `filters`.
+    pub(crate) filters: Vec<Filter>,
+    // Frame rate streamed whatever clients ask for, None for the rate of the stream config.
+    pub(crate) framerate: Option<u32>,
+}
+
+pub static STREAMING_STATE_GUARD: Mutex<Option<StreamingState>> = Mutex::new(None);
+
+// ----------------------------------------------------------------------
+// --- GStreamer Functions (Now Thread-Safe) ----------------------------
+// ----------------------------------------------------------------------
+
+#[derive(Copy, Clone)]
+pub(crate) enum ConnectionStatus {
+    Ready,
+    Connected,
+    Error,
+}
+
+pub(crate) fn init_gstreamer() {
+    // This function will initialize GStreamer only once.
+    PIPELINE_INIT.call_once(|| {
+        gst::init().unwrap();
+        info!("GStreamer initialized.");
+        gst::log::set_default_threshold(gst::DebugLevel::Warning);
+    });
+}
+
+// Elements every stream uses, on top of the encoder and payloader.
+const WARM_UP_ELEMENTS: [&str; 9] = [
+    "d3d11screencapturesrc",
+    "d3d11convert",
+    "videorate",
+    "rtpbin",
+    "wasapi2src",
+    "opusenc",
+    "rtpopuspay",
+    "udpsink",
+    "udpsrc",
+];
+
+/// Initializes GStreamer and loads the plugins of the stream ahead of time, so the first client
+/// does not wait for the registry and DLLs while the server stands by. Blocking.
+pub fn warm_up() {
+    let started = Instant::now();
+    init_gstreamer();
+
+    let (codec, encoder) = {
+        let guard = STREAMING_STATE_GUARD.lock().unwrap();
+        let state = guard
+            .as_ref()
+            .expect("Streaming state was not initialized!");
+        (state.video_codec, state.preferred_encoder)
+    };
+    let (codec, encoder) = select_codec(codec, &[codec], encoder);
+
+    for name in WARM_UP_ELEMENTS
+        .into_iter()
+        .chain([encoder.factory_name(codec), codec.payloader()])
+    {
+        let loaded = gst::ElementFactory::find(name).and_then(|factory| factory.load().ok());
+        if loaded.is_none() {
+            warn!("Failed to preload {}.", name);
+        }
+    }
+
+    info!(
+        "Standing by, warmed up in {} ms.",
+        started.elapsed().as_millis()
+    );
+}
+
+/// Whether a client passed authentication and keeps the stream going.
+pub(crate) fn has_authenticated_peer() -> bool {
+    let guard = STREAMING_STATE_GUARD.lock().unwrap();
+    guard.as_ref().map_or(false, |state| {
+        state.peers.values().any(|peer| peer.authenticated)
+    })
+}
+
+/// What the client at `addr` may do, going by the trusted client it authenticated as.
+pub(crate) fn peer_permissions(addr: SocketAddr) -> Permissions {
+    let client_id = {
+        let guard = STREAMING_STATE_GUARD.lock().unwrap();
+        guard
+            .as_ref()
+            .and_then(|state| state.peers.get(&addr))
+            .and_then(|peer| peer.client_id.clone())
+    };
+    client_id.map_or(Permissions::NONE, |id| crate::trust::permissions(&id))
+}
+
+/// What the client that authenticated from `ip` may do, None if none did. Input arrives over
+/// ENet, which only knows the address.
+pub(crate) fn permissions_from_ip(ip: IpAddr) -> Option<Permissions> {
+    let client_id = {
+        let guard = STREAMING_STATE_GUARD.lock().unwrap();
+        guard.as_ref().and_then(|state| {
+            state
+                .peers
+                .iter()
+                .find(|(addr, peer)| addr.ip() == ip && peer.authenticated)
+                .and_then(|(_, peer)| peer.client_id.clone())
+        })
+    }?;
+    Some(crate::trust::permissions(&client_id))
+}
+
+// fn udpsrc_sink_pad_probe(_pad: &gst::Pad, info: &mut gst::PadProbeInfo) -> gst::PadProbeReturn {
+//     if let Some(gst::PadProbeData::Buffer(ref buffer)) = info.data {
+//         // Acquire the lock for the global pipeline state.
+//         let mut guard = PIPELINE_GUARD.lock().unwrap();
+//
+//         // Use `Option::take()` to extract the pipeline and replace the value with None.
+//         // The extracted pipeline reference will then be dropped when it goes out of scope.
+//         if let Some(pipeline) = guard.as_ref() {
+//             // Check pipeline
+//             let dot_data = pipeline.debug_to_dot_data(gst::DebugGraphDetails::ALL);
+//             let dot_str = dot_data.as_str();
+//             let a = 2;
+//             let b = 2;
+//             let c = a + b;
+//         }
+//     }
+//
+//     gst::PadProbeReturn::Ok
+// }
+
+pub(crate) fn check_factory_exists(factory_name: &str) -> bool {
+    gst::ElementFactory::find(factory_name).is_some()
+}
+
+fn start_gstreamer_pipeline(addr: SocketAddr, config: StreamConfigMessage) {
+    // Acquire the lock for the global pipeline state
+    let mut guard = PIPELINE_GUARD.lock().unwrap();
+
+    // Check if a pipeline is already running
+    if guard.is_some() {
+        warn!("Pipeline already running. Not restarting.");
+        return;
+    }
+
+    *PIPELINE_TARGET.lock().unwrap() = Some((addr, config.clone()));
+
+    // Settings that start on nearly any host, after the pipeline kept failing. The target keeps
+    // the client's own, for leaving safe mode.
+    let safe_mode = crate::safemode::is_active();
+    let config = if safe_mode {
+        warn!(
+            "Safe mode is active: {}.",
+            crate::safemode::SAFE_MODE_DESCRIPTION
+        );
+        let (video_width, video_height) =
+            crate::safemode::fit_size(config.video_width, config.video_height);
+        StreamConfigMessage {
+            video_width,
+            video_height,
+            intra_refresh: false,
+            hdr: false,
+            chroma_444: false,
+            codecs: Vec::new(),
+            fec: false,
+            ..config
+        }
+    } else {
+        config
+    };
+
+    let (capture_target, region_crop) = crate::monitor::capture_target();
+
+    let latency_preset;
+    let intra_refresh;
+    let slice_count;
+    let max_slice_size;
+    let stereo_mode;
+    let audio_codec;
+    let audio_bitrate_kbps;
+    let audio_frame_size;
+    let native_resolution;
+    let queue_max_buffers;
+    let queue_max_time_ms;
+    let queue_leaky;
+    let capture_pool_buffers;
+    let codec;
+    let encoder;
+    let framerate;
+    let video_fec_percentage;
+    let gpu_adapter;
+    let filters;
+    let allow_chroma_444;
+    let colorimetry;
+    let av1_tuning;
+    let content_tune;
+    let b_frames;
+    {
+        let mut state_guard = STREAMING_STATE_GUARD.lock().unwrap();
+        let state = state_guard
+            .as_mut()
+            .expect("Streaming state was not initialized!");
+
+        (codec, encoder) = if safe_mode {
+            (VideoCodec::H264, VideoEncoder::Software)
+        } else {
+            select_codec(state.video_codec, &config.codecs, state.preferred_encoder)
+        };
+        gpu_adapter = state.gpu_adapter.clone();
+        filters = if safe_mode {
+            Vec::new()
+        } else {
+            state.filters.clone()
+        };
+        allow_chroma_444 = state.chroma_444;
+        colorimetry =
+            Colorimetry::resolve(state.color_range, state.color_matrix, config.video_height);
+        av1_tuning = state.av1_tuning;
+        content_tune = config.tune.unwrap_or(state.content_tune);
+        // Clients that do not say they reorder frames only get frames in display order.
+        b_frames = if config.b_frames { state.b_frames } else { 0 };
+
+        latency_preset = state.latency_preset;
+        intra_refresh = state.intra_refresh && config.intra_refresh;
+        slice_count = state.slice_count;
+        max_slice_size = state.max_slice_size;
+        stereo_mode = if safe_mode {
+            StereoMode::Mono
+        } else {
+            state.stereo_mode
+        };
+        audio_codec = crate::audio::select_codec(state.audio_codec, &config.audio_codecs);
+        audio_bitrate_kbps = state.audio_bitrate_kbps;
+        audio_frame_size = state.audio_frame_size;
+        native_resolution = state.native_resolution;
+        queue_max_buffers = state.queue_max_buffers;
+        queue_max_time_ms = state.queue_max_time_ms;
+        queue_leaky = state.queue_leaky;
+        capture_pool_buffers = state.capture_pool_buffers;
+        framerate = if safe_mode {
+            delivered_framerate(state, addr, &config).min(crate::safemode::SAFE_FRAMERATE)
+        } else {
+            delivered_framerate(state, addr, &config)
+        };
+        video_fec_percentage =
+            (state.video_fec && config.fec).then_some(state.video_fec_percentage);
+    }
+
+    // HDR needs a client showing it, a 10-bit encoder and a monitor in HDR mode, see `hdr`.
+    let hdr_metadata = if config.hdr && stereo_mode == StereoMode::Mono {
+        if !encoder.encodes_10bit(codec) {
+            info!("{} cannot encode 10-bit {}, streaming SDR.", encoder, codec);
+            None
+        } else {
+            crate::hdr::captured_display_hdr()
+        }
+    } else {
+        None
+    };
+    let (raw_format, colorimetry_str) = match &hdr_metadata {
+        Some(metadata) => {
+            info!("Streaming HDR10 ({:?}).", metadata);
+            ("P010_10LE", metadata.caps_fields())
+        }
+        None => ("NV12", colorimetry.caps_field()),
+    };
+
+    // 4:4:4 needs an encoder that takes it, which may not be the preferred one. HDR wins.
+    let chroma_444 = config.chroma_444 && allow_chroma_444 && hdr_metadata.is_none();
+    let (encoder, raw_format) = match chroma_444
+        .then(|| crate::encoder::select_444_encoder(codec, encoder))
+        .flatten()
+    {
+        Some(encoder) => {
+            info!("Streaming 4:4:4 {} with {}.", codec, encoder);
+            (encoder, "Y444")
+        }
+        None => {
+            if chroma_444 {
+                info!("No encoder takes 4:4:4 {}, streaming 4:2:0.", codec);
+            }
+            (encoder, raw_format)
+        }
+    };
+    let chroma_444 = raw_format == "Y444";
+
+    // Hardware encoders register an element per GPU.
+    let factory_name = match &gpu_adapter {
+        Some(adapter) if encoder.is_hardware() => {
+            crate::gpu::encoder_factory(encoder.factory_name(codec), adapter.luid)
+        }
+        _ => encoder.factory_name(codec).to_string(),
+    };
+    if let Some(stream_config) = STREAMING_STATE_GUARD
+        .lock()
+        .unwrap()
+        .as_mut()
+        .and_then(|state| state.stream_config.as_mut())
+    {
+        stream_config.encoder = factory_name.clone();
+    }
+
+    info!("Using encoder: {} ({})", encoder, codec);
+    info!("Using latency preset: {}", latency_preset);
+    let preset = latency_preset.params();
+    let audio_frame_size = audio_codec.frame_size(if audio_frame_size > 0 {
+        audio_frame_size
+    } else {
+        preset.audio_frame_size
+    });
+
+    // Only a few raw frames may queue up in front of the encoder, by default older ones are dropped.
+    let queue_max_buffers = if queue_max_buffers > 0 {
+        queue_max_buffers
+    } else {
+        preset.queue_max_buffers
+    };
+    if intra_refresh && (encoder.is_hardware() || codec != VideoCodec::H264) {
+        info!(
+            "{} has no intra-refresh, using periodic keyframes.",
+            encoder.factory_name(codec)
+        );
+    }
+
+    // x264 takes slice settings as raw options.
+    let mut slice_options = Vec::new();
+    if slice_count > 0 {
+        slice_options.push(format!("slices={}", slice_count));
+    }
+    if max_slice_size > 0 {
+        slice_options.push(format!("slice-max-size={}", max_slice_size.min(RTP_MTU)));
+    }
+    if (encoder.is_hardware() || codec != VideoCodec::H264) && !slice_options.is_empty() {
+        info!(
+            "{} does not expose slice settings, using its defaults.",
+            encoder.factory_name(codec)
+        );
+    }
+
+    if !content_tune.is_supported_by(encoder, codec) {
+        info!("{} has no {} tune for {}.", encoder, content_tune, codec);
+    }
+    if b_frames > 0 && !encoder.takes_b_frames(codec) {
+        info!(
+            "{} gets no B-frames, sending frames in display order.",
+            encoder.factory_name(codec)
+        );
+    }
+
+    if codec == VideoCodec::Av1 && !av1_tuning.is_supported_by(encoder) {
+        info!("{} ignores some AV1 tuning: {:?}", encoder, av1_tuning);
+    }
+
+    // Stereo capture is of two whole monitors.
+    let region_crop = region_crop.filter(|_| stereo_mode == StereoMode::Mono);
+
+    // The filters and the input-to-photon marker work on 8-bit frames.
+    if hdr_metadata.is_some() {
+        if filters.iter().any(|filter| filter.enabled) {
+            info!("Filters are skipped for HDR.");
+        }
+        if crate::photon::is_enabled() {
+            info!("The input-to-photon marker is skipped for HDR.");
+        }
+    }
+
+    // Capture stays on the GPU driving the monitor, conversion moves to the chosen one.
+    let adapter = gpu_adapter.as_ref().map(|adapter| adapter.index);
+
+    // An HDR desktop streamed as SDR, because the client or the encoder has no HDR.
+    let tone_map = hdr_metadata.is_none()
+        && stereo_mode == StereoMode::Mono
+        && crate::hdr::captured_display_is_hdr();
+    if tone_map {
+        info!("Tone-mapping the HDR desktop to SDR.");
+    }
+
+    let raw_caps = format!(
+        "video/x-raw{},width={},height={},format={},framerate={}/1{}",
+        if encoder.takes_d3d11_memory() {
+            "(memory:D3D11Memory)"
+        } else {
+            ""
+        },
+        config.video_width,
+        config.video_height,
+        raw_format,
+        framerate,
+        colorimetry_str
+    );
+    let x264_options = slice_options.join(":");
+    let encoded_caps_str = if hdr_metadata.is_some() {
+        codec.hdr_caps_str()
+    } else if chroma_444 {
+        codec.caps_444_str()
+    } else {
+        codec.caps_str()
+    };
+
+    let audio_source = (!safe_mode).then(crate::audio::source_device);
+    let audio_channels = crate::audio::select_channels(
+        audio_source
+            .as_ref()
+            .map_or(STEREO_CHANNELS, |source| source.channels),
+        config.audio_channels,
+    );
+    let mic_device_id = if safe_mode {
+        None
+    } else {
+        crate::mic::device_id()
+    };
+    let mut session = RtpSession::new(
+        config.bundle,
+        codec,
+        video_fec_percentage.is_some(),
+        hdr_metadata.is_some(),
+        chroma_444,
+        mic_device_id.is_some(),
+        audio_codec,
+        audio_channels,
+    );
+    // HDR10 signals its own.
+    if hdr_metadata.is_none() {
+        info!("Streaming {:?}.", colorimetry);
+        session.colorimetry = Some(colorimetry);
+    }
+
+    crate::audio::reset_measured_loss();
+
+    if let Some(audio_source) = &audio_source {
+        info!(
+            "Capturing {} audio channels from {}.",
+            audio_channels, audio_source.name
+        );
+    }
+
+    // Capture and the encoder get bins of their own, so the encoder can be replaced while the
+    // stream runs.
+    let builder = PipelineBuilder::new();
+    let build = || -> Result<PipelineHandles, BuildError> {
+        let rtpbin = builder.add(make("rtpbin")?.name("rtp"))?;
+
+        let capture = gst::Bin::with_name(CAPTURE_BIN);
+        let source = stereo_mode.add_video_source(
+            &capture,
+            config.video_width,
+            config.video_height,
+            encoder.is_hardware(),
+            capture_target,
+        )?;
+        // Conversion gets a thread of its own, so capture of the next frame overlaps conversion
+        // and encoding of the previous ones instead of waiting for them.
+        let mut capture_chain = vec![make("queue")?
+            .name("convertqueue")
+            .property("max-size-buffers", preset.convert_queue_max_buffers)
+            .property("max-size-bytes", 0u32)
+            .property("max-size-time", 0u64)
+            .property_from_str("leaky", "downstream")
+            .build()?];
+        if tone_map {
+            capture_chain.extend(crate::hdr::make_tone_map(adapter)?);
+        }
+        if let Some((left, top, right, bottom)) = region_crop {
+            capture_chain.push(
+                make("videocrop")?
+                    .name("regioncrop")
+                    .property("left", left)
+                    .property("top", top)
+                    .property("right", right)
+                    .property("bottom", bottom)
+                    .build()?,
+            );
+        }
+        // Clients may show only part of the desktop, see `view`.
+        capture_chain.push(crate::view::make_crop(native_resolution)?);
+        if hdr_metadata.is_none() {
+            capture_chain.extend(crate::filters::make_filters(&filters)?);
+            capture_chain.extend(crate::photon::make_marker()?);
+        }
+        // Hardware encoders convert on the GPU, x264 needs the frames in system memory anyway.
+        if encoder.is_hardware() {
+            let on_adapter = |factory: &str| -> Result<gst::Element, BuildError> {
+                let element = make(factory)?;
+                Ok(match adapter {
+                    Some(adapter) => element.property("adapter", adapter as i32),
+                    None => element,
+                }
+                .build()?)
+            };
+            capture_chain.push(on_adapter("d3d11convert")?);
+            if !encoder.takes_d3d11_memory() {
+                capture_chain.push(on_adapter("d3d11download")?);
+            }
+        } else {
+            capture_chain.push(make("videoconvert")?.build()?);
+            capture_chain.push(make("videoscale")?.build()?);
+        }
+        capture_chain.push(make("videorate")?.build()?);
+        capture_chain.push(capsfilter(Some("ratefilter"), &raw_caps)?);
+        add_chain(&capture, Some(&source), &capture_chain)?;
+
+        let mut last = capture_chain.last().unwrap().clone();
+        if let Some(tee) = crate::local::add_video_branch(&capture, encoder.takes_d3d11_memory())? {
+            last.link(&tee)?;
+            last = tee;
+        }
+        let encoder_queue = make("queue")?
+            .name("encqueue")
+            .property("max-size-buffers", queue_max_buffers)
+            .property("max-size-bytes", 0u32)
+            .property("max-size-time", queue_max_time_ms as u64 * 1_000_000)
+            .property_from_str("leaky", queue_leaky.as_str())
+            .build()?;
+        add_chain(&capture, Some(&last), std::slice::from_ref(&encoder_queue))?;
+        ghost_pad(&capture, &encoder_queue, "src")?;
+        builder.add_bin(&capture)?;
+
+        let video_encoder = encoder.make_element(
+            &factory_name,
+            codec,
+            &preset,
+            config.bitrate * 1024,
+            intra_refresh,
+            &x264_options,
+            &av1_tuning,
+            content_tune,
+            b_frames,
+        )?;
+        let video_encoder_bin = make_encoder_bin(None, &video_encoder, encoded_caps_str)?;
+        builder.add_bin(&video_encoder_bin)?;
+        info!(
+            "Building pipeline with video: \n{}\nencoded by: \n{}",
+            crate::pipeline::describe(&capture_chain),
+            crate::pipeline::describe(std::slice::from_ref(&video_encoder))
+        );
+
+        let video_encoded_tee = builder.add(
+            make("tee")?
+                .name("videoenctee")
+                .property("allow-not-linked", true),
+        )?;
+        let video_payloader = builder.add(
+            codec
+                .payloader_options(make(codec.payloader())?.name("videopay"))
+                .property("mtu", RTP_MTU)
+                .property("ssrc", session.video_ssrc)
+                .property("pt", session.video_payload_type as u32),
+        )?;
+        let video_rtp_caps = builder.capsfilter(
+            None,
+            &format!(
+                "application/x-rtp,encoding-name={},clock-rate=90000,media=video,payload={}",
+                codec.encoding_name(),
+                session.video_payload_type
+            ),
+        )?;
+        let mut video_chain = vec![
+            capture.upcast::<gst::Element>(),
+            video_encoder_bin.upcast::<gst::Element>(),
+            video_encoded_tee,
+            video_payloader.clone(),
+            video_rtp_caps,
+        ];
+
+        // Redundancy for lossy links, only for clients that said they can use it.
+        if let (Some(fec), Some(percentage)) = (session.video_fec, video_fec_percentage) {
+            info!("Sending video FEC with {}% overhead.", percentage);
+            video_chain.push(
+                builder.add(
+                    make("rtpulpfecenc")?
+                        .name("videofec")
+                        .property("pt", fec.ulpfec_payload_type as u32)
+                        .property("percentage", percentage),
+                )?,
+            );
+            video_chain.push(
+                builder.add(
+                    make("rtpredenc")?
+                        .property("pt", fec.red_payload_type as i32)
+                        .property("allow-no-red-blocks", true),
+                )?,
+            );
+        }
+        builder.chain(&video_chain.iter().collect::<Vec<_>>())?;
+        builder.link_to_request(video_chain.last().unwrap(), &rtpbin, "send_rtp_sink_0")?;
+
+        let video_rtp_tee = builder.add(
+            make("tee")?
+                .name("videotee")
+                .property("allow-not-linked", true),
+        )?;
+        builder.link_from(&rtpbin, "send_rtp_src_0", &video_rtp_tee)?;
+        let video_rtcp_src = builder.add(
+            make("udpsrc")?
+                .name("videortcpsrc")
+                .property("port", crate::ports::current().video_rtcp as i32)
+                .property("caps", gst::Caps::new_empty_simple("application/x-rtcp")),
+        )?;
+        builder.link_to_request(&video_rtcp_src, &rtpbin, "recv_rtcp_sink_0")?;
+
+        let mut audio_encoder = None;
+        if let Some(audio_source) = &audio_source {
+            let mut source = make("wasapi2src")?
+                .name("audiosrc")
+                .property("loopback", audio_source.loopback)
+                .property("low-latency", true)
+                .property("latency-time", (audio_frame_size * 1000) as i64)
+                .property(
+                    "buffer-time",
+                    (audio_frame_size * 1000 * AUDIO_BUFFERED_FRAMES) as i64,
+                );
+            if let Some(device_id) = &audio_source.device_id {
+                source = source.property("device", device_id.as_str());
+            }
+            let encoder_chain =
+                audio_codec.make_encoder(audio_bitrate_kbps, audio_frame_size, audio_channels)?;
+            audio_encoder = encoder_chain.first().cloned();
+            let mut audio_chain = vec![
+                source.build()?,
+                make("queue")?.build()?,
+                make("audioconvert")?.build()?,
+                make("audioresample")?.build()?,
+                capsfilter(
+                    None,
+                    &format!("audio/x-raw,rate=48000,channels={}", audio_channels),
+                )?,
+            ];
+            audio_chain.extend(encoder_chain);
+            info!("Audio: \n{}", crate::pipeline::describe(&audio_chain));
+            builder.add_chain(&audio_chain)?;
+
+            let audio_encoded_tee = builder.add(
+                make("tee")?
+                    .name("audioenctee")
+                    .property("allow-not-linked", true),
+            )?;
+            let audio_payloader = builder.add(
+                make(audio_codec.payloader())?
+                    .name("audiopay")
+                    .property("ssrc", session.audio_ssrc)
+                    .property("pt", session.audio_payload_type as u32),
+            )?;
+            let audio_rtp_caps = builder.capsfilter(
+                None,
+                &format!(
+                    "application/x-rtp,encoding-name={},media=audio,payload={}",
+                    audio_codec.encoding_name(audio_channels),
+                    session.audio_payload_type
+                ),
+            )?;
+            builder.chain(&[
+                audio_chain.last().unwrap(),
+                &audio_encoded_tee,
+                &audio_payloader,
+                &audio_rtp_caps,
+            ])?;
+            builder.link_to_request(&audio_rtp_caps, &rtpbin, "send_rtp_sink_1")?;
+
+            let audio_rtp_tee = builder.add(
+                make("tee")?
+                    .name("audiotee")
+                    .property("allow-not-linked", true),
+            )?;
+            builder.link_from(&rtpbin, "send_rtp_src_1", &audio_rtp_tee)?;
+            let audio_rtcp_src = builder.add(
+                make("udpsrc")?
+                    .name("audiortcpsrc")
+                    .property("port", crate::ports::current().audio_rtcp as i32)
+                    .property("caps", gst::Caps::new_empty_simple("application/x-rtcp")),
+            )?;
+            builder.link_to_request(&audio_rtcp_src, &rtpbin, "recv_rtcp_sink_1")?;
+        }
+
+        if let Some(device_id) = &mic_device_id {
+            builder.add_chain(&crate::mic::make_branch(device_id)?)?;
+        }
+
+        Ok(PipelineHandles {
+            video_encoder,
+            video_payloader,
+            audio_encoder,
+        })
+    };
+
+    let handles = match build() {
+        Ok(handles) => handles,
+        Err(e) => {
+            crate::safemode::record_failed_start();
+            let code = match &e {
+                BuildError::MissingElements(missing) => {
+                    error!("Missing element(s): {:?}", missing);
+                    if missing.iter().any(|element| element.ends_with("enc")) {
+                        ErrorCode::EncoderMissing
+                    } else {
+                        ErrorCode::PipelineFailed
+                    }
+                }
+                BuildError::Failed(_) => {
+                    error!("Failed to build pipeline: {}", e);
+                    ErrorCode::PipelineFailed
+                }
+            };
+            report_error(addr, code, e.to_string());
+            return;
+        }
+    };
+    let pipeline = builder.build();
+
+    // // Add a probe
+    // {
+    //     let udpsrc = pipeline
+    //         .by_name("videoudpsrc")
+    //         .expect("Could not find videoudpsrc element.");
+    //
+    //     let pad = udpsrc
+    //         .static_pad("sink")
+    //         .expect("Could not find static sink pad in videoudpsrc.");
+    //
+    //     pad.add_probe(gst::PadProbeType::BUFFER, move |pad, info| {
+    //         udpsrc_sink_pad_probe(pad, info)
+    //     });
+    // }
+
+    // A full leaky queue drops a frame on every overrun, waiting is not counted as a drop.
+    if queue_leaky != QueueLeaky::No {
+        if let Some(queue) = pipeline.by_name("encqueue") {
+            queue.connect("overrun", false, |_| {
+                crate::encoder::record_dropped_frame();
+                None
+            });
+        }
+    }
+    if let Some(queue) = pipeline.by_name("convertqueue") {
+        queue.connect("overrun", false, |_| {
+            crate::encoder::record_dropped_frame();
+            None
+        });
+    }
+
+    crate::bufferpool::attach(&pipeline, capture_pool_buffers.0, capture_pool_buffers.1);
+    crate::telemetry::add_frame_probes(&pipeline);
+    crate::stats::attach(&pipeline, &handles.video_encoder, b_frames as usize);
+    crate::filters::attach_lut(&pipeline, &filters);
+    crate::photon::attach(&pipeline);
+
+    // Count outgoing video for the session timeline.
+    if let Some(pad) = handles.video_payloader.static_pad("src") {
+        crate::timeline::start_session();
+        pad.add_probe(
+            gst::PadProbeType::BUFFER | gst::PadProbeType::BUFFER_LIST,
+            |_, info| {
+                match info.data.as_ref() {
+                    Some(gst::PadProbeData::Buffer(buffer)) => {
+                        crate::timeline::record_video_packet(buffer);
+                    }
+                    Some(gst::PadProbeData::BufferList(list)) => {
+                        for buffer in list.iter() {
+                            crate::timeline::record_video_packet(buffer);
+                        }
+                    }
+                    _ => {}
+                }
+                gst::PadProbeReturn::Ok
+            },
+        );
+    }
+
+    // Protected content is captured as black frames, which clients should hear about.
+    if let Some(pad) = pipeline
+        .by_name("capture")
+        .and_then(|capture| capture.static_pad("src"))
+    {
+        crate::protected::start();
+        pad.add_probe(gst::PadProbeType::BUFFER, |pad, info| {
+            if let Some(gst::PadProbeData::Buffer(ref buffer)) = info.data {
+                if crate::protected::check_frame(buffer, pad.current_caps().as_deref()) {
+                    return gst::PadProbeReturn::Drop;
+                }
+            }
+            gst::PadProbeReturn::Ok
+        });
+    }
+
+    // Watch the captured audio for glitches the host would otherwise never notice.
+    if let Some(pad) = pipeline
+        .by_name("audiosrc")
+        .and_then(|src| src.static_pad("src"))
+    {
+        crate::audiostats::start();
+        pad.add_probe(gst::PadProbeType::BUFFER, |_, info| {
+            if let Some(gst::PadProbeData::Buffer(ref buffer)) = info.data {
+                crate::audiostats::record_buffer(buffer);
+            }
+            gst::PadProbeReturn::Ok
+        });
+    }
+
+    // Receiver reports of the audio stream tell the encoder how much FEC is worth sending.
+    let opusenc = handles
+        .audio_encoder
+        .clone()
+        .filter(|encoder| encoder.name() == "opusenc");
+    if let (Some(pad), Some(opusenc)) = (
+        pipeline
+            .by_name("audiortcpsrc")
+            .and_then(|src| src.static_pad("src")),
+        opusenc.clone(),
+    ) {
+        let audio_ssrc = session.audio_ssrc;
+        pad.add_probe(gst::PadProbeType::BUFFER, move |_, info| {
+            if let Some(gst::PadProbeData::Buffer(ref buffer)) = info.data {
+                if let Ok(map) = buffer.map_readable() {
+                    if let Some(loss) = reported_loss(&map, audio_ssrc) {
+                        if crate::audio::record_measured_loss(loss) {
+                            crate::audio::configure_opus(&opusenc);
+                        }
+                    }
+                }
+            }
+            gst::PadProbeReturn::Ok
+        });
+    }
+
+    // Only let RTCP about this session's streams reach rtpbin.
+    for (name, ssrc) in [
+        ("videortcpsrc", session.video_ssrc),
+        ("audiortcpsrc", session.audio_ssrc),
+    ] {
+        if let Some(pad) = pipeline.by_name(name).and_then(|src| src.static_pad("src")) {
+            pad.add_probe(gst::PadProbeType::BUFFER, move |_, info| {
+                if let Some(gst::PadProbeData::Buffer(ref buffer)) = info.data {
+                    if let Ok(map) = buffer.map_readable() {
+                        if !validate_rtcp(&map, ssrc) {
+                            return gst::PadProbeReturn::Drop;
+                        }
+                    }
+                }
+                gst::PadProbeReturn::Ok
+            });
+        }
+    }
+
+    if let Some(opusenc) = &opusenc {
+        crate::audio::configure_opus(opusenc);
+    }
+    if let Some(mic) = session.mic {
+        crate::mic::attach_ssrc_filter(&pipeline, mic.ssrc);
+    }
+
+    // Check pipeline
+    // let dot_data = pipeline.debug_to_dot_data(gst::DebugGraphDetails::ALL);
+    // let _dot_str = dot_data.as_str();
+
+    let bus = pipeline.bus().unwrap();
+
+    // Streaming threads announce themselves from inside the thread as they start and end,
+    // the only place to pin them and raise their priority.
+    bus.set_sync_handler(|_, msg| match msg.view() {
+        MessageView::StreamStatus(status) => {
+            match status.get().0 {
+                gst::StreamStatusType::Enter => crate::affinity::tune_stream_thread(),
+                gst::StreamStatusType::Leave => crate::affinity::release_stream_thread(),
+                _ => {}
+            }
+            gst::BusSyncReply::Drop
+        }
+        _ => gst::BusSyncReply::Pass,
+    });
+
+    // Nothing runs a GLib main loop, so a bus watch would never be dispatched.
+    // Poll the bus on a dedicated thread instead, until this pipeline is stopped or replaced.
+    let bus_pipeline = pipeline.clone();
+    thread::spawn(move || loop {
+        if PIPELINE_GUARD.lock().unwrap().as_ref() != Some(&bus_pipeline) {
+            break;
+        }
+
+        if let Some(msg) = bus.timed_pop(gst::ClockTime::from_mseconds(BUS_POLL_INTERVAL_MILLIS)) {
+            handle_bus_message(&bus_pipeline, &msg);
+        }
+    });
+
+    // After a rebuild for another client's settings, clients that asked for the same stream
+    // keep getting it. The others are asked to send a stream config again rather than getting a
+    // codec or resolution they may not decode.
+    let target_config = PIPELINE_TARGET
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|(_, target_config)| target_config.clone());
+    let mut renegotiate = Vec::new();
+    let viewers: Vec<(SocketAddr, StreamConfigMessage)> = {
+        let mut state_guard = STREAMING_STATE_GUARD.lock().unwrap();
+        let mut viewers = Vec::new();
+        if let Some(state) = state_guard.as_mut() {
+            for (peer_addr, peer) in state.peers.iter_mut() {
+                let Some(viewer_config) = peer.stream.clone() else {
+                    continue;
+                };
+                if !may_view(peer) {
+                    continue;
+                }
+                let same_stream = *peer_addr == addr
+                    || target_config
+                        .as_ref()
+                        .map_or(false, |target| target.same_stream_settings(&viewer_config));
+                if same_stream {
+                    viewers.push((*peer_addr, viewer_config));
+                } else {
+                    peer.stream = None;
+                    renegotiate.push(*peer_addr);
+                }
+            }
+        }
+        viewers
+    };
+    for viewer_addr in renegotiate {
+        info!(
+            "Not streaming to {}, it asked for other settings than {}.",
+            viewer_addr, addr
+        );
+        send_event(
+            viewer_addr,
+            &ControlEvent::StreamRenegotiate {
+                video_width: config.video_width,
+                video_height: config.video_height,
+                framerate,
+                codec,
+            },
+        );
+    }
+    for (viewer_addr, viewer_config) in &viewers {
+        if let Err(e) = add_stream_branch(&pipeline, *viewer_addr, viewer_config, &session) {
+            error!("Failed to add the stream for {}: {}", viewer_addr, e);
+        }
+    }
+
+    // Store the running pipeline in the global Mutex
+    *guard = Some(pipeline.clone());
+    *PIPELINE_HANDLES.lock().unwrap() = Some(handles);
+    *ENCODER_SETUP.lock().unwrap() = Some(EncoderSetup {
+        encoder,
+        codec,
+        encoded_caps: encoded_caps_str,
+        hdr: hdr_metadata.is_some(),
+        chroma_444,
+        latency_preset,
+        preset_lowered: false,
+        intra_refresh,
+        x264_options,
+        av1_tuning,
+        content_tune,
+        b_frames,
+    });
+
+    // Set pipeline to playing
+    if let Err(e) = pipeline.set_state(gst::State::Playing) {
+        error!("Failed to set pipeline to Playing: {}", e);
+
+        // Leave nothing behind, so the next client or a restart can build the pipeline again.
+        let _ = pipeline.set_state(gst::State::Null);
+        *guard = None;
+        *ENCODER_SETUP.lock().unwrap() = None;
+
+        // The failing element posted the details, report them before the bus thread gives up.
+        // The handles and branches tell which element it was.
+        if let Some(bus) = pipeline.bus() {
+            while let Some(msg) = bus.pop_filtered(&[gst::MessageType::Error]) {
+                handle_bus_message(&pipeline, &msg);
+            }
+        }
+        *PIPELINE_HANDLES.lock().unwrap() = None;
+        STREAM_BRANCHES.lock().unwrap().clear();
+    } else {
+        info!(
+            "Pipeline started playing to {} client(s) with the settings of {}!",
+            viewers.len(),
+            addr
+        );
+
+        *CURRENT_SESSION.lock().unwrap() = Some(session);
+        *ACTIVE_STEREO_MODE.lock().unwrap() = stereo_mode;
+        for (viewer_addr, viewer_config) in &viewers {
+            send_stream_description(*viewer_addr, viewer_config);
+        }
+        crate::recording::start(&pipeline, &session);
+
+        if encoder.is_hardware() {
+            start_thermal_monitor();
+            crate::gpustats::start_monitor();
+        }
+    }
+}
+
+/// The encoder bin, `encoder` behind `memory` if frames have to change memory first, then a
+/// capsfilter for `caps`. Its pads link like those of an element.
+fn make_encoder_bin(
+    memory: Option<&str>,
+    encoder: &gst::Element,
+    caps: &str,
+) -> Result<gst::Bin, BuildError> {
+    let bin = gst::Bin::with_name(ENCODER_BIN);
+    let mut elements = Vec::new();
+    if let Some(memory) = memory {
+        elements.push(make(memory)?.build()?);
+    }
+    elements.push(encoder.clone());
+    elements.push(capsfilter(None, caps)?);
+    add_chain(&bin, None, &elements)?;
+    ghost_pad(&bin, elements.first().unwrap(), "sink")?;
+    ghost_pad(&bin, elements.last().unwrap(), "src")?;
+    Ok(bin)
+}
+
+fn handle_bus_message(pipeline: &gst::Pipeline, msg: &gst::Message) {
+    match msg.view() {
+        MessageView::Error(err) => {
+            error!(
+                "Error from {:?}: {} ({:?})",
+                err.src().map(|s| s.path_string()),
+                err.error(),
+                err.debug()
+            );
+
+            if caused_by_encoder_switch(err.src()) {
+                info!("The encoder is being replaced, ignoring the error.");
+                return;
+            }
+
+            // Hardware encoders fail e.g. when the GPU is out of encode sessions. Step down
+            // instead of leaving the client with no video.
+            let from_encoder = err.src().map_or(false, |src| {
+                pipeline_handles().map_or(false, |handles| {
+                    handles.video_encoder.upcast_ref::<gst::Object>() == src
+                })
+            });
+            if from_encoder {
+                let error = err.error().to_string();
+                if let Some((to, preset)) = encoder_fallback(&error, err.debug().as_deref()) {
+                    expect_encoder_switch(pipeline);
+                    thread::spawn(move || {
+                        if !switch_encoder(to, preset) {
+                            restart_gstreamer_pipeline();
+                        }
+                    });
+                    return;
+                }
+            }
+
+            // Errors once the stream plays are not the settings' fault, a restart may fix them.
+            // Failed starts are left to safe mode.
+            let playing = pipeline.current_state() == gst::State::Playing;
+            if !playing {
+                crate::safemode::record_failed_start();
+            }
+
+            let message = err.error().to_string();
+            let bind_failed = message.to_lowercase().contains("bind");
+            let code = match err.src().map(|src| src.name()).as_deref() {
+                _ if from_encoder => ErrorCode::EncoderFailed,
+                Some("capture") | Some("capture_right") => ErrorCode::CaptureDenied,
+                Some("videortcpsrc") | Some("audiortcpsrc") | Some("micsrc") if bind_failed => {
+                    ErrorCode::PortBusy
+                }
+                _ if bind_failed && err.src().map_or(false, is_branch_sink) => ErrorCode::PortBusy,
+                _ => ErrorCode::PipelineFailed,
+            };
+            broadcast_error(code, message.clone());
+            if playing {
+                schedule_recovery(&message);
+            }
+        }
+        MessageView::Warning(warning) => {
+            warn!(
+                "Warning from {:?}: {} ({:?})",
+                warning.src().map(|s| s.path_string()),
+                warning.error(),
+                warning.debug()
+            );
+        }
+        MessageView::Eos(_) => {
+            // Live sources never end, a capture source that did lost its device or window.
+            error!("End of stream reached.");
+            schedule_recovery("The capture ended unexpectedly");
+        }
+        MessageView::Latency(_) => {
+            crate::latency::update_pipeline_latency(pipeline);
+        }
+        MessageView::StateChanged(state_changed) => {
+            // Live sources only report their latency once the pipeline runs.
+            if state_changed.src() == Some(pipeline.upcast_ref::<gst::Object>())
+                && state_changed.current() == gst::State::Playing
+            {
+                crate::safemode::record_started();
+                RECOVERY.lock().unwrap().playing_since = Some(Instant::now());
+                crate::latency::update_pipeline_latency(pipeline);
+            }
+
+            error!(
+                "Pipeline state changed from {:?} to {:?} (pending: {:?})",
+                state_changed.old(),
+                state_changed.current(),
+                state_changed.pending(),
+            );
+        }
+        // Add more match arms for other message types you care about
+        _ => {
+            error!("Unhandled message: {:?}", msg.type_()); // Uncomment for all messages
+        }
+    }
+}
+
+// Restarts the pipeline after a runtime failure, waiting longer with every attempt in a row so a
+// persistent failure does not restart it in a tight loop. Clients stay connected meanwhile.
+fn schedule_recovery(reason: &str) {
+    let (attempt, delay) = {
+        let mut recovery = RECOVERY.lock().unwrap();
+        if recovery.pending {
+            return;
+        }
+        if recovery
+            .playing_since
+            .take()
+            .is_some_and(|since| since.elapsed() >= RECOVERY_STABLE_TIME)
+        {
+            recovery.attempts = 0;
+        }
+        if recovery.attempts >= MAX_RECOVERY_ATTEMPTS {
+            // Whoever starts the stream again starts with a clean slate.
+            recovery.attempts = 0;
+            drop(recovery);
+            error!(
+                "The pipeline failed {} times in a row, not restarting it again.",
+                MAX_RECOVERY_ATTEMPTS
+            );
+            broadcast_error(
+                ErrorCode::PipelineFailed,
+                format!("The stream keeps failing: {}", reason),
+            );
+            return;
+        }
+        recovery.attempts += 1;
+        recovery.pending = true;
+        let delay = RECOVERY_BASE_DELAY
+            .saturating_mul(1 << (recovery.attempts - 1))
+            .min(RECOVERY_MAX_DELAY);
+        (recovery.attempts, delay)
+    };
+
+    warn!(
+        "Restarting the pipeline in {} ms, attempt {} of {}: {}",
+        delay.as_millis(),
+        attempt,
+        MAX_RECOVERY_ATTEMPTS,
+        reason
+    );
+    broadcast_event(&ControlEvent::StreamRecovering {
+        attempt,
+        max_attempts: MAX_RECOVERY_ATTEMPTS,
+        retry_in_ms: delay.as_millis() as u64,
+    });
+
+    let reason = reason.to_string();
+    thread::spawn(move || {
+        thread::sleep(delay);
+        RECOVERY.lock().unwrap().pending = false;
+        restart_gstreamer_pipeline();
+
+        // A restart that fails right away posts no error once playing, so keep trying from here.
+        let active = SESSION.lock().unwrap().state != SessionState::Idle;
+        if active && !is_pipeline_running() {
+            schedule_recovery(&reason);
+        }
+    });
+}
+
+// Marks the errors that follow a failed encoder as expected, before the bus delivers them.
+fn expect_encoder_switch(pipeline: &gst::Pipeline) {
+    if let (Some(capture), Some(encoder)) =
+        (pipeline.by_name(CAPTURE_BIN), pipeline.by_name(ENCODER_BIN))
+    {
+        *ENCODER_SWITCH.lock().unwrap() = Some((Instant::now(), capture, encoder));
+    }
+}
+
+fn caused_by_encoder_switch(src: Option<&gst::Object>) -> bool {
+    let switch = ENCODER_SWITCH.lock().unwrap();
+    let (Some(src), Some((started, capture, encoder))) = (src, switch.as_ref()) else {
+        return false;
+    };
+    started.elapsed() < ENCODER_SWITCH_GRACE
+        && (src.has_as_ancestor(capture) || src.has_as_ancestor(encoder))
+}
+
+/// Replaces the video encoder of the running stream with `to` set up by `preset` without
+/// rebuilding the pipeline, e.g. when the GPU runs out of encode sessions. Capture restarts,
+/// while clients, audio and recording keep going. Clients are told to expect a keyframe with new
+/// parameter sets. False if the stream cannot switch like this and needs a restart. Blocking.
+pub(crate) fn switch_encoder(to: VideoEncoder, preset: LatencyPreset) -> bool {
+    let guard = PIPELINE_GUARD.lock().unwrap();
+    let Some(pipeline) = guard.as_ref() else {
+        return false;
+    };
+    let bitrate_kbps = match PIPELINE_TARGET.lock().unwrap().as_ref() {
+        Some((_, config)) => config.bitrate * 1024,
+        None => return false,
+    };
+    let gpu_adapter = STREAMING_STATE_GUARD
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|state| state.gpu_adapter.clone());
+
+    let mut setup_guard = ENCODER_SETUP.lock().unwrap();
+    let Some(setup) = setup_guard.as_mut() else {
+        return false;
+    };
+    // 10-bit and 4:4:4 frames only suit some encoders.
+    if !to.is_available(setup.codec)
+        || (setup.hdr && !to.encodes_10bit(setup.codec))
+        || (setup.chroma_444 && !to.encodes_444(setup.codec))
+    {
+        info!(
+            "{} cannot take over the {} stream, restarting it.",
+            to, setup.codec
+        );
+        return false;
+    }
+    let (Some(capture), Some(old_encoder), Some(tee)) = (
+        pipeline.by_name(CAPTURE_BIN),
+        pipeline.by_name(ENCODER_BIN),
+        pipeline.by_name("videoenctee"),
+    ) else {
+        return false;
+    };
+
+    let factory_name = match &gpu_adapter {
+        Some(adapter) if to.is_hardware() => {
+            crate::gpu::encoder_factory(to.factory_name(setup.codec), adapter.luid)
+        }
+        _ => to.factory_name(setup.codec).to_string(),
+    };
+    // Frames stay in the memory the previous encoder took them in.
+    let memory = match (setup.encoder.takes_d3d11_memory(), to.takes_d3d11_memory()) {
+        (true, false) => Some("d3d11download"),
+        (false, true) => Some("d3d11upload"),
+        _ => None,
+    };
+    let made = to
+        .make_element(
+            &factory_name,
+            setup.codec,
+            &preset.params(),
+            bitrate_kbps,
+            setup.intra_refresh,
+            &setup.x264_options,
+            &setup.av1_tuning,
+            setup.content_tune,
+            setup.b_frames,
+        )
+        .and_then(|enc| Ok((make_encoder_bin(memory, &enc, setup.encoded_caps)?, enc)));
+    let (new_encoder, enc) = match made {
+        Ok(made) => made,
+        Err(e) => {
+            error!("Failed to make the {} encoder: {}", to, e);
+            return false;
+        }
+    };
+    info!(
+        "Switching the stream from {} to {} with the {} preset.",
+        setup.encoder, to, preset
+    );
+
+    // Capture stops once the encoder fails, going through Ready starts it afresh and drops the
+    // frames queued for the old encoder.
+    let _ = capture.set_state(gst::State::Ready);
+    let _ = old_encoder.set_state(gst::State::Null);
+    capture.unlink(&old_encoder);
+    old_encoder.unlink(&tee);
+    if let Err(e) = pipeline.remove(&old_encoder) {
+        error!("Failed to remove the encoder: {}", e);
+        return false;
+    }
+    let linked = pipeline
+        .add(&new_encoder)
+        .and_then(|_| gst::Element::link_many([&capture, new_encoder.upcast_ref(), &tee]));
+    if let Err(e) = linked {
+        error!("Failed to link the {} encoder: {}", to, e);
+        return false;
+    }
+    let _ = new_encoder.sync_state_with_parent();
+    let _ = capture.sync_state_with_parent();
+
+    setup.preset_lowered |= preset != setup.latency_preset;
+    setup.encoder = to;
+    setup.latency_preset = preset;
+    if let Some(handles) = PIPELINE_HANDLES.lock().unwrap().as_mut() {
+        crate::stats::add_encoder_probe(&enc);
+        handles.video_encoder = enc;
+    }
+    if let Some(stream_config) = STREAMING_STATE_GUARD
+        .lock()
+        .unwrap()
+        .as_mut()
+        .and_then(|state| state.stream_config.as_mut())
+    {
+        stream_config.encoder = factory_name;
+    }
+
+    broadcast_event(&ControlEvent::EncoderSwitched {
+        encoder: to.to_string(),
+    });
+    true
+}
+
+pub fn stop_gstreamer_pipeline() {
+    // Acquire the lock for the global pipeline state.
+    let mut guard = PIPELINE_GUARD.lock().unwrap();
+
+    // Use `Option::take()` to extract the pipeline and replace the value with None.
+    // The extracted pipeline reference will then be dropped when it goes out of scope.
+    if let Some(pipeline) = guard.take() {
+        *PIPELINE_HANDLES.lock().unwrap() = None;
+        *ENCODER_SETUP.lock().unwrap() = None;
+        *ENCODER_SWITCH.lock().unwrap() = None;
+        // Finished while the pipeline still runs, a muxer cut off midway leaves a broken file.
+        crate::recording::stop();
+        pipeline
+            .set_state(gst::State::Null)
+            .expect("Unable to set the pipeline to the `Null` state");
+        *CURRENT_SESSION.lock().unwrap() = None;
+        STREAM_BRANCHES.lock().unwrap().clear();
+        crate::audiostats::stop();
+        crate::stats::stop();
+        crate::protected::stop();
+        crate::latency::reset_pipeline_latency();
+        info!("Pipeline stopped.");
+        crate::gui::request_repaint();
+    }
+    // The lock is automatically released when `guard` goes out of scope.
+}
+
+pub fn is_pipeline_running() -> bool {
+    PIPELINE_GUARD.lock().unwrap().is_some()
+}
+
+/// Looks up an element of the running pipeline by name.
+pub fn pipeline_element(name: &str) -> Option<gst::Element> {
+    PIPELINE_GUARD.lock().unwrap().as_ref()?.by_name(name)
+}
+
+/// The elements of the running pipeline that are controlled at runtime.
+pub fn pipeline_handles() -> Option<PipelineHandles> {
+    PIPELINE_HANDLES.lock().unwrap().clone()
+}
+
+/// Changes the video FEC overhead, also of the running stream.
+pub fn set_video_fec_percentage(percentage: u32) {
+    {
+        let mut guard = STREAMING_STATE_GUARD.lock().unwrap();
+        if let Some(state) = guard.as_mut() {
+            state.video_fec_percentage = percentage;
+        }
+    }
+
+    if let Some(fec) = pipeline_element("videofec") {
+        fec.set_property("percentage", percentage);
+    }
+}
+
+// What replaces the hardware encoder of the running stream after it failed: the same encoder
+// with a faster preset first, then the next encoder that can take the stream over, down to the
+// software one. None for the software encoder, which has nothing to step down to.
+fn encoder_fallback(error: &str, debug: Option<&str>) -> Option<(VideoEncoder, LatencyPreset)> {
+    let (failed, codec, hdr, chroma_444, preset, preset_lowered) = {
+        let guard = ENCODER_SETUP.lock().unwrap();
+        let setup = guard.as_ref().filter(|setup| setup.encoder.is_hardware())?;
+        (
+            setup.encoder,
+            setup.codec,
+            setup.hdr,
+            setup.chroma_444,
+            setup.latency_preset,
+            setup.preset_lowered,
+        )
+    };
+    let cause = describe_encoder_error(error, debug);
+
+    // Running out of sessions is not a matter of the preset.
+    if let Some(faster) = preset
+        .faster()
+        .filter(|_| !preset_lowered && !is_session_limit_error(error, debug))
+    {
+        set_encoder_warning(format!(
+            "{} Retrying with the encoder settings of {}.",
+            cause, faster
+        ));
+        return Some((failed, faster));
+    }
+
+    let to = VideoEncoder::ALL
+        .into_iter()
+        .find(|encoder| {
+            *encoder != failed
+                && encoder.is_available(codec)
+                && (!hdr || encoder.encodes_10bit(codec))
+                && (!chroma_444 || encoder.encodes_444(codec))
+        })
+        .unwrap_or(VideoEncoder::Software);
+    block_encoder(failed, format!("{} Switching to {}.", cause, to));
+    Some((to, preset))
+}
+
+// Sends the stream to a client as well, with a new branch off the tees. Returns false if the
+// running pipeline can't serve it as is.
+fn attach_peer(addr: SocketAddr, config: &StreamConfigMessage) -> bool {
+    let guard = PIPELINE_GUARD.lock().unwrap();
+    let Some(pipeline) = guard.as_ref() else {
+        return false;
+    };
+
+    let mut target = PIPELINE_TARGET.lock().unwrap();
+    let Some((target_addr, target_config)) = target.as_ref() else {
+        return false;
+    };
+
+    if !target_config.same_stream_settings(config) {
+        return false;
+    }
+
+    // A roaming client reconnects from a new address, its settings now drive the stream.
+    let target_gone = {
+        let state_guard = STREAMING_STATE_GUARD.lock().unwrap();
+        state_guard
+            .as_ref()
+            .map_or(true, |state| !state.peers.contains_key(target_addr))
+    };
+    if target_gone {
+        info!("Retargeted pipeline from {} to {}.", target_addr, addr);
+        *target = Some((addr, config.clone()));
+    }
+    drop(target);
+
+    let Some(session) = *CURRENT_SESSION.lock().unwrap() else {
+        return false;
+    };
+
+    // A repeated stream config replaces the branch.
+    remove_stream_branch(addr);
+    if let Err(e) = add_stream_branch(pipeline, addr, config, &session) {
+        error!("Failed to add the stream for {}: {}", addr, e);
+        report_error(addr, ErrorCode::PipelineFailed, e.to_string());
+        return true;
+    }
+
+    // A frame rate cap of the previous client no longer holds once others watch too.
+    refresh_delivered_framerate(pipeline);
+
+    // The stream continues, so the new client must expect the same identifiers and layout,
+    // and needs a keyframe to start decoding.
+    send_stream_description(addr, config);
+    request_keyframe();
+    info!("Streaming to {} as well.", addr);
+    true
+}
+
+// Links a queue and UDP sinks for `addr` to the tees. Bundled audio leaves through the video
+// socket, so both share one 5-tuple.
+fn add_stream_branch(
+    pipeline: &gst::Pipeline,
+    addr: SocketAddr,
+    config: &StreamConfigMessage,
+    session: &RtpSession,
+) -> Result<(), BuildError> {
+    if config.local && addr.ip().is_loopback() && crate::local::is_enabled() {
+        info!(
+            "{} reads the stream from {}, not sending RTP.",
+            addr,
+            crate::local::VIDEO_PIPE_NAME
+        );
+        return Ok(());
+    }
+
+    let host = addr.ip().to_string();
+    let (video_port, audio_port) = assign_media_ports(addr, config);
+    let (video_paused, audio_paused, delay_seconds) = {
+        let guard = STREAMING_STATE_GUARD.lock().unwrap();
+        guard
+            .as_ref()
+            .and_then(|state| {
+                let peer = state.peers.get(&addr)?;
+                let delay_seconds = if is_spectator(peer) {
+                    state.spectator_delay_seconds
+                } else {
+                    0
+                };
+                Some((peer.video_paused, peer.audio_paused, delay_seconds))
+            })
+            .unwrap_or((false, false, 0))
+    };
+
+    // A spectator's queue holds back its stream until it is `delay_seconds` behind.
+    let delay_ns = delay_seconds as u64 * 1_000_000_000;
+    if delay_seconds > 0 {
+        info!(
+            "Delaying the stream of spectator {} by {} s.",
+            addr, delay_seconds
+        );
+    }
+    let make_input = |paused: bool| -> Result<(gst::Element, gst::Element), BuildError> {
+        let queue = make("queue")?
+            .property("max-size-buffers", 0u32)
+            .property("max-size-bytes", 0u32)
+            .property(
+                "max-size-time",
+                delay_ns + BRANCH_QUEUE_MAX_TIME_MS * 1_000_000,
+            )
+            .property("min-threshold-time", delay_ns)
+            .property_from_str("leaky", "downstream")
+            .build()?;
+        let valve = make("valve")?.property("drop", paused).build()?;
+        Ok((queue, valve))
+    };
+
+    let bin = gst::Bin::new();
+    let video = make_input(video_paused)?;
+    // MPEG-TS has no audio stream to link to without audio, the muxer would wait for it forever.
+    let audio = if config.mpegts && pipeline.by_name("audioenctee").is_none() {
+        None
+    } else {
+        Some(make_input(audio_paused)?)
+    };
+    for (queue, valve) in std::iter::once(&video).chain(&audio) {
+        add_chain(&bin, None, &[queue.clone(), valve.clone()])?;
+    }
+    let audio_valve = audio.as_ref().map(|(_, valve)| valve);
+
+    let mut sinks = Vec::new();
+    let mut webrtc = None;
+    if config.webrtc {
+        info!("Streaming to {} over WebRTC.", addr);
+        let element = crate::webrtc::add_branch(&bin, &video.1, audio_valve)?;
+        crate::webrtc::attach(&element, addr);
+        webrtc = Some(element);
+    } else if config.mpegts {
+        info!("Streaming MPEG-TS to {} on port {}.", addr, video_port);
+        sinks.push(crate::mpegts::add_branch(
+            &bin,
+            &video.1,
+            audio_valve,
+            &host,
+            video_port,
+            session,
+        )?);
+    } else if config.bundle {
+        info!(
+            "Bundling audio with video on port {} for {}.",
+            video_port, addr
+        );
+        let funnel = make("funnel")?.name("bundle").build()?;
+        let sink = make_udpsink("videosink", &host, video_port)?;
+        add_chain(&bin, None, &[funnel.clone(), sink.clone()])?;
+        for valve in std::iter::once(&video.1).chain(audio_valve) {
+            valve.link(&funnel)?;
+        }
+        sinks.push(sink);
+    } else {
+        let sink = make_udpsink("videosink", &host, video_port)?;
+        add_chain(&bin, Some(&video.1), std::slice::from_ref(&sink))?;
+        sinks.push(sink);
+        if let Some(valve) = audio_valve {
+            let sink = make_udpsink("audiosink", &host, audio_port)?;
+            add_chain(&bin, Some(valve), std::slice::from_ref(&sink))?;
+            sinks.push(sink);
+        }
+    }
+
+    pipeline.add(&bin)?;
+    bin.sync_state_with_parent()?;
+
+    // Kept even if linking fails halfway, so removing the branch cleans up what was linked.
+    let mut branch = StreamBranch {
+        addr,
+        bin,
+        video,
+        audio,
+        encoded: config.mpegts,
+        sinks,
+        webrtc,
+        tee_pads: Vec::new(),
+    };
+    let linked = link_stream_branch(pipeline, &mut branch);
+    STREAM_BRANCHES.lock().unwrap().push(branch);
+    linked.map_err(BuildError::from)
+}
+
+/// A udpsink that sends to `host` on `port`, for a client branch.
+pub(crate) fn make_udpsink(name: &str, host: &str, port: u16) -> Result<gst::Element, BuildError> {
+    Ok(make("udpsink")?
+        .name(name)
+        .property("host", host)
+        .property("port", port as i32)
+        .property("sync", false)
+        .build()?)
+}
+
+fn link_stream_branch(
+    pipeline: &gst::Pipeline,
+    branch: &mut StreamBranch,
+) -> Result<(), gst::glib::BoolError> {
+    let tees = if branch.encoded {
+        ENCODED_TEES
+    } else {
+        RTP_TEES
+    };
+    let queues = std::iter::once(&branch.video)
+        .chain(&branch.audio)
+        .map(|(queue, _)| queue);
+    for (tee_name, queue) in tees.into_iter().zip(queues) {
+        let (Some(tee), Some(queue_pad)) = (pipeline.by_name(tee_name), queue.static_pad("sink"))
+        else {
+            continue;
+        };
+
+        let ghost_pad = gst::GhostPad::with_target(&queue_pad)?;
+        ghost_pad.set_active(true)?;
+        branch.bin.add_pad(&ghost_pad)?;
+
+        let Some(tee_pad) = tee.request_pad_simple("src_%u") else {
+            continue;
+        };
+        if let Err(e) = tee_pad.link(&ghost_pad) {
+            tee.release_request_pad(&tee_pad);
+            return Err(gst::glib::bool_error!(
+                "Failed to link {}: {:?}",
+                tee_name,
+                e
+            ));
+        }
+        branch.tee_pads.push((tee, tee_pad));
+    }
+    Ok(())
+}
+
+// Where the client at `addr` receives video and audio: the ports it asked for, otherwise the
+// configured ones. Clients sharing an IP, e.g. behind NAT, get the next free pair instead. The
+// ports stay with the client until it disconnects.
+fn assign_media_ports(addr: SocketAddr, config: &StreamConfigMessage) -> (u16, u16) {
+    let mut guard = STREAMING_STATE_GUARD.lock().unwrap();
+    let Some(state) = guard.as_mut() else {
+        return (
+            config.video_port.unwrap_or(CLIENT_VIDEO_PORT),
+            config.audio_port.unwrap_or(CLIENT_AUDIO_PORT),
+        );
+    };
+
+    let (base_video, base_audio) = state.media_ports;
+    let max_clients = state.max_clients as u16;
+    let taken: Vec<u16> = state
+        .peers
+        .iter()
+        .filter(|(peer_addr, _)| **peer_addr != addr && peer_addr.ip() == addr.ip())
+        .filter_map(|(_, peer)| peer.media_ports)
+        .flat_map(|(video, audio)| [video, audio])
+        .collect();
+    let Some(peer) = state.peers.get_mut(&addr) else {
+        return (base_video, base_audio);
+    };
+
+    let allocated = peer.media_ports.unwrap_or_else(|| {
+        // Steps of two keep adjacent defaults like 5601/5602 from overlapping.
+        (0..max_clients)
+            .filter_map(|index| {
+                Some((
+                    base_video.checked_add(index * 2)?,
+                    base_audio.checked_add(index * 2)?,
+                ))
+            })
+            .find(|(video, audio)| !taken.contains(video) && !taken.contains(audio))
+            .unwrap_or((base_video, base_audio))
+    });
+    let ports = (
+        config.video_port.unwrap_or(allocated.0),
+        config.audio_port.unwrap_or(allocated.1),
+    );
+    if peer.media_ports != Some(ports) && ports != (base_video, base_audio) {
+        info!(
+            "Sending video to port {} and audio to port {} of {}.",
+            ports.0, ports.1, addr
+        );
+    }
+    peer.media_ports = Some(ports);
+    ports
+}
+
+// Whether the host still lets `peer` receive the stream, it may have been revoked since.
+fn may_view(peer: &Peer) -> bool {
+    peer.client_id
+        .as_deref()
+        .map_or(false, |id| crate::trust::permissions(id).view)
+}
+
+// Whether a client only watches, without any input. Its stream gets the spectator delay.
+fn is_spectator(peer: &Peer) -> bool {
+    peer.client_id
+        .as_deref()
+        .map_or(false, |id| crate::trust::permissions(id).is_view_only())
+}
+
+/// Delays the stream of clients that only watch by `seconds`, 0 for none, so players can let
+/// friends watch without them calling out positions. Spectators streaming now switch over,
+/// others once permissions change and they connect again. Blocking.
+pub fn set_spectator_delay(seconds: u32) {
+    let guard = PIPELINE_GUARD.lock().unwrap();
+
+    let spectators: Vec<(SocketAddr, StreamConfigMessage)> = {
+        let mut state_guard = STREAMING_STATE_GUARD.lock().unwrap();
+        let Some(state) = state_guard.as_mut() else {
+            return;
+        };
+        if state.spectator_delay_seconds == seconds {
+            return;
+        }
+        state.spectator_delay_seconds = seconds;
+        state
+            .peers
+            .iter()
+            .filter(|(_, peer)| may_view(peer) && is_spectator(peer))
+            .filter_map(|(addr, peer)| Some((*addr, peer.stream.clone()?)))
+            .collect()
+    };
+
+    info!("Spectator delay set to {} s.", seconds);
+    let Some(pipeline) = guard.as_ref() else {
+        return;
+    };
+    let Some(session) = *CURRENT_SESSION.lock().unwrap() else {
+        return;
+    };
+    for (addr, config) in &spectators {
+        remove_stream_branch(*addr);
+        if let Err(e) = add_stream_branch(pipeline, *addr, config, &session) {
+            error!("Failed to add the stream for {}: {}", addr, e);
+        }
+    }
+    if !spectators.is_empty() {
+        request_keyframe();
+    }
+}
+
+/// The webrtcbin of the branch streaming to `addr`, if it streams over WebRTC.
+pub(crate) fn branch_webrtc(addr: SocketAddr) -> Option<gst::Element> {
+    STREAM_BRANCHES
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|branch| branch.addr == addr)
+        .and_then(|branch| branch.webrtc.clone())
+}
+
+// Whether `src` sends to a client, so a failed bind is a port taken on this machine.
+fn is_branch_sink(src: &gst::Object) -> bool {
+    STREAM_BRANCHES
+        .lock()
+        .unwrap()
+        .iter()
+        .flat_map(|branch| &branch.sinks)
+        .any(|sink| sink.upcast_ref::<gst::Object>() == src)
+}
+
+// Drops what a client paused at the valves of its branch.
+fn set_valves(branch: &StreamBranch, video_paused: bool, audio_paused: bool) {
+    branch.video.1.set_property("drop", video_paused);
+    if let Some((_, valve)) = &branch.audio {
+        valve.set_property("drop", audio_paused);
+    }
+}
+
+/// Stops or resumes sending `media` to the client at `addr`, e.g. while its app is in the
+/// background. Its branch drops the buffers, so resuming is instant and the pipeline and the
+/// other clients go on. Blocking.
+pub(crate) fn set_stream_paused(
+    addr: SocketAddr,
+    media: StreamMedia,
+    paused: bool,
+) -> std::io::Result<()> {
+    let guard = PIPELINE_GUARD.lock().unwrap();
+
+    let (video_paused, audio_paused) = {
+        let mut state_guard = STREAMING_STATE_GUARD.lock().unwrap();
+        let Some(peer) = state_guard
+            .as_mut()
+            .and_then(|state| state.peers.get_mut(&addr))
+        else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "Unknown client",
+            ));
+        };
+        if media != StreamMedia::Audio {
+            peer.video_paused = paused;
+        }
+        if media != StreamMedia::Video {
+            peer.audio_paused = paused;
+        }
+        (peer.video_paused, peer.audio_paused)
+    };
+
+    info!(
+        "{} {} the {} of its stream.",
+        addr,
+        if paused { "paused" } else { "resumed" },
+        media
+    );
+    if let Some(branch) = STREAM_BRANCHES
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|branch| branch.addr == addr)
+    {
+        set_valves(branch, video_paused, audio_paused);
+    }
+
+    // Decoding picks up at the next keyframe, which would otherwise be seconds away.
+    if !paused && media != StreamMedia::Audio {
+        if guard.is_some() {
+            request_keyframe();
+        }
+    }
+    crate::gui::request_repaint();
+    Ok(())
+}
+
+// Stops sending the stream to `addr`. The other clients are not interrupted: each tee pad is
+// released once no buffer passes it, and the branch is removed from outside the streaming threads.
+fn remove_stream_branch(addr: SocketAddr) {
+    let branch = {
+        let mut branches = STREAM_BRANCHES.lock().unwrap();
+        let Some(index) = branches.iter().position(|branch| branch.addr == addr) else {
+            return;
+        };
+        branches.remove(index)
+    };
+
+    info!("Stopped streaming to {}.", addr);
+
+    if branch.tee_pads.is_empty() {
+        remove_branch_bin(&branch.bin);
+        return;
+    }
+
+    let remaining = Arc::new(AtomicUsize::new(branch.tee_pads.len()));
+    for (tee, tee_pad) in branch.tee_pads {
+        let bin = branch.bin.clone();
+        let remaining = remaining.clone();
+
+        tee_pad.add_probe(gst::PadProbeType::IDLE, move |pad, _| {
+            if let Some(peer) = pad.peer() {
+                let _ = pad.unlink(&peer);
+            }
+            tee.release_request_pad(pad);
+
+            if remaining.fetch_sub(1, Ordering::SeqCst) == 1 {
+                bin.call_async(remove_branch_bin);
+            }
+            gst::PadProbeReturn::Remove
+        });
+    }
+}
+
+fn remove_branch_bin(bin: &gst::Bin) {
+    let _ = bin.set_state(gst::State::Null);
+    if let Some(parent) = bin.parent().and_downcast::<gst::Bin>() {
+        let _ = parent.remove(bin);
+    }
+}
+
+// Starts the stream for an authenticated client: joins the running pipeline if it fits, or
+// rebuilds it with the settings of this client for everyone.
+fn stream_to_peer(addr: SocketAddr, config: StreamConfigMessage) {
+    {
+        // Waits for a session that is ending to be torn down, then starts a new one.
+        let mut session = SESSION.lock().unwrap();
+        if session.state == SessionState::Idle {
+            info!("Session started.");
+        }
+        session.state = SessionState::Active;
+        session.generation += 1;
+    }
+
+    if attach_peer(addr, &config) {
+        return;
+    }
+
+    if is_pipeline_running() {
+        info!(
+            "{} wants different stream settings, rebuilding the pipeline.",
+            addr
+        );
+        stop_gstreamer_pipeline();
+    }
+    start_gstreamer_pipeline(addr, config);
 }
 
-pub static STREAMING_STATE_GUARD: Mutex<Option<StreamingState>> = Mutex::new(None);
+/// Hands the session to the client at `addr`, e.g. when the user moves from the phone to the PC.
+/// Its stream settings and input take over, every other client is told and let go. The game
+/// keeps running, the pipeline is only rebuilt if the new device needs other settings. Blocking.
+pub(crate) fn hand_over_session(addr: SocketAddr) -> std::io::Result<()> {
+    let (config, name, previous) = {
+        let guard = STREAMING_STATE_GUARD.lock().unwrap();
+        let Some((state, peer)) = guard
+            .as_ref()
+            .and_then(|state| state.peers.get(&addr).map(|peer| (state, peer)))
+        else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "Unknown client",
+            ));
+        };
+        let Some(config) = peer.stream.clone() else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Send a stream config before taking over",
+            ));
+        };
+        let name = peer
+            .client_id
+            .as_deref()
+            .and_then(crate::trust::find)
+            .map_or(addr.ip().to_string(), |client| {
+                client.display_name().to_string()
+            });
+        let previous: Vec<SocketAddr> = state
+            .peers
+            .iter()
+            .filter(|(other, peer)| **other != addr && peer.authenticated)
+            .map(|(other, _)| *other)
+            .collect();
+        (config, name, previous)
+    };
 
-// ----------------------------------------------------------------------
-// --- GStreamer Functions (Now Thread-Safe) ----------------------------
-// ----------------------------------------------------------------------
+    info!("{} takes over the session from {:?}.", name, previous);
+    for other in previous {
+        send_event(other, &ControlEvent::SessionTakenOver { by: name.clone() });
+        disconnect_peer(other, EndReason::TakenOver);
+    }
+    // ENet serves one client at a time, the previous one would keep the new one out.
+    crate::input::hand_over(addr.ip());
+
+    let retargeted = {
+        let guard = PIPELINE_GUARD.lock().unwrap();
+        let mut target = PIPELINE_TARGET.lock().unwrap();
+        match (guard.as_ref(), target.as_mut()) {
+            (Some(pipeline), Some(target)) if target.1.same_stream_settings(&config) => {
+                *target = (addr, config.clone());
+                let framerate = {
+                    let state_guard = STREAMING_STATE_GUARD.lock().unwrap();
+                    state_guard.as_ref().map_or(config.framerate, |state| {
+                        delivered_framerate(state, addr, &config)
+                    })
+                };
+                set_delivered_framerate(pipeline, framerate);
+                true
+            }
+            _ => false,
+        }
+    };
+    if !retargeted {
+        if is_pipeline_running() {
+            info!(
+                "Rebuilding the pipeline for the stream settings of {}.",
+                name
+            );
+            stop_gstreamer_pipeline();
+        }
+        start_gstreamer_pipeline(addr, config);
+    }
+    crate::gui::request_repaint();
+    Ok(())
+}
 
-#[derive(Copy, Clone)]
-pub(crate) enum ConnectionStatus {
-    Ready,
-    Connected,
-    Error,
+// Stops the stream of a client that left, the others keep theirs.
+fn detach_peer(addr: SocketAddr) {
+    // Waits for a pipeline being built, which may have added a branch for this client.
+    let guard = PIPELINE_GUARD.lock().unwrap();
+    remove_stream_branch(addr);
+    // The client left behind may have a frame rate cap that holds now.
+    if let Some(pipeline) = guard.as_ref() {
+        refresh_delivered_framerate(pipeline);
+    }
 }
 
-fn init_gstreamer() {
-    // This function will initialize GStreamer only once.
-    PIPELINE_INIT.call_once(|| {
-        gst::init().unwrap();
-        info!("GStreamer initialized.");
-        gst::log::set_default_threshold(gst::DebugLevel::Warning);
-    });
+// Whether `addr` is the only client receiving the stream, so capping it slows no one else.
+fn streams_alone(state: &StreamingState, addr: SocketAddr) -> bool {
+    state
+        .peers
+        .iter()
+        .all(|(peer_addr, peer)| *peer_addr == addr || peer.stream.is_none())
 }
 
-// fn udpsrc_sink_pad_probe(_pad: &gst::Pad, info: &mut gst::PadProbeInfo) -> gst::PadProbeReturn {
-//     if let Some(gst::PadProbeData::Buffer(ref buffer)) = info.data {
-//         // Acquire the lock for the global pipeline state.
-//         let mut guard = PIPELINE_GUARD.lock().unwrap();
-//
-//         // Use `Option::take()` to extract the pipeline and replace the value with None.
-//         // The extracted pipeline reference will then be dropped when it goes out of scope.
-//         if let Some(pipeline) = guard.as_ref() {
-//             // Check pipeline
-//             let dot_data = pipeline.debug_to_dot_data(gst::DebugGraphDetails::ALL);
-//             let dot_str = dot_data.as_str();
-//             let a = 2;
-//             let b = 2;
-//             let c = a + b;
-//         }
-//     }
-//
-//     gst::PadProbeReturn::Ok
-// }
+// The frame rate set on the host or else the client's, unless the client asked for less and
+// nobody else watches. All clients share the encoder, which runs at one rate.
+fn delivered_framerate(
+    state: &StreamingState,
+    addr: SocketAddr,
+    config: &StreamConfigMessage,
+) -> u32 {
+    let framerate = state.framerate.unwrap_or(config.framerate);
+    state
+        .peers
+        .get(&addr)
+        .and_then(|peer| peer.max_fps)
+        .filter(|_| streams_alone(state, addr))
+        .map_or(framerate, |max_fps| max_fps.min(framerate))
+}
 
-fn check_factory_exists(factory_name: &str) -> bool {
-    gst::ElementFactory::find(factory_name).is_some()
+// Applies the frame rate of the client the pipeline follows, after clients came or went.
+fn refresh_delivered_framerate(pipeline: &gst::Pipeline) {
+    let target = PIPELINE_TARGET.lock().unwrap();
+    let framerate = {
+        let state_guard = STREAMING_STATE_GUARD.lock().unwrap();
+        state_guard
+            .as_ref()
+            .zip(target.as_ref())
+            .map(|(state, (addr, config))| delivered_framerate(state, *addr, config))
+    };
+    if let Some(framerate) = framerate {
+        set_delivered_framerate(pipeline, framerate);
+    }
 }
 
-fn start_gstreamer_pipeline(addr: SocketAddr, config: StreamConfigMessage) {
-    // Acquire the lock for the global pipeline state
-    let mut guard = PIPELINE_GUARD.lock().unwrap();
+// Renegotiates the frame rate in front of the encoder, videorate drops frames to match.
+fn set_delivered_framerate(pipeline: &gst::Pipeline, framerate: u32) {
+    let Some(filter) = pipeline.by_name("ratefilter") else {
+        return;
+    };
 
-    // Check if a pipeline is already running
-    if guard.is_some() {
-        warn!("Pipeline already running. Not restarting.");
+    let mut caps = filter.property::<gst::Caps>("caps");
+    let current = caps
+        .structure(0)
+        .and_then(|structure| structure.get::<gst::Fraction>("framerate").ok());
+    if current == Some(gst::Fraction::new(framerate as i32, 1)) {
         return;
     }
+    caps.make_mut()
+        .set("framerate", gst::Fraction::new(framerate as i32, 1));
+    filter.set_property("caps", caps);
+    info!("Delivering {} FPS.", framerate);
+}
+
+/// Streams `fps` frames per second whatever clients ask for, 0 goes back to the rate of their
+/// stream config. The running stream changes over without a restart.
+pub fn set_framerate(fps: u32) -> std::io::Result<()> {
+    if fps > MAX_FRAMERATE {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("The frame rate must be at most {} FPS", MAX_FRAMERATE),
+        ));
+    }
 
-    let host = addr.ip().to_string();
+    let guard = PIPELINE_GUARD.lock().unwrap();
+    let target = PIPELINE_TARGET.lock().unwrap();
+
+    let framerate = {
+        let mut state_guard = STREAMING_STATE_GUARD.lock().unwrap();
+        let Some(state) = state_guard.as_mut() else {
+            return Ok(());
+        };
+        state.framerate = (fps > 0).then_some(fps);
+
+        let framerate = target
+            .as_ref()
+            .map(|(addr, config)| delivered_framerate(state, *addr, config));
+        if let (Some(stream_config), Some(framerate)) = (state.stream_config.as_mut(), framerate) {
+            stream_config.framerate = framerate;
+        }
+        framerate
+    };
 
-    let found_amf = check_factory_exists("amfh264enc");
+    match fps {
+        0 => info!("Frame rate follows the clients again."),
+        fps => info!("Frame rate set to {} FPS.", fps),
+    }
+    if let (Some(pipeline), Some(framerate)) = (guard.as_ref(), framerate) {
+        set_delivered_framerate(pipeline, framerate);
+    }
+    Ok(())
+}
 
-    let encoder_str = if found_amf {
-        info!("amfh264enc is available.");
+/// Changes the video bitrate of the running stream in place, without a keyframe gap or
+/// renegotiation. Pipeline restarts keep the new bitrate.
+pub fn set_bitrate(mbps: u32) -> std::io::Result<()> {
+    if !(1..=MAX_BITRATE_MBPS).contains(&mbps) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("The bitrate must be 1 to {} Mbps", MAX_BITRATE_MBPS),
+        ));
+    }
 
-        format!(
-            "d3d11convert ! \
-        videorate ! \
-        video/x-raw(memory:D3D11Memory),width={},height={},format=NV12,framerate={}/1 ! \
-        amfh264enc name=enc preset=speed usage=ultra-low-latency rate-control=cbr bitrate={} gop-size=30 ! ",
-            config.video_width,
-            config.video_height,
-            config.framerate,
-            config.bitrate * 1024
-        )
-    } else {
-        format!("videoconvert ! \
-        videoscale ! \
-        videorate ! \
-        video/x-raw,width={},height={},format=NV12,framerate={}/1 ! \
-        x264enc name=enc tune=zerolatency sliced-threads=true speed-preset=ultrafast bframes=0 bitrate={} key-int-max=30 ! ",
-                config.video_width,
-                config.video_height,
-                config.framerate,
-                config.bitrate * 1024
-        )
+    let Some(enc) = pipeline_handles().map(|handles| handles.video_encoder) else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotConnected,
+            "No stream is running",
+        ));
     };
 
-    let pipeline_str = format!(
-        "rtpbin name=rtp \
-        d3d11screencapturesrc show-cursor=true ! \
-        {}\
-        video/x-h264,profile=baseline ! \
-        rtph264pay config-interval=-1 aggregate-mode=zero-latency ! \
-        application/x-rtp,encoding-name=H264,clock-rate=90000,media=video,payload=96 ! \
-        rtp.send_rtp_sink_0 \
-        rtp.send_rtp_src_0 ! \
-        udpsink name=videoudpsrc host={} port=5601 sync=false \
-        wasapi2src loopback=true low-latency=true ! \
-        queue ! \
-        audioconvert ! \
-        audioresample ! \
-        audio/x-raw,rate=48000 ! \
-        opusenc perfect-timestamp=true audio-type=restricted-lowdelay bitrate-type=cbr frame-size=10 ! \
-        rtpopuspay ! \
-        application/x-rtp,encoding-name=OPUS,media=audio,payload=127 !
-        rtp.send_rtp_sink_1 \
-        rtp.send_rtp_src_1 ! \
-        udpsink host={} port=5602 sync=false",
-        encoder_str, host, host
-    );
-
-    info!("Attempting to parse pipeline: \n{}", pipeline_str);
+    // SVT-AV1 names it differently, and encoders disagree on the integer type.
+    let property = if enc.find_property("target-bitrate").is_some() {
+        "target-bitrate"
+    } else {
+        "bitrate"
+    };
+    enc.set_property_from_str(property, &(mbps * 1024).to_string());
+    info!("Video bitrate changed to {} Mbps.", mbps);
 
-    let mut context = gst::ParseContext::new();
+    if let Some((_, config)) = PIPELINE_TARGET.lock().unwrap().as_mut() {
+        config.bitrate = mbps;
+    }
+    let mut state_guard = STREAMING_STATE_GUARD.lock().unwrap();
+    if let Some(stream_config) = state_guard
+        .as_mut()
+        .and_then(|state| state.stream_config.as_mut())
+    {
+        stream_config.bitrate = mbps;
+    }
+    Ok(())
+}
 
-    let pipeline = match gst::parse::launch_full(
-        &pipeline_str,
-        Some(&mut context),
-        gst::ParseFlags::empty(),
-    ) {
-        Ok(pipeline) => pipeline,
-        Err(err) => {
-            if let Some(gst::ParseError::NoSuchElement) = err.kind::<gst::ParseError>() {
-                error!("Missing element(s): {:?}", context.missing_elements());
-            } else {
-                error!("Failed to parse pipeline: {err}");
-            }
-            return;
+/// Makes the encoder send an IDR frame with parameter sets next, so a client can recover from
+/// corruption without waiting for the next periodic keyframe.
+pub fn force_keyframe() -> std::io::Result<()> {
+    {
+        let mut last = LAST_FORCED_KEYFRAME.lock().unwrap();
+        if last.map_or(false, |last| last.elapsed() < MIN_FORCED_KEYFRAME_INTERVAL) {
+            return Ok(());
         }
-    };
+        *last = Some(Instant::now());
+    }
 
-    let pipeline = pipeline.downcast::<gst::Pipeline>().unwrap();
+    if !is_pipeline_running() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotConnected,
+            "No stream is running",
+        ));
+    }
 
-    // // Add a probe
-    // {
-    //     let udpsrc = pipeline
-    //         .by_name("videoudpsrc")
-    //         .expect("Could not find videoudpsrc element.");
-    //
-    //     let pad = udpsrc
-    //         .static_pad("sink")
-    //         .expect("Could not find static sink pad in videoudpsrc.");
-    //
-    //     pad.add_probe(gst::PadProbeType::BUFFER, move |pad, info| {
-    //         udpsrc_sink_pad_probe(pad, info)
-    //     });
-    // }
+    if !request_keyframe() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "The encoder did not accept the keyframe request",
+        ));
+    }
+    info!("Forced a keyframe.");
+    Ok(())
+}
 
-    // Check pipeline
-    // let dot_data = pipeline.debug_to_dot_data(gst::DebugGraphDetails::ALL);
-    // let _dot_str = dot_data.as_str();
+// Asks the encoder for an IDR frame with parameter sets next.
+pub(crate) fn request_keyframe() -> bool {
+    let Some(pad) = pipeline_handles().and_then(|handles| handles.video_encoder.static_pad("src"))
+    else {
+        return false;
+    };
 
-    let bus = pipeline.bus().unwrap();
+    let event = gst_video::UpstreamForceKeyUnitEvent::builder()
+        .all_headers(true)
+        .build();
+    pad.send_event(event)
+}
 
-    let _bus_watch_id = bus.add_watch(move |_, msg| {
-        match msg.view() {
-            MessageView::Error(err) => {
-                error!(
-                    "Error from {:?}: {} ({:?})",
-                    err.src().map(|s| s.path_string()),
-                    err.error(),
-                    err.debug()
-                );
-                // An error occurred, you might want to quit the application here
-                // Returning `glib::Continue(false)` stops the watch.
-                // In a real app, you'd send an event to the main thread to handle shutdown.
-                // For simplicity here, we'll just log and continue.
-            }
-            MessageView::Warning(warning) => {
-                error!(
-                    "Warning from {:?}: {} ({:?})",
-                    warning.src().map(|s| s.path_string()),
-                    warning.error(),
-                    warning.debug()
-                );
-            }
-            MessageView::Eos(_) => {
-                error!("End of stream reached.");
-                // End of stream, you might want to quit the application here
-                // Returning `glib::Continue(false)` stops the watch.
-            }
-            MessageView::StateChanged(state_changed) => {
-                error!(
-                    "Pipeline state changed from {:?} to {:?} (pending: {:?})",
-                    state_changed.old(),
-                    state_changed.current(),
-                    state_changed.pending(),
-                );
-            }
-            // Add more match arms for other message types you care about
-            _ => {
-                error!("Unhandled message: {:?}", msg.type_()); // Uncomment for all messages
+/// Limits the frame rate sent to `addr`, 0 lifts the limit. Clients share one encoder, so the
+/// limit only applies while `addr` is the only one watching, and fails while others watch. A
+/// client that does not stream yet gets it once it does.
+pub fn set_peer_max_fps(addr: SocketAddr, max_fps: u32) -> std::io::Result<()> {
+    let guard = PIPELINE_GUARD.lock().unwrap();
+    let target = PIPELINE_TARGET.lock().unwrap();
+
+    let framerate = {
+        let mut state_guard = STREAMING_STATE_GUARD.lock().unwrap();
+        let Some(state) = state_guard.as_mut() else {
+            return Ok(());
+        };
+        let streaming = match state.peers.get(&addr) {
+            Some(peer) => peer.stream.is_some(),
+            None => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    "Unknown client",
+                ))
             }
+        };
+        // The encoder is shared, a cap for one client would slow the stream of the others.
+        let is_target = target
+            .as_ref()
+            .map_or(false, |(target_addr, _)| *target_addr == addr);
+        if max_fps > 0 && streaming && !(is_target && streams_alone(state, addr)) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "Other clients watch the same stream, its frame rate cannot be capped for one \
+                of them",
+            ));
+        }
+        if let Some(peer) = state.peers.get_mut(&addr) {
+            peer.max_fps = (max_fps > 0).then_some(max_fps);
         }
-        ControlFlow::Continue
-    });
 
-    // Store the running pipeline in the global Mutex
-    *guard = Some(pipeline.clone());
+        target
+            .as_ref()
+            .filter(|_| is_target)
+            .map(|(_, config)| delivered_framerate(state, addr, config))
+    };
 
-    // Set pipeline to playing
-    if let Err(e) = pipeline.set_state(gst::State::Playing) {
-        error!("Failed to set pipeline to Playing: {}", e);
-    } else {
-        info!("Pipeline started playing to {}!", addr);
+    if let (Some(pipeline), Some(framerate)) = (guard.as_ref(), framerate) {
+        set_delivered_framerate(pipeline, framerate);
     }
+    Ok(())
 }
 
-pub fn stop_gstreamer_pipeline() {
-    // Acquire the lock for the global pipeline state.
-    let mut guard = PIPELINE_GUARD.lock().unwrap();
+// Tells a client how to receive and present the running stream.
+fn send_stream_description(addr: SocketAddr, config: &StreamConfigMessage) {
+    if let Some(session) = *CURRENT_SESSION.lock().unwrap() {
+        // Each client chose for itself whether its branch bundles audio with video.
+        let session = RtpSession {
+            bundle: config.bundle,
+            ..session
+        };
+        send_event(addr, &ControlEvent::RtpSession(session));
+    }
 
-    // Use `Option::take()` to extract the pipeline and replace the value with None.
-    // The extracted pipeline reference will then be dropped when it goes out of scope.
-    if let Some(pipeline) = guard.take() {
-        pipeline
-            .set_state(gst::State::Null)
-            .expect("Unable to set the pipeline to the `Null` state");
-        info!("Pipeline stopped.");
+    let (video_port, audio_port) = assign_media_ports(addr, config);
+    send_event(
+        addr,
+        &ControlEvent::MediaPorts {
+            video_port,
+            audio_port,
+        },
+    );
+
+    let layout = *ACTIVE_STEREO_MODE.lock().unwrap();
+    let (eye_width, eye_height) = layout.eye_size(config.video_width, config.video_height);
+    send_event(
+        addr,
+        &ControlEvent::StreamLayout {
+            layout,
+            eye_width,
+            eye_height,
+        },
+    );
+}
+
+// Rebuilds the pipeline for the same client, e.g. after switching encoders.
+pub fn restart_gstreamer_pipeline() {
+    // A session that ended meanwhile stays ended.
+    let session = SESSION.lock().unwrap();
+    if session.state == SessionState::Idle {
+        return;
+    }
+
+    let target = PIPELINE_TARGET.lock().unwrap().clone();
+
+    stop_gstreamer_pipeline();
+
+    if let Some((addr, config)) = target {
+        info!("Restarting pipeline.");
+        start_gstreamer_pipeline(addr, config);
     }
-    // The lock is automatically released when `guard` goes out of scope.
 }
 
 // ----------------------------------------------------------------------
 // --- Asynchronous WebSocket Functions ---------------------------------
 // ----------------------------------------------------------------------
 
-async fn handle_connection(
-    peer_map: PeerMap,
-    raw_stream: TcpStream,
-    addr: SocketAddr,
-    start_once: GstPipelineControl,
-) {
+async fn handle_connection(peer_map: PeerMap, raw_stream: TcpStream, addr: SocketAddr) {
     info!("Incoming TCP connection from: {}", addr);
+    let local_ip = raw_stream.local_addr().ok().map(|local| local.ip());
 
-    let ws_stream = async_tungstenite::accept_async(raw_stream)
-        .await
-        .expect("Error during the websocket handshake occurred");
+    let (connected, max_clients) = {
+        let guard = STREAMING_STATE_GUARD.lock().unwrap();
+        guard.as_ref().map_or((0, DEFAULT_MAX_CLIENTS), |state| {
+            (state.peers.len(), state.max_clients)
+        })
+    };
+    if connected >= max_clients as usize {
+        warn!(
+            "Refusing {}, {} clients are connected already.",
+            addr, connected
+        );
+        return;
+    }
 
-    info!("WebSocket connection established: {}", addr);
+    // Clients check the certificate against the fingerprint shown on the host and announced.
+    if let Some(acceptor) = crate::tls::acceptor() {
+        let tls_stream = match acceptor.accept(raw_stream).await {
+            Ok(tls_stream) => tls_stream,
+            Err(e) => {
+                warn!("TLS handshake with {} failed: {}", addr, e);
+                return;
+            }
+        };
+        let ws_stream = async_tungstenite::accept_async(tls_stream)
+            .await
+            .expect("Error during the websocket handshake occurred");
 
-    // Initialize gstreamer.
-    let init_gst = move || {
-        init_gstreamer();
-    };
-    start_once.call_once(init_gst);
+        info!("Secure WebSocket connection established: {}", addr);
+        serve_websocket(peer_map, ws_stream, addr, local_ip).await;
+    } else {
+        let ws_stream = async_tungstenite::accept_async(raw_stream)
+            .await
+            .expect("Error during the websocket handshake occurred");
+
+        info!("WebSocket connection established: {}", addr);
+        serve_websocket(peer_map, ws_stream, addr, local_ip).await;
+    }
+}
+
+async fn serve_websocket<S>(
+    peer_map: PeerMap,
+    ws_stream: WebSocketStream<S>,
+    addr: SocketAddr,
+    local_ip: Option<IpAddr>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    init_gstreamer();
 
     // Insert the write part of this peer to the peer map.
     let (tx, rx) = unbounded();
@@ -320,32 +2665,56 @@ async fn handle_connection(
                     time_connected: date_as_string,
                     tx: tx,
                     shutdown_tx: Some(shutdown_tx),
+                    authenticated: false,
+                    client_id: None,
+                    max_fps: None,
+                    stream: None,
+                    video_paused: false,
+                    audio_paused: false,
+                    end_reason: None,
+                    local_ip,
+                    media_ports: None,
                 },
             );
         }
     }
+    crate::gui::request_repaint();
+
+    // Tell the client what it can ask for in its stream config.
+    send_event(
+        addr,
+        &ControlEvent::ServerCapabilities {
+            video_codecs: supported_codecs(),
+            audio_codecs: crate::audio::supported_codecs(),
+            ports: crate::ports::current(),
+            audio_channels: crate::audio::available_channels(),
+            hdr: crate::hdr::is_available(),
+            webrtc: crate::webrtc::is_available(),
+            mpegts: crate::mpegts::is_available(),
+            chroma_444: STREAMING_STATE_GUARD
+                .lock()
+                .unwrap()
+                .as_ref()
+                .is_some_and(|state| state.chroma_444)
+                && crate::encoder::supports_444(),
+            b_frames: STREAMING_STATE_GUARD
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map_or(0, |state| state.b_frames),
+        },
+    );
 
     let (outgoing, incoming) = ws_stream.split();
 
-    let broadcast_incoming = incoming
+    let handle_incoming = incoming
         .try_filter(|msg| future::ready(!msg.is_close()))
         .try_for_each(|msg| {
             let current_peer_map = peer_map.clone();
 
             // Handle the incoming message/command
             if msg.is_text() {
-                let text_msg = msg.clone();
-                handle_text_message(text_msg, addr, current_peer_map);
-            }
-
-            let peers = peer_map.lock().unwrap();
-            let broadcast_recipients = peers
-                .iter()
-                .filter(|(peer_addr, _)| peer_addr != &&addr)
-                .map(|(_, ws_sink)| ws_sink);
-
-            for recp in broadcast_recipients {
-                recp.unbounded_send(msg.clone()).unwrap();
+                handle_text_message(msg, addr, current_peer_map);
             }
 
             future::ok(())
@@ -353,38 +2722,100 @@ async fn handle_connection(
 
     let receive_from_others = rx.map(Ok).forward(outgoing);
 
-    pin_mut!(broadcast_incoming, receive_from_others, shutdown_rx);
+    pin_mut!(handle_incoming, receive_from_others, shutdown_rx);
 
     // Select on both the connection futures AND the shutdown signal
     future::select(
-        future::select(broadcast_incoming, receive_from_others),
+        future::select(handle_incoming, receive_from_others),
         shutdown_rx,
     )
     .await;
 
     info!("WebSocket {} disconnected", &addr);
     peer_map.lock().unwrap().remove(&addr);
+    crate::pairing::cancel(Some(addr));
 
+    let mut end_reason = EndReason::ClientLeft;
     {
         let mut guard = STREAMING_STATE_GUARD.lock().unwrap();
         if let Some(state) = guard.as_mut() {
-            state.peers.remove(&addr);
-            state.stream_config = None;
-            state.connection_status = ConnectionStatus::Ready;
+            if let Some(reason) = state.peers.remove(&addr).and_then(|peer| peer.end_reason) {
+                end_reason = reason;
+            }
+            if !state.peers.values().any(|peer| peer.authenticated) {
+                state.stream_config = None;
+                state.connection_status = ConnectionStatus::Ready;
+            }
+        }
+    }
+    crate::gui::request_repaint();
+
+    task::spawn_blocking(move || detach_peer(addr));
+
+    // Go back to standby if this was the last authorized client, unless it reconnects in time.
+    // Connections that never authenticate do not keep the pipeline running.
+    if !has_authenticated_peer() {
+        if let Some(generation) = linger_session(end_reason) {
+            let grace_seconds = if end_reason == EndReason::NetworkChanged {
+                FAILOVER_GRACE_SECONDS
+            } else {
+                RECONNECT_GRACE_SECONDS
+            };
+            task::spawn(async move {
+                task::sleep(Duration::from_secs(grace_seconds)).await;
+                task::spawn_blocking(move || end_session(generation)).await;
+            });
         }
     }
+}
+
+// Marks the session as waiting for a client to come back. Returns the generation to end, if a
+// session is running.
+fn linger_session(reason: EndReason) -> Option<u64> {
+    let mut session = SESSION.lock().unwrap();
+    if session.state != SessionState::Active {
+        return None;
+    }
+    session.state = SessionState::Lingering;
+    session.end_reason = reason;
+    Some(session.generation)
+}
 
-    // Stop Pipeline if this was the last client
-    if peer_map.lock().unwrap().is_empty() {
-        // Spawn a task to run the blocking pipeline stop function
-        task::spawn_blocking(stop_gstreamer_pipeline);
+// Ends the session after its grace period, unless a client streamed again since.
+fn end_session(generation: u64) {
+    let mut session = SESSION.lock().unwrap();
+    if session.state != SessionState::Lingering || session.generation != generation {
+        return;
+    }
+    if has_authenticated_peer() {
+        info!("A client connected again, keeping the session.");
+        return;
     }
+
+    stop_gstreamer_pipeline();
+    *PIPELINE_TARGET.lock().unwrap() = None;
+    crate::watchdog::stop_watching();
+    crate::display::restore_display_settings();
+    crate::accessibility::restore_accessibility();
+    crate::power::leave_performance_mode();
+    crate::view::reset();
+    crate::timeline::finish_session(session.end_reason);
+
+    session.state = SessionState::Idle;
+    info!("Session ended.");
 }
 
-pub fn disconnect_peer(addr: SocketAddr) {
+/// Closes the connection of a client. One that streamed gets the summary of the session first.
+pub fn disconnect_peer(addr: SocketAddr, reason: EndReason) {
     let mut guard = STREAMING_STATE_GUARD.lock().unwrap();
     if let Some(state) = guard.as_mut() {
         if let Some(peer) = state.peers.get_mut(&addr) {
+            peer.end_reason = Some(reason);
+            if let Some(summary) = crate::timeline::summary(reason).filter(|_| peer.authenticated) {
+                let text = serde_json::to_string(&ControlEvent::SessionSummary(summary)).unwrap();
+                let _ = peer.tx.unbounded_send(Message::Text(text.into()));
+            }
+
             // Send the shutdown signal to the async task
             if let Some(shutdown_tx) = peer.shutdown_tx.take() {
                 let _ = shutdown_tx.send(());
@@ -396,35 +2827,149 @@ pub fn disconnect_peer(addr: SocketAddr) {
     }
 }
 
+/// Clients connected to one of the host addresses in `ips`.
+pub(crate) fn peers_reached_through(ips: &[IpAddr]) -> Vec<SocketAddr> {
+    let guard = STREAMING_STATE_GUARD.lock().unwrap();
+    guard.as_ref().map_or(Vec::new(), |state| {
+        state
+            .peers
+            .iter()
+            .filter(|(_, peer)| peer.local_ip.is_some_and(|ip| ips.contains(&ip)))
+            .map(|(addr, _)| *addr)
+            .collect()
+    })
+}
+
+pub fn disconnect_all_peers(reason: EndReason) {
+    let addrs: Vec<SocketAddr> = {
+        let guard = STREAMING_STATE_GUARD.lock().unwrap();
+        guard
+            .as_ref()
+            .map_or(Vec::new(), |state| state.peers.keys().copied().collect())
+    };
+
+    for addr in addrs {
+        disconnect_peer(addr, reason);
+    }
+}
+
 pub async fn run_websocket(port: u32) -> Result<(), IoError> {
     let addr = format!("0.0.0.0:{}", port);
 
     let state = PeerMap::new(Mutex::new(HashMap::new()));
-    let gst_control = GstPipelineControl::new(Once::new());
 
-    let try_socket = TcpListener::bind(&addr).await;
-    let listener = try_socket.expect("Failed to bind");
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to listen on {}: {}", addr, e);
+            return Err(e);
+        }
+    };
     info!("WebSocket listening on: {}", addr);
 
     while let Ok((stream, addr)) = listener.accept().await {
-        task::spawn(handle_connection(
-            state.clone(),
-            stream,
-            addr,
-            gst_control.clone(),
-        ));
+        task::spawn(handle_connection(state.clone(), stream, addr));
     }
 
     Ok(())
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// The part of a client's stream a pause or resume applies to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamMedia {
+    #[default]
+    All,
+    Video,
+    Audio,
+}
+
+impl std::fmt::Display for StreamMedia {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StreamMedia::All => write!(f, "video and audio"),
+            StreamMedia::Video => write!(f, "video"),
+            StreamMedia::Audio => write!(f, "audio"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StreamConfigMessage {
     pub pin: String,
     pub video_width: u32,
     pub video_height: u32,
     pub framerate: u32,
     pub bitrate: u32,
+    // Whether the client decodes streams without periodic IDR frames.
+    #[serde(default)]
+    pub intra_refresh: bool,
+    // Whether the client displays HDR10 video.
+    #[serde(default)]
+    pub hdr: bool,
+    // Whether the client wants full-resolution chroma, for text-heavy desktops.
+    #[serde(default)]
+    pub chroma_444: bool,
+    // Audio codecs the client decodes. Empty means Opus only.
+    #[serde(default)]
+    pub audio_codecs: Vec<AudioCodec>,
+    // Most audio channels the client plays, e.g. 6 for 5.1. None means stereo.
+    #[serde(default)]
+    pub audio_channels: Option<u32>,
+    // What the client streams, None for the host setting.
+    #[serde(default)]
+    pub tune: Option<ContentTune>,
+    // Whether the client wants audio and video on one UDP port.
+    #[serde(default)]
+    pub bundle: bool,
+    // The id and secret the host issued the client at pairing, see `ControlEvent::Paired`.
+    #[serde(default)]
+    pub client_id: Option<String>,
+    #[serde(default)]
+    pub client_secret: Option<String>,
+    // Codecs the client decodes, out of those the server advertised. Empty means H.264 only.
+    #[serde(default)]
+    pub codecs: Vec<VideoCodec>,
+    // Whether the client recovers video from ULPFEC in RED.
+    #[serde(default)]
+    pub fec: bool,
+    // Where the client receives RTP, so several clients on one host don't collide.
+    #[serde(default)]
+    pub video_port: Option<u16>,
+    #[serde(default)]
+    pub audio_port: Option<u16>,
+    // Whether the client runs on this machine and reads raw frames from shared memory, see `local`.
+    #[serde(default)]
+    pub local: bool,
+    // Whether the client receives over WebRTC instead of plain RTP, e.g. a browser.
+    #[serde(default)]
+    pub webrtc: bool,
+    // Whether the client takes video and audio muxed into MPEG-TS on its video port, without
+    // RTP, e.g. a TV.
+    #[serde(default)]
+    pub mpegts: bool,
+    // Whether the client reorders B-frames of HEVC and AV1 streams before display.
+    #[serde(default)]
+    pub b_frames: bool,
+}
+
+impl StreamConfigMessage {
+    // Whether a pipeline built for `self` can serve `other` as is.
+    fn same_stream_settings(&self, other: &StreamConfigMessage) -> bool {
+        self.video_width == other.video_width
+            && self.video_height == other.video_height
+            && self.framerate == other.framerate
+            && self.bitrate == other.bitrate
+            && self.intra_refresh == other.intra_refresh
+            && self.codecs == other.codecs
+            && self.fec == other.fec
+            && self.hdr == other.hdr
+            && self.chroma_444 == other.chroma_444
+            && self.tune == other.tune
+            && self.audio_codecs == other.audio_codecs
+            && self.audio_channels == other.audio_channels
+            && self.b_frames == other.b_frames
+    }
 }
 
 // Video control via WebSocket.
@@ -433,41 +2978,63 @@ fn handle_text_message(msg: Message, addr: SocketAddr, peer_map: PeerMap) {
         Message::Text(t) => t,
         _ => return, // Handle other message types
     };
+    crate::replay::record_control(&text);
+
+    if let Ok(command) = serde_json::from_str::<ControlCommand>(&text) {
+        let authenticated = {
+            let guard = STREAMING_STATE_GUARD.lock().unwrap();
+            guard
+                .as_ref()
+                .and_then(|state| state.peers.get(&addr))
+                .map_or(false, |peer| peer.authenticated)
+        };
+
+        if authenticated || matches!(command, ControlCommand::RequestPairingCode) {
+            task::spawn_blocking(move || handle_command(command, addr));
+        } else {
+            warn!("Ignoring command from unauthenticated peer {}.", addr);
+        }
+        return;
+    }
 
     match serde_json::from_str::<StreamConfigMessage>(&text) {
         Ok(config_msg) => {
             info!(
-                "✅ Stream config received successfully:\n\tPIN: {}\n\tVideo Size: {}x{}\n\tBitrate: {}",
-                config_msg.pin, config_msg.video_width, config_msg.video_height, config_msg.bitrate
+                "✅ Stream config received successfully:\n\tVideo Size: {}x{}\n\tBitrate: {}",
+                config_msg.video_width, config_msg.video_height, config_msg.bitrate
             );
 
+            // Only the secret issued at pairing proves which client this is, the id alone does not.
+            let mut known_id = crate::trust::identify(
+                config_msg.client_id.as_deref(),
+                config_msg.client_secret.as_deref(),
+            );
             let mut authenticated = false;
+            let mut performance_mode = false;
 
             {
-                let mut guard = STREAMING_STATE_GUARD.lock().unwrap();
-                if let Some(state) = guard.as_mut() {
-                    authenticated = state.pin == config_msg.pin;
-
-                    if authenticated {
-                        let config = StreamConfig {
-                            resolution: (config_msg.video_width, config_msg.video_height),
-                            framerate: config_msg.framerate,
-                            bitrate: config_msg.bitrate,
-                        };
-
-                        state.stream_config = Some(config);
-                        state.connection_status = ConnectionStatus::Connected;
+                let guard = STREAMING_STATE_GUARD.lock().unwrap();
+                if let Some(state) = guard.as_ref() {
+                    authenticated = state.pin == config_msg.pin
+                        || crate::pairing::take_code(addr, &config_msg.pin);
+                    performance_mode = state.performance_mode;
+                    // A client that renegotiates keeps the identity it authenticated with.
+                    if known_id.is_none() {
+                        known_id = state
+                            .peers
+                            .get(&addr)
+                            .and_then(|peer| peer.client_id.clone());
                     }
                 }
             }
 
-            if authenticated {
-                // Spawn a task to run the blocking pipeline start function
-                task::spawn_blocking(move || {
-                    start_gstreamer_pipeline(addr, config_msg);
-                });
-            } else {
+            if !authenticated {
                 warn!("Authentication failed for {}. Closing connection.", addr);
+                report_error(
+                    addr,
+                    ErrorCode::AuthFailed,
+                    "The PIN is incorrect.".to_string(),
+                );
                 if let Some(tx) = peer_map.lock().unwrap().get(&addr) {
                     if let Err(e) = tx.unbounded_send(Message::Close(Some(CloseFrame {
                         code: CloseCode::Invalid,
@@ -476,15 +3043,77 @@ fn handle_text_message(msg: Message, addr: SocketAddr, peer_map: PeerMap) {
                         error!("Failed to send close message to {}: {}", addr, e);
                     }
                 }
-                // The `broadcast_incoming` loop will eventually detect the send error or the actual close
+                // The `handle_incoming` loop will eventually detect the send error or the actual close
                 // and the connection will be handled as disconnected by the `future::select` below.
+                return;
+            }
+
+            let client_id = match known_id {
+                Some(id) => {
+                    crate::trust::record_seen(&id, addr);
+                    id
+                }
+                None => {
+                    let (id, secret) = crate::trust::pair(addr);
+                    send_event(
+                        addr,
+                        &ControlEvent::Paired {
+                            client_id: id.clone(),
+                            client_secret: secret,
+                        },
+                    );
+                    id
+                }
+            };
+            // A client refused the stream gets no session, neither commands nor a branch.
+            let may_view = crate::trust::permissions(&client_id).view;
+
+            {
+                let mut guard = STREAMING_STATE_GUARD.lock().unwrap();
+                if let Some(state) = guard.as_mut() {
+                    if may_view {
+                        let config = StreamConfig {
+                            resolution: (config_msg.video_width, config_msg.video_height),
+                            framerate: state.framerate.unwrap_or(config_msg.framerate),
+                            bitrate: config_msg.bitrate,
+                            encoder: String::new(),
+                        };
+
+                        state.stream_config = Some(config);
+                        state.connection_status = ConnectionStatus::Connected;
+
+                        if let Some(peer) = state.peers.get_mut(&addr) {
+                            peer.authenticated = true;
+                            peer.client_id = Some(client_id.clone());
+                            peer.stream = Some(config_msg.clone());
+                        }
+                    }
+                }
+            }
+
+            if !may_view {
+                report_error(
+                    addr,
+                    ErrorCode::PermissionDenied,
+                    "This client may not view the stream.".to_string(),
+                );
+                return;
             }
+
+            // Spawn a task to run the blocking pipeline start function
+            task::spawn_blocking(move || {
+                if performance_mode {
+                    crate::power::enter_performance_mode();
+                }
+                stream_to_peer(addr, config_msg);
+            });
         }
         Err(e) => {
             error!(
                 "❌ ERROR: Failed to deserialize JSON: {}\n\tPayload was: {}",
                 e, text
             );
+            report_error(addr, ErrorCode::InvalidMessage, e.to_string());
         }
     }
 }
@@ -0,0 +1,215 @@
+use gstreamer as gst;
+use std::time::SystemTime;
+
+#[cfg(feature = "otel")]
+use gst::prelude::*;
+#[cfg(feature = "otel")]
+use log::{info, warn};
+#[cfg(feature = "otel")]
+use opentelemetry::trace::{Span, TraceContextExt, Tracer};
+#[cfg(feature = "otel")]
+use opentelemetry::{global, Context, KeyValue};
+#[cfg(feature = "otel")]
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+#[cfg(feature = "otel")]
+use std::sync::Mutex;
+
+// One traced frame per second at 60 FPS keeps the overhead negligible.
+#[cfg(feature = "otel")]
+const TRACE_EVERY_FRAMES: u64 = 60;
+// Frames that never reach the sink, e.g. dropped by a queue, are given up after this many.
+#[cfg(feature = "otel")]
+const MAX_FRAMES_IN_FLIGHT: usize = 4;
+
+// Points a video frame passes on its way to the network, in pipeline order.
+#[cfg(feature = "otel")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FrameStage {
+    Captured = 0,
+    Encoded = 1,
+    Packetized = 2,
+    Sent = 3,
+}
+
+#[cfg(feature = "otel")]
+static ENABLED: AtomicBool = AtomicBool::new(false);
+#[cfg(feature = "otel")]
+static FRAME_COUNTER: AtomicU64 = AtomicU64::new(0);
+// Sampled frames by PTS, with the time each stage first saw them.
+#[cfg(feature = "otel")]
+static FRAMES_IN_FLIGHT: Mutex<Vec<(gst::ClockTime, [Option<SystemTime>; 4])>> =
+    Mutex::new(Vec::new());
+
+/// Exports traces over OTLP when built with the `otel` feature and
+/// `OTEL_EXPORTER_OTLP_ENDPOINT` is set, e.g. to `http://localhost:4318` for Jaeger.
+pub fn init() {
+    #[cfg(feature = "otel")]
+    {
+        let Ok(endpoint) = std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT") else {
+            return;
+        };
+
+        let config = opentelemetry_sdk::trace::Config::default().with_resource(
+            opentelemetry_sdk::Resource::new([KeyValue::new("service.name", crate::NAME)]),
+        );
+        let provider = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(opentelemetry_otlp::new_exporter().http())
+            .with_trace_config(config)
+            .install_batch(opentelemetry_sdk::runtime::AsyncStd);
+
+        match provider {
+            Ok(provider) => {
+                global::set_tracer_provider(provider);
+                ENABLED.store(true, Ordering::Relaxed);
+                info!("Exporting traces to {}.", endpoint);
+            }
+            Err(e) => warn!("Failed to set up trace export: {}", e),
+        }
+    }
+}
+
+/// Sends the spans still buffered. Called on exit.
+pub fn shutdown() {
+    #[cfg(feature = "otel")]
+    if ENABLED.load(Ordering::Relaxed) {
+        global::shutdown_tracer_provider();
+    }
+}
+
+// Notes that a frame reached `stage`. Called from pad probes, frames are matched by PTS.
+#[cfg(feature = "otel")]
+fn record_frame_stage(stage: FrameStage, pts: Option<gst::ClockTime>) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let Some(pts) = pts else {
+        return;
+    };
+    let now = SystemTime::now();
+
+    let finished = {
+        let mut frames = FRAMES_IN_FLIGHT.lock().unwrap();
+
+        if stage == FrameStage::Captured {
+            if FRAME_COUNTER.fetch_add(1, Ordering::Relaxed) % TRACE_EVERY_FRAMES != 0 {
+                return;
+            }
+            if frames.len() >= MAX_FRAMES_IN_FLIGHT {
+                frames.remove(0);
+            }
+            frames.push((pts, [Some(now), None, None, None]));
+            return;
+        }
+
+        let Some(index) = frames.iter().position(|(frame_pts, _)| *frame_pts == pts) else {
+            return;
+        };
+        // Packets of a frame share its PTS, the first one counts.
+        let times = &mut frames[index].1;
+        if times[stage as usize].is_none() {
+            times[stage as usize] = Some(now);
+        }
+
+        if stage != FrameStage::Sent {
+            return;
+        }
+        frames.remove(index).1
+    };
+
+    export_frame(pts, finished);
+}
+
+/// Follows sampled frames through the video branch of a new pipeline, if tracing is on.
+pub fn add_frame_probes(pipeline: &gst::Pipeline) {
+    #[cfg(feature = "otel")]
+    {
+        if !ENABLED.load(Ordering::Relaxed) {
+            return;
+        }
+
+        for (element_name, pad_name, stage) in [
+            ("capture", "src", FrameStage::Captured),
+            ("enc", "src", FrameStage::Encoded),
+            ("videopay", "src", FrameStage::Packetized),
+            ("videotee", "sink", FrameStage::Sent),
+        ] {
+            let Some(pad) = pipeline
+                .by_name(element_name)
+                .and_then(|element| element.static_pad(pad_name))
+            else {
+                warn!("No {} pad on {} to trace frames.", pad_name, element_name);
+                continue;
+            };
+
+            pad.add_probe(
+                gst::PadProbeType::BUFFER | gst::PadProbeType::BUFFER_LIST,
+                move |_, info| {
+                    match info.data.as_ref() {
+                        Some(gst::PadProbeData::Buffer(buffer)) => {
+                            record_frame_stage(stage, buffer.pts());
+                        }
+                        Some(gst::PadProbeData::BufferList(list)) => {
+                            if let Some(buffer) = list.get(0) {
+                                record_frame_stage(stage, buffer.pts());
+                            }
+                        }
+                        _ => {}
+                    }
+                    gst::PadProbeReturn::Ok
+                },
+            );
+        }
+    }
+
+    #[cfg(not(feature = "otel"))]
+    let _ = pipeline;
+}
+
+#[cfg(feature = "otel")]
+fn export_frame(pts: gst::ClockTime, times: [Option<SystemTime>; 4]) {
+    let [Some(captured), Some(encoded), Some(packetized), Some(sent)] = times else {
+        return;
+    };
+
+    let tracer = global::tracer("frame");
+    let frame = tracer
+        .span_builder("frame")
+        .with_start_time(captured)
+        .with_attributes([KeyValue::new("pts_ns", pts.nseconds() as i64)])
+        .start(&tracer);
+    let cx = Context::current_with_span(frame);
+
+    for (name, start, end) in [
+        ("encode", captured, encoded),
+        ("packetize", encoded, packetized),
+        ("send", packetized, sent),
+    ] {
+        let mut span = tracer
+            .span_builder(name)
+            .with_start_time(start)
+            .start_with_context(&tracer, &cx);
+        span.end_with_timestamp(end);
+    }
+    cx.span().end_with_timestamp(sent);
+}
+
+/// Traces one input packet from its arrival to the end of its injection.
+pub fn record_input(received: SystemTime) {
+    #[cfg(feature = "otel")]
+    {
+        if !ENABLED.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let tracer = global::tracer("input");
+        let mut span = tracer
+            .span_builder("input")
+            .with_start_time(received)
+            .start(&tracer);
+        span.end();
+    }
+
+    #[cfg(not(feature = "otel"))]
+    let _ = received;
+}
@@ -0,0 +1,308 @@
+use crate::encoder;
+use crate::network;
+use chrono::Utc;
+use gstreamer as gst;
+use log::info;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+// Finished sessions the GUI lists, older ones are forgotten.
+const MAX_HISTORY: usize = 20;
+// Two hours, so a forgotten session cannot grow without bound.
+pub const DEFAULT_TIMELINE_MINUTES: u32 = 120;
+
+/// One second of a streaming session.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct StatsSample {
+    // Seconds since the session started.
+    pub time_s: u64,
+    pub bitrate_kbps: f32,
+    pub fps: u32,
+    // Raw frames dropped in front of the encoder.
+    pub dropped_frames: u32,
+    // Fraction of lost input packets, 0 to 1.
+    pub loss: f32,
+    pub rtt_ms: f32,
+    pub input_events: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct SessionTimeline {
+    started_at: String,
+    // The oldest samples are dropped once the timeline is full.
+    samples: VecDeque<StatsSample>,
+    // Over the whole session, for its summary.
+    #[serde(skip)]
+    total_kbits: f64,
+    #[serde(skip)]
+    total_dropped_frames: u64,
+    #[serde(skip)]
+    total_samples: u64,
+}
+
+impl SessionTimeline {
+    fn summary(&self, started: Instant, reason: EndReason) -> SessionSummary {
+        SessionSummary {
+            started_at: self.started_at.clone(),
+            duration_s: started.elapsed().as_secs(),
+            average_bitrate_kbps: if self.total_samples > 0 {
+                (self.total_kbits / self.total_samples as f64) as f32
+            } else {
+                0.0
+            },
+            dropped_frames: self.total_dropped_frames,
+            reason,
+        }
+    }
+}
+
+/// Why a session, or a client's part in it, ended.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EndReason {
+    // The client disconnected, or its connection dropped.
+    ClientLeft,
+    // The host disconnected it from the server window.
+    HostDisconnected,
+    // Another client took the session over.
+    TakenOver,
+    // The streamed app exited and the host chose to stop streaming then.
+    AppExited,
+    // The network interface the client reached the host through went away.
+    NetworkChanged,
+}
+
+impl std::fmt::Display for EndReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EndReason::ClientLeft => write!(f, "Client left"),
+            EndReason::HostDisconnected => write!(f, "Disconnected by the host"),
+            EndReason::TakenOver => write!(f, "Taken over by another client"),
+            EndReason::AppExited => write!(f, "App exited"),
+            EndReason::NetworkChanged => write!(f, "Host network changed"),
+        }
+    }
+}
+
+/// What a session came to. Sent to clients the host disconnects and kept for the GUI.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionSummary {
+    pub started_at: String,
+    pub duration_s: u64,
+    pub average_bitrate_kbps: f32,
+    // Raw frames dropped in front of the encoder.
+    pub dropped_frames: u64,
+    pub reason: EndReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReportFormat {
+    Csv,
+    Json,
+}
+
+// Counted on the streaming threads, collected once per sample.
+static VIDEO_BYTES: AtomicU64 = AtomicU64::new(0);
+static VIDEO_FRAMES: AtomicU32 = AtomicU32::new(0);
+static INPUT_EVENTS: AtomicU32 = AtomicU32::new(0);
+
+static MAX_SAMPLES: AtomicUsize = AtomicUsize::new(DEFAULT_TIMELINE_MINUTES as usize * 60);
+
+// The running session, and the last finished one for reports after the client left.
+static CURRENT: Mutex<Option<(SessionTimeline, Instant)>> = Mutex::new(None);
+static LAST: Mutex<Option<SessionTimeline>> = Mutex::new(None);
+// Summaries of finished sessions, the most recent last.
+static HISTORY: Mutex<VecDeque<SessionSummary>> = Mutex::new(VecDeque::new());
+
+/// Counts one outgoing video RTP packet. The marker bit ends a frame.
+pub fn record_video_packet(buffer: &gst::BufferRef) {
+    VIDEO_BYTES.fetch_add(buffer.size() as u64, Ordering::Relaxed);
+
+    if let Ok(map) = buffer.map_readable() {
+        if map.len() >= 2 && map[1] & 0x80 != 0 {
+            VIDEO_FRAMES.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+pub fn record_input_event() {
+    INPUT_EVENTS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Starts recording a timeline, unless the session already has one.
+/// Pipeline restarts within a session keep adding to the same timeline.
+pub fn start_session() {
+    let session_started = Instant::now();
+    {
+        let mut current = CURRENT.lock().unwrap();
+        if current.is_some() {
+            return;
+        }
+        *current = Some((
+            SessionTimeline {
+                started_at: Utc::now().to_rfc3339(),
+                samples: VecDeque::new(),
+                total_kbits: 0.0,
+                total_dropped_frames: 0,
+                total_samples: 0,
+            },
+            session_started,
+        ));
+    }
+
+    VIDEO_BYTES.store(0, Ordering::Relaxed);
+    VIDEO_FRAMES.store(0, Ordering::Relaxed);
+    INPUT_EVENTS.store(0, Ordering::Relaxed);
+    encoder::reset_dropped_frames();
+
+    thread::spawn(move || loop {
+        thread::sleep(SAMPLE_INTERVAL);
+
+        // Reporting drops broadcasts, which locks the streaming state. Do it before taking
+        // the timeline, the GUI locks them the other way around.
+        let dropped_frames = encoder::collect_dropped_frames();
+
+        let mut current = CURRENT.lock().unwrap();
+        // Also stops when a new session started within the interval, it has its own thread.
+        let Some((timeline, started)) = current
+            .as_mut()
+            .filter(|(_, started)| *started == session_started)
+        else {
+            return;
+        };
+
+        let link = network::latest_stats().unwrap_or_default();
+        let sample = StatsSample {
+            time_s: started.elapsed().as_secs(),
+            bitrate_kbps: VIDEO_BYTES.swap(0, Ordering::Relaxed) as f32 * 8.0 / 1000.0,
+            fps: VIDEO_FRAMES.swap(0, Ordering::Relaxed),
+            dropped_frames,
+            loss: link.loss,
+            rtt_ms: link.rtt_ms,
+            input_events: INPUT_EVENTS.swap(0, Ordering::Relaxed),
+        };
+
+        timeline.total_kbits += sample.bitrate_kbps as f64;
+        timeline.total_dropped_frames += sample.dropped_frames as u64;
+        timeline.total_samples += 1;
+
+        let max_samples = MAX_SAMPLES.load(Ordering::Relaxed);
+        while timeline.samples.len() >= max_samples {
+            timeline.samples.pop_front();
+        }
+        timeline.samples.push_back(sample);
+    });
+}
+
+/// Limits how much of a session the timeline keeps, the older part is dropped.
+pub fn set_max_minutes(minutes: u32) {
+    MAX_SAMPLES.store(minutes.max(1) as usize * 60, Ordering::Relaxed);
+}
+
+/// The summary of the running session so far, None if none is running.
+pub fn summary(reason: EndReason) -> Option<SessionSummary> {
+    CURRENT
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|(timeline, started)| timeline.summary(*started, reason))
+}
+
+/// Ends the timeline of the session, keeping it for `save_report()` and its summary for
+/// `history()`.
+pub fn finish_session(reason: EndReason) {
+    let Some((timeline, started)) = CURRENT.lock().unwrap().take() else {
+        return;
+    };
+    info!(
+        "Session timeline finished with {} samples.",
+        timeline.samples.len()
+    );
+
+    let summary = timeline.summary(started, reason);
+    info!(
+        "Session summary: {} s, {:.0} kbps on average, {} dropped frames, {}.",
+        summary.duration_s, summary.average_bitrate_kbps, summary.dropped_frames, summary.reason
+    );
+    {
+        let mut history = HISTORY.lock().unwrap();
+        while history.len() >= MAX_HISTORY {
+            history.pop_front();
+        }
+        history.push_back(summary);
+    }
+    *LAST.lock().unwrap() = Some(timeline);
+}
+
+/// Summaries of the finished sessions, the most recent first.
+pub fn history() -> Vec<SessionSummary> {
+    HISTORY.lock().unwrap().iter().rev().cloned().collect()
+}
+
+/// Whether there is a running or finished session to report on.
+pub fn has_report() -> bool {
+    CURRENT.lock().unwrap().is_some() || LAST.lock().unwrap().is_some()
+}
+
+/// Saves the per-second stats of the running session, or of the last one if none is running.
+pub fn save_report(format: ReportFormat) -> std::io::Result<PathBuf> {
+    let timeline = match CURRENT.lock().unwrap().as_ref() {
+        Some((timeline, _)) => Some(timeline.clone()),
+        None => LAST.lock().unwrap().clone(),
+    };
+    let Some(timeline) = timeline else {
+        return Err(Error::new(ErrorKind::NotFound, "No session to report on"));
+    };
+
+    let extension = match format {
+        ReportFormat::Csv => "csv",
+        ReportFormat::Json => "json",
+    };
+    let path = PathBuf::from(format!(
+        "session_report_{}.{}",
+        Utc::now().format("%Y%m%d_%H%M%S"),
+        extension
+    ));
+    let mut writer = BufWriter::new(File::create(&path)?);
+
+    match format {
+        ReportFormat::Csv => {
+            writeln!(
+                writer,
+                "time_s,bitrate_kbps,fps,dropped_frames,loss,rtt_ms,input_events"
+            )?;
+            for sample in &timeline.samples {
+                writeln!(
+                    writer,
+                    "{},{:.1},{},{},{:.4},{:.1},{}",
+                    sample.time_s,
+                    sample.bitrate_kbps,
+                    sample.fps,
+                    sample.dropped_frames,
+                    sample.loss,
+                    sample.rtt_ms,
+                    sample.input_events
+                )?;
+            }
+        }
+        ReportFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, &timeline)?;
+        }
+    }
+    writer.flush()?;
+
+    info!(
+        "Saved a session report with {} samples to {}.",
+        timeline.samples.len(),
+        path.display()
+    );
+    Ok(path)
+}
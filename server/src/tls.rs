@@ -0,0 +1,96 @@
+use futures_rustls::rustls::crypto::ring::default_provider;
+use futures_rustls::rustls::pki_types::pem::PemObject;
+use futures_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use futures_rustls::rustls::ServerConfig;
+use futures_rustls::TlsAcceptor;
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+const CERT_FILE: &str = "server_cert.pem";
+const KEY_FILE: &str = "server_key.pem";
+
+struct Identity {
+    acceptor: TlsAcceptor,
+    // SHA-256 of the certificate, as colon-separated hex.
+    fingerprint: String,
+}
+
+// Set while TLS is enabled.
+static IDENTITY: Mutex<Option<Identity>> = Mutex::new(None);
+
+/// Serves the WebSocket over TLS from now on, with a self-signed certificate that is created on
+/// first use and kept, so clients can pin its fingerprint.
+pub fn enable() -> std::io::Result<()> {
+    if !Path::new(CERT_FILE).exists() || !Path::new(KEY_FILE).exists() {
+        create_certificate()?;
+    }
+
+    let cert = CertificateDer::from_pem_file(CERT_FILE)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+    let key = PrivateKeyDer::from_pem_file(KEY_FILE)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+    let fingerprint = fingerprint_of(&cert);
+
+    let config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| {
+            builder
+                .with_no_client_auth()
+                .with_single_cert(vec![cert], key)
+        })
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+
+    info!("TLS enabled, certificate fingerprint {}.", fingerprint);
+    *IDENTITY.lock().unwrap() = Some(Identity {
+        acceptor: TlsAcceptor::from(Arc::new(config)),
+        fingerprint,
+    });
+    Ok(())
+}
+
+pub fn disable() {
+    if IDENTITY.lock().unwrap().take().is_some() {
+        info!("TLS disabled.");
+    }
+}
+
+fn create_certificate() -> std::io::Result<()> {
+    let hostname = gethostname::gethostname().to_string_lossy().into_owned();
+    let certified = rcgen::generate_simple_self_signed(vec![hostname])
+        .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+
+    fs::write(CERT_FILE, certified.cert.pem())?;
+    fs::write(KEY_FILE, certified.key_pair.serialize_pem())?;
+    warn!("Created a new TLS certificate, clients that pinned the old one will warn about it.");
+    Ok(())
+}
+
+fn fingerprint_of(cert: &CertificateDer) -> String {
+    Sha256::digest(cert.as_ref())
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Accepts TLS on new connections, if enabled.
+pub fn acceptor() -> Option<TlsAcceptor> {
+    IDENTITY
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|identity| identity.acceptor.clone())
+}
+
+/// The certificate fingerprint clients should see, if TLS is enabled.
+pub fn fingerprint() -> Option<String> {
+    IDENTITY
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|identity| identity.fingerprint.clone())
+}
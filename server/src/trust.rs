@@ -0,0 +1,296 @@
+use chrono::{SubsecRound, Utc};
+use log::{info, warn};
+use rand::distributions::{Alphanumeric, DistString};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+
+const TRUST_STORE_FILE: &str = "trusted_clients.json";
+// Clients seen longest ago are forgotten beyond this, e.g. browsers that lose their credentials.
+const MAX_TRUSTED_CLIENTS: usize = 256;
+
+/// Something the host may allow or deny each paired client.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Permission {
+    // Receiving the stream at all.
+    View,
+    Mouse,
+    Keyboard,
+    Gamepad,
+    // Display power, mode and HDR.
+    Power,
+    AppLaunch,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 6] = [
+        Permission::View,
+        Permission::Mouse,
+        Permission::Keyboard,
+        Permission::Gamepad,
+        Permission::Power,
+        Permission::AppLaunch,
+    ];
+}
+
+impl std::fmt::Display for Permission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Permission::View => write!(f, "View"),
+            Permission::Mouse => write!(f, "Mouse"),
+            Permission::Keyboard => write!(f, "Keyboard"),
+            Permission::Gamepad => write!(f, "Gamepad"),
+            Permission::Power => write!(f, "Power"),
+            Permission::AppLaunch => write!(f, "App launch"),
+        }
+    }
+}
+
+/// What a paired client may do. By default it may watch and play, the host allows the rest.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Permissions {
+    pub view: bool,
+    pub mouse: bool,
+    pub keyboard: bool,
+    pub gamepad: bool,
+    pub power: bool,
+    pub app_launch: bool,
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        Permissions {
+            view: true,
+            mouse: true,
+            keyboard: true,
+            gamepad: true,
+            power: false,
+            app_launch: false,
+        }
+    }
+}
+
+impl Permissions {
+    pub const NONE: Permissions = Permissions {
+        view: false,
+        mouse: false,
+        keyboard: false,
+        gamepad: false,
+        power: false,
+        app_launch: false,
+    };
+
+    fn flag(&mut self, permission: Permission) -> &mut bool {
+        match permission {
+            Permission::View => &mut self.view,
+            Permission::Mouse => &mut self.mouse,
+            Permission::Keyboard => &mut self.keyboard,
+            Permission::Gamepad => &mut self.gamepad,
+            Permission::Power => &mut self.power,
+            Permission::AppLaunch => &mut self.app_launch,
+        }
+    }
+
+    pub fn allows(mut self, permission: Permission) -> bool {
+        *self.flag(permission)
+    }
+
+    /// Whether the client may only watch, a spectator.
+    pub fn is_view_only(&self) -> bool {
+        !self.mouse && !self.keyboard && !self.gamepad
+    }
+
+    pub fn set(&mut self, permission: Permission, allowed: bool) {
+        *self.flag(permission) = allowed;
+    }
+}
+
+/// A client that authenticated at least once, with the labels the host gave it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrustedClient {
+    // Issued by the host at pairing, like the secret.
+    pub id: String,
+    // SHA-256 of the secret the client proves it is this one with. Clients paired before there
+    // were secrets have none and pair anew.
+    #[serde(default)]
+    secret_hash: String,
+    #[serde(default)]
+    pub label: String,
+    #[serde(default)]
+    pub notes: String,
+    pub last_ip: String,
+    pub last_seen: String,
+    #[serde(default)]
+    pub permissions: Permissions,
+}
+
+impl TrustedClient {
+    /// The label if the host gave one, otherwise the last IP.
+    pub fn display_name(&self) -> &str {
+        if self.label.is_empty() {
+            &self.last_ip
+        } else {
+            &self.label
+        }
+    }
+}
+
+static TRUSTED_CLIENTS: Mutex<Vec<TrustedClient>> = Mutex::new(Vec::new());
+// What clients get when they pair, set by the host.
+static NEW_CLIENT_PERMISSIONS: Mutex<Option<Permissions>> = Mutex::new(None);
+
+/// Sets what clients that pair from now on may do.
+pub fn configure(new_client_permissions: Permissions) {
+    *NEW_CLIENT_PERMISSIONS.lock().unwrap() = Some(new_client_permissions);
+}
+
+fn hash_secret(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// The paired client with the id and secret the host issued it, None if they do not match one.
+pub fn identify(id: Option<&str>, secret: Option<&str>) -> Option<String> {
+    let (id, secret) = (id?, secret?);
+    let secret_hash = hash_secret(secret);
+    TRUSTED_CLIENTS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|client| {
+            client.id == id && !client.secret_hash.is_empty() && client.secret_hash == secret_hash
+        })
+        .map(|client| client.id.clone())
+}
+
+/// Reads the trust store from disk. A missing file just means no client paired yet.
+pub fn load() {
+    let clients = match fs::read_to_string(TRUST_STORE_FILE) {
+        Ok(contents) => match serde_json::from_str::<Vec<TrustedClient>>(&contents) {
+            Ok(clients) => clients,
+            Err(e) => {
+                warn!("Failed to parse {}: {}", TRUST_STORE_FILE, e);
+                return;
+            }
+        },
+        Err(_) => Vec::new(),
+    };
+
+    info!("Loaded {} trusted clients.", clients.len());
+    *TRUSTED_CLIENTS.lock().unwrap() = clients;
+}
+
+/// Writes the trust store to disk.
+pub fn save() {
+    let json_string = serde_json::to_string_pretty(&*TRUSTED_CLIENTS.lock().unwrap()).unwrap();
+    if let Err(e) = fs::write(TRUST_STORE_FILE, json_string) {
+        warn!("Failed to write {}: {}", TRUST_STORE_FILE, e);
+    }
+}
+
+/// All clients that ever paired, most recently seen first.
+pub fn trusted_clients() -> Vec<TrustedClient> {
+    let mut clients = TRUSTED_CLIENTS.lock().unwrap().clone();
+    clients.sort_by(|a, b| b.last_seen.cmp(&a.last_seen));
+    clients
+}
+
+pub fn find(id: &str) -> Option<TrustedClient> {
+    TRUSTED_CLIENTS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|client| client.id == id)
+        .cloned()
+}
+
+/// Remembers that a paired client authenticated again.
+pub fn record_seen(id: &str, addr: SocketAddr) {
+    {
+        let mut clients = TRUSTED_CLIENTS.lock().unwrap();
+        if let Some(client) = clients.iter_mut().find(|client| client.id == id) {
+            client.last_ip = addr.ip().to_string();
+            client.last_seen = Utc::now().trunc_subsecs(0).to_string();
+        }
+    }
+    save();
+}
+
+/// Pairs a client that authenticated without credentials, with the permissions the host gives
+/// new clients. Returns the id and secret to hand it, only the hash of the secret is kept.
+pub fn pair(addr: SocketAddr) -> (String, String) {
+    let id = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+    let secret = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let permissions = NEW_CLIENT_PERMISSIONS.lock().unwrap().unwrap_or_default();
+    {
+        let mut clients = TRUSTED_CLIENTS.lock().unwrap();
+        info!("New trusted client {} ({}).", id, addr);
+        if clients.len() >= MAX_TRUSTED_CLIENTS {
+            if let Some(oldest) = clients
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| a.last_seen.cmp(&b.last_seen))
+                .map(|(index, _)| index)
+            {
+                info!("Forgetting {}, seen longest ago.", clients[oldest].id);
+                clients.remove(oldest);
+            }
+        }
+        clients.push(TrustedClient {
+            id: id.clone(),
+            secret_hash: hash_secret(&secret),
+            label: String::new(),
+            notes: String::new(),
+            last_ip: addr.ip().to_string(),
+            last_seen: Utc::now().trunc_subsecs(0).to_string(),
+            permissions,
+        });
+    }
+    save();
+    (id, secret)
+}
+
+/// Changes the label and notes of a client. Call `save()` once editing is done.
+pub fn set_label(id: &str, label: &str, notes: &str) {
+    let mut clients = TRUSTED_CLIENTS.lock().unwrap();
+    if let Some(client) = clients.iter_mut().find(|client| client.id == id) {
+        client.label = label.to_string();
+        client.notes = notes.to_string();
+    }
+}
+
+/// What a client may do, nothing for one the host forgot.
+pub fn permissions(id: &str) -> Permissions {
+    find(id).map_or(Permissions::NONE, |client| client.permissions)
+}
+
+/// Allows or denies a client something, from its next request on.
+pub fn set_permission(id: &str, permission: Permission, allowed: bool) {
+    {
+        let mut clients = TRUSTED_CLIENTS.lock().unwrap();
+        let Some(client) = clients.iter_mut().find(|client| client.id == id) else {
+            return;
+        };
+        client.permissions.set(permission, allowed);
+    }
+    info!(
+        "{} {} for {}.",
+        if allowed { "Allowed" } else { "Denied" },
+        permission,
+        id
+    );
+    save();
+}
+
+pub fn forget(id: &str) {
+    TRUSTED_CLIENTS
+        .lock()
+        .unwrap()
+        .retain(|client| client.id != id);
+    save();
+}
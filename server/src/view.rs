@@ -0,0 +1,344 @@
+use crate::control::{broadcast_event, ControlEvent};
+use crate::pipeline::{make, BuildError};
+use crate::stereo::StereoMode;
+use crate::stream::{pipeline_element, STREAMING_STATE_GUARD};
+use gst::prelude::*;
+use gstreamer as gst;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::io::{Error, ErrorKind};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Smallest region side, so a bad hint cannot blow single pixels up to the whole stream.
+const MIN_REGION_SIZE: f32 = 0.05;
+const ANIMATION_DURATION: Duration = Duration::from_millis(250);
+// About one frame at 60 Hz.
+const ANIMATION_STEP: Duration = Duration::from_millis(16);
+
+/// Part of the captured picture that is streamed, in fractions of its size.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ViewRegion {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl ViewRegion {
+    pub const FULL: ViewRegion = ViewRegion {
+        x: 0.0,
+        y: 0.0,
+        width: 1.0,
+        height: 1.0,
+    };
+
+    /// The region of `1 / zoom` size around a centre point.
+    pub fn around(center_x: f32, center_y: f32, zoom: f32) -> Self {
+        let size = 1.0 / zoom.max(1.0);
+        ViewRegion {
+            x: center_x - size / 2.0,
+            y: center_y - size / 2.0,
+            width: size,
+            height: size,
+        }
+    }
+
+    fn lerp(self, other: ViewRegion, t: f32) -> Self {
+        ViewRegion {
+            x: self.x + (other.x - self.x) * t,
+            y: self.y + (other.y - self.y) * t,
+            width: self.width + (other.width - self.width) * t,
+            height: self.height + (other.height - self.height) * t,
+        }
+    }
+
+    // Reshapes the region around its centre to `aspect` (width over height, in fractions)
+    // and moves it inside the picture. Matching the stream aspect keeps the scaler from
+    // adding borders, so client coordinates map linearly onto the region.
+    fn fitted(self, aspect: f32) -> Self {
+        let center_x = self.x + self.width / 2.0;
+        let center_y = self.y + self.height / 2.0;
+
+        let mut width = self.width.clamp(MIN_REGION_SIZE, 1.0);
+        let mut height = width / aspect;
+        if height > 1.0 {
+            height = 1.0;
+            width = aspect;
+        }
+        if height < MIN_REGION_SIZE {
+            height = MIN_REGION_SIZE;
+            width = (height * aspect).min(1.0);
+        }
+
+        ViewRegion {
+            x: (center_x - width / 2.0).clamp(0.0, 1.0 - width),
+            y: (center_y - height / 2.0).clamp(0.0, 1.0 - height),
+            width,
+            height,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Orientation {
+    Landscape,
+    Portrait,
+}
+
+fn default_zoom() -> f32 {
+    1.0
+}
+
+/// What a client actually shows of the stream.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ViewportHint {
+    // Size of the client view in physical pixels.
+    pub width: u32,
+    pub height: u32,
+    pub orientation: Orientation,
+    // The visible part of the desktop, if the client only shows part of it.
+    #[serde(default)]
+    pub region: Option<ViewRegion>,
+    // Used around the centre when no region is given.
+    #[serde(default = "default_zoom")]
+    pub zoom: f32,
+}
+
+static CURRENT_REGION: Mutex<ViewRegion> = Mutex::new(ViewRegion::FULL);
+static LAST_HINT: Mutex<Option<ViewportHint>> = Mutex::new(None);
+
+/// The streamed part of the picture.
+pub fn current_region() -> ViewRegion {
+    *CURRENT_REGION.lock().unwrap()
+}
+
+/// The viewport the client last described, for the GUI.
+pub fn last_hint() -> Option<ViewportHint> {
+    LAST_HINT.lock().unwrap().clone()
+}
+
+/// Maps a position in the stream (fractions of its size) to the same fractions of the captured picture.
+pub fn map_to_capture(x: f32, y: f32) -> (f32, f32) {
+    let region = current_region();
+    (region.x + x * region.width, region.y + y * region.height)
+}
+
+/// Records a client's viewport and, if the host allows it, crops the stream to what it shows.
+pub fn set_viewport_hint(hint: ViewportHint) -> std::io::Result<()> {
+    info!(
+        "Client viewport {}x{} ({:?}), region {:?}, zoom {}.",
+        hint.width, hint.height, hint.orientation, hint.region, hint.zoom
+    );
+
+    let allowed = {
+        let guard = STREAMING_STATE_GUARD.lock().unwrap();
+        guard
+            .as_ref()
+            .map_or(false, |state| state.allow_viewport_crop)
+    };
+
+    let region = hint
+        .region
+        .unwrap_or_else(|| ViewRegion::around(0.5, 0.5, hint.zoom));
+    *LAST_HINT.lock().unwrap() = Some(hint);
+
+    if !allowed {
+        info!("Viewport cropping is disabled, streaming the whole picture.");
+        return Ok(());
+    }
+
+    set_region(region)
+}
+
+/// Streams `region` of the captured picture, scaled to the stream resolution.
+pub fn set_region(region: ViewRegion) -> std::io::Result<()> {
+    let region = fit_region(region)?;
+    *ANIMATION.lock().unwrap() = None;
+    apply_region(region);
+
+    broadcast_event(&ControlEvent::ViewChanged { region });
+    Ok(())
+}
+
+/// Zooms to `zoom` times magnification, around the given centre or the current one.
+pub fn set_zoom(zoom: f32, center: Option<(f32, f32)>) -> std::io::Result<()> {
+    check_zoom_allowed()?;
+
+    let current = current_region();
+    let (center_x, center_y) = center.unwrap_or((
+        current.x + current.width / 2.0,
+        current.y + current.height / 2.0,
+    ));
+    animate_to(ViewRegion::around(center_x, center_y, zoom))
+}
+
+/// Moves the view by fractions of its own size, e.g. `dx = 0.5` pans half a view to the right.
+pub fn pan(dx: f32, dy: f32) -> std::io::Result<()> {
+    check_zoom_allowed()?;
+
+    let current = current_region();
+    animate_to(ViewRegion {
+        x: current.x + dx * current.width,
+        y: current.y + dy * current.height,
+        ..current
+    })
+}
+
+fn check_zoom_allowed() -> std::io::Result<()> {
+    let allowed = {
+        let guard = STREAMING_STATE_GUARD.lock().unwrap();
+        guard
+            .as_ref()
+            .map_or(false, |state| state.allow_viewport_crop)
+    };
+
+    if !allowed {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "Zooming is disabled on the host",
+        ));
+    }
+    Ok(())
+}
+
+// Validates `region` against the running stream and fits it to the stream aspect.
+fn fit_region(region: ViewRegion) -> std::io::Result<ViewRegion> {
+    let (stream_resolution, capture_size, stereo_mode) = {
+        let guard = STREAMING_STATE_GUARD.lock().unwrap();
+        let state = guard
+            .as_ref()
+            .expect("Streaming state was not initialized!");
+        (
+            state.stream_config.as_ref().map(|config| config.resolution),
+            state.native_resolution,
+            state.stereo_mode,
+        )
+    };
+
+    let Some(stream_resolution) = stream_resolution else {
+        return Err(Error::new(ErrorKind::Other, "No stream is configured"));
+    };
+    if stereo_mode != StereoMode::Mono {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            "Cropping is not available with stereo capture",
+        ));
+    }
+
+    let aspect = (stream_resolution.0 as f32 / stream_resolution.1 as f32)
+        / (capture_size.0 as f32 / capture_size.1 as f32);
+    Ok(region.fitted(aspect))
+}
+
+// Crops the running stream to a region that was already fitted.
+fn apply_region(region: ViewRegion) {
+    *CURRENT_REGION.lock().unwrap() = region;
+
+    let capture_size = {
+        let guard = STREAMING_STATE_GUARD.lock().unwrap();
+        guard
+            .as_ref()
+            .expect("Streaming state was not initialized!")
+            .native_resolution
+    };
+
+    if let Some(crop) = pipeline_element("viewcrop") {
+        let (left, top, right, bottom) = crop_pixels(region, capture_size);
+        crop.set_property("left", left);
+        crop.set_property("top", top);
+        crop.set_property("right", right);
+        crop.set_property("bottom", bottom);
+    }
+}
+
+struct Animation {
+    from: ViewRegion,
+    to: ViewRegion,
+    started: Instant,
+}
+
+// The pan or zoom in progress. A new one starts from wherever the last one got to.
+static ANIMATION: Mutex<Option<Animation>> = Mutex::new(None);
+
+fn animate_to(target: ViewRegion) -> std::io::Result<()> {
+    let target = fit_region(target)?;
+
+    let already_running = {
+        let mut animation = ANIMATION.lock().unwrap();
+        let running = animation.is_some();
+        *animation = Some(Animation {
+            from: current_region(),
+            to: target,
+            started: Instant::now(),
+        });
+        running
+    };
+
+    if !already_running {
+        thread::spawn(run_animation);
+    }
+    Ok(())
+}
+
+// Steps the crop towards the animation target once per frame, then tells clients where it ended.
+fn run_animation() {
+    loop {
+        let (region, done) = {
+            let mut animation = ANIMATION.lock().unwrap();
+            let Some(current) = animation.as_ref() else {
+                return;
+            };
+
+            let t = (current.started.elapsed().as_secs_f32() / ANIMATION_DURATION.as_secs_f32())
+                .min(1.0);
+            // Smoothstep, so the view eases in and out instead of jerking.
+            let eased = t * t * (3.0 - 2.0 * t);
+            let region = current.from.lerp(current.to, eased);
+
+            if t >= 1.0 {
+                *animation = None;
+            }
+            (region, t >= 1.0)
+        };
+
+        apply_region(region);
+
+        if done {
+            broadcast_event(&ControlEvent::ViewChanged { region });
+            return;
+        }
+        thread::sleep(ANIMATION_STEP);
+    }
+}
+
+// Pixels videocrop removes on each side of a picture of `size`.
+fn crop_pixels(region: ViewRegion, size: (u32, u32)) -> (i32, i32, i32, i32) {
+    let (width, height) = (size.0 as f32, size.1 as f32);
+    let left = (region.x * width) as i32;
+    let top = (region.y * height) as i32;
+    let right = ((1.0 - region.x - region.width) * width).max(0.0) as i32;
+    let bottom = ((1.0 - region.y - region.height) * height).max(0.0) as i32;
+    (left, top, right, bottom)
+}
+
+/// The crop element of the video branch, starting at the current region.
+pub fn make_crop(capture_size: (u32, u32)) -> Result<gst::Element, BuildError> {
+    let (left, top, right, bottom) = crop_pixels(current_region(), capture_size);
+    Ok(make("videocrop")?
+        .name("viewcrop")
+        .property("left", left)
+        .property("top", top)
+        .property("right", right)
+        .property("bottom", bottom)
+        .build()?)
+}
+
+/// Goes back to the whole picture once the session ends.
+pub fn reset() {
+    *ANIMATION.lock().unwrap() = None;
+    *CURRENT_REGION.lock().unwrap() = ViewRegion::FULL;
+    *LAST_HINT.lock().unwrap() = None;
+}
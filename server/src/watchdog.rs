@@ -0,0 +1,222 @@
+use crate::audio::route_to_stream_device;
+use crate::control::{broadcast_event, ControlEvent};
+use crate::launcher::running_steam_app;
+use crate::process::list_processes;
+use crate::stream::{disconnect_all_peers, STREAMING_STATE_GUARD};
+use crate::timeline::EndReason;
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::process::Child;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+const WATCHDOG_POLL_INTERVAL_SECONDS: u64 = 2;
+// Gives clients a moment to receive the exit notification before their connection is closed.
+const DISCONNECT_DELAY_MILLIS: u64 = 500;
+
+/// What to do with the session when the launched app exits.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AppExitAction {
+    StopStream,
+    Desktop,
+}
+
+impl AppExitAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AppExitAction::StopStream => "stop_stream",
+            AppExitAction::Desktop => "desktop",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "stop_stream" => Some(AppExitAction::StopStream),
+            "desktop" => Some(AppExitAction::Desktop),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for AppExitAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppExitAction::StopStream => write!(f, "Stop streaming"),
+            AppExitAction::Desktop => write!(f, "Return to desktop"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AppExitReason {
+    Exited,
+    Crashed,
+}
+
+enum WatchedApp {
+    Process {
+        name: String,
+        child: Child,
+        // The launched process and all of its descendants seen so far.
+        tree: HashSet<u32>,
+    },
+    Steam {
+        app_id: u32,
+        // Steam takes a while to start the game, only treat it as exited once it was seen running.
+        seen_running: bool,
+    },
+}
+
+static WATCHED_APP: Mutex<Option<WatchedApp>> = Mutex::new(None);
+static WATCHDOG_RUNNING: Mutex<bool> = Mutex::new(false);
+
+/// Ends the session once `child` and every process it spawned have exited.
+pub fn watch_process(name: String, child: Child) {
+    let tree = HashSet::from([child.id()]);
+    *WATCHED_APP.lock().unwrap() = Some(WatchedApp::Process { name, child, tree });
+    start_watchdog();
+}
+
+/// Ends the session once Steam no longer reports `app_id` as running.
+pub fn watch_steam_app(app_id: u32) {
+    *WATCHED_APP.lock().unwrap() = Some(WatchedApp::Steam {
+        app_id,
+        seen_running: false,
+    });
+    start_watchdog();
+}
+
+/// PIDs of the launched process tree. Empty for Steam games, which Steam starts on its own.
+pub fn watched_pids() -> Vec<u32> {
+    match WATCHED_APP.lock().unwrap().as_ref() {
+        Some(WatchedApp::Process { tree, .. }) => tree.iter().copied().collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Stops watching without taking any action, e.g. because the session already ended.
+pub fn stop_watching() {
+    WATCHED_APP.lock().unwrap().take();
+}
+
+// Returns the app name, why it exited and its exit code once the app is gone.
+fn poll(app: &mut WatchedApp) -> Option<(String, AppExitReason, Option<i32>)> {
+    match app {
+        WatchedApp::Process { name, child, tree } => {
+            let root_status = match child.try_wait() {
+                Ok(status) => status,
+                Err(e) => {
+                    warn!("Failed to poll {}: {}", name, e);
+                    None
+                }
+            };
+
+            let processes = match list_processes() {
+                Ok(processes) => processes,
+                Err(e) => {
+                    warn!("Failed to list processes: {}", e);
+                    return None;
+                }
+            };
+
+            // Adopt new descendants, then forget the ones that are gone.
+            loop {
+                let known = tree.len();
+                for process in &processes {
+                    if tree.contains(&process.parent_pid) && tree.insert(process.pid) {
+                        // Games started by a launcher need to be routed too.
+                        route_to_stream_device(process.pid);
+                    }
+                }
+                if tree.len() == known {
+                    break;
+                }
+            }
+            tree.retain(|pid| processes.iter().any(|process| process.pid == *pid));
+
+            let status = root_status?;
+            if !tree.is_empty() {
+                // The launched process was only a launcher/bootstrapper, the game lives on.
+                return None;
+            }
+
+            let reason = if status.success() {
+                AppExitReason::Exited
+            } else {
+                AppExitReason::Crashed
+            };
+            Some((name.clone(), reason, status.code()))
+        }
+        WatchedApp::Steam {
+            app_id,
+            seen_running,
+        } => {
+            if running_steam_app() == Some(*app_id) {
+                *seen_running = true;
+                return None;
+            }
+
+            seen_running.then(|| (format!("steam:{}", app_id), AppExitReason::Exited, None))
+        }
+    }
+}
+
+fn handle_app_exit(name: String, reason: AppExitReason, exit_code: Option<i32>) {
+    info!("{} {:?} (exit code {:?}).", name, reason, exit_code);
+
+    let action = {
+        let guard = STREAMING_STATE_GUARD.lock().unwrap();
+        guard
+            .as_ref()
+            .map_or(AppExitAction::StopStream, |state| state.app_exit_action)
+    };
+
+    broadcast_event(&ControlEvent::AppExited {
+        name,
+        reason,
+        exit_code,
+    });
+
+    if action == AppExitAction::StopStream {
+        thread::sleep(Duration::from_millis(DISCONNECT_DELAY_MILLIS));
+        disconnect_all_peers(EndReason::AppExited);
+    }
+}
+
+fn start_watchdog() {
+    {
+        let mut running = WATCHDOG_RUNNING.lock().unwrap();
+        if *running {
+            return;
+        }
+        *running = true;
+    }
+
+    thread::spawn(|| {
+        loop {
+            thread::sleep(Duration::from_secs(WATCHDOG_POLL_INTERVAL_SECONDS));
+
+            let exited = {
+                let mut watched = WATCHED_APP.lock().unwrap();
+                let Some(app) = watched.as_mut() else {
+                    // Cleared while still holding the lock so a concurrent watch_*() can't be missed.
+                    *WATCHDOG_RUNNING.lock().unwrap() = false;
+                    break;
+                };
+
+                let exited = poll(app);
+                if exited.is_some() {
+                    watched.take();
+                }
+                exited
+            };
+
+            if let Some((name, reason, exit_code)) = exited {
+                handle_app_exit(name, reason, exit_code);
+            }
+        }
+    });
+}
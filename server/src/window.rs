@@ -0,0 +1,141 @@
+use crate::process::list_processes;
+use windows::Win32::Foundation::{BOOL, HWND, LPARAM, RECT};
+use windows::Win32::Graphics::Dwm::{DwmGetWindowAttribute, DWMWA_EXTENDED_FRAME_BOUNDS};
+use windows::Win32::UI::Input::KeyboardAndMouse::{
+    keybd_event, KEYBD_EVENT_FLAGS, KEYEVENTF_KEYUP, VK_MENU,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    EnumWindows, GetForegroundWindow, GetWindowRect, GetWindowTextLengthW, GetWindowTextW,
+    GetWindowThreadProcessId, IsIconic, IsWindow, IsWindowVisible, SetForegroundWindow, ShowWindow,
+    SW_RESTORE,
+};
+
+#[derive(Debug, Clone)]
+pub struct WindowInfo {
+    pub hwnd: HWND,
+    pub title: String,
+    pub pid: u32,
+    pub process_name: String,
+}
+
+unsafe extern "system" fn collect_window(hwnd: HWND, lparam: LPARAM) -> BOOL {
+    let handles = &mut *(lparam.0 as *mut Vec<HWND>);
+    handles.push(hwnd);
+    BOOL(1)
+}
+
+fn window_title(hwnd: HWND) -> String {
+    unsafe {
+        let len = GetWindowTextLengthW(hwnd);
+        if len <= 0 {
+            return String::new();
+        }
+
+        let mut buffer = vec![0u16; len as usize + 1];
+        let copied = GetWindowTextW(hwnd, &mut buffer);
+        String::from_utf16_lossy(&buffer[..copied.max(0) as usize])
+    }
+}
+
+fn window_pid(hwnd: HWND) -> u32 {
+    let mut pid = 0;
+    unsafe {
+        GetWindowThreadProcessId(hwnd, Some(&mut pid));
+    }
+    pid
+}
+
+fn window_area(hwnd: HWND) -> i64 {
+    let mut rect = RECT::default();
+    if unsafe { GetWindowRect(hwnd, &mut rect) }.is_err() {
+        return 0;
+    }
+    (rect.right - rect.left) as i64 * (rect.bottom - rect.top) as i64
+}
+
+/// Lists visible top-level windows that have a title.
+pub fn list_windows() -> Vec<WindowInfo> {
+    let mut handles: Vec<HWND> = Vec::new();
+    unsafe {
+        let _ = EnumWindows(
+            Some(collect_window),
+            LPARAM(&mut handles as *mut Vec<HWND> as isize),
+        );
+    }
+
+    let processes = list_processes().unwrap_or_default();
+
+    handles
+        .into_iter()
+        .filter(|hwnd| unsafe { IsWindowVisible(*hwnd) }.as_bool())
+        .filter_map(|hwnd| {
+            let title = window_title(hwnd);
+            if title.is_empty() {
+                return None;
+            }
+
+            let pid = window_pid(hwnd);
+            let process_name = processes
+                .iter()
+                .find(|process| process.pid == pid)
+                .map_or(String::new(), |process| process.exe_name.clone());
+
+            Some(WindowInfo {
+                hwnd,
+                title,
+                pid,
+                process_name,
+            })
+        })
+        .collect()
+}
+
+/// The largest visible window owned by any of `pids`.
+pub fn find_main_window(pids: &[u32]) -> Option<WindowInfo> {
+    list_windows()
+        .into_iter()
+        .filter(|window| pids.contains(&window.pid))
+        .max_by_key(|window| window_area(window.hwnd))
+}
+
+/// Where `hwnd` is on the virtual desktop, as window capture sees it: without the invisible
+/// resize borders. None once the window is closed.
+pub fn window_bounds(hwnd: HWND) -> Option<RECT> {
+    if !unsafe { IsWindow(hwnd) }.as_bool() {
+        return None;
+    }
+
+    let mut rect = RECT::default();
+    let frame_bounds = unsafe {
+        DwmGetWindowAttribute(
+            hwnd,
+            DWMWA_EXTENDED_FRAME_BOUNDS,
+            &mut rect as *mut RECT as *mut _,
+            size_of::<RECT>() as u32,
+        )
+    };
+    if frame_bounds.is_err() {
+        unsafe { GetWindowRect(hwnd, &mut rect) }.ok()?;
+    }
+    Some(rect)
+}
+
+pub fn foreground_window() -> HWND {
+    unsafe { GetForegroundWindow() }
+}
+
+/// Restores `hwnd` if minimized and brings it to the foreground.
+pub fn focus_window(hwnd: HWND) -> bool {
+    unsafe {
+        if IsIconic(hwnd).as_bool() {
+            let _ = ShowWindow(hwnd, SW_RESTORE);
+        }
+
+        // Windows only lets the process that received the last input take the foreground.
+        // A synthetic Alt tap makes that us.
+        keybd_event(VK_MENU.0 as u8, 0, KEYBD_EVENT_FLAGS(0), 0);
+        keybd_event(VK_MENU.0 as u8, 0, KEYEVENTF_KEYUP, 0);
+
+        SetForegroundWindow(hwnd).as_bool()
+    }
+}